@@ -0,0 +1,23 @@
+use std::env;
+use std::process::Command;
+
+/// Embed the git commit the binary is built from, taken from `GIT_COMMIT` (e.g. a Docker build
+/// argument) or the working tree.
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let commit = env::var("GIT_COMMIT").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+
+    if let Some(commit) = commit.filter(|commit| !commit.is_empty()) {
+        println!("cargo:rustc-env=BENCHETH_GIT_COMMIT={}", commit);
+    }
+}
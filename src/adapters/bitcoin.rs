@@ -0,0 +1,311 @@
+//! Bitcoin Core JSON-RPC adapter.
+//!
+//! Bitcoin Core speaks the legacy JSON-RPC 1.0 dialect (responses carry no `jsonrpc` field and
+//! errors are returned with HTTP 500), which the ethers transport refuses to parse, so this
+//! adapter speaks it over the plain [`MeasuredHttp`] transport instead.
+//!
+//! We poll `getblockcount` and, for every new height, fetch the block with `getblockhash` and
+//! `getblock` and a sample of its transactions with `getrawtransaction`. Passing the block hash
+//! to `getrawtransaction` lets it work on nodes without `-txindex`.
+
+use crate::block_metrics::BlockMetrics;
+use crate::budget::Budget;
+use crate::chain::ChainConfig;
+use crate::error_kind::ErrorKind;
+use crate::limits::RequestLimits;
+use crate::measured_http_client::MeasuredHttp;
+use crate::schedule::Schedule;
+use crate::sketch::LatencySketches;
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use prometheus::Registry;
+use reqwest::{Method, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Number of transactions fetched from each new block.
+const SAMPLED_TRANSACTIONS: usize = 10;
+
+#[derive(Debug, Error)]
+pub enum BitcoinRpcError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("unexpected HTTP status {0}")]
+    Status(reqwest::StatusCode),
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
+    #[error("({code}) {message}")]
+    Rpc { code: i64, message: String },
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcErrorObject {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<'a> {
+    #[serde(borrow, default)]
+    result: Option<&'a RawValue>,
+    error: Option<RpcErrorObject>,
+}
+
+/// A measured JSON-RPC 1.0 client for Bitcoin Core.
+#[derive(Clone, Debug)]
+pub struct BitcoinRpc {
+    http: MeasuredHttp,
+}
+
+impl BitcoinRpc {
+    pub fn new(url: Url, basic_auth: Option<(String, String)>, registry: &Registry) -> Self {
+        Self {
+            http: MeasuredHttp::new(url, basic_auth, registry),
+        }
+    }
+
+    /// Count every request against `budget`, waiting while it is used up.
+    pub fn with_budget(self, budget: Budget) -> Self {
+        Self {
+            http: self.http.with_budget(budget),
+        }
+    }
+
+    /// Send requests under `limits` as well as the global ones.
+    pub fn with_limits(self, limits: RequestLimits) -> Self {
+        Self {
+            http: self.http.with_limits(limits),
+        }
+    }
+
+    pub fn latency_sketches(&self) -> LatencySketches {
+        self.http.latency_sketches()
+    }
+
+    pub async fn request<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<R, BitcoinRpcError> {
+        let res = self.send(method, params).await;
+
+        match &res {
+            // transport errors are already counted by the transport
+            Ok(_) | Err(BitcoinRpcError::Http(_)) => {}
+            Err(e) => {
+                log::debug!("Bitcoin RPC error: {:?}", e);
+                let (code, kind) = match e {
+                    BitcoinRpcError::Status(status) => (
+                        status.as_u16().to_string(),
+                        ErrorKind::from_status(Some(status.as_u16())),
+                    ),
+                    BitcoinRpcError::Rpc { code, message } => {
+                        (code.to_string(), ErrorKind::classify(*code, message, None))
+                    }
+                    _ => ("unknown".to_string(), ErrorKind::Other),
+                };
+                self.http.record_error(method, &code, kind);
+            }
+        }
+
+        res
+    }
+
+    async fn send<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<R, BitcoinRpcError> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "bencheth",
+            "method": method,
+            "params": params,
+        });
+
+        let response = self.http.request(Method::POST, "", Some(&body)).await?;
+
+        // errors come back with a 500 status but a regular JSON-RPC body
+        let rpc_response: RpcResponse = match self.http.deserialize(&response.body) {
+            Ok(rpc_response) => rpc_response,
+            Err(_) if !response.status.is_success() => {
+                return Err(BitcoinRpcError::Status(response.status))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(RpcErrorObject { code, message }) = rpc_response.error {
+            return Err(BitcoinRpcError::Rpc { code, message });
+        }
+
+        let result = rpc_response.result.map_or("null", RawValue::get);
+        Ok(self.http.deserialize(result)?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BitcoinBlock {
+    hash: String,
+    time: i64,
+    #[serde(default)]
+    tx: Vec<String>,
+}
+
+pub struct BitcoinMonitor {
+    chain: Arc<ChainConfig>,
+    client: BitcoinRpc,
+    block_metrics: BlockMetrics,
+    transactions: Schedule,
+}
+
+impl BitcoinMonitor {
+    pub fn new(chain: Arc<ChainConfig>, client: BitcoinRpc, block_metrics: BlockMetrics) -> Self {
+        let transactions = Schedule::from_chain(&chain, "TRANSACTIONS");
+
+        Self {
+            chain,
+            client,
+            block_metrics,
+            transactions,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let chain = &self.chain.name;
+        let mut interval = self.block_metrics.poll_interval(self.chain.poll_interval);
+        let mut curr_height = 0;
+
+        loop {
+            interval.tick().await;
+
+            let latest_height: u64 = match self.client.request("getblockcount", json!([])).await {
+                Ok(height) => height,
+                Err(e) => {
+                    log::warn!("[{}] Failed to get block count: {:?}", chain, e);
+                    continue;
+                }
+            };
+
+            self.block_metrics.observe_head(latest_height);
+
+            if curr_height == 0 {
+                log::info!("[{}] Current block height: {}", chain, latest_height);
+                self.block_metrics.set_height(latest_height);
+                curr_height = latest_height;
+                continue;
+            }
+
+            if latest_height == curr_height {
+                continue;
+            }
+
+            if latest_height < curr_height {
+                log::warn!(
+                    "[{}] Latest block height {} is lower than current block height {}",
+                    chain,
+                    latest_height,
+                    curr_height
+                );
+                continue;
+            }
+
+            let detected_at = Utc::now();
+            log::info!(
+                "[{}] Current block height: {} ({} new blocks)",
+                chain,
+                latest_height,
+                latest_height - curr_height
+            );
+
+            while curr_height < latest_height {
+                curr_height += 1;
+                self.process_height(curr_height, detected_at).await;
+            }
+        }
+    }
+
+    async fn process_height(&self, height: u64, detected_at: DateTime<Utc>) {
+        let chain = &self.chain.name;
+
+        let fetch_start = Instant::now();
+        let hash: String = match self.client.request("getblockhash", json!([height])).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::warn!("[{}] Failed to get block hash {}: {:?}", chain, height, e);
+                return;
+            }
+        };
+
+        let block: BitcoinBlock = match self.client.request("getblock", json!([hash, 1])).await {
+            Ok(block) => block,
+            Err(e) => {
+                log::warn!("[{}] Failed to get block {}: {:?}", chain, height, e);
+                return;
+            }
+        };
+
+        let Some(timestamp) = DateTime::<Utc>::from_timestamp(block.time, 0) else {
+            log::warn!("[{}] Invalid block timestamp {}", chain, block.time);
+            return;
+        };
+
+        self.block_metrics
+            .observe_block(height, &hash, timestamp, Some(block.tx.len()));
+        let fetch = fetch_start.elapsed();
+
+        let sampled = block
+            .tx
+            .iter()
+            .take(if self.transactions.is_active() {
+                SAMPLED_TRANSACTIONS
+            } else {
+                0
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let fan_out_start = Instant::now();
+        let transactions = tokio_stream::iter(sampled)
+            .map(|txid| self.get_transaction(txid, &block.hash))
+            .buffer_unordered(num_cpus::get())
+            .collect::<Vec<_>>()
+            .await;
+        self.block_metrics.observe_budget(
+            timestamp,
+            detected_at,
+            fetch,
+            self.transactions
+                .is_active()
+                .then(|| fan_out_start.elapsed()),
+        );
+
+        log::info!(
+            "[{}] New block height {} at {} with timestamp {} with {} txs ({} sampled) found after {}.",
+            chain,
+            height,
+            block.hash,
+            timestamp,
+            block.tx.len(),
+            transactions.len(),
+            Utc::now() - timestamp
+        );
+    }
+
+    async fn get_transaction(&self, txid: String, block_hash: &str) {
+        let tx: Result<Value, _> = self
+            .client
+            .request("getrawtransaction", json!([&txid, true, block_hash]))
+            .await;
+
+        match tx {
+            Ok(_) => log::trace!("Transaction {} found at {}", txid, Utc::now()),
+            Err(e) => log::warn!("Failed to get transaction {}: {:?}", txid, e),
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Config-defined HTTP probes for REST-style chain APIs.
+//!
+//! Not every chain API is JSON-RPC: Tron's full node API and many rollup sequencer health
+//! endpoints are plain REST. Chains with `<CHAIN>_KIND=http` are benchmarked by sending
+//! configured requests over the [`MeasuredHttp`] transport and extracting values from the JSON
+//! responses.
+//!
+//! The optional head probe feeds the usual block metrics:
+//!
+//! ```text
+//! TRON_HEAD_PATH=/wallet/getnowblock
+//! TRON_HEAD_METHOD=POST
+//! TRON_HEAD_HEIGHT_FIELD=block_header.raw_data.number
+//! TRON_HEAD_TIMESTAMP_FIELD=block_header.raw_data.timestamp
+//! TRON_HEAD_HASH_FIELD=blockID
+//! ```
+//!
+//! Any number of additional probes export `probe_success` and, when a field is configured,
+//! `probe_value`, labelled by probe name:
+//!
+//! ```text
+//! TRON_PROBES=node_info
+//! TRON_PROBE_NODE_INFO_PATH=/wallet/getnodeinfo
+//! TRON_PROBE_NODE_INFO_FIELD=activeConnectCount
+//! ```
+//!
+//! Fields are dot-separated paths (`a.b.0.c`) or JSON pointers (`/a/b/0/c`). Numbers, numeric and
+//! `0x` hex strings and booleans are accepted as values; timestamps may be RFC 3339 strings or
+//! unix timestamps in seconds, milliseconds or microseconds.
+
+use crate::block_metrics::BlockMetrics;
+use crate::chain::ChainConfig;
+use crate::error_kind::ErrorKind;
+use crate::measured_http_client::MeasuredHttp;
+use crate::schedule::Schedule;
+
+use chrono::{DateTime, Utc};
+use prometheus::{GaugeVec, Opts, Registry};
+use reqwest::Method;
+use serde_json::Value;
+
+use std::sync::Arc;
+
+/// Look up a dot-separated path or JSON pointer in a JSON value.
+pub fn extract_field<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    if field.starts_with('/') {
+        return value.pointer(field);
+    }
+    value.pointer(&format!("/{}", field.replace('.', "/")))
+}
+
+/// Interpret a JSON value as a number.
+pub fn value_as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok().map(|n| n as f64),
+            None => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// Interpret a JSON value as a timestamp, guessing the unit of numeric timestamps.
+pub fn value_as_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    if let Value::String(s) = value {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+            return Some(timestamp.with_timezone(&Utc));
+        }
+    }
+
+    let timestamp = value_as_f64(value)? as i64;
+    match timestamp {
+        t if t < 100_000_000_000 => DateTime::<Utc>::from_timestamp(t, 0),
+        t if t < 100_000_000_000_000 => DateTime::<Utc>::from_timestamp_millis(t),
+        t => DateTime::<Utc>::from_timestamp_micros(t),
+    }
+}
+
+/// A configured HTTP request.
+#[derive(Clone, Debug)]
+pub struct HttpProbe {
+    pub name: String,
+    pub method: Method,
+    pub path: String,
+    pub body: Option<Value>,
+    pub field: Option<String>,
+    pub schedule: Schedule,
+}
+
+impl HttpProbe {
+    /// Read a probe from `<CHAIN>_<prefix>PATH`, `_METHOD`, `_BODY`, `_FIELD` and `_SCHEDULE`.
+    fn from_chain(chain: &ChainConfig, name: &str, prefix: &str) -> Option<Self> {
+        let path = chain.var(&format!("{}PATH", prefix))?;
+        let method = chain
+            .var(&format!("{}METHOD", prefix))
+            .map(|method| {
+                method
+                    .to_uppercase()
+                    .parse::<Method>()
+                    .unwrap_or_else(|_| panic!("Invalid {}METHOD", prefix))
+            })
+            .unwrap_or(Method::GET);
+        let body = chain.var(&format!("{}BODY", prefix)).map(|body| {
+            serde_json::from_str(&body).unwrap_or_else(|_| panic!("Invalid {}BODY", prefix))
+        });
+        let field = chain.var(&format!("{}FIELD", prefix));
+        let schedule = Schedule::from_chain(chain, prefix.trim_end_matches('_'));
+
+        Some(Self {
+            name: name.to_string(),
+            method,
+            path,
+            body,
+            field,
+            schedule,
+        })
+    }
+
+    /// Send the probe, returning the JSON response when the request succeeded.
+    pub async fn send(&self, http: &MeasuredHttp) -> Option<Value> {
+        let response = match http
+            .request(self.method.clone(), &self.path, self.body.as_ref())
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                log::warn!("Probe {} failed: {:?}", self.name, e);
+                return None;
+            }
+        };
+
+        if !response.status.is_success() {
+            log::warn!("Probe {} returned {}", self.name, response.status);
+            http.record_error(
+                &MeasuredHttp::method_label(&self.path, self.body.as_ref()),
+                &response.status.as_u16().to_string(),
+                ErrorKind::from_status(Some(response.status.as_u16())),
+            );
+            return None;
+        }
+
+        match serde_json::from_str(&response.body) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::warn!("Probe {} returned invalid JSON: {:?}", self.name, e);
+                http.record_error(
+                    &MeasuredHttp::method_label(&self.path, self.body.as_ref()),
+                    "unknown",
+                    ErrorKind::Other,
+                );
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HeadProbe {
+    probe: HttpProbe,
+    height_field: String,
+    timestamp_field: Option<String>,
+    hash_field: Option<String>,
+}
+
+impl HeadProbe {
+    fn from_chain(chain: &ChainConfig) -> Option<Self> {
+        let probe = HttpProbe::from_chain(chain, "head", "HEAD_")?;
+        let height_field = chain
+            .var("HEAD_HEIGHT_FIELD")
+            .expect("HEAD_HEIGHT_FIELD is required with HEAD_PATH");
+
+        Some(Self {
+            probe,
+            height_field,
+            timestamp_field: chain.var("HEAD_TIMESTAMP_FIELD"),
+            hash_field: chain.var("HEAD_HASH_FIELD"),
+        })
+    }
+}
+
+pub struct HttpMonitor {
+    chain: Arc<ChainConfig>,
+    http: MeasuredHttp,
+    head: Option<HeadProbe>,
+    probes: Vec<HttpProbe>,
+    block_metrics: BlockMetrics,
+    probe_success: GaugeVec,
+    probe_value: GaugeVec,
+}
+
+impl HttpMonitor {
+    pub fn new(
+        chain: Arc<ChainConfig>,
+        http: MeasuredHttp,
+        registry: &Registry,
+        block_metrics: BlockMetrics,
+    ) -> Self {
+        let probe_success = GaugeVec::new(
+            Opts::new("probe_success", "Whether the last HTTP probe succeeded"),
+            &["probe"],
+        )
+        .unwrap();
+        let probe_value = GaugeVec::new(
+            Opts::new(
+                "probe_value",
+                "Value extracted from the last HTTP probe response",
+            ),
+            &["probe"],
+        )
+        .unwrap();
+        registry.register(Box::new(probe_success.clone())).unwrap();
+        registry.register(Box::new(probe_value.clone())).unwrap();
+
+        let head = HeadProbe::from_chain(&chain);
+        let probes = chain
+            .var("PROBES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let prefix = format!("PROBE_{}_", name.to_uppercase().replace('-', "_"));
+                HttpProbe::from_chain(&chain, name, &prefix)
+                    .unwrap_or_else(|| panic!("{}PATH is required", prefix))
+            })
+            .collect::<Vec<_>>();
+
+        if head.is_none() && probes.is_empty() {
+            log::warn!("[{}] No HTTP probes configured", chain.name);
+        }
+
+        Self {
+            chain,
+            http,
+            head,
+            probes,
+            block_metrics,
+            probe_success,
+            probe_value,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = self.block_metrics.poll_interval(self.chain.poll_interval);
+        let mut curr_height = 0;
+
+        loop {
+            interval.tick().await;
+
+            if let Some(head) = &self.head {
+                if let Some(height) = self.poll_head(head, curr_height).await {
+                    curr_height = height;
+                }
+            }
+
+            futures::future::join_all(self.probes.iter().map(|probe| self.run_probe(probe))).await;
+        }
+    }
+
+    async fn run_probe(&self, probe: &HttpProbe) {
+        if !probe.schedule.is_active() {
+            return;
+        }
+
+        let response = probe.send(&self.http).await;
+        self.probe_success
+            .with_label_values(&[&probe.name])
+            .set(if response.is_some() { 1.0 } else { 0.0 });
+
+        let (Some(response), Some(field)) = (response, &probe.field) else {
+            return;
+        };
+
+        match extract_field(&response, field).and_then(value_as_f64) {
+            Some(value) => self
+                .probe_value
+                .with_label_values(&[&probe.name])
+                .set(value),
+            None => log::warn!(
+                "[{}] Probe {} response has no numeric {}",
+                self.chain.name,
+                probe.name,
+                field
+            ),
+        }
+    }
+
+    /// Poll the head probe, returning the new head height if it advanced.
+    async fn poll_head(&self, head: &HeadProbe, curr_height: u64) -> Option<u64> {
+        let chain = &self.chain.name;
+        let response = head.probe.send(&self.http).await;
+        self.probe_success
+            .with_label_values(&[&head.probe.name])
+            .set(if response.is_some() { 1.0 } else { 0.0 });
+        let response = response?;
+
+        let Some(height) = extract_field(&response, &head.height_field).and_then(value_as_f64)
+        else {
+            log::warn!(
+                "[{}] Head response has no numeric {}",
+                chain,
+                head.height_field
+            );
+            return None;
+        };
+        let height = height as u64;
+
+        self.block_metrics.observe_head(height);
+
+        if height <= curr_height {
+            if height < curr_height {
+                log::warn!(
+                    "[{}] Latest block height {} is lower than current block height {}",
+                    chain,
+                    height,
+                    curr_height
+                );
+            }
+            return None;
+        }
+
+        let timestamp = head
+            .timestamp_field
+            .as_ref()
+            .and_then(|field| extract_field(&response, field))
+            .and_then(value_as_timestamp);
+        let hash = head
+            .hash_field
+            .as_ref()
+            .and_then(|field| extract_field(&response, field))
+            .map(|hash| hash.as_str().map_or(hash.to_string(), str::to_string))
+            .unwrap_or_default();
+
+        match timestamp {
+            Some(timestamp) if curr_height != 0 => {
+                self.block_metrics
+                    .observe_block(height, &hash, timestamp, None);
+                log::info!(
+                    "[{}] New block height {} at {} with timestamp {} found after {}.",
+                    chain,
+                    height,
+                    hash,
+                    timestamp,
+                    Utc::now() - timestamp
+                );
+            }
+            _ => {
+                log::info!("[{}] Current block height: {}", chain, height);
+                self.block_metrics.set_height(height);
+            }
+        }
+
+        Some(height)
+    }
+}
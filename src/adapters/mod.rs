@@ -0,0 +1,15 @@
+//! Adapters benchmarking non-EVM chains.
+//!
+//! Every adapter drives its own polling loop but reports through the same
+//! [`MeasuredJsonRpc`](crate::measured_json_rpc_client::MeasuredJsonRpc) transport and
+//! [`BlockMetrics`](crate::block_metrics::BlockMetrics), so latency, errors, freshness and
+//! height lag look the same on dashboards whatever the chain. APIs that aren't JSON-RPC 2.0,
+//! like Bitcoin Core's legacy dialect or REST APIs, go through the plain
+//! [`MeasuredHttp`](crate::measured_http_client::MeasuredHttp) transport instead, which records
+//! the same request metrics.
+
+pub mod bitcoin;
+pub mod http;
+pub mod solana;
+pub mod starknet;
+pub mod tendermint;
@@ -0,0 +1,259 @@
+//! Solana JSON-RPC adapter.
+//!
+//! Polls `getSlot` and, whenever the slot advances, fetches the new block with `getBlock`,
+//! a sample of its transactions with `getTransaction` and the recent priority fees with
+//! `getRecentPrioritizationFees`. Solana produces a slot every ~400ms and blocks carry
+//! thousands of transactions, so unlike the EVM monitor we neither backfill skipped slots nor
+//! fetch every transaction.
+
+use crate::block_metrics::BlockMetrics;
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::schedule::Schedule;
+
+use chrono::{DateTime, Utc};
+use ethers::providers::JsonRpcClient;
+use futures::StreamExt;
+use prometheus::{Gauge, Registry};
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::value::RawValue;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Number of transactions fetched from each new block.
+const SAMPLED_TRANSACTIONS: usize = 10;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SolanaBlock {
+    blockhash: String,
+    block_time: Option<i64>,
+    #[serde(default)]
+    signatures: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PrioritizationFee {
+    prioritization_fee: u64,
+}
+
+pub struct SolanaMonitor {
+    chain: Arc<ChainConfig>,
+    client: MeasuredJsonRpc,
+    block_metrics: BlockMetrics,
+    prioritization_fee: Gauge,
+    transactions: Schedule,
+}
+
+impl SolanaMonitor {
+    pub fn new(
+        chain: Arc<ChainConfig>,
+        client: MeasuredJsonRpc,
+        registry: &Registry,
+        block_metrics: BlockMetrics,
+    ) -> Self {
+        let prioritization_fee = Gauge::new(
+            "solana_prioritization_fee",
+            "Median recent prioritization fee in micro-lamports per compute unit",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(prioritization_fee.clone()))
+            .unwrap();
+
+        let transactions = Schedule::from_chain(&chain, "TRANSACTIONS");
+
+        Self {
+            chain,
+            client,
+            block_metrics,
+            prioritization_fee,
+            transactions,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let chain = &self.chain.name;
+        let mut interval = self.block_metrics.poll_interval(self.chain.poll_interval);
+        let mut curr_slot = 0;
+
+        loop {
+            interval.tick().await;
+
+            let slot: u64 = match self
+                .client
+                .request("getSlot", json!([{ "commitment": "confirmed" }]))
+                .await
+            {
+                Ok(slot) => slot,
+                Err(e) => {
+                    log::warn!("[{}] Failed to get slot: {:?}", chain, e);
+                    continue;
+                }
+            };
+
+            self.block_metrics.observe_head(slot);
+
+            if slot <= curr_slot {
+                if slot < curr_slot {
+                    log::warn!(
+                        "[{}] Latest slot {} is lower than current slot {}",
+                        chain,
+                        slot,
+                        curr_slot
+                    );
+                }
+                continue;
+            }
+
+            if curr_slot == 0 {
+                log::info!("[{}] Current slot: {}", chain, slot);
+                self.block_metrics.set_height(slot);
+                curr_slot = slot;
+                continue;
+            }
+
+            log::info!(
+                "[{}] Current slot: {} ({} new slots)",
+                chain,
+                slot,
+                slot - curr_slot
+            );
+            curr_slot = slot;
+
+            self.process_slot(slot, Utc::now()).await;
+        }
+    }
+
+    async fn process_slot(&self, slot: u64, detected_at: DateTime<Utc>) {
+        let chain = &self.chain.name;
+
+        let fetch_start = Instant::now();
+        let block: SolanaBlock = match self
+            .client
+            .request(
+                "getBlock",
+                json!([slot, {
+                    "commitment": "confirmed",
+                    "encoding": "json",
+                    "transactionDetails": "signatures",
+                    "maxSupportedTransactionVersion": 0,
+                    "rewards": false,
+                }]),
+            )
+            .await
+        {
+            Ok(block) => block,
+            Err(e) => {
+                // skipped slots and slots not yet available also end up here
+                log::warn!("[{}] Failed to get block {}: {:?}", chain, slot, e);
+                return;
+            }
+        };
+
+        let fetch = fetch_start.elapsed();
+        let Some(timestamp) = block
+            .block_time
+            .and_then(|block_time| DateTime::<Utc>::from_timestamp(block_time, 0))
+        else {
+            log::warn!("[{}] Block {} has no block time", chain, slot);
+            return;
+        };
+
+        self.block_metrics.observe_block(
+            slot,
+            &block.blockhash,
+            timestamp,
+            Some(block.signatures.len()),
+        );
+
+        let sampled = block
+            .signatures
+            .iter()
+            .take(if self.transactions.is_active() {
+                SAMPLED_TRANSACTIONS
+            } else {
+                0
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let fan_out_start = Instant::now();
+        let transactions = tokio_stream::iter(sampled)
+            .map(|signature| self.get_transaction(signature))
+            .buffer_unordered(num_cpus::get())
+            .collect::<Vec<_>>()
+            .await;
+        self.block_metrics.observe_budget(
+            timestamp,
+            detected_at,
+            fetch,
+            self.transactions
+                .is_active()
+                .then(|| fan_out_start.elapsed()),
+        );
+
+        self.update_prioritization_fee().await;
+
+        log::info!(
+            "[{}] New slot {} at {} with timestamp {} with {} txs ({} sampled) found after {}.",
+            chain,
+            slot,
+            block.blockhash,
+            timestamp,
+            block.signatures.len(),
+            transactions.len(),
+            Utc::now() - timestamp
+        );
+    }
+
+    async fn get_transaction(&self, signature: String) {
+        let tx: Result<Option<Box<RawValue>>, _> = self
+            .client
+            .request(
+                "getTransaction",
+                json!([&signature, {
+                    "commitment": "confirmed",
+                    "encoding": "json",
+                    "maxSupportedTransactionVersion": 0,
+                }]),
+            )
+            .await;
+
+        match tx {
+            Ok(Some(_)) => log::trace!("Transaction {} found at {}", signature, Utc::now()),
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to get transaction {}: {:?}", signature, e),
+        }
+    }
+
+    async fn update_prioritization_fee(&self) {
+        let fees: Vec<PrioritizationFee> = match self
+            .client
+            .request("getRecentPrioritizationFees", json!([]))
+            .await
+        {
+            Ok(fees) => fees,
+            Err(e) => {
+                log::warn!(
+                    "[{}] Failed to get recent prioritization fees: {:?}",
+                    self.chain.name,
+                    e
+                );
+                return;
+            }
+        };
+
+        let mut fees = fees
+            .into_iter()
+            .map(|fee| fee.prioritization_fee)
+            .collect::<Vec<_>>();
+        if fees.is_empty() {
+            return;
+        }
+        fees.sort_unstable();
+        self.prioritization_fee.set(fees[fees.len() / 2] as f64);
+    }
+}
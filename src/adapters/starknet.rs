@@ -0,0 +1,224 @@
+//! Starknet JSON-RPC adapter.
+//!
+//! Starknet's JSON-RPC has nothing in common with the EVM's besides the envelope, so the ethers
+//! provider can't be used, but the measured transport can. We poll `starknet_blockNumber` and,
+//! for every new height, fetch the block with `starknet_getBlockWithTxs` and run a
+//! `starknet_call` against it.
+//!
+//! The call defaults to `decimals()` on the ETH fee token, which is deployed at the same address
+//! on mainnet and Sepolia. It can be changed with `<CHAIN>_STARKNET_CALL_CONTRACT`,
+//! `<CHAIN>_STARKNET_CALL_SELECTOR` and `<CHAIN>_STARKNET_CALL_CALLDATA` (comma-separated felts).
+
+use crate::block_metrics::BlockMetrics;
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::schedule::Schedule;
+
+use chrono::{DateTime, Utc};
+use ethers::providers::JsonRpcClient;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use std::sync::Arc;
+use std::time::Instant;
+
+const ETH_FEE_TOKEN: &str = "0x049d36570d4e46f48e99674bd3fcc84644ddd6b96f7c741b1562b82f9e004dc7";
+/// `starknet_keccak("decimals")`
+const DECIMALS_SELECTOR: &str =
+    "0x004c4fb1ab068f6039d5780c68dd0fa2f8742cceb3426d19667778ca7f3518a9";
+
+#[derive(Debug, Deserialize)]
+struct StarknetBlock {
+    block_hash: String,
+    timestamp: i64,
+    /// Only counted, so left unparsed.
+    #[serde(default)]
+    transactions: Vec<IgnoredAny>,
+}
+
+#[derive(Clone, Debug)]
+struct StarknetCall {
+    contract_address: String,
+    entry_point_selector: String,
+    calldata: Vec<String>,
+}
+
+impl StarknetCall {
+    fn from_chain(chain: &ChainConfig) -> Self {
+        Self {
+            contract_address: chain
+                .var("STARKNET_CALL_CONTRACT")
+                .unwrap_or_else(|| ETH_FEE_TOKEN.to_string()),
+            entry_point_selector: chain
+                .var("STARKNET_CALL_SELECTOR")
+                .unwrap_or_else(|| DECIMALS_SELECTOR.to_string()),
+            calldata: chain
+                .var("STARKNET_CALL_CALLDATA")
+                .map(|calldata| {
+                    calldata
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|felt| !felt.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub struct StarknetMonitor {
+    chain: Arc<ChainConfig>,
+    client: MeasuredJsonRpc,
+    call: StarknetCall,
+    calls: Schedule,
+    block_metrics: BlockMetrics,
+}
+
+impl StarknetMonitor {
+    pub fn new(
+        chain: Arc<ChainConfig>,
+        client: MeasuredJsonRpc,
+        block_metrics: BlockMetrics,
+    ) -> Self {
+        let call = StarknetCall::from_chain(&chain);
+        let calls = Schedule::from_chain(&chain, "CALLS");
+
+        Self {
+            chain,
+            client,
+            call,
+            calls,
+            block_metrics,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let chain = &self.chain.name;
+        let mut interval = self.block_metrics.poll_interval(self.chain.poll_interval);
+        let mut curr_height = 0;
+
+        loop {
+            interval.tick().await;
+
+            let latest_height: u64 =
+                match self.client.request("starknet_blockNumber", json!([])).await {
+                    Ok(height) => height,
+                    Err(e) => {
+                        log::warn!("[{}] Failed to get block number: {:?}", chain, e);
+                        continue;
+                    }
+                };
+
+            self.block_metrics.observe_head(latest_height);
+
+            if curr_height == 0 {
+                log::info!("[{}] Current block height: {}", chain, latest_height);
+                self.block_metrics.set_height(latest_height);
+                curr_height = latest_height;
+                continue;
+            }
+
+            if latest_height == curr_height {
+                continue;
+            }
+
+            if latest_height < curr_height {
+                log::warn!(
+                    "[{}] Latest block height {} is lower than current block height {}",
+                    chain,
+                    latest_height,
+                    curr_height
+                );
+                continue;
+            }
+
+            let detected_at = Utc::now();
+            log::info!(
+                "[{}] Current block height: {} ({} new blocks)",
+                chain,
+                latest_height,
+                latest_height - curr_height
+            );
+
+            while curr_height < latest_height {
+                curr_height += 1;
+                self.process_height(curr_height, detected_at).await;
+            }
+        }
+    }
+
+    async fn process_height(&self, height: u64, detected_at: DateTime<Utc>) {
+        let chain = &self.chain.name;
+        let block_id = json!({ "block_number": height });
+
+        let fetch_start = Instant::now();
+        let block: StarknetBlock = match self
+            .client
+            .request("starknet_getBlockWithTxs", json!({ "block_id": block_id }))
+            .await
+        {
+            Ok(block) => block,
+            Err(e) => {
+                log::warn!("[{}] Failed to get block {}: {:?}", chain, height, e);
+                return;
+            }
+        };
+
+        let Some(timestamp) = DateTime::<Utc>::from_timestamp(block.timestamp, 0) else {
+            log::warn!("[{}] Invalid block timestamp {}", chain, block.timestamp);
+            return;
+        };
+
+        self.block_metrics.observe_block(
+            height,
+            &block.block_hash,
+            timestamp,
+            Some(block.transactions.len()),
+        );
+        // the block comes with its transactions
+        self.block_metrics
+            .observe_budget(timestamp, detected_at, fetch_start.elapsed(), None);
+
+        if self.calls.is_active() {
+            self.call(height, &block_id).await;
+        }
+
+        log::info!(
+            "[{}] New block height {} at {} with timestamp {} with {} txs found after {}.",
+            chain,
+            height,
+            block.block_hash,
+            timestamp,
+            block.transactions.len(),
+            Utc::now() - timestamp
+        );
+    }
+
+    async fn call(&self, height: u64, block_id: &Value) {
+        let call: Result<Vec<String>, _> = self
+            .client
+            .request(
+                "starknet_call",
+                json!({
+                    "request": {
+                        "contract_address": self.call.contract_address,
+                        "entry_point_selector": self.call.entry_point_selector,
+                        "calldata": self.call.calldata,
+                    },
+                    "block_id": block_id,
+                }),
+            )
+            .await;
+
+        if let Err(e) = call {
+            log::warn!(
+                "[{}] Failed to call at block {}: {:?}",
+                self.chain.name,
+                height,
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Cosmos/Tendermint (CometBFT) RPC adapter.
+//!
+//! CometBFT serves its RPC as JSON-RPC 2.0 over HTTP POST, so it goes through the same measured
+//! transport. We poll `status` for the latest height and, for every new height, fetch the block
+//! with `block` and look up its transactions with `tx_search`.
+
+use crate::block_metrics::BlockMetrics;
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::schedule::Schedule;
+
+use chrono::{DateTime, Utc};
+use ethers::providers::JsonRpcClient;
+use serde::Deserialize;
+use serde_json::json;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Page size used for `tx_search`, CometBFT caps it at 100.
+const TX_SEARCH_PAGE_SIZE: u64 = 100;
+
+// CometBFT encodes 64 bit integers as strings.
+#[derive(Debug, Deserialize)]
+struct Status {
+    sync_info: SyncInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct SyncInfo {
+    latest_block_height: String,
+    catching_up: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockResponse {
+    block_id: BlockId,
+    block: Block,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockId {
+    hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Block {
+    header: Header,
+    data: BlockData,
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    time: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockData {
+    #[serde(default)]
+    txs: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TxSearch {
+    total_count: String,
+}
+
+pub struct TendermintMonitor {
+    chain: Arc<ChainConfig>,
+    client: MeasuredJsonRpc,
+    block_metrics: BlockMetrics,
+    transactions: Schedule,
+}
+
+impl TendermintMonitor {
+    pub fn new(
+        chain: Arc<ChainConfig>,
+        client: MeasuredJsonRpc,
+        block_metrics: BlockMetrics,
+    ) -> Self {
+        let transactions = Schedule::from_chain(&chain, "TRANSACTIONS");
+
+        Self {
+            chain,
+            client,
+            block_metrics,
+            transactions,
+        }
+    }
+
+    async fn get_height(&self) -> Option<u64> {
+        let status: Status = match self.client.request("status", json!({})).await {
+            Ok(status) => status,
+            Err(e) => {
+                log::warn!("[{}] Failed to get status: {:?}", self.chain.name, e);
+                return None;
+            }
+        };
+
+        if status.sync_info.catching_up {
+            log::warn!("[{}] Node is catching up", self.chain.name);
+        }
+
+        match status.sync_info.latest_block_height.parse() {
+            Ok(height) => Some(height),
+            Err(e) => {
+                log::warn!(
+                    "[{}] Invalid latest block height {}: {:?}",
+                    self.chain.name,
+                    status.sync_info.latest_block_height,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let chain = &self.chain.name;
+        let mut interval = self.block_metrics.poll_interval(self.chain.poll_interval);
+        let mut curr_height = 0;
+
+        loop {
+            interval.tick().await;
+
+            let Some(latest_height) = self.get_height().await else {
+                continue;
+            };
+
+            self.block_metrics.observe_head(latest_height);
+
+            if curr_height == 0 {
+                log::info!("[{}] Current block height: {}", chain, latest_height);
+                self.block_metrics.set_height(latest_height);
+                curr_height = latest_height;
+                continue;
+            }
+
+            if latest_height == curr_height {
+                continue;
+            }
+
+            if latest_height < curr_height {
+                log::warn!(
+                    "[{}] Latest block height {} is lower than current block height {}",
+                    chain,
+                    latest_height,
+                    curr_height
+                );
+                continue;
+            }
+
+            let detected_at = Utc::now();
+            log::info!(
+                "[{}] Current block height: {} ({} new blocks)",
+                chain,
+                latest_height,
+                latest_height - curr_height
+            );
+
+            while curr_height < latest_height {
+                curr_height += 1;
+                self.process_height(curr_height, detected_at).await;
+            }
+        }
+    }
+
+    async fn process_height(&self, height: u64, detected_at: DateTime<Utc>) {
+        let chain = &self.chain.name;
+
+        let fetch_start = Instant::now();
+        let response: BlockResponse = match self
+            .client
+            .request("block", json!({ "height": height.to_string() }))
+            .await
+        {
+            Ok(block) => block,
+            Err(e) => {
+                log::warn!("[{}] Failed to get block {}: {:?}", chain, height, e);
+                return;
+            }
+        };
+
+        let fetch = fetch_start.elapsed();
+        let timestamp = response.block.header.time;
+        let block_txs = response.block.data.txs.map_or(0, |txs| txs.len());
+        self.block_metrics.observe_block(
+            height,
+            &response.block_id.hash,
+            timestamp,
+            Some(block_txs),
+        );
+        let search_start = Instant::now();
+        let indexed_txs = if block_txs == 0 {
+            Some(0)
+        } else if self.transactions.is_active() {
+            self.search_transactions(height).await
+        } else {
+            None
+        };
+        self.block_metrics.observe_budget(
+            timestamp,
+            detected_at,
+            fetch,
+            self.transactions
+                .is_active()
+                .then(|| search_start.elapsed()),
+        );
+
+        if let Some(indexed_txs) = indexed_txs {
+            if indexed_txs != block_txs as u64 {
+                log::warn!(
+                    "[{}] Block {} has {} txs but tx_search found {}",
+                    chain,
+                    height,
+                    block_txs,
+                    indexed_txs
+                );
+            }
+        }
+
+        log::info!(
+            "[{}] New block height {} at {} with timestamp {} with {} txs found after {}.",
+            chain,
+            height,
+            response.block_id.hash,
+            timestamp,
+            block_txs,
+            Utc::now() - timestamp
+        );
+    }
+
+    async fn search_transactions(&self, height: u64) -> Option<u64> {
+        let result: Result<TxSearch, _> = self
+            .client
+            .request(
+                "tx_search",
+                json!({
+                    "query": format!("tx.height={}", height),
+                    "prove": false,
+                    "page": "1",
+                    "per_page": TX_SEARCH_PAGE_SIZE.to_string(),
+                    "order_by": "asc",
+                }),
+            )
+            .await;
+
+        match result.map(|search| search.total_count.parse::<u64>()) {
+            Ok(Ok(count)) => Some(count),
+            Ok(Err(e)) => {
+                log::warn!("Invalid tx_search total count at {}: {:?}", height, e);
+                None
+            }
+            Err(e) => {
+                log::warn!("Failed to search transactions at {}: {:?}", height, e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Grafana annotations for provider incidents.
+//!
+//! Every provider status change is pushed to the Grafana HTTP API as an annotation tagged with
+//! `bencheth`, the chain, the RPC host and the new status, so latency charts carry incident
+//! context. Annotations are global by default and can be shown on any dashboard with a
+//! "Grafana" annotation query filtering on these tags, or pinned to a dashboard (and panel).
+//!
+//! - `GRAFANA_ANNOTATIONS_URL`: base URL of the Grafana instance.
+//! - `GRAFANA_API_TOKEN`: service account token allowed to write annotations.
+//! - `GRAFANA_DASHBOARD_UID`, `GRAFANA_PANEL_ID`: optional dashboard and panel to annotate.
+
+use super::{Alert, AlertSink, StatusChange, TIMEOUT};
+
+use async_trait::async_trait;
+use reqwest::Url;
+use serde_json::json;
+
+use std::env;
+
+pub struct GrafanaAnnotations {
+    client: reqwest::Client,
+    url: Url,
+    token: String,
+    dashboard_uid: Option<String>,
+    panel_id: Option<u64>,
+}
+
+impl GrafanaAnnotations {
+    /// `None` unless `GRAFANA_ANNOTATIONS_URL` is set.
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("GRAFANA_ANNOTATIONS_URL").ok()?;
+        let url = Url::parse(&url)
+            .and_then(|url| url.join("api/annotations"))
+            .expect("Invalid GRAFANA_ANNOTATIONS_URL");
+        let token = env::var("GRAFANA_API_TOKEN")
+            .expect("GRAFANA_API_TOKEN is required with GRAFANA_ANNOTATIONS_URL");
+
+        Some(Self {
+            client: reqwest::Client::new(),
+            url,
+            token,
+            dashboard_uid: env::var("GRAFANA_DASHBOARD_UID").ok(),
+            panel_id: env::var("GRAFANA_PANEL_ID")
+                .ok()
+                .map(|id| id.parse::<u64>().expect("Invalid GRAFANA_PANEL_ID")),
+        })
+    }
+}
+
+#[async_trait]
+impl AlertSink for GrafanaAnnotations {
+    // individual rules are too noisy for a chart, status changes summarize them
+    async fn alert(&self, _alert: &Alert) {}
+
+    async fn status_changed(&self, change: &StatusChange) {
+        let mut text = format!(
+            "{} {}: {} → {}",
+            change.chain, change.rpc, change.from, change.to
+        );
+        if change.maintenance {
+            text.push_str(" (maintenance)");
+        }
+
+        let mut annotation = json!({
+            "time": change.at.timestamp_millis(),
+            "tags": ["bencheth", change.chain, change.rpc, change.to.to_string()],
+            "text": text,
+        });
+        if let Some(uid) = &self.dashboard_uid {
+            annotation["dashboardUID"] = json!(uid);
+        }
+        if let Some(panel_id) = self.panel_id {
+            annotation["panelId"] = json!(panel_id);
+        }
+
+        let res = self
+            .client
+            .post(self.url.clone())
+            .bearer_auth(&self.token)
+            .json(&annotation)
+            .timeout(TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = res {
+            log::warn!("Failed to push Grafana annotation: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Provider status evaluation and alerting.
+//!
+//! Every provider is periodically checked against a set of rules. A firing rule makes the
+//! provider `degraded` or `down`; its status is exported as `provider_status` (0 up, 1 degraded,
+//! 2 down) and every rule that starts firing or resolves is sent to the configured sinks:
+//!
+//! - `stalled` (down): no new head for `ALERT_STALL_BLOCKS` expected block times (default 5).
+//! - `lagging` (degraded): more than `ALERT_MAX_HEIGHT_LAG` blocks behind the highest head seen
+//!   across the chain's providers (default 3).
+//! - `stale` (degraded): a `freshness_score` above `ALERT_MAX_FRESHNESS` (default 3).
+//! - `over_budget` (degraded): the provider's [request budget](crate::budget) is used up. While
+//!   it fires, the other rules aren't evaluated, as they would only report the throttling.
+//!
+//! Besides the rules, events can be raised from anywhere through [`Alerts::events`], e.g. a task
+//! of a provider that [panicked](crate::supervisor). They fire once and never resolve.
+//!
+//! Rules are evaluated every `ALERT_INTERVAL_SECS` (default 15). Alerts are always logged and,
+//! with `ALERT_WEBHOOK_URL`, posted as JSON. Status changes can be pushed to Grafana as
+//! annotations, see [`grafana`], alerts sent to PagerDuty, see [`pagerduty`], and both
+//! published over MQTT, see [`crate::mqtt`]. Alerts of providers in a maintenance window are
+//! suppressed.
+
+pub mod grafana;
+pub mod pagerduty;
+
+use crate::block_metrics::BlockMetrics;
+use crate::budget::Budget;
+use crate::maintenance::Maintenance;
+use grafana::GrafanaAnnotations;
+use pagerduty::PagerDuty;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use prometheus::{IntGauge, Registry};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Degraded,
+    Down,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Up => write!(f, "up"),
+            Status::Degraded => write!(f, "degraded"),
+            Status::Down => write!(f, "down"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+/// A rule of a provider that started firing or resolved.
+#[derive(Clone, Debug, Serialize)]
+pub struct Alert {
+    pub chain: String,
+    pub rpc: String,
+    pub rule: &'static str,
+    /// Status the rule puts the provider in while firing.
+    pub severity: Status,
+    pub state: AlertState,
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+/// A provider's status transition.
+#[derive(Clone, Debug, Serialize)]
+pub struct StatusChange {
+    pub chain: String,
+    pub rpc: String,
+    pub from: Status,
+    pub to: Status,
+    /// Whether the provider was in a maintenance window.
+    pub maintenance: bool,
+    pub at: DateTime<Utc>,
+}
+
+/// Raises alerts outside of the rules, sent to the sinks on the next turn of [`Alerts`].
+#[derive(Clone)]
+pub struct AlertEvents(mpsc::UnboundedSender<Alert>);
+
+impl AlertEvents {
+    /// Raise a `rule` of the provider `rpc` of `chain` that fires once.
+    pub fn fire(
+        &self,
+        chain: &str,
+        rpc: &str,
+        rule: &'static str,
+        severity: Status,
+        message: &str,
+    ) {
+        let alert = Alert {
+            chain: chain.to_string(),
+            rpc: rpc.to_string(),
+            rule,
+            severity,
+            state: AlertState::Firing,
+            message: message.to_string(),
+            at: Utc::now(),
+        };
+        // without a running `Alerts` the alert is only logged where it was raised
+        let _ = self.0.send(alert);
+    }
+}
+
+/// Receives alerts and status changes.
+#[async_trait]
+pub trait AlertSink: Send + Sync {
+    async fn alert(&self, alert: &Alert);
+
+    async fn status_changed(&self, _change: &StatusChange) {}
+}
+
+struct LogSink;
+
+#[async_trait]
+impl AlertSink for LogSink {
+    async fn alert(&self, alert: &Alert) {
+        match alert.state {
+            AlertState::Firing => log::warn!(
+                "[🚨][{}] {} {}: {}",
+                alert.chain,
+                alert.rpc,
+                alert.rule,
+                alert.message
+            ),
+            AlertState::Resolved => log::info!(
+                "[✅][{}] {} {} resolved",
+                alert.chain,
+                alert.rpc,
+                alert.rule
+            ),
+        }
+    }
+
+    async fn status_changed(&self, change: &StatusChange) {
+        log::info!(
+            "[{}] {} is {} (was {})",
+            change.chain,
+            change.rpc,
+            change.to,
+            change.from
+        );
+    }
+}
+
+/// Deliveries of alerts taking longer than this are given up on, so a sink that hangs doesn't
+/// hold up the evaluation of the rules.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts every alert as JSON to a URL.
+struct WebhookSink {
+    client: reqwest::Client,
+    url: reqwest::Url,
+}
+
+#[async_trait]
+impl AlertSink for WebhookSink {
+    async fn alert(&self, alert: &Alert) {
+        let res = self
+            .client
+            .post(self.url.clone())
+            .json(alert)
+            .timeout(TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = res {
+            log::warn!("Failed to post alert to webhook: {:?}", e);
+        }
+    }
+}
+
+struct Thresholds {
+    stall_blocks: f64,
+    max_height_lag: f64,
+    max_freshness: f64,
+}
+
+impl Thresholds {
+    fn from_env() -> Self {
+        let var = |name: &str, default: f64| {
+            env::var(name)
+                .ok()
+                .map(|value| {
+                    value
+                        .parse::<f64>()
+                        .unwrap_or_else(|_| panic!("Invalid {}", name))
+                })
+                .unwrap_or(default)
+        };
+
+        Self {
+            stall_blocks: var("ALERT_STALL_BLOCKS", 5.0),
+            max_height_lag: var("ALERT_MAX_HEIGHT_LAG", 3.0),
+            max_freshness: var("ALERT_MAX_FRESHNESS", 3.0),
+        }
+    }
+}
+
+struct WatchedProvider {
+    chain: String,
+    rpc: String,
+    expected_block_time: Duration,
+    block_metrics: BlockMetrics,
+    budget: Budget,
+    status_gauge: IntGauge,
+    started: Instant,
+    status: Status,
+    /// Firing rules with their severity and whether they were sent to the sinks.
+    firing: HashMap<&'static str, (Status, bool)>,
+}
+
+impl WatchedProvider {
+    fn alert(
+        &self,
+        rule: &'static str,
+        severity: Status,
+        state: AlertState,
+        message: &str,
+        at: DateTime<Utc>,
+    ) -> Alert {
+        Alert {
+            chain: self.chain.clone(),
+            rpc: self.rpc.clone(),
+            rule,
+            severity,
+            state,
+            message: message.to_string(),
+            at,
+        }
+    }
+
+    /// The rules currently firing, with the status they imply and a description.
+    fn evaluate(&self, thresholds: &Thresholds) -> Vec<(&'static str, Status, String)> {
+        if let Some(message) = self.budget.exhausted() {
+            return vec![("over_budget", Status::Degraded, message)];
+        }
+
+        let mut firing = Vec::new();
+
+        let block_time = self.expected_block_time.as_secs_f64();
+        let since_head = self
+            .block_metrics
+            .since_last_head()
+            .unwrap_or_else(|| self.started.elapsed())
+            .as_secs_f64();
+        if since_head > thresholds.stall_blocks * block_time {
+            firing.push((
+                "stalled",
+                Status::Down,
+                format!("no new head for {:.0}s", since_head),
+            ));
+        }
+
+        let height_lag = self.block_metrics.height_lag();
+        if height_lag > thresholds.max_height_lag {
+            firing.push((
+                "lagging",
+                Status::Degraded,
+                format!("{} blocks behind the highest head", height_lag),
+            ));
+        }
+
+        let freshness = self.block_metrics.freshness_score();
+        if freshness > thresholds.max_freshness {
+            firing.push((
+                "stale",
+                Status::Degraded,
+                format!("blocks observed {:.1} block times late", freshness),
+            ));
+        }
+
+        firing
+    }
+}
+
+/// Evaluates the alerting rules of every watched provider.
+pub struct Alerts {
+    thresholds: Thresholds,
+    interval: Duration,
+    maintenance: Maintenance,
+    sinks: Vec<Box<dyn AlertSink>>,
+    providers: Vec<WatchedProvider>,
+    events: AlertEvents,
+    raised: mpsc::UnboundedReceiver<Alert>,
+}
+
+impl Alerts {
+    pub fn from_env(maintenance: Maintenance) -> Self {
+        let interval = env::var("ALERT_INTERVAL_SECS")
+            .ok()
+            .map(|secs| secs.parse::<u64>().expect("Invalid ALERT_INTERVAL_SECS"))
+            .unwrap_or(15);
+
+        let mut sinks: Vec<Box<dyn AlertSink>> = vec![Box::new(LogSink)];
+        if let Ok(url) = env::var("ALERT_WEBHOOK_URL") {
+            sinks.push(Box::new(WebhookSink {
+                client: reqwest::Client::new(),
+                url: url.parse().expect("Invalid ALERT_WEBHOOK_URL"),
+            }));
+        }
+
+        if let Some(grafana) = GrafanaAnnotations::from_env() {
+            sinks.push(Box::new(grafana));
+        }
+        if let Some(pagerduty) = PagerDuty::from_env() {
+            sinks.push(Box::new(pagerduty));
+        }
+
+        let (events, raised) = mpsc::unbounded_channel();
+
+        Self {
+            thresholds: Thresholds::from_env(),
+            interval: Duration::from_secs(interval),
+            maintenance,
+            sinks,
+            providers: Vec::new(),
+            events: AlertEvents(events),
+            raised,
+        }
+    }
+
+    /// Send alerts and status changes to `sink` as well.
+    pub fn add_sink(&mut self, sink: Box<dyn AlertSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// A handle raising alerts outside of the rules.
+    pub fn events(&self) -> AlertEvents {
+        self.events.clone()
+    }
+
+    /// Watch the provider `rpc` of `chain`, exporting its status to `registry`.
+    pub fn watch(
+        &mut self,
+        chain: &str,
+        rpc: &str,
+        expected_block_time: Duration,
+        block_metrics: BlockMetrics,
+        budget: Budget,
+        registry: &Registry,
+    ) {
+        let status_gauge = IntGauge::new(
+            "provider_status",
+            "Status of the provider: 0 up, 1 degraded, 2 down",
+        )
+        .unwrap();
+        registry.register(Box::new(status_gauge.clone())).unwrap();
+
+        self.providers.push(WatchedProvider {
+            chain: chain.to_string(),
+            rpc: rpc.to_string(),
+            expected_block_time,
+            block_metrics,
+            budget,
+            status_gauge,
+            started: Instant::now(),
+            status: Status::Up,
+            firing: HashMap::new(),
+        });
+    }
+
+    pub async fn run(mut self) {
+        let mut interval = time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.evaluate().await,
+                Some(alert) = self.raised.recv() => {
+                    if self.maintenance.is_active(&alert.chain, &alert.rpc) {
+                        continue;
+                    }
+                    for sink in &self.sinks {
+                        sink.alert(&alert).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn evaluate(&mut self) {
+        for provider in &mut self.providers {
+            let maintenance = self.maintenance.is_active(&provider.chain, &provider.rpc);
+            let firing = provider.evaluate(&self.thresholds);
+            let now = Utc::now();
+
+            let mut alerts = Vec::new();
+            for (rule, severity, message) in &firing {
+                let (_, notified) = provider.firing.entry(rule).or_insert((*severity, false));
+                if !*notified && !maintenance {
+                    *notified = true;
+                    alerts.push(provider.alert(rule, *severity, AlertState::Firing, message, now));
+                }
+            }
+
+            let resolved = provider
+                .firing
+                .keys()
+                .filter(|rule| !firing.iter().any(|(firing, _, _)| firing == *rule))
+                .copied()
+                .collect::<Vec<_>>();
+            for rule in resolved {
+                // only resolve what was sent, suppressed alerts were never seen
+                if let Some((severity, true)) = provider.firing.remove(rule) {
+                    let message = format!("{} resolved", rule);
+                    alerts.push(provider.alert(
+                        rule,
+                        severity,
+                        AlertState::Resolved,
+                        &message,
+                        now,
+                    ));
+                }
+            }
+
+            let status = firing
+                .iter()
+                .map(|(_, severity, _)| *severity)
+                .max()
+                .unwrap_or(Status::Up);
+            provider.status_gauge.set(status as i64);
+
+            for alert in &alerts {
+                for sink in &self.sinks {
+                    sink.alert(alert).await;
+                }
+            }
+
+            if status != provider.status {
+                let change = StatusChange {
+                    chain: provider.chain.clone(),
+                    rpc: provider.rpc.clone(),
+                    from: provider.status,
+                    to: status,
+                    maintenance,
+                    at: now,
+                };
+                provider.status = status;
+                for sink in &self.sinks {
+                    sink.status_changed(&change).await;
+                }
+            }
+        }
+    }
+}
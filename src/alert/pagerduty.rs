@@ -0,0 +1,83 @@
+//! PagerDuty Events API v2 integration.
+//!
+//! Firing rules trigger an incident and resolved rules resolve it. The dedup key ties an
+//! incident to its provider and rule (`bencheth/<chain>/<rpc>/<rule>`), so a rule flapping
+//! while the incident is open doesn't page twice.
+//!
+//! - `PAGERDUTY_ROUTING_KEY`: integration key of the PagerDuty service.
+//! - `PAGERDUTY_EVENTS_URL`: events endpoint, `https://events.pagerduty.com/v2/enqueue` by
+//!   default.
+
+use super::{Alert, AlertSink, AlertState, Status, TIMEOUT};
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use std::env;
+
+const DEFAULT_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+pub struct PagerDuty {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    routing_key: String,
+}
+
+impl PagerDuty {
+    /// `None` unless `PAGERDUTY_ROUTING_KEY` is set.
+    pub fn from_env() -> Option<Self> {
+        let routing_key = env::var("PAGERDUTY_ROUTING_KEY").ok()?;
+        let url = env::var("PAGERDUTY_EVENTS_URL")
+            .unwrap_or_else(|_| DEFAULT_EVENTS_URL.to_string())
+            .parse()
+            .expect("Invalid PAGERDUTY_EVENTS_URL");
+
+        Some(Self {
+            client: reqwest::Client::new(),
+            url,
+            routing_key,
+        })
+    }
+}
+
+#[async_trait]
+impl AlertSink for PagerDuty {
+    async fn alert(&self, alert: &Alert) {
+        let dedup_key = format!("bencheth/{}/{}/{}", alert.chain, alert.rpc, alert.rule);
+        let event = match alert.state {
+            AlertState::Firing => json!({
+                "routing_key": self.routing_key,
+                "event_action": "trigger",
+                "dedup_key": dedup_key,
+                "payload": {
+                    "summary": format!("{} {} {}: {}", alert.chain, alert.rpc, alert.rule, alert.message),
+                    "source": alert.rpc,
+                    "severity": match alert.severity {
+                        Status::Down => "critical",
+                        _ => "warning",
+                    },
+                    "component": alert.chain,
+                    "class": alert.rule,
+                    "timestamp": alert.at.to_rfc3339(),
+                },
+            }),
+            AlertState::Resolved => json!({
+                "routing_key": self.routing_key,
+                "event_action": "resolve",
+                "dedup_key": dedup_key,
+            }),
+        };
+
+        let res = self
+            .client
+            .post(self.url.clone())
+            .json(&event)
+            .timeout(TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = res {
+            log::warn!("Failed to send PagerDuty event: {:?}", e);
+        }
+    }
+}
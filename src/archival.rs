@@ -0,0 +1,206 @@
+//! Archival depth probe.
+//!
+//! "Archive" is a marketing label: some providers serve full state for every block, others prune
+//! it after a few hundred blocks or a few months. With `<CHAIN>_ARCHIVAL_DEPTH=true`, the oldest
+//! block at which an EVM provider still answers state queries is binary-searched between genesis
+//! and its head, and the number of blocks of state it keeps is exported as
+//! `archival_depth_blocks`, labelled with the `method` of the query: `eth_getBalance` and
+//! `eth_call` of `<CHAIN>_ARCHIVAL_DEPTH_ADDRESS` (the zero address by default), as some nodes
+//! keep balances longer than the storage calls need.
+//!
+//! A search takes a few dozen requests, so it's repeated every
+//! `<CHAIN>_ARCHIVAL_DEPTH_INTERVAL_SECS` (default 3600) following
+//! `<CHAIN>_ARCHIVAL_DEPTH_SCHEDULE`. Requests are sent outside of the measured transports, though
+//! under the request limits and the provider's budget, and a search hitting a rate limit or a
+//! transient failure is abandoned rather than mistaken for pruned state.
+
+use crate::budget::Budget;
+use crate::chain::{ChainConfig, ChainKind};
+use crate::error_kind::ErrorKind;
+use crate::limits::{Limited, RequestLimits};
+use crate::rpc_method::RpcMethod;
+use crate::schedule::Schedule;
+use crate::vendor::Vendor;
+
+use ethers::providers::{Authorization, Http, HttpClientError, JsonRpcClient};
+use ethers::types::{Address, U64};
+use prometheus::{GaugeVec, Opts, Registry};
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::time;
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_INTERVAL_SECS: u64 = 3600;
+
+const METHODS: [RpcMethod; 2] = [RpcMethod::EthGetBalance, RpcMethod::EthCall];
+
+pub struct ArchivalDepthProbe {
+    chain: Arc<ChainConfig>,
+    endpoint: String,
+    client: Limited<Http>,
+    vendor: Option<Vendor>,
+    address: Address,
+    interval: Duration,
+    schedule: Schedule,
+    depth: GaugeVec,
+}
+
+impl ArchivalDepthProbe {
+    /// The probe of the provider at `rpc_url`, sending requests within its `budget` and `limits`
+    /// and reporting to its `registry`. `None` unless
+    /// `<CHAIN>_ARCHIVAL_DEPTH=true` on an EVM chain and an HTTP RPC URL.
+    pub fn from_chain(
+        chain: Arc<ChainConfig>,
+        rpc_url: &Url,
+        endpoint: &str,
+        budget: Budget,
+        limits: RequestLimits,
+        registry: &Registry,
+    ) -> Option<Self> {
+        if chain.var("ARCHIVAL_DEPTH").as_deref() != Some("true") {
+            return None;
+        }
+        if chain.kind != ChainKind::Evm || !rpc_url.scheme().starts_with("http") {
+            log::warn!(
+                "[{}] The archival depth probe only supports HTTP RPC URLs of EVM chains",
+                chain.name
+            );
+            return None;
+        }
+        let client = match &chain.basic_auth {
+            Some((username, password)) => {
+                Http::new_with_auth(rpc_url.clone(), Authorization::basic(username, password))
+                    .expect("could not initialize http")
+            }
+            None => Http::from_str(rpc_url.as_str()).expect("could not initialize http"),
+        };
+        let client = Limited::new(client).with_budget(budget).with_limits(limits);
+        let address = chain
+            .var("ARCHIVAL_DEPTH_ADDRESS")
+            .map(|address| address.parse().expect("Invalid ARCHIVAL_DEPTH_ADDRESS"))
+            .unwrap_or_default();
+        let interval = chain
+            .var("ARCHIVAL_DEPTH_INTERVAL_SECS")
+            .map(|secs| {
+                secs.parse::<u64>()
+                    .expect("Invalid ARCHIVAL_DEPTH_INTERVAL_SECS")
+            })
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+        let depth = GaugeVec::new(
+            Opts::new(
+                "archival_depth_blocks",
+                "Number of most recent blocks at which the provider answers state queries",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        registry.register(Box::new(depth.clone())).unwrap();
+
+        Some(Self {
+            schedule: Schedule::from_chain(&chain, "ARCHIVAL_DEPTH"),
+            chain,
+            endpoint: endpoint.to_string(),
+            client,
+            vendor: rpc_url.host_str().and_then(Vendor::detect),
+            address,
+            interval: Duration::from_secs(interval),
+            depth,
+        })
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = time::interval(self.interval);
+
+        loop {
+            interval.tick().await;
+            if !self.schedule.is_active() {
+                continue;
+            }
+
+            for method in METHODS {
+                match self.search(&method).await {
+                    Ok(depth) => {
+                        log::info!(
+                            "[🗄️][{}] {} answers {} for the last {} blocks",
+                            self.chain.name,
+                            self.endpoint,
+                            method,
+                            depth
+                        );
+                        self.depth
+                            .with_label_values(&[method.as_str()])
+                            .set(depth as f64);
+                    }
+                    Err(e) => log::warn!(
+                        "[🗄️][{}] Failed to probe the archival depth of {} with {}: {}",
+                        self.chain.name,
+                        self.endpoint,
+                        method,
+                        e
+                    ),
+                }
+            }
+        }
+    }
+
+    /// The number of blocks, up to the head, at which `method` is answered.
+    async fn search(&self, method: &RpcMethod) -> Result<u64, HttpClientError> {
+        let head = self
+            .client
+            .request::<_, U64>(RpcMethod::EthBlockNumber.as_str(), ())
+            .await?
+            .as_u64();
+        if !self.answers(method, head).await? {
+            return Ok(0);
+        }
+        if self.answers(method, 0).await? {
+            return Ok(head + 1);
+        }
+
+        // `pruned` is never answered, `oldest` always
+        let (mut pruned, mut oldest) = (0, head);
+        while oldest - pruned > 1 {
+            let block = pruned + (oldest - pruned) / 2;
+            if self.answers(method, block).await? {
+                oldest = block;
+            } else {
+                pruned = block;
+            }
+        }
+        Ok(head - oldest + 1)
+    }
+
+    /// Whether `method` is answered at `block`. Failures which aren't about the block's state,
+    /// e.g. rate limits, are errors.
+    async fn answers(&self, method: &RpcMethod, block: u64) -> Result<bool, HttpClientError> {
+        let params = match method {
+            RpcMethod::EthCall => json!([{ "to": self.address, "data": "0x" }, U64::from(block)]),
+            _ => json!([self.address, U64::from(block)]),
+        };
+        match self
+            .client
+            .request::<_, Value>(method.as_str(), params)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(HttpClientError::JsonRpcError(e))
+                if !ErrorKind::classify(e.code, &e.message, self.vendor).is_retryable() =>
+            {
+                log::debug!(
+                    "[{}] {} doesn't answer {} at block {}: {}",
+                    self.chain.name,
+                    self.endpoint,
+                    method,
+                    block,
+                    e
+                );
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Thresholds checked at the end of a bench run.
+//!
+//! `bencheth bench --assert <check>` fails the run, exiting with an error, when a provider
+//! violates the check, so a CI job can gate RPC provider changes on a fixed-duration run:
+//!
+//! ```sh
+//! bencheth bench --duration 120 --assert 'p95_latency<500ms' --assert 'error_rate<1%'
+//! ```
+//!
+//! A check is a metric, a comparison (`<`, `<=`, `>` or `>=`) and a threshold, and every
+//! provider of the run must pass it. The metrics are:
+//!
+//! - `p<N>_latency`, e.g. `p50_latency` or `p999_latency`: the request latency percentile over
+//!   all methods, estimated from the sketches. `mean_latency` and `max_latency` as well.
+//!   Thresholds take a unit, `ms` or `s`.
+//! - `p50_delay`, `p90_delay` and `p99_delay`: the block propagation delay percentile over the
+//!   run, with a unit as well.
+//! - `error_rate`: the errors among the requests, as a fraction or a percentage, e.g. `0.01`
+//!   or `1%`.
+//!
+//! A provider without a sample of the metric, e.g. one that never answered, fails the check.
+//! With `--rps`, the checks apply to the [load report](crate::load) instead, which has no
+//! propagation delay.
+
+use crate::load::LoadReport;
+use crate::report::{ProviderReport, Summary};
+use crate::sketch::DDSketch;
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug)]
+enum Metric {
+    /// A request latency quantile.
+    Latency(f64),
+    MeanLatency,
+    MaxLatency,
+    /// A propagation delay percentile of the run summary.
+    Delay(fn(&Summary) -> f64),
+    ErrorRate,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Less => value < threshold,
+            Comparison::LessOrEqual => value <= threshold,
+            Comparison::Greater => value > threshold,
+            Comparison::GreaterOrEqual => value >= threshold,
+        }
+    }
+}
+
+/// A check of `--assert`, e.g. `p95_latency<500ms`.
+#[derive(Clone, Debug)]
+pub struct Assertion {
+    /// As passed, for reporting.
+    text: String,
+    metric: Metric,
+    comparison: Comparison,
+    /// In seconds, or as a fraction for `error_rate`.
+    threshold: f64,
+}
+
+impl FromStr for Assertion {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let position = text
+            .find(['<', '>'])
+            .ok_or_else(|| format!("{}: expected a comparison, e.g. p95_latency<500ms", text))?;
+        let (metric, rest) = text.split_at(position);
+        let (comparison, threshold) = match rest.split_at(1) {
+            ("<", threshold) => match threshold.strip_prefix('=') {
+                Some(threshold) => (Comparison::LessOrEqual, threshold),
+                None => (Comparison::Less, threshold),
+            },
+            (_, threshold) => match threshold.strip_prefix('=') {
+                Some(threshold) => (Comparison::GreaterOrEqual, threshold),
+                None => (Comparison::Greater, threshold),
+            },
+        };
+
+        let metric = match metric.trim() {
+            "error_rate" => Metric::ErrorRate,
+            "mean_latency" => Metric::MeanLatency,
+            "max_latency" => Metric::MaxLatency,
+            "p50_delay" => Metric::Delay(|summary| summary.p50),
+            "p90_delay" => Metric::Delay(|summary| summary.p90),
+            "p99_delay" => Metric::Delay(|summary| summary.p99),
+            metric => {
+                let digits = metric
+                    .strip_prefix('p')
+                    .and_then(|metric| metric.strip_suffix("_latency"))
+                    .filter(|digits| {
+                        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+                    })
+                    .ok_or_else(|| format!("{}: unknown metric {}", text, metric))?;
+                // p5 is the 0.05 quantile, p95 the 0.95 one and p999 the 0.999 one
+                let scale = 10f64.powi(digits.len().max(2) as i32);
+                Metric::Latency(digits.parse::<f64>().unwrap() / scale)
+            }
+        };
+
+        let threshold = threshold.trim();
+        let threshold = match metric {
+            Metric::ErrorRate => match threshold.strip_suffix('%') {
+                Some(percent) => percent.trim().parse::<f64>().map(|percent| percent / 100.0),
+                None => threshold.parse::<f64>(),
+            },
+            _ => match threshold.strip_suffix("ms") {
+                Some(ms) => ms.trim().parse::<f64>().map(|ms| ms / 1000.0),
+                None => match threshold.strip_suffix('s') {
+                    Some(secs) => secs.trim().parse::<f64>(),
+                    None => return Err(format!("{}: the threshold needs a unit, ms or s", text)),
+                },
+            },
+        }
+        .map_err(|_| format!("{}: invalid threshold {}", text, threshold))?;
+
+        Ok(Self {
+            text: text.to_string(),
+            metric,
+            comparison,
+            threshold,
+        })
+    }
+}
+
+/// The measurements of a provider checks apply to.
+pub trait Measured {
+    fn chain(&self) -> &str;
+    fn rpc(&self) -> &str;
+    /// Request latency over all methods.
+    fn latency(&self) -> DDSketch;
+    fn propagation_delay(&self) -> Option<&Summary>;
+    /// Requests made and the errors among them.
+    fn requests(&self) -> (u64, u64);
+}
+
+impl Measured for ProviderReport {
+    fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    fn rpc(&self) -> &str {
+        &self.rpc
+    }
+
+    fn latency(&self) -> DDSketch {
+        let mut all = DDSketch::default();
+        for sketch in self.request_latency_sketches.values() {
+            all.merge(sketch);
+        }
+        all
+    }
+
+    fn propagation_delay(&self) -> Option<&Summary> {
+        self.propagation_delay_seconds.run.as_ref()
+    }
+
+    fn requests(&self) -> (u64, u64) {
+        (self.requests, self.errors)
+    }
+}
+
+impl Measured for LoadReport {
+    fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    fn rpc(&self) -> &str {
+        &self.rpc
+    }
+
+    fn latency(&self) -> DDSketch {
+        self.latency_sketch.clone()
+    }
+
+    fn propagation_delay(&self) -> Option<&Summary> {
+        None
+    }
+
+    fn requests(&self) -> (u64, u64) {
+        (self.requests, self.errors)
+    }
+}
+
+impl Assertion {
+    /// The value of the metric for `provider`, `None` without samples.
+    fn value(&self, provider: &impl Measured) -> Option<f64> {
+        let latency = || Some(provider.latency()).filter(|all| all.count > 0);
+        match self.metric {
+            Metric::Latency(quantile) => latency()?.quantile(quantile),
+            Metric::MeanLatency => latency().map(|all| all.sum / all.count as f64),
+            Metric::MaxLatency => latency().map(|all| all.max),
+            Metric::Delay(percentile) => provider.propagation_delay().map(percentile),
+            Metric::ErrorRate => match provider.requests() {
+                (0, _) => None,
+                (requests, errors) => Some(errors as f64 / requests as f64),
+            },
+        }
+    }
+
+    fn format(&self, value: f64) -> String {
+        match self.metric {
+            Metric::ErrorRate => format!("{:.2}%", value * 100.0),
+            _ => format!("{:.0}ms", value * 1000.0),
+        }
+    }
+}
+
+/// The result of an assertion for a provider.
+pub struct Check<'a> {
+    assertion: &'a Assertion,
+    chain: &'a str,
+    rpc: &'a str,
+    value: Option<f64>,
+    pub passed: bool,
+}
+
+impl fmt::Display for Check<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed { "PASS" } else { "FAIL" };
+        let value = self
+            .value
+            .map(|value| self.assertion.format(value))
+            .unwrap_or_else(|| "no samples".to_string());
+        write!(
+            f,
+            "{} {}/{} {} (measured {})",
+            status, self.chain, self.rpc, self.assertion.text, value
+        )
+    }
+}
+
+/// Check every assertion against every provider.
+pub fn check<'a>(assertions: &'a [Assertion], providers: &'a [impl Measured]) -> Vec<Check<'a>> {
+    let mut checks = Vec::new();
+    for assertion in assertions {
+        for provider in providers {
+            let value = assertion.value(provider);
+            checks.push(Check {
+                assertion,
+                chain: provider.chain(),
+                rpc: provider.rpc(),
+                value,
+                passed: value
+                    .is_some_and(|value| assertion.comparison.holds(value, assertion.threshold)),
+            });
+        }
+    }
+    checks
+}
@@ -0,0 +1,132 @@
+//! alloy's HTTP transport.
+
+use super::Backend;
+use crate::error_kind::ErrorKind;
+use crate::measured_json_rpc_client::{
+    record_io, should_retry_json_rpc_error, MeasuredJsonRpcError, Metrics, RetrySettings,
+};
+
+use alloy_rpc_client::RpcClient;
+use alloy_transport_http::reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Client, Url,
+};
+use async_trait::async_trait;
+use ethers::providers::{Authorization, JsonRpcError};
+use serde_json::value::RawValue;
+use tokio::time;
+
+use std::time::Instant;
+
+#[derive(Debug)]
+pub struct AlloyBackend {
+    client: RpcClient,
+    metrics: Metrics,
+    /// Rate limited requests are retried as with the ethers backend.
+    retry: RetrySettings,
+}
+
+impl AlloyBackend {
+    pub fn new(url: &reqwest::Url, auth: Option<Authorization>, metrics: Metrics) -> Self {
+        let url = Url::parse(url.as_str()).expect("could not parse url");
+        let mut headers = HeaderMap::new();
+        if let Some(auth) = auth {
+            let mut auth_value = HeaderValue::from_str(&auth.to_string()).expect("invalid auth");
+            auth_value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, auth_value);
+        }
+        let client = Client::builder()
+            .default_headers(headers)
+            .build()
+            .expect("could not initialize http");
+
+        Self {
+            client: RpcClient::new_http_with_client(client, url),
+            metrics,
+            retry: RetrySettings::from_env(),
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for AlloyBackend {
+    async fn request(
+        &self,
+        method: &str,
+        params: Option<Box<RawValue>>,
+    ) -> Result<Box<RawValue>, MeasuredJsonRpcError> {
+        let sent = method.len() + params.as_ref().map_or(0, |params| params.get().len());
+        let mut backoff = self.retry.initial_backoff;
+        let mut retries = 0;
+
+        loop {
+            let start = Instant::now();
+            let res = match &params {
+                Some(params) => {
+                    self.client
+                        .request::<_, Box<RawValue>>(method.to_string(), params.clone())
+                        .await
+                }
+                None => {
+                    self.client
+                        .request_noparams::<Box<RawValue>>(method.to_string())
+                        .await
+                }
+            };
+            // alloy parses the response itself, which can't be told apart from the I/O
+            record_io(start.elapsed());
+
+            let e = match res {
+                Ok(result) => {
+                    self.metrics.record_bytes(method, sent, result.get().len());
+                    return Ok(result);
+                }
+                Err(e) => e,
+            };
+            self.metrics.record_bytes(method, sent, 0);
+
+            let (error, should_retry) = match e.as_error_resp() {
+                Some(payload) => {
+                    let error = JsonRpcError {
+                        code: payload.code,
+                        message: payload.message.to_string(),
+                        data: payload
+                            .data
+                            .as_ref()
+                            .and_then(|data| serde_json::from_str(data.get()).ok()),
+                    };
+                    let should_retry = should_retry_json_rpc_error(
+                        &error,
+                        &self.metrics.request_errors,
+                        self.metrics.vendor,
+                    );
+                    (MeasuredJsonRpcError::JsonRpc(error), should_retry)
+                }
+                None => {
+                    let status = e
+                        .as_transport_err()
+                        .and_then(|kind| kind.as_http_error())
+                        .map(|http| http.status);
+                    log::debug!("alloy transport error: {:?}", e);
+                    let kind = ErrorKind::from_status(status);
+                    self.metrics.record_error(
+                        method,
+                        &status.map(|s| s.to_string()).unwrap_or_default(),
+                        kind,
+                    );
+                    (
+                        MeasuredJsonRpcError::Transport(e.to_string()),
+                        kind.is_retryable(),
+                    )
+                }
+            };
+
+            if !should_retry || retries >= self.retry.rate_limit_retries {
+                return Err(error);
+            }
+            retries += 1;
+            time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
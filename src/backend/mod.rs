@@ -0,0 +1,101 @@
+//! Transports the measured JSON-RPC client sends its requests with.
+//!
+//! [`MeasuredJsonRpc`](crate::measured_json_rpc_client::MeasuredJsonRpc) times, counts and
+//! budgets requests independently of how they are sent. `RPC_BACKEND` selects the transport:
+//!
+//! - `ethers`: ethers' retry client over the metered HTTP transport, the default.
+//! - `alloy`: alloy's HTTP transport, available when built with the `alloy` feature and the
+//!   default then. ethers is no longer maintained, and alloy is meant to replace it.
+//!
+//! The exported metrics and commands are the same with either backend. alloy's transport doesn't
+//! expose the raw response, so with it the request and response sizes are those of the JSON-RPC
+//! payloads rather than the bodies on the wire, `request_io_seconds` includes alloy's parsing of
+//! the response, `server_processing_seconds` and `network_seconds` aren't recorded, request
+//! bodies aren't logged and TLS session metrics aren't exported.
+
+#[cfg(feature = "alloy")]
+mod alloy;
+
+#[cfg(feature = "alloy")]
+pub use self::alloy::AlloyBackend;
+
+use crate::measured_json_rpc_client::{MeasuredJsonRpcError, MeteredHttp};
+
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, RetryClient};
+use serde_json::value::RawValue;
+
+use std::env;
+use std::fmt::Debug;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RpcBackend {
+    Ethers,
+    Alloy,
+}
+
+impl Default for RpcBackend {
+    fn default() -> Self {
+        if cfg!(feature = "alloy") {
+            RpcBackend::Alloy
+        } else {
+            RpcBackend::Ethers
+        }
+    }
+}
+
+impl FromStr for RpcBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ethers" => Ok(RpcBackend::Ethers),
+            "alloy" => Ok(RpcBackend::Alloy),
+            _ => Err(format!("unknown RPC backend {}", s)),
+        }
+    }
+}
+
+impl RpcBackend {
+    pub fn from_env() -> Self {
+        let backend = env::var("RPC_BACKEND")
+            .ok()
+            .map(|backend| backend.parse::<RpcBackend>().expect("Invalid RPC_BACKEND"))
+            .unwrap_or_default();
+
+        if backend == RpcBackend::Alloy && !cfg!(feature = "alloy") {
+            panic!("Invalid RPC_BACKEND: built without the alloy feature");
+        }
+
+        backend
+    }
+}
+
+/// A JSON-RPC transport.
+#[async_trait]
+pub trait Backend: Debug + Send + Sync {
+    /// Send a request of `method` with `params`, `None` for a call without parameters, and return
+    /// its raw result.
+    async fn request(
+        &self,
+        method: &str,
+        params: Option<Box<RawValue>>,
+    ) -> Result<Box<RawValue>, MeasuredJsonRpcError>;
+}
+
+#[async_trait]
+impl Backend for RetryClient<MeteredHttp> {
+    async fn request(
+        &self,
+        method: &str,
+        params: Option<Box<RawValue>>,
+    ) -> Result<Box<RawValue>, MeasuredJsonRpcError> {
+        let res = match params {
+            Some(params) => JsonRpcClient::request(self, method, params).await,
+            // like ethers, omit the params of parameterless calls instead of sending `null`
+            None => JsonRpcClient::request(self, method, ()).await,
+        };
+        res.map_err(Into::into)
+    }
+}
@@ -0,0 +1,246 @@
+//! Block gap backfilling.
+//!
+//! The monitor loop is tuned for freshness: a block it fails to fetch is skipped so the next one
+//! isn't held back, and after a stall it works through every block it missed before getting back
+//! to the head. With `<CHAIN>_BACKFILL=true`, completeness is left to a separate worker per EVM
+//! provider instead:
+//!
+//! - Blocks the monitor failed to fetch, or the provider didn't have yet, are queued as missed.
+//! - When the head advanced by more than `<CHAIN>_BACKFILL_MAX_CATCH_UP` blocks (default 10) at
+//!   once, the monitor skips straight to the head and queues the blocks in between as a catch-up
+//!   range.
+//!
+//! The worker fetches queued blocks at `<CHAIN>_BACKFILL_RPS` (default 1) requests per second,
+//! missed blocks before catch-up ranges and newer blocks first, and requeues failures behind
+//! everything else up to `<CHAIN>_BACKFILL_MAX_ATTEMPTS` attempts (default 3). Blocks waiting are
+//! exported as `backfill_pending_blocks`, queued blocks as `backfill_queued_blocks_total` by
+//! `reason` (`missed` or `catch_up`), and finished ones as `backfill_blocks_total` by `outcome`
+//! (`filled` or `abandoned`). Backfilling follows `<CHAIN>_BACKFILL_SCHEDULE`.
+
+use crate::chain::ChainConfig;
+use crate::completeness::Completeness;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::schedule::Schedule;
+
+use ethers::prelude::*;
+use prometheus::{IntCounterVec, IntGauge, Opts, Registry};
+use tokio::sync::Notify;
+use tokio::time::{self, MissedTickBehavior};
+
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_RPS: f64 = 1.0;
+const DEFAULT_MAX_CATCH_UP: u64 = 10;
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// Why blocks are queued, in increasing order of priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Reason {
+    Retry,
+    CatchUp,
+    Missed,
+}
+
+/// A range of blocks to backfill, from `from` up to `to` inclusive.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Gap {
+    // the field order makes the ordering: by reason, then newest first
+    reason: Reason,
+    to: u64,
+    from: u64,
+    attempts: u32,
+}
+
+impl Gap {
+    fn len(&self) -> u64 {
+        self.to - self.from + 1
+    }
+}
+
+pub struct Backfill {
+    chain: String,
+    rps: f64,
+    max_catch_up: u64,
+    max_attempts: u32,
+    schedule: Schedule,
+    gaps: Mutex<BinaryHeap<Gap>>,
+    queued: Notify,
+    pending: IntGauge,
+    queued_blocks: IntCounterVec,
+    blocks: IntCounterVec,
+}
+
+impl Backfill {
+    /// The backfill worker of a provider of `chain`, reporting to its `registry`. `None` unless
+    /// `<CHAIN>_BACKFILL=true`.
+    pub fn from_chain(chain: &ChainConfig, registry: &Registry) -> Option<Self> {
+        if chain.var("BACKFILL").as_deref() != Some("true") {
+            return None;
+        }
+        let rps = chain
+            .var("BACKFILL_RPS")
+            .map(|rps| match rps.parse::<f64>() {
+                Ok(rps) if rps > 0.0 => rps,
+                _ => panic!("Invalid BACKFILL_RPS: must be greater than zero"),
+            })
+            .unwrap_or(DEFAULT_RPS);
+        let max_catch_up = chain
+            .var("BACKFILL_MAX_CATCH_UP")
+            .map(|blocks| blocks.parse().expect("Invalid BACKFILL_MAX_CATCH_UP"))
+            .unwrap_or(DEFAULT_MAX_CATCH_UP);
+        let max_attempts = chain
+            .var("BACKFILL_MAX_ATTEMPTS")
+            .map(|attempts| attempts.parse().expect("Invalid BACKFILL_MAX_ATTEMPTS"))
+            .unwrap_or(DEFAULT_MAX_ATTEMPTS);
+
+        let pending = IntGauge::new(
+            "backfill_pending_blocks",
+            "Number of blocks waiting to be backfilled",
+        )
+        .unwrap();
+        let queued_blocks = IntCounterVec::new(
+            Opts::new(
+                "backfill_queued_blocks_total",
+                "Number of blocks queued for backfilling",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let blocks = IntCounterVec::new(
+            Opts::new(
+                "backfill_blocks_total",
+                "Number of blocks done backfilling, filled or abandoned after failing",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        registry.register(Box::new(pending.clone())).unwrap();
+        registry.register(Box::new(queued_blocks.clone())).unwrap();
+        registry.register(Box::new(blocks.clone())).unwrap();
+
+        Some(Self {
+            chain: chain.name.clone(),
+            rps,
+            max_catch_up,
+            max_attempts,
+            schedule: Schedule::from_chain(chain, "BACKFILL"),
+            gaps: Mutex::new(BinaryHeap::new()),
+            queued: Notify::new(),
+            pending,
+            queued_blocks,
+            blocks,
+        })
+    }
+
+    /// Blocks the monitor works through itself when the head advanced by several at once.
+    pub fn max_catch_up(&self) -> u64 {
+        self.max_catch_up
+    }
+
+    /// Queue block `number`, which the monitor failed to fetch.
+    pub fn missed(&self, number: u64) {
+        self.queued_blocks.with_label_values(&["missed"]).inc();
+        self.push(Gap {
+            reason: Reason::Missed,
+            to: number,
+            from: number,
+            attempts: 0,
+        });
+    }
+
+    /// Queue the blocks from `from` up to `to` inclusive, which the monitor skipped.
+    pub fn skipped(&self, from: u64, to: u64) {
+        if from > to {
+            return;
+        }
+        let gap = Gap {
+            reason: Reason::CatchUp,
+            to,
+            from,
+            attempts: 0,
+        };
+        self.queued_blocks
+            .with_label_values(&["catch_up"])
+            .inc_by(gap.len());
+        self.push(gap);
+    }
+
+    fn push(&self, gap: Gap) {
+        self.pending.add(gap.len() as i64);
+        self.gaps.lock().unwrap().push(gap);
+        self.queued.notify_one();
+    }
+
+    /// The next block to fetch, taken from the gap with the highest priority.
+    fn pop(&self) -> Option<(u64, Reason, u32)> {
+        let mut gaps = self.gaps.lock().unwrap();
+        let mut gap = gaps.pop()?;
+        self.pending.dec();
+        let next = (gap.to, gap.reason, gap.attempts);
+        if gap.from < gap.to {
+            gap.to -= 1;
+            gaps.push(gap);
+        }
+        Some(next)
+    }
+
+    /// Backfill from `provider`, recording filled blocks in its `completeness`.
+    pub async fn run(
+        self: Arc<Self>,
+        provider: Arc<Provider<MeasuredJsonRpc>>,
+        completeness: Option<Arc<Completeness>>,
+    ) {
+        let mut interval = time::interval(Duration::from_secs_f64(1.0 / self.rps));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if !self.schedule.is_active() {
+                continue;
+            }
+            let Some((number, reason, attempts)) = self.pop() else {
+                self.queued.notified().await;
+                continue;
+            };
+
+            match provider.get_block(number).await {
+                Ok(Some(block)) => {
+                    log::debug!("[{}] Backfilled block {}", self.chain, number);
+                    if let Some(completeness) = &completeness {
+                        completeness.observe_block(&block);
+                    }
+                    self.blocks.with_label_values(&["filled"]).inc();
+                }
+                res => {
+                    let attempts = attempts + 1;
+                    if attempts >= self.max_attempts {
+                        log::warn!(
+                            "[{}] Abandoned backfilling block {} after {} attempts: {:?}",
+                            self.chain,
+                            number,
+                            attempts,
+                            res.err()
+                        );
+                        self.blocks.with_label_values(&["abandoned"]).inc();
+                        continue;
+                    }
+                    log::debug!(
+                        "[{}] Failed to backfill {:?} block {}: {:?}",
+                        self.chain,
+                        reason,
+                        number,
+                        res.err()
+                    );
+                    self.push(Gap {
+                        reason: Reason::Retry,
+                        to: number,
+                        from: number,
+                        attempts,
+                    });
+                }
+            }
+        }
+    }
+}
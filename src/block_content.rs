@@ -0,0 +1,136 @@
+//! Post-merge block fields.
+//!
+//! Providers sometimes drop the fields newer forks added to blocks, breaking indexers that rely
+//! on them. For every block fetched from an EVM chain, the number of withdrawals and the blob gas
+//! are exported as `block_withdrawals`, `block_blob_gas_used` and `block_excess_blob_gas`.
+//!
+//! Blocks produced after a fork are checked for the fields it introduced, Shanghai's
+//! `withdrawals` and `withdrawalsRoot` and Cancun's `blobGasUsed`, `excessBlobGas` and
+//! `parentBeaconBlockRoot`. Missing fields are counted in `block_missing_fields_total` by
+//! `field`. The forks' activation timestamps are known for Ethereum mainnet, Sepolia and Holesky
+//! when `<CHAIN>_CHAIN_ID` is set, and are otherwise read from `<CHAIN>_SHANGHAI_TIMESTAMP` and
+//! `<CHAIN>_CANCUN_TIMESTAMP`.
+
+use crate::chain::ChainConfig;
+
+use ethers::types::{Block, H256};
+use prometheus::{Gauge, IntCounterVec, Opts, Registry};
+
+/// Shanghai and Cancun activation timestamps of well known chains by chain id.
+const FORKS: [(u64, u64, u64); 3] = [
+    (1, 1681338455, 1710338135),
+    (11155111, 1677557088, 1706655072),
+    (17000, 1696000704, 1707305664),
+];
+
+pub struct BlockContent {
+    chain: String,
+    shanghai: Option<u64>,
+    cancun: Option<u64>,
+    withdrawals: Gauge,
+    blob_gas_used: Gauge,
+    excess_blob_gas: Gauge,
+    missing_fields: IntCounterVec,
+}
+
+impl BlockContent {
+    pub fn from_chain(chain: &ChainConfig, registry: &Registry) -> Self {
+        let known = FORKS
+            .iter()
+            .find(|(chain_id, _, _)| Some(*chain_id) == chain.chain_id);
+        let timestamp = |fork: &str| {
+            chain.var(&format!("{}_TIMESTAMP", fork)).map(|timestamp| {
+                timestamp
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| panic!("Invalid {}_TIMESTAMP", fork))
+            })
+        };
+        let shanghai = timestamp("SHANGHAI").or(known.map(|(_, shanghai, _)| *shanghai));
+        let cancun = timestamp("CANCUN").or(known.map(|(_, _, cancun)| *cancun));
+
+        let withdrawals = Gauge::new(
+            "block_withdrawals",
+            "Number of withdrawals in the latest block",
+        )
+        .unwrap();
+        let blob_gas_used =
+            Gauge::new("block_blob_gas_used", "Blob gas used by the latest block").unwrap();
+        let excess_blob_gas = Gauge::new(
+            "block_excess_blob_gas",
+            "Excess blob gas of the latest block",
+        )
+        .unwrap();
+        let missing_fields = IntCounterVec::new(
+            Opts::new(
+                "block_missing_fields_total",
+                "Number of blocks missing a field required by an activated fork",
+            ),
+            &["field"],
+        )
+        .unwrap();
+        registry.register(Box::new(withdrawals.clone())).unwrap();
+        registry.register(Box::new(blob_gas_used.clone())).unwrap();
+        registry
+            .register(Box::new(excess_blob_gas.clone()))
+            .unwrap();
+        registry.register(Box::new(missing_fields.clone())).unwrap();
+
+        Self {
+            chain: chain.name.clone(),
+            shanghai,
+            cancun,
+            withdrawals,
+            blob_gas_used,
+            excess_blob_gas,
+            missing_fields,
+        }
+    }
+
+    pub fn observe(&self, block: &Block<H256>) {
+        if let Some(withdrawals) = &block.withdrawals {
+            self.withdrawals.set(withdrawals.len() as f64);
+        }
+        if let Some(blob_gas_used) = block.blob_gas_used {
+            self.blob_gas_used.set(blob_gas_used.as_u128() as f64);
+        }
+        if let Some(excess_blob_gas) = block.excess_blob_gas {
+            self.excess_blob_gas.set(excess_blob_gas.as_u128() as f64);
+        }
+
+        let timestamp = block.timestamp.as_u64();
+        let activated = |fork: Option<u64>| fork.is_some_and(|fork| timestamp >= fork);
+        let mut missing = Vec::new();
+        if activated(self.shanghai) {
+            if block.withdrawals.is_none() {
+                missing.push("withdrawals");
+            }
+            if block.withdrawals_root.is_none() {
+                missing.push("withdrawalsRoot");
+            }
+        }
+        if activated(self.cancun) {
+            if block.blob_gas_used.is_none() {
+                missing.push("blobGasUsed");
+            }
+            if block.excess_blob_gas.is_none() {
+                missing.push("excessBlobGas");
+            }
+            if block.parent_beacon_block_root.is_none() {
+                missing.push("parentBeaconBlockRoot");
+            }
+        }
+
+        if missing.is_empty() {
+            return;
+        }
+        log::warn!(
+            "[{}] Block {} is missing {}",
+            self.chain,
+            block.number.unwrap_or_default(),
+            missing.join(", ")
+        );
+        for field in missing {
+            self.missing_fields.with_label_values(&[field]).inc();
+        }
+    }
+}
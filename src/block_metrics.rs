@@ -0,0 +1,369 @@
+//! Metrics describing the blocks observed on a chain, shared by every chain adapter.
+//!
+//! The time from a block's timestamp until the adapter is done with it is broken down into a
+//! latency budget per block: `block_budget_detection_seconds` until the new head was detected,
+//! `block_budget_fetch_seconds` fetching the block and `block_budget_transactions_seconds`
+//! fetching its transactions, where the adapter does.
+
+use crate::block_webhook::BlockEvents;
+use crate::measured_json_rpc_client::latency_buckets;
+use crate::poll::{PollInterval, PollMetrics};
+use crate::report::ProviderStats;
+
+use chrono::{DateTime, Utc};
+use prometheus::{histogram_opts, Gauge, Histogram, IntCounter, Registry};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A detection interval longer than this many expected block times counts as a stall.
+const STALL_FACTOR: f64 = 3.0;
+
+/// Buckets of the detection interval histogram, in expected block times.
+const DETECTION_INTERVAL_BUCKETS: [f64; 10] =
+    [0.25, 0.5, 0.75, 1.0, 1.25, 1.5, 2.0, 3.0, 5.0, 10.0];
+
+/// Heads of the reference RPC endpoint remembered to time the providers' head lag.
+const REFERENCE_HISTORY: usize = 1024;
+
+/// What is known about the head of a chain: the highest head height observed across all of its
+/// providers and, when reference endpoints are configured, the estimated canonical head and the
+/// heads of the reference RPC endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct ChainHead {
+    highest: Arc<AtomicU64>,
+    canonical: Arc<AtomicU64>,
+    /// Heads of the reference RPC endpoint and when they were first seen, oldest first.
+    reference: Arc<Mutex<VecDeque<(u64, Instant)>>>,
+}
+
+impl ChainHead {
+    /// Record a provider's head height, returning how far it is behind the chain head.
+    pub fn observe(&self, height: u64) -> u64 {
+        let head = self
+            .highest
+            .fetch_max(height, Ordering::Relaxed)
+            .max(height);
+        head - height
+    }
+
+    /// The highest head seen across the chain's providers.
+    pub fn highest(&self) -> u64 {
+        self.highest.load(Ordering::Relaxed)
+    }
+
+    pub fn set_canonical(&self, height: u64) {
+        self.canonical.store(height, Ordering::Relaxed);
+    }
+
+    /// How far `height` is behind the canonical head, negative when ahead of it. `None` until
+    /// the canonical head has been estimated.
+    pub fn canonical_lag(&self, height: u64) -> Option<i64> {
+        match self.canonical.load(Ordering::Relaxed) {
+            0 => None,
+            canonical => Some(canonical as i64 - height as i64),
+        }
+    }
+
+    /// Record a head height reported by the reference RPC endpoint.
+    pub fn observe_reference(&self, height: u64) {
+        let mut reference = self.reference.lock().unwrap();
+        if reference.back().is_some_and(|(head, _)| *head >= height) {
+            return;
+        }
+        reference.push_back((height, Instant::now()));
+        if reference.len() > REFERENCE_HISTORY {
+            reference.pop_front();
+        }
+    }
+
+    /// How far `height` trails the reference RPC endpoint, in blocks, negative when ahead of it,
+    /// and in time since the reference reached the block after `height`. `None` until the
+    /// reference reported a head.
+    pub fn reference_lag(&self, height: u64) -> Option<(i64, Duration)> {
+        let reference = self.reference.lock().unwrap();
+        let (head, _) = *reference.back()?;
+        let blocks = head as i64 - height as i64;
+        let since = reference
+            .iter()
+            .find(|(head, _)| *head > height)
+            .map(|(_, at)| at.elapsed())
+            .unwrap_or_default();
+        Some((blocks, since))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockMetrics {
+    chain: String,
+    endpoint: String,
+    expected_block_time: Duration,
+    chain_head: ChainHead,
+    stats: ProviderStats,
+    events: Vec<BlockEvents>,
+    block_number: Gauge,
+    height_lag: Gauge,
+    canonical_head_lag: Gauge,
+    head_lag_blocks: Gauge,
+    head_lag_seconds: Gauge,
+    block_delay: Gauge,
+    freshness_score: Gauge,
+    detection_interval: Histogram,
+    detection_stalls: IntCounter,
+    budget_detection: Histogram,
+    budget_fetch: Histogram,
+    budget_transactions: Histogram,
+    poll: PollMetrics,
+    /// Head height of the provider and when it last advanced.
+    last_detection: Arc<Mutex<(u64, Option<Instant>)>>,
+}
+
+impl BlockMetrics {
+    pub fn new(
+        registry: &Registry,
+        chain: &str,
+        endpoint: &str,
+        expected_block_time: Duration,
+        chain_head: ChainHead,
+        stats: ProviderStats,
+        events: Vec<BlockEvents>,
+    ) -> Self {
+        let block_number = Gauge::new("block_number", "Block number").unwrap();
+        let height_lag = Gauge::new(
+            "height_lag",
+            "Blocks (or slots) behind the highest head seen across the chain's providers",
+        )
+        .unwrap();
+        let canonical_head_lag = Gauge::new(
+            "canonical_head_lag",
+            "Blocks (or slots) behind the canonical head estimated from reference endpoints",
+        )
+        .unwrap();
+        let head_lag_blocks = Gauge::new(
+            "head_lag_blocks",
+            "Blocks (or slots) behind the head of the reference RPC endpoint",
+        )
+        .unwrap();
+        let head_lag_seconds = Gauge::new(
+            "head_lag_seconds",
+            "Time since the reference RPC endpoint reached the block after the provider's head",
+        )
+        .unwrap();
+        let block_delay = Gauge::new(
+            "block_delay_seconds",
+            "Time between the block timestamp and when the block was observed",
+        )
+        .unwrap();
+        let freshness_score = Gauge::new(
+            "freshness_score",
+            "Observed block delay divided by the chain's expected block time",
+        )
+        .unwrap();
+
+        let block_time = expected_block_time.as_secs_f64();
+        let detection_interval = Histogram::with_opts(histogram_opts!(
+            "block_detection_interval_seconds",
+            "Time between successive detections of a new head",
+            DETECTION_INTERVAL_BUCKETS
+                .iter()
+                .map(|factor| factor * block_time)
+                .collect()
+        ))
+        .unwrap();
+        let detection_stalls = IntCounter::new(
+            "block_detection_stalls_total",
+            "Number of times no new head was detected for several expected block times",
+        )
+        .unwrap();
+
+        let budget_detection = Histogram::with_opts(histogram_opts!(
+            "block_budget_detection_seconds",
+            "Time between the block timestamp and the detection of the new head",
+            latency_buckets()
+        ))
+        .unwrap();
+        let budget_fetch = Histogram::with_opts(histogram_opts!(
+            "block_budget_fetch_seconds",
+            "Time taken to fetch a new block",
+            latency_buckets()
+        ))
+        .unwrap();
+        let budget_transactions = Histogram::with_opts(histogram_opts!(
+            "block_budget_transactions_seconds",
+            "Time taken to fetch the transactions of a new block",
+            latency_buckets()
+        ))
+        .unwrap();
+
+        registry.register(Box::new(block_number.clone())).unwrap();
+        registry.register(Box::new(height_lag.clone())).unwrap();
+        registry
+            .register(Box::new(canonical_head_lag.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(head_lag_blocks.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(head_lag_seconds.clone()))
+            .unwrap();
+        registry.register(Box::new(block_delay.clone())).unwrap();
+        registry
+            .register(Box::new(freshness_score.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(detection_interval.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(detection_stalls.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(budget_detection.clone()))
+            .unwrap();
+        registry.register(Box::new(budget_fetch.clone())).unwrap();
+        registry
+            .register(Box::new(budget_transactions.clone()))
+            .unwrap();
+
+        Self {
+            chain: chain.to_string(),
+            endpoint: endpoint.to_string(),
+            expected_block_time,
+            chain_head,
+            stats,
+            events,
+            block_number,
+            height_lag,
+            canonical_head_lag,
+            head_lag_blocks,
+            head_lag_seconds,
+            block_delay,
+            freshness_score,
+            detection_interval,
+            detection_stalls,
+            budget_detection,
+            budget_fetch,
+            budget_transactions,
+            poll: PollMetrics::new(registry),
+            last_detection: Default::default(),
+        }
+    }
+
+    /// Create the interval driving the provider's polling loop.
+    pub fn poll_interval(&self, period: Duration) -> PollInterval {
+        self.poll.interval(period)
+    }
+
+    /// The head of the provider's chain.
+    pub fn chain_head(&self) -> &ChainHead {
+        &self.chain_head
+    }
+
+    /// Record the latest known head height without a block timestamp.
+    pub fn set_height(&self, height: u64) {
+        self.block_number.set(height as f64);
+    }
+
+    /// Record the head height reported by the provider on a poll.
+    pub fn observe_head(&self, height: u64) {
+        self.height_lag.set(self.chain_head.observe(height) as f64);
+        if let Some(lag) = self.chain_head.canonical_lag(height) {
+            self.canonical_head_lag.set(lag as f64);
+        }
+        if let Some((blocks, since)) = self.chain_head.reference_lag(height) {
+            self.head_lag_blocks.set(blocks as f64);
+            self.head_lag_seconds.set(since.as_secs_f64());
+        }
+
+        self.observe_detection(height);
+    }
+
+    /// Measure the time since the head last advanced. A provider that stalls and then catches
+    /// up in a burst has a fine head lag most of the time, but long detection intervals.
+    fn observe_detection(&self, height: u64) {
+        let mut last_detection = self.last_detection.lock().unwrap();
+        let (last_height, last_at) = *last_detection;
+        if height <= last_height {
+            return;
+        }
+
+        let now = Instant::now();
+        *last_detection = (height, Some(now));
+
+        let Some(last_at) = last_at else {
+            return;
+        };
+
+        let interval = now.duration_since(last_at).as_secs_f64();
+        self.detection_interval.observe(interval);
+        self.stats.record_detection_interval(interval);
+
+        if interval > STALL_FACTOR * self.expected_block_time.as_secs_f64() {
+            self.detection_stalls.inc();
+            log::warn!(
+                "[{}] No new head detected from {} for {:.1}s before block {} ({} new blocks)",
+                self.chain,
+                self.endpoint,
+                interval,
+                height,
+                height - last_height
+            );
+        }
+    }
+
+    pub fn height_lag(&self) -> f64 {
+        self.height_lag.get()
+    }
+
+    pub fn freshness_score(&self) -> f64 {
+        self.freshness_score.get()
+    }
+
+    /// Time since the provider's head last advanced, `None` until a head has been seen.
+    pub fn since_last_head(&self) -> Option<Duration> {
+        self.last_detection.lock().unwrap().1.map(|at| at.elapsed())
+    }
+
+    /// Record a newly observed block and how long after its timestamp it was seen, with the
+    /// number of transactions in it when known.
+    pub fn observe_block(
+        &self,
+        height: u64,
+        hash: &str,
+        timestamp: DateTime<Utc>,
+        transactions: Option<usize>,
+    ) {
+        let now = Utc::now();
+        let delay = now - timestamp;
+        let delay_secs = delay.num_milliseconds() as f64 / 1000.0;
+
+        self.block_number.set(height as f64);
+        self.block_delay.set(delay_secs);
+        self.stats.record_propagation_delay(delay_secs);
+        self.freshness_score
+            .set(delay_secs / self.expected_block_time.as_secs_f64());
+
+        for events in &self.events {
+            events.emit(height, hash, timestamp, transactions, now, delay_secs);
+        }
+    }
+
+    /// Record the latency budget of a block with timestamp `timestamp` whose head was detected
+    /// at `detected_at`: how long detection took, then fetching the block and, if the adapter
+    /// fetched them, its transactions.
+    pub fn observe_budget(
+        &self,
+        timestamp: DateTime<Utc>,
+        detected_at: DateTime<Utc>,
+        fetch: Duration,
+        transactions: Option<Duration>,
+    ) {
+        // block timestamps have a resolution of a second
+        let detection = (detected_at - timestamp).to_std().unwrap_or_default();
+        self.budget_detection.observe(detection.as_secs_f64());
+        self.budget_fetch.observe(fetch.as_secs_f64());
+        if let Some(transactions) = transactions {
+            self.budget_transactions.observe(transactions.as_secs_f64());
+        }
+    }
+}
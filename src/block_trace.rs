@@ -0,0 +1,277 @@
+//! Block processing traces.
+//!
+//! Metrics show how long each request takes, not where the time between a block appearing and
+//! the agent being done with it goes. With `OTLP_ENDPOINT` set, e.g. `http://collector:4318`, a
+//! share `OTLP_SAMPLE_RATE` (default 0.01) of the blocks observed by EVM providers is traced and
+//! exported as OTLP/HTTP JSON to `<OTLP_ENDPOINT>/v1/traces`. A trace has a `block` root span
+//! from the detection of the new head to the completion of the block, with child spans:
+//!
+//! - `detect`: the `eth_blockNumber` call that found the new head.
+//! - `fetch_block`: fetching the block.
+//! - `transactions`: the transaction fan-out, with a `fetch_transaction` span per transaction.
+//! - `simulations`, `raw_retrieval`, `transactions_root`, `receipts_root` and `extended_methods`:
+//!   the scenarios that are enabled.
+//!
+//! `OTLP_HEADERS` adds `key=value` headers to the exports, comma-separated, e.g. for
+//! authentication. Traces are batched and sent from the auxiliary runtime, and dropped when
+//! `OTLP_QUEUE` (default 256) traces are waiting.
+
+use crate::limits;
+
+use ethers::core::rand::{thread_rng, Rng};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_SAMPLE_RATE: f64 = 0.01;
+const DEFAULT_QUEUE: usize = 256;
+
+/// Traces sent in a single export at most.
+const BATCH_SIZE: usize = 64;
+
+/// Exports taking longer than this are given up on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+struct Span {
+    id: [u8; 8],
+    parent: Option<[u8; 8]>,
+    name: &'static str,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Span {
+    fn to_otlp(&self, trace_id: &[u8; 16]) -> Value {
+        let nanos = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+                .to_string()
+        };
+        json!({
+            "traceId": hex::encode(trace_id),
+            "spanId": hex::encode(self.id),
+            "parentSpanId": self.parent.map(hex::encode).unwrap_or_default(),
+            "name": self.name,
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(self.end),
+            "attributes": attributes(&self.attributes),
+        })
+    }
+}
+
+fn attributes(attributes: &[(&str, String)]) -> Value {
+    attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+/// The trace of a single block, sent when finished.
+#[derive(Debug)]
+pub struct BlockTrace {
+    trace_id: [u8; 16],
+    root: Span,
+    spans: Vec<Span>,
+    sender: mpsc::Sender<Vec<Value>>,
+    chain: String,
+}
+
+impl BlockTrace {
+    /// Record a child span of the block.
+    pub fn span(&mut self, name: &'static str, start: SystemTime, end: SystemTime) -> [u8; 8] {
+        self.child(self.root.id, name, start, end, Vec::new())
+    }
+
+    /// Record a span under `parent` with `attributes`.
+    pub fn child(
+        &mut self,
+        parent: [u8; 8],
+        name: &'static str,
+        start: SystemTime,
+        end: SystemTime,
+        attributes: Vec<(&'static str, String)>,
+    ) -> [u8; 8] {
+        let id = thread_rng().gen();
+        self.spans.push(Span {
+            id,
+            parent: Some(parent),
+            name,
+            start,
+            end,
+            attributes,
+        });
+        id
+    }
+
+    /// End the block's span now and send the trace.
+    pub fn finish(mut self) {
+        if limits::memory_exceeded() {
+            return;
+        }
+        self.root.end = SystemTime::now();
+        let spans = std::iter::once(&self.root)
+            .chain(&self.spans)
+            .map(|span| span.to_otlp(&self.trace_id))
+            .collect();
+        if self.sender.try_send(spans).is_err() {
+            log::debug!(
+                "[{}] Trace queue is full, dropping a block trace",
+                self.chain
+            );
+        }
+    }
+}
+
+/// Handle a provider's monitor starts block traces with.
+#[derive(Clone, Debug)]
+pub struct ProviderTracer {
+    chain: String,
+    endpoint: String,
+    sample_rate: f64,
+    sender: mpsc::Sender<Vec<Value>>,
+}
+
+impl ProviderTracer {
+    /// Start the trace of block `number`, detected when `detected_at` and found by the
+    /// `eth_blockNumber` call started at `detect_start`. `None` unless the block is sampled.
+    pub fn start(
+        &self,
+        number: u64,
+        detect_start: SystemTime,
+        detected_at: SystemTime,
+    ) -> Option<BlockTrace> {
+        if !thread_rng().gen_bool(self.sample_rate) {
+            return None;
+        }
+
+        let mut trace = BlockTrace {
+            trace_id: thread_rng().gen(),
+            root: Span {
+                id: thread_rng().gen(),
+                parent: None,
+                name: "block",
+                start: detect_start,
+                end: detected_at,
+                attributes: vec![
+                    ("chain", self.chain.clone()),
+                    ("endpoint", self.endpoint.clone()),
+                    ("block.number", number.to_string()),
+                ],
+            },
+            spans: Vec::new(),
+            sender: self.sender.clone(),
+            chain: self.chain.clone(),
+        };
+        trace.span("detect", detect_start, detected_at);
+        Some(trace)
+    }
+}
+
+pub struct BlockTracer {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    sample_rate: f64,
+    sender: mpsc::Sender<Vec<Value>>,
+    receiver: mpsc::Receiver<Vec<Value>>,
+}
+
+impl BlockTracer {
+    /// `None` unless `OTLP_ENDPOINT` is set.
+    pub fn from_env() -> Option<Self> {
+        let endpoint = env::var("OTLP_ENDPOINT").ok()?;
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'))
+            .parse()
+            .expect("Invalid OTLP_ENDPOINT");
+        let sample_rate = env::var("OTLP_SAMPLE_RATE")
+            .ok()
+            .map(|rate| rate.parse::<f64>().expect("Invalid OTLP_SAMPLE_RATE"))
+            .unwrap_or(DEFAULT_SAMPLE_RATE);
+        if !(0.0..=1.0).contains(&sample_rate) {
+            panic!("Invalid OTLP_SAMPLE_RATE: must be between 0 and 1");
+        }
+        let queue = env::var("OTLP_QUEUE")
+            .ok()
+            .map(|n| n.parse::<usize>().expect("Invalid OTLP_QUEUE"))
+            .unwrap_or(DEFAULT_QUEUE);
+
+        let mut headers = HeaderMap::new();
+        if let Ok(pairs) = env::var("OTLP_HEADERS") {
+            for pair in pairs.split(',').filter(|pair| !pair.trim().is_empty()) {
+                let (name, value) = pair.split_once('=').expect("Invalid OTLP_HEADERS");
+                let mut value = HeaderValue::from_str(value.trim()).expect("Invalid OTLP_HEADERS");
+                value.set_sensitive(true);
+                headers.insert(
+                    HeaderName::from_bytes(name.trim().as_bytes()).expect("Invalid OTLP_HEADERS"),
+                    value,
+                );
+            }
+        }
+        let (sender, receiver) = mpsc::channel(queue.max(1));
+
+        Some(Self {
+            client: reqwest::Client::builder()
+                .default_headers(headers)
+                .build()
+                .expect("could not initialize http"),
+            url,
+            sample_rate,
+            sender,
+            receiver,
+        })
+    }
+
+    /// The handle to trace the blocks of a provider with.
+    pub fn provider(&self, chain: &str, endpoint: &str) -> ProviderTracer {
+        ProviderTracer {
+            chain: chain.to_string(),
+            endpoint: endpoint.to_string(),
+            sample_rate: self.sample_rate,
+            sender: self.sender.clone(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        // only the providers' handles keep the channel open
+        drop(self.sender);
+        while let Some(trace) = self.receiver.recv().await {
+            let mut spans = trace;
+            for _ in 1..BATCH_SIZE {
+                match self.receiver.try_recv() {
+                    Ok(trace) => spans.extend(trace),
+                    Err(_) => break,
+                }
+            }
+
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": {
+                        "attributes": attributes(&[
+                            ("service.name", "bencheth".to_string()),
+                            ("service.version", env!("CARGO_PKG_VERSION").to_string()),
+                        ]),
+                    },
+                    "scopeSpans": [{ "scope": { "name": "bencheth" }, "spans": spans }],
+                }],
+            });
+            let res = self
+                .client
+                .post(self.url.clone())
+                .json(&body)
+                .timeout(TIMEOUT)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = res {
+                log::warn!("Failed to export block traces: {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Block event webhooks.
+//!
+//! Lightweight downstream systems that want to react to new blocks shouldn't need Kafka or a
+//! Prometheus server. With `BLOCK_WEBHOOK_URL` set, every block observed by a provider is POSTed
+//! there as JSON:
+//!
+//! ```json
+//! {"chain": "ethereum", "rpc": "eth.example", "height": 19000000, "hash": "0x...",
+//!  "timestamp": "2024-01-01T00:00:00Z", "transactions": 150,
+//!  "observed_at": "2024-01-01T00:00:00.850Z", "propagation_delay_seconds": 0.85}
+//! ```
+//!
+//! `transactions` is left out for `http` chains, which don't fetch the block.
+//!
+//! Events are sent from the auxiliary runtime in the order they were observed. A slow receiver
+//! doesn't hold up the measurements: once `BLOCK_WEBHOOK_QUEUE` (default 1024) events are queued,
+//! new ones are dropped.
+
+use crate::limits;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use std::env;
+use std::time::Duration;
+
+const DEFAULT_QUEUE: usize = 1024;
+
+/// Events taking longer than this to deliver are given up on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BlockEvent {
+    pub chain: String,
+    pub rpc: String,
+    pub height: u64,
+    pub hash: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transactions: Option<usize>,
+    pub observed_at: DateTime<Utc>,
+    pub propagation_delay_seconds: f64,
+}
+
+/// Handle a provider's block metrics emit its events with.
+#[derive(Clone, Debug)]
+pub struct BlockEvents {
+    chain: String,
+    rpc: String,
+    sender: mpsc::Sender<BlockEvent>,
+}
+
+impl BlockEvents {
+    pub fn new(chain: &str, rpc: &str, sender: mpsc::Sender<BlockEvent>) -> Self {
+        Self {
+            chain: chain.to_string(),
+            rpc: rpc.to_string(),
+            sender,
+        }
+    }
+
+    pub fn emit(
+        &self,
+        height: u64,
+        hash: &str,
+        timestamp: DateTime<Utc>,
+        transactions: Option<usize>,
+        observed_at: DateTime<Utc>,
+        propagation_delay_seconds: f64,
+    ) {
+        if limits::memory_exceeded() {
+            return;
+        }
+        let event = BlockEvent {
+            chain: self.chain.clone(),
+            rpc: self.rpc.clone(),
+            height,
+            hash: hash.to_string(),
+            timestamp,
+            transactions,
+            observed_at,
+            propagation_delay_seconds,
+        };
+        if self.sender.try_send(event).is_err() {
+            log::warn!(
+                "[{}] Block event queue is full, dropping block {} of {}",
+                self.chain,
+                height,
+                self.rpc
+            );
+        }
+    }
+}
+
+pub struct BlockWebhook {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    sender: mpsc::Sender<BlockEvent>,
+    receiver: mpsc::Receiver<BlockEvent>,
+}
+
+impl BlockWebhook {
+    /// `None` unless `BLOCK_WEBHOOK_URL` is set.
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("BLOCK_WEBHOOK_URL")
+            .ok()?
+            .parse()
+            .expect("Invalid BLOCK_WEBHOOK_URL");
+        let queue = env::var("BLOCK_WEBHOOK_QUEUE")
+            .ok()
+            .map(|n| n.parse::<usize>().expect("Invalid BLOCK_WEBHOOK_QUEUE"))
+            .unwrap_or(DEFAULT_QUEUE);
+        let (sender, receiver) = mpsc::channel(queue.max(1));
+
+        Some(Self {
+            client: reqwest::Client::new(),
+            url,
+            sender,
+            receiver,
+        })
+    }
+
+    /// The handle to emit the events of a provider with.
+    pub fn provider(&self, chain: &str, rpc: &str) -> BlockEvents {
+        BlockEvents::new(chain, rpc, self.sender.clone())
+    }
+
+    pub async fn run(mut self) {
+        // only the providers' handles keep the channel open
+        drop(self.sender);
+        while let Some(event) = self.receiver.recv().await {
+            let res = self
+                .client
+                .post(self.url.clone())
+                .json(&event)
+                .timeout(TIMEOUT)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = res {
+                log::warn!("Failed to send block webhook: {:?}", e);
+            }
+        }
+    }
+}
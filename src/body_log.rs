@@ -0,0 +1,94 @@
+//! Sampled logging of full request and response bodies.
+//!
+//! Provider specific serialization issues (hex vs decimal quantities, missing fields, odd error
+//! shapes) are hard to debug from metrics alone. With `BODY_LOG_SAMPLE_RATE` set to a fraction
+//! between 0 and 1, that fraction of requests is logged in full at debug level under the
+//! `bencheth::body_log` target, e.g. with `RUST_LOG=bencheth::body_log=debug`. Bodies are cut
+//! at `BODY_LOG_MAX_BYTES` (default 4096) and credentials are redacted from URLs and headers.
+
+use crate::limits;
+use crate::redact;
+
+use ethers::core::rand::{thread_rng, Rng};
+use reqwest::header::HeaderMap;
+use reqwest::{StatusCode, Url};
+
+use std::env;
+
+const DEFAULT_MAX_BYTES: usize = 4096;
+
+#[derive(Clone, Debug, Default)]
+pub struct BodyLog {
+    sample_rate: f64,
+    max_bytes: usize,
+}
+
+impl BodyLog {
+    pub fn from_env() -> Self {
+        let sample_rate = env::var("BODY_LOG_SAMPLE_RATE")
+            .ok()
+            .map(|rate| rate.parse::<f64>().expect("Invalid BODY_LOG_SAMPLE_RATE"))
+            .unwrap_or(0.0);
+        if !(0.0..=1.0).contains(&sample_rate) {
+            panic!("Invalid BODY_LOG_SAMPLE_RATE: must be between 0 and 1");
+        }
+        let max_bytes = env::var("BODY_LOG_MAX_BYTES")
+            .ok()
+            .map(|bytes| bytes.parse::<usize>().expect("Invalid BODY_LOG_MAX_BYTES"))
+            .unwrap_or(DEFAULT_MAX_BYTES);
+
+        Self {
+            sample_rate,
+            max_bytes,
+        }
+    }
+
+    /// Whether to log the next request.
+    pub fn sample(&self) -> bool {
+        self.sample_rate > 0.0
+            && !limits::memory_exceeded()
+            && log::log_enabled!(target: "bencheth::body_log", log::Level::Debug)
+            && thread_rng().gen::<f64>() < self.sample_rate
+    }
+
+    fn truncate<'a>(&self, body: &'a [u8]) -> (std::borrow::Cow<'a, str>, &'static str) {
+        let truncated = if body.len() > self.max_bytes {
+            " (truncated)"
+        } else {
+            ""
+        };
+        (
+            String::from_utf8_lossy(&body[..body.len().min(self.max_bytes)]),
+            truncated,
+        )
+    }
+
+    pub fn log_request(&self, url: &Url, headers: &HeaderMap, body: &[u8]) {
+        let len = body.len();
+        let (body, truncated) = self.truncate(body);
+        log::debug!(
+            target: "bencheth::body_log",
+            "request to {} [{}] ({} bytes{}): {}",
+            redact::url(url),
+            redact::headers(headers),
+            len,
+            truncated,
+            body
+        );
+    }
+
+    pub fn log_response(&self, url: &Url, status: StatusCode, headers: &HeaderMap, body: &[u8]) {
+        let len = body.len();
+        let (body, truncated) = self.truncate(body);
+        log::debug!(
+            target: "bencheth::body_log",
+            "response from {} {} [{}] ({} bytes{}): {}",
+            redact::url(url),
+            status,
+            redact::headers(headers),
+            len,
+            truncated,
+            body
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! Request budgets.
+//!
+//! Long soak tests against metered providers can run up a bill. Each provider of a chain can be
+//! given a budget for every UTC day and calendar month:
+//!
+//! - `<CHAIN>_DAILY_REQUEST_BUDGET` / `<CHAIN>_MONTHLY_REQUEST_BUDGET`: requests.
+//! - `<CHAIN>_DAILY_COST_BUDGET` / `<CHAIN>_MONTHLY_COST_BUDGET`: estimated cost, with requests
+//!   priced at `<CHAIN>_COST_PER_MILLION_REQUESTS`, or an estimate for [known
+//!   vendors](crate::vendor).
+//!
+//! Once a budget is used up, `BUDGET_ACTION` decides what happens until its period ends:
+//! `throttle` (default) lets one request through every `BUDGET_TRICKLE_INTERVAL_SECS` (default
+//! 60), `stop` holds every request. Either way the provider's `over_budget` alert fires instead of
+//! the alerts about its stalled head. The share of the budget used is exported as
+//! `budget_used_ratio`.
+//!
+//! Usage is counted from the start of the agent, a restart starts from zero.
+
+use crate::chain::ChainConfig;
+use crate::vendor::Vendor;
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, Utc};
+use prometheus::{Gauge, Registry};
+use tokio::time::{self, Instant};
+
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Throttle,
+    Stop,
+}
+
+#[derive(Debug)]
+struct Usage {
+    day: NaiveDate,
+    daily: u64,
+    monthly: u64,
+}
+
+impl Usage {
+    /// Start over when a new day or month began.
+    fn roll_over(&mut self, today: NaiveDate) {
+        if today == self.day {
+            return;
+        }
+        if (today.year(), today.month()) != (self.day.year(), self.day.month()) {
+            self.monthly = 0;
+        }
+        self.daily = 0;
+        self.day = today;
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    daily_max: Option<u64>,
+    monthly_max: Option<u64>,
+    action: Action,
+    trickle: Duration,
+    usage: Mutex<Usage>,
+    next_trickle: Mutex<Instant>,
+    used_ratio: Gauge,
+}
+
+impl Inner {
+    fn daily_exceeded(&self, usage: &Usage) -> bool {
+        matches!(self.daily_max, Some(max) if usage.daily >= max)
+    }
+
+    fn monthly_exceeded(&self, usage: &Usage) -> bool {
+        matches!(self.monthly_max, Some(max) if usage.monthly >= max)
+    }
+
+    /// When the used up budgets renew, `None` while requests are within budget.
+    fn renews_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut usage = self.usage.lock().unwrap();
+        usage.roll_over(now.date_naive());
+        if self.monthly_exceeded(&usage) {
+            Some(next_month(now))
+        } else if self.daily_exceeded(&usage) {
+            Some(next_day(now))
+        } else {
+            None
+        }
+    }
+
+    fn count(&self) {
+        let mut usage = self.usage.lock().unwrap();
+        usage.roll_over(Utc::now().date_naive());
+        usage.daily += 1;
+        usage.monthly += 1;
+
+        let ratio = |used: u64, max: Option<u64>| max.map(|max| used as f64 / max.max(1) as f64);
+        let used = [
+            ratio(usage.daily, self.daily_max),
+            ratio(usage.monthly, self.monthly_max),
+        ];
+        self.used_ratio
+            .set(used.into_iter().flatten().fold(0.0, f64::max));
+    }
+}
+
+fn next_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    let tomorrow = now.date_naive() + ChronoDuration::days(1);
+    tomorrow.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+fn next_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = match now.month() {
+        12 => (now.year() + 1, 1),
+        month => (now.year(), month + 1),
+    };
+    let first = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+    first.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+/// The request budget of a provider, shared by its transport, alerts and watchdog.
+#[derive(Clone, Debug, Default)]
+pub struct Budget(Option<Arc<Inner>>);
+
+impl Budget {
+    /// The budget of a provider of `chain`, unlimited unless one of the chain's budgets is set.
+    /// Requests cost what its `vendor` charges unless `<CHAIN>_COST_PER_MILLION_REQUESTS` is set.
+    pub fn from_chain(chain: &ChainConfig, vendor: Option<Vendor>, registry: &Registry) -> Self {
+        let var = |name: &str| {
+            chain.var(name).map(|value| {
+                value
+                    .parse::<f64>()
+                    .unwrap_or_else(|_| panic!("Invalid {}", name))
+            })
+        };
+        let cost_per_million = var("COST_PER_MILLION_REQUESTS")
+            .or_else(|| vendor.and_then(|vendor| vendor.cost_per_million_requests()));
+        let cost_budget = |name: &str| {
+            var(name).map(|cost| {
+                let cost_per_million = cost_per_million.unwrap_or_else(|| {
+                    panic!("Invalid {}: requires COST_PER_MILLION_REQUESTS", name)
+                });
+                (cost / cost_per_million * 1e6) as u64
+            })
+        };
+        let max = |requests: Option<f64>, cost: Option<u64>| {
+            [requests.map(|requests| requests as u64), cost]
+                .into_iter()
+                .flatten()
+                .min()
+        };
+        let daily_max = max(
+            var("DAILY_REQUEST_BUDGET"),
+            cost_budget("DAILY_COST_BUDGET"),
+        );
+        let monthly_max = max(
+            var("MONTHLY_REQUEST_BUDGET"),
+            cost_budget("MONTHLY_COST_BUDGET"),
+        );
+        if daily_max.is_none() && monthly_max.is_none() {
+            return Self(None);
+        }
+
+        let action = match env::var("BUDGET_ACTION").as_deref() {
+            Ok("throttle") | Err(_) => Action::Throttle,
+            Ok("stop") => Action::Stop,
+            Ok(_) => panic!("Invalid BUDGET_ACTION: must be throttle or stop"),
+        };
+        let trickle = env::var("BUDGET_TRICKLE_INTERVAL_SECS")
+            .ok()
+            .map(|secs| {
+                secs.parse::<u64>()
+                    .expect("Invalid BUDGET_TRICKLE_INTERVAL_SECS")
+            })
+            .unwrap_or(60);
+
+        let used_ratio = Gauge::new(
+            "budget_used_ratio",
+            "Highest share of the provider's daily or monthly request budget used",
+        )
+        .unwrap();
+        registry.register(Box::new(used_ratio.clone())).unwrap();
+
+        Self(Some(Arc::new(Inner {
+            daily_max,
+            monthly_max,
+            action,
+            trickle: Duration::from_secs(trickle),
+            usage: Mutex::new(Usage {
+                day: Utc::now().date_naive(),
+                daily: 0,
+                monthly: 0,
+            }),
+            next_trickle: Mutex::new(Instant::now()),
+            used_ratio,
+        })))
+    }
+
+    /// Wait until a request may be sent within the budget, and count it.
+    pub async fn admit(&self) {
+        let Some(inner) = &self.0 else {
+            return;
+        };
+
+        loop {
+            let now = Utc::now();
+            let Some(renews_at) = inner.renews_at(now) else {
+                break;
+            };
+            match inner.action {
+                Action::Throttle => {
+                    let at = {
+                        let mut next = inner.next_trickle.lock().unwrap();
+                        let at = (*next).max(Instant::now());
+                        *next = at + inner.trickle;
+                        at
+                    };
+                    time::sleep_until(at).await;
+                    break;
+                }
+                Action::Stop => {
+                    let wait = (renews_at - now).to_std().unwrap_or_default();
+                    time::sleep(wait).await;
+                }
+            }
+        }
+        inner.count();
+    }
+
+    /// A description of the used up budget, `None` while within budget.
+    pub fn exhausted(&self) -> Option<String> {
+        let inner = self.0.as_ref()?;
+        let renews_at = inner.renews_at(Utc::now())?;
+        let action = match inner.action {
+            Action::Throttle => format!("throttled to a request every {:?}", inner.trickle),
+            Action::Stop => "stopped".to_string(),
+        };
+        Some(format!(
+            "request budget used up, {} until {}",
+            action,
+            renews_at.to_rfc3339()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn inner(daily_max: Option<u64>, monthly_max: Option<u64>, usage: Usage) -> Inner {
+        Inner {
+            daily_max,
+            monthly_max,
+            action: Action::Stop,
+            trickle: Duration::from_secs(60),
+            usage: Mutex::new(usage),
+            next_trickle: Mutex::new(Instant::now()),
+            used_ratio: Gauge::new("budget_used_ratio", "help").unwrap(),
+        }
+    }
+
+    #[test]
+    fn roll_over() {
+        let mut usage = Usage {
+            day: date("2024-05-30"),
+            daily: 10,
+            monthly: 100,
+        };
+        usage.roll_over(date("2024-05-30"));
+        assert_eq!((usage.daily, usage.monthly), (10, 100));
+
+        usage.roll_over(date("2024-05-31"));
+        assert_eq!(
+            (usage.day, usage.daily, usage.monthly),
+            (date("2024-05-31"), 0, 100)
+        );
+
+        usage.daily = 5;
+        usage.roll_over(date("2024-06-01"));
+        assert_eq!((usage.daily, usage.monthly), (0, 0));
+
+        // the same month of another year is another month
+        usage.monthly = 7;
+        usage.roll_over(date("2025-06-01"));
+        assert_eq!(usage.monthly, 0);
+    }
+
+    #[test]
+    fn renewals() {
+        assert_eq!(
+            next_day(at("2024-02-28T13:00:00Z")),
+            at("2024-02-29T00:00:00Z")
+        );
+        assert_eq!(
+            next_month(at("2024-02-28T13:00:00Z")),
+            at("2024-03-01T00:00:00Z")
+        );
+        assert_eq!(
+            next_month(at("2024-12-31T23:59:59Z")),
+            at("2025-01-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn renews_at() {
+        let now = at("2024-05-30T12:00:00Z");
+        let usage = |daily, monthly| Usage {
+            day: now.date_naive(),
+            daily,
+            monthly,
+        };
+        assert_eq!(
+            inner(Some(10), Some(100), usage(9, 99)).renews_at(now),
+            None
+        );
+        assert_eq!(
+            inner(Some(10), Some(100), usage(10, 50)).renews_at(now),
+            Some(at("2024-05-31T00:00:00Z"))
+        );
+        // a used up month outlasts the day
+        assert_eq!(
+            inner(Some(10), Some(100), usage(10, 100)).renews_at(now),
+            Some(at("2024-06-01T00:00:00Z"))
+        );
+        assert_eq!(inner(None, Some(100), usage(500, 99)).renews_at(now), None);
+
+        // yesterday's usage doesn't count today
+        let inner = inner(
+            Some(10),
+            None,
+            Usage {
+                day: date("2024-05-29"),
+                daily: 10,
+                monthly: 10,
+            },
+        );
+        assert_eq!(inner.renews_at(now), None);
+    }
+
+    #[test]
+    fn used_ratio() {
+        let inner = inner(
+            Some(10),
+            Some(100),
+            Usage {
+                day: Utc::now().date_naive(),
+                daily: 4,
+                monthly: 89,
+            },
+        );
+        inner.count();
+        assert_eq!(inner.used_ratio.get(), 0.9);
+        inner.count();
+        assert_eq!(inner.used_ratio.get(), 0.91);
+    }
+
+    #[tokio::test]
+    async fn unlimited() {
+        let budget = Budget::default();
+        budget.admit().await;
+        assert_eq!(budget.exhausted(), None);
+    }
+}
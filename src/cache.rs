@@ -0,0 +1,281 @@
+//! Client-side caching simulation for head data.
+//!
+//! Gateways can cache `eth_blockNumber` and `eth_gasPrice` for a short TTL to save upstream
+//! requests, at the cost of serving stale heads. The scenario polls both methods of every
+//! configured EVM RPC URL and replays the observations through a cache for each TTL, giving the
+//! staleness-versus-savings curve a cache policy can be picked from.
+//!
+//! Run it with `bencheth cache`. Every RPC URL is polled every `CACHE_POLL_INTERVAL_MS`
+//! (default 50) for `CACHE_DURATION_SECS` (default 60), and the TTLs are `CACHE_TTLS_MS`
+//! (comma-separated, default `250,500,1000`). The poll interval should be well below the
+//! shortest TTL, as every poll stands for a client request.
+//!
+//! For every TTL, the report contains the share of requests the cache would have saved, the
+//! share of stale responses, how long the fresher value had been available when a stale one was
+//! served, and how far it was off: blocks behind for `eth_blockNumber`, percent for
+//! `eth_gasPrice`.
+
+use crate::chain::{ChainConfig, ChainKind};
+use crate::redact;
+use crate::report::Summary;
+use crate::rpc_method::RpcMethod;
+use crate::tls;
+
+use prometheus::Registry;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::time::{self, MissedTickBehavior};
+
+use std::env;
+use std::time::{Duration, Instant};
+
+const DEFAULT_CACHE_TTLS_MS: [u64; 3] = [250, 500, 1000];
+const DEFAULT_CACHE_DURATION_SECS: u64 = 60;
+const DEFAULT_CACHE_POLL_INTERVAL_MS: u64 = 50;
+
+const METHODS: [RpcMethod; 2] = [RpcMethod::EthBlockNumber, RpcMethod::EthGasPrice];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TtlResult {
+    pub ttl_ms: u64,
+    /// Requests the cache would have forwarded to the provider.
+    pub upstream_requests: usize,
+    /// Share of requests served from the cache.
+    pub savings: f64,
+    /// Share of requests answered with a value older than the provider's.
+    pub stale_ratio: f64,
+    /// How long the fresher value had been available when a stale one was served, in
+    /// milliseconds.
+    pub staleness_ms: Summary,
+    /// How far stale values were off: blocks behind for `eth_blockNumber`, percent for
+    /// `eth_gasPrice`.
+    pub deviation: Summary,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheReport {
+    pub chain: String,
+    pub rpc: String,
+    pub method: String,
+    /// Successful polls, each standing for a client request.
+    pub requests: usize,
+    pub errors: usize,
+    pub ttls: Vec<TtlResult>,
+}
+
+struct Scenario {
+    ttls: Vec<Duration>,
+    duration: Duration,
+    interval: Duration,
+}
+
+/// A value returned by the provider, `at` the time since polling started.
+struct Observation {
+    at: Duration,
+    value: u128,
+}
+
+impl Scenario {
+    fn from_env() -> Self {
+        let ttls = env::var("CACHE_TTLS_MS")
+            .map(|ttls| {
+                ttls.split(',')
+                    .map(|ttl| ttl.trim().parse::<u64>().expect("Invalid CACHE_TTLS_MS"))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_else(|_| DEFAULT_CACHE_TTLS_MS.to_vec());
+        let duration = env::var("CACHE_DURATION_SECS")
+            .ok()
+            .map(|secs| secs.parse::<u64>().expect("Invalid CACHE_DURATION_SECS"))
+            .unwrap_or(DEFAULT_CACHE_DURATION_SECS);
+        let interval = env::var("CACHE_POLL_INTERVAL_MS")
+            .ok()
+            .map(|ms| ms.parse::<u64>().expect("Invalid CACHE_POLL_INTERVAL_MS"))
+            .unwrap_or(DEFAULT_CACHE_POLL_INTERVAL_MS);
+        if interval == 0 {
+            panic!("Invalid CACHE_POLL_INTERVAL_MS: must be greater than zero");
+        }
+
+        Self {
+            ttls: ttls.into_iter().map(Duration::from_millis).collect(),
+            duration: Duration::from_secs(duration),
+            interval: Duration::from_millis(interval),
+        }
+    }
+
+    /// Poll the head data of `url` and replay it through a cache for every TTL.
+    async fn run(&self, chain: &ChainConfig, url: &Url) -> Vec<CacheReport> {
+        let client = tls::client_builder(&Registry::new())
+            .build()
+            .expect("could not initialize http");
+        let mut observations: Vec<Vec<Observation>> = METHODS.iter().map(|_| Vec::new()).collect();
+        let mut errors = vec![0; METHODS.len()];
+
+        let mut interval = time::interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let start = Instant::now();
+        while start.elapsed() < self.duration {
+            interval.tick().await;
+            let at = start.elapsed();
+            let results = futures::future::join_all(
+                METHODS
+                    .iter()
+                    .map(|method| fetch(&client, url, &chain.basic_auth, method)),
+            )
+            .await;
+            for (i, res) in results.into_iter().enumerate() {
+                match res {
+                    Ok(value) => observations[i].push(Observation { at, value }),
+                    Err(e) => {
+                        log::debug!(
+                            "[🗄️][{}] {} request to {} failed: {}",
+                            chain.name,
+                            METHODS[i],
+                            redact::url(url),
+                            e
+                        );
+                        errors[i] += 1;
+                    }
+                }
+            }
+        }
+
+        METHODS
+            .iter()
+            .zip(observations)
+            .zip(errors)
+            .map(|((method, observations), errors)| CacheReport {
+                chain: chain.name.clone(),
+                rpc: url.host_str().unwrap_or_default().to_string(),
+                method: method.to_string(),
+                requests: observations.len(),
+                errors,
+                ttls: self
+                    .ttls
+                    .iter()
+                    .map(|ttl| replay(method, &observations, *ttl))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+async fn fetch(
+    client: &Client,
+    url: &Url,
+    basic_auth: &Option<(String, String)>,
+    method: &RpcMethod,
+) -> Result<u128, String> {
+    let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method.as_str(), "params": [] });
+    let mut request = client.post(url.clone()).json(&body);
+    if let Some((username, password)) = basic_auth {
+        request = request.basic_auth(username, Some(password));
+    }
+    let response: Value = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let result = response["result"]
+        .as_str()
+        .ok_or_else(|| format!("unexpected response {}", response))?;
+    u128::from_str_radix(result.trim_start_matches("0x"), 16).map_err(|e| e.to_string())
+}
+
+/// Replay `observations` through a cache holding values for `ttl`.
+fn replay(method: &RpcMethod, observations: &[Observation], ttl: Duration) -> TtlResult {
+    let mut cached: Option<&Observation> = None;
+    let mut upstream_requests = 0;
+    // when the provider started returning its current value
+    let mut fresh_since = Duration::ZERO;
+    let mut previous: Option<u128> = None;
+    let mut staleness = Vec::new();
+    let mut deviation = Vec::new();
+
+    for observation in observations {
+        if previous != Some(observation.value) {
+            fresh_since = observation.at;
+            previous = Some(observation.value);
+        }
+
+        let served = match cached {
+            Some(cached) if observation.at - cached.at < ttl => cached.value,
+            _ => {
+                upstream_requests += 1;
+                cached = Some(observation);
+                observation.value
+            }
+        };
+        if served == observation.value {
+            continue;
+        }
+
+        staleness.push((observation.at - fresh_since).as_secs_f64() * 1000.0);
+        deviation.push(match method {
+            RpcMethod::EthBlockNumber => observation.value.saturating_sub(served) as f64,
+            _ => {
+                (observation.value as f64 - served as f64).abs() / observation.value as f64 * 100.0
+            }
+        });
+    }
+
+    let requests = observations.len().max(1) as f64;
+    TtlResult {
+        ttl_ms: ttl.as_millis() as u64,
+        upstream_requests,
+        savings: 1.0 - upstream_requests as f64 / requests,
+        stale_ratio: staleness.len() as f64 / requests,
+        staleness_ms: Summary::from_samples(staleness).unwrap_or_default(),
+        deviation: Summary::from_samples(deviation).unwrap_or_default(),
+    }
+}
+
+/// Run the scenario against every configured EVM RPC URL at once, so all of them are polled
+/// under the same conditions.
+pub async fn run(chains: &[ChainConfig]) -> Vec<CacheReport> {
+    let scenario = Scenario::from_env();
+
+    let mut runs = Vec::new();
+    for chain in chains {
+        if chain.kind != ChainKind::Evm {
+            log::warn!(
+                "[{}] The caching simulation only supports EVM chains, skipping",
+                chain.name
+            );
+            continue;
+        }
+        for url in &chain.rpc_urls {
+            runs.push(scenario.run(chain, url));
+        }
+    }
+
+    let reports = futures::future::join_all(runs)
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    for report in &reports {
+        log_report(report);
+    }
+    reports
+}
+
+fn log_report(report: &CacheReport) {
+    for result in &report.ttls {
+        log::info!(
+            "[🗄️][{}] {} {} cached for {}ms: {:.1}% saved, {:.1}% stale, p99 staleness {:.0}ms",
+            report.chain,
+            report.rpc,
+            report.method,
+            result.ttl_ms,
+            result.savings * 100.0,
+            result.stale_ratio * 100.0,
+            result.staleness_ms.p99
+        );
+    }
+}
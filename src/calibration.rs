@@ -0,0 +1,219 @@
+//! Self-calibration against an in-process mock server.
+//!
+//! Every latency BenchETH measures includes its own overhead: JSON serialization and
+//! deserialization, the ethers middleware, task scheduling and metrics. For providers a few
+//! milliseconds away this overhead can dominate the comparison. Calibration serves canned
+//! JSON-RPC responses from a local mock server and measures each request twice, once through the
+//! full measured provider and once as a raw HTTP round trip of the pre-serialized request. The
+//! paired difference is the harness overhead.
+//!
+//! Run it with `bencheth calibrate`, or set `CALIBRATE=true` to calibrate on startup and include
+//! the result in the run report.
+
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::report::Summary;
+
+use ethers::prelude::*;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use prometheus::Registry;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Requests made per method when `CALIBRATION_REQUESTS` isn't set.
+pub const DEFAULT_CALIBRATION_REQUESTS: usize = 1000;
+
+/// Requests made per method, `CALIBRATION_REQUESTS` (default 1000).
+pub fn requests() -> usize {
+    std::env::var("CALIBRATION_REQUESTS")
+        .ok()
+        .map(|n| n.parse::<usize>().expect("Invalid CALIBRATION_REQUESTS"))
+        .unwrap_or(DEFAULT_CALIBRATION_REQUESTS)
+}
+
+/// Transactions in the mock block, roughly a mainnet block.
+const MOCK_BLOCK_TRANSACTIONS: u64 = 150;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MethodCalibration {
+    /// Latency through the full measured provider, in milliseconds.
+    pub measured_ms: Summary,
+    /// Latency of the raw HTTP round trip, in milliseconds.
+    pub baseline_ms: Summary,
+    /// Paired difference between the two, in milliseconds.
+    pub overhead_ms: Summary,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Calibration {
+    pub requests: usize,
+    pub methods: BTreeMap<String, MethodCalibration>,
+}
+
+/// Canned results served by the mock server, keyed by method.
+fn mock_results() -> BTreeMap<&'static str, Value> {
+    let tx_hashes = (1..=MOCK_BLOCK_TRANSACTIONS)
+        .map(H256::from_low_u64_be)
+        .collect::<Vec<_>>();
+    let block = Block::<H256> {
+        hash: Some(H256::from_low_u64_be(1)),
+        number: Some(U64::from(1)),
+        timestamp: U256::from(1_700_000_000u64),
+        transactions: tx_hashes,
+        ..Default::default()
+    };
+    let tx = Transaction {
+        hash: H256::from_low_u64_be(1),
+        block_hash: Some(H256::from_low_u64_be(1)),
+        block_number: Some(U64::from(1)),
+        ..Default::default()
+    };
+
+    BTreeMap::from([
+        ("eth_blockNumber", json!("0x1")),
+        ("eth_getBlockByNumber", serde_json::to_value(block).unwrap()),
+        (
+            "eth_getTransactionByHash",
+            serde_json::to_value(tx).unwrap(),
+        ),
+    ])
+}
+
+async fn handle(
+    results: Arc<BTreeMap<&'static str, Value>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .unwrap_or_default();
+    let request: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let method = request["method"].as_str().unwrap_or_default();
+
+    let response = match results.get(method) {
+        Some(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+        None => json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "error": { "code": -32601, "message": "method not found" },
+        }),
+    };
+
+    Ok(Response::new(Body::from(response.to_string())))
+}
+
+/// Start the mock server on an ephemeral local port.
+async fn start_mock_server() -> SocketAddr {
+    let results = Arc::new(mock_results());
+    let make_svc = make_service_fn(move |_| {
+        let results = results.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(results.clone(), req))) }
+    });
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Calibration mock server error: {}", e);
+        }
+    });
+    addr
+}
+
+fn to_ms(samples: &[f64]) -> Summary {
+    Summary::from_samples(samples.iter().copied()).unwrap_or_default()
+}
+
+/// Measure the harness overhead with `requests` requests per method.
+pub async fn calibrate(requests: usize) -> Calibration {
+    let addr = start_mock_server().await;
+    let url = format!("http://{}", addr);
+
+    // a throwaway registry, calibration requests must not show up in the provider metrics
+    let registry = Registry::new();
+    let provider = Provider::new(MeasuredJsonRpc::new(url.as_str(), &registry));
+    let raw = reqwest::Client::new();
+
+    let mut methods = BTreeMap::new();
+    for method in mock_results().keys() {
+        let params = match *method {
+            "eth_blockNumber" => json!([]),
+            "eth_getBlockByNumber" => json!(["0x1", false]),
+            _ => json!([H256::from_low_u64_be(1)]),
+        };
+        let raw_body =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+
+        let mut measured = Vec::with_capacity(requests);
+        let mut baseline = Vec::with_capacity(requests);
+        let mut overhead = Vec::with_capacity(requests);
+
+        for _ in 0..requests {
+            let start = Instant::now();
+            let res = match *method {
+                "eth_blockNumber" => provider.get_block_number().await.map(|_| ()),
+                "eth_getBlockByNumber" => provider.get_block(1u64).await.map(|_| ()),
+                _ => provider
+                    .get_transaction(H256::from_low_u64_be(1))
+                    .await
+                    .map(|_| ()),
+            };
+            let full = start.elapsed().as_secs_f64() * 1000.0;
+            if let Err(e) = res {
+                log::warn!("Calibration request {} failed: {:?}", method, e);
+                continue;
+            }
+
+            let start = Instant::now();
+            let res = raw
+                .post(url.as_str())
+                .header("content-type", "application/json")
+                .body(raw_body.clone())
+                .send()
+                .await;
+            let res = match res {
+                Ok(response) => response.bytes().await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+            let bare = start.elapsed().as_secs_f64() * 1000.0;
+            if let Err(e) = res {
+                log::warn!("Calibration baseline request {} failed: {:?}", method, e);
+                continue;
+            }
+
+            measured.push(full);
+            baseline.push(bare);
+            overhead.push(full - bare);
+        }
+
+        methods.insert(
+            method.to_string(),
+            MethodCalibration {
+                measured_ms: to_ms(&measured),
+                baseline_ms: to_ms(&baseline),
+                overhead_ms: to_ms(&overhead),
+            },
+        );
+    }
+
+    Calibration { requests, methods }
+}
+
+/// Log a calibration result.
+pub fn log_calibration(calibration: &Calibration) {
+    for (method, result) in &calibration.methods {
+        log::info!(
+            "[🎯] {}: harness overhead p50 {:.3}ms p99 {:.3}ms (measured p50 {:.3}ms, baseline p50 {:.3}ms)",
+            method,
+            result.overhead_ms.p50,
+            result.overhead_ms.p99,
+            result.measured_ms.p50,
+            result.baseline_ms.p50
+        );
+    }
+}
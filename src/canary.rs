@@ -0,0 +1,280 @@
+//! Canary transactions.
+//!
+//! Everything else BenchETH measures is read-only, while wallets and bots care as much about how
+//! fast a provider gets their transactions out. With `<CHAIN>_CANARY_PRIVATE_KEY` set, every
+//! `<CHAIN>_CANARY_INTERVAL_SECS` (default 300) each HTTP RPC URL of an EVM chain signs and submits
+//! a zero value transfer from the key's address to itself, and measures from the start of
+//! `eth_sendRawTransaction`:
+//!
+//! - `canary_submit_seconds`: until the provider accepted the transaction.
+//! - `canary_mempool_seconds`: until `eth_getTransactionByHash` returned it.
+//! - `canary_inclusion_seconds`: until its receipt was available, i.e. it was included in a block.
+//!
+//! Failures are counted in `canary_errors_total` by `stage`: `prepare` for the nonce and gas
+//! price, `submit`, `mempool` and `inclusion`, the latter two after `<CHAIN>_CANARY_TIMEOUT_SECS`
+//! (default 300). Canaries of the providers of a chain are sent one at a time, as they share the
+//! key's nonces, and a canary that timed out is replaced by the next one with the same nonce.
+//! Their requests are sent under the request limits and the provider's budget.
+//!
+//! Every canary pays for its gas, so the key should be a dedicated one holding little more than
+//! the fees. Like every `*_KEY` variable, it is redacted from logs.
+
+use crate::budget::Budget;
+use crate::chain::{ChainConfig, ChainKind};
+use crate::limits::{Limited, RequestLimits};
+
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use prometheus::{Histogram, HistogramOpts, IntCounterVec, Opts, Registry};
+use reqwest::Url;
+use tokio::sync::Mutex;
+use tokio::time::{self, Instant};
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_INTERVAL_SECS: u64 = 300;
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
+/// How often the mempool is checked for the canary.
+const MEMPOOL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Gas of a plain transfer.
+const TRANSFER_GAS: u64 = 21_000;
+
+/// Bids above the current gas price, in percent, so canaries aren't left behind and replace
+/// canaries that timed out.
+const GAS_PRICE_PREMIUM: u64 = 25;
+
+/// Locks shared by the canaries of a chain, so one is in flight at a time.
+pub type CanaryLock = Arc<Mutex<()>>;
+
+fn register_histogram(registry: &Registry, name: &str, help: &str) -> Histogram {
+    let histogram = Histogram::with_opts(
+        HistogramOpts::new(name, help)
+            .buckets(prometheus::exponential_buckets(0.05, 2.0, 14).unwrap()),
+    )
+    .unwrap();
+    registry.register(Box::new(histogram.clone())).unwrap();
+    histogram
+}
+
+pub struct Canary {
+    chain: Arc<ChainConfig>,
+    endpoint: String,
+    provider: Provider<Limited<Http>>,
+    wallet: LocalWallet,
+    interval: Duration,
+    timeout: Duration,
+    lock: CanaryLock,
+    submit: Histogram,
+    mempool: Histogram,
+    inclusion: Histogram,
+    errors: IntCounterVec,
+}
+
+impl Canary {
+    /// The canary of the provider at `rpc_url`, sending requests within its `budget` and `limits`
+    /// and reporting to its `registry`. `None` unless `<CHAIN>_CANARY_PRIVATE_KEY` is set on an
+    /// EVM chain and an HTTP RPC URL.
+    pub fn from_chain(
+        chain: Arc<ChainConfig>,
+        rpc_url: &Url,
+        endpoint: &str,
+        lock: CanaryLock,
+        budget: Budget,
+        limits: RequestLimits,
+        registry: &Registry,
+    ) -> Option<Self> {
+        let key = chain.var("CANARY_PRIVATE_KEY")?;
+        if chain.kind != ChainKind::Evm || !rpc_url.scheme().starts_with("http") {
+            log::warn!(
+                "[{}] Canary transactions are only supported on HTTP RPC URLs of EVM chains",
+                chain.name
+            );
+            return None;
+        }
+        let wallet = LocalWallet::from_str(key.trim_start_matches("0x"))
+            .expect("Invalid CANARY_PRIVATE_KEY");
+        let http = match &chain.basic_auth {
+            Some((username, password)) => {
+                Http::new_with_auth(rpc_url.clone(), Authorization::basic(username, password))
+                    .expect("could not initialize http")
+            }
+            None => Http::from_str(rpc_url.as_str()).expect("could not initialize http"),
+        };
+        let http = Limited::new(http).with_budget(budget).with_limits(limits);
+        let secs = |name: &str, default: u64| {
+            chain
+                .var(name)
+                .map(|secs| match secs.parse::<u64>() {
+                    Ok(secs) if secs > 0 => secs,
+                    _ => panic!("Invalid {}: must be greater than zero", name),
+                })
+                .unwrap_or(default)
+        };
+        let interval = secs("CANARY_INTERVAL_SECS", DEFAULT_INTERVAL_SECS);
+        let timeout = secs("CANARY_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS);
+
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "canary_errors_total",
+                "Number of canary transactions that failed, by stage",
+            ),
+            &["stage"],
+        )
+        .unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+
+        Some(Self {
+            endpoint: endpoint.to_string(),
+            provider: Provider::new(http),
+            wallet,
+            interval: Duration::from_secs(interval),
+            timeout: Duration::from_secs(timeout),
+            lock,
+            submit: register_histogram(
+                registry,
+                "canary_submit_seconds",
+                "Time taken for the provider to accept a canary transaction",
+            ),
+            mempool: register_histogram(
+                registry,
+                "canary_mempool_seconds",
+                "Time from submitting a canary transaction until the provider returned it",
+            ),
+            inclusion: register_histogram(
+                registry,
+                "canary_inclusion_seconds",
+                "Time from submitting a canary transaction until it was included in a block",
+            ),
+            errors,
+            chain,
+        })
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = time::interval(self.interval);
+
+        loop {
+            interval.tick().await;
+            let _lock = self.lock.lock().await;
+            if let Err((stage, e)) = self.send().await {
+                log::warn!(
+                    "[{}] Canary transaction via {} failed to {}: {}",
+                    self.chain.name,
+                    self.endpoint,
+                    stage,
+                    e
+                );
+                self.errors.with_label_values(&[stage]).inc();
+            }
+        }
+    }
+
+    /// The signed canary transaction.
+    async fn prepare(&self) -> Result<Bytes, String> {
+        let address = self.wallet.address();
+        let chain_id = match self.chain.chain_id {
+            Some(chain_id) => chain_id,
+            None => self
+                .provider
+                .get_chainid()
+                .await
+                .map_err(|e| e.to_string())?
+                .as_u64(),
+        };
+        // the confirmed nonce, so a canary stuck in the mempool is replaced
+        let nonce = self
+            .provider
+            .get_transaction_count(address, Some(BlockNumber::Latest.into()))
+            .await
+            .map_err(|e| e.to_string())?;
+        let gas_price = self
+            .provider
+            .get_gas_price()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(address)
+            .to(address)
+            .value(0)
+            .nonce(nonce)
+            .gas(TRANSFER_GAS)
+            .gas_price(gas_price * (100 + GAS_PRICE_PREMIUM) / 100)
+            .chain_id(chain_id)
+            .into();
+        let signature = self
+            .wallet
+            .clone()
+            .with_chain_id(chain_id)
+            .sign_transaction(&tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(tx.rlp_signed(&signature))
+    }
+
+    /// Send a canary and follow it into a block.
+    async fn send(&self) -> Result<(), (&'static str, String)> {
+        let raw = self.prepare().await.map_err(|e| ("prepare", e))?;
+
+        let start = Instant::now();
+        let hash = self
+            .provider
+            .send_raw_transaction(raw)
+            .await
+            .map_err(|e| ("submit", e.to_string()))?
+            .tx_hash();
+        self.submit.observe(start.elapsed().as_secs_f64());
+
+        let deadline = start + self.timeout;
+        loop {
+            match self.provider.get_transaction(hash).await {
+                Ok(Some(_)) => break,
+                Ok(None) => {}
+                Err(e) => log::debug!(
+                    "[{}] Failed to look up canary {:?}: {}",
+                    self.chain.name,
+                    hash,
+                    e
+                ),
+            }
+            if Instant::now() >= deadline {
+                return Err(("mempool", format!("{:?} not seen in time", hash)));
+            }
+            time::sleep(MEMPOOL_POLL_INTERVAL).await;
+        }
+        self.mempool.observe(start.elapsed().as_secs_f64());
+
+        loop {
+            match self.provider.get_transaction_receipt(hash).await {
+                Ok(Some(receipt)) => {
+                    let elapsed = start.elapsed();
+                    self.inclusion.observe(elapsed.as_secs_f64());
+                    log::info!(
+                        "[{}] Canary {:?} via {} included in block {} after {:?}",
+                        self.chain.name,
+                        hash,
+                        self.endpoint,
+                        receipt.block_number.unwrap_or_default(),
+                        elapsed
+                    );
+                    return Ok(());
+                }
+                Ok(None) => {}
+                Err(e) => log::debug!(
+                    "[{}] Failed to get the receipt of canary {:?}: {}",
+                    self.chain.name,
+                    hash,
+                    e
+                ),
+            }
+            if Instant::now() >= deadline {
+                return Err(("inclusion", format!("{:?} not included in time", hash)));
+            }
+            time::sleep(self.chain.poll_interval).await;
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! Method support of EVM providers.
+//!
+//! Providers differ widely in which methods they serve, and what they advertise isn't always what
+//! works. `bencheth capabilities` reports, for every RPC URL of the configured EVM chains:
+//!
+//! - `methods`: the support matrix, from calling every method of a fixed set with cheap
+//!   parameters against the latest block. A method is `supported` when it returns a result,
+//!   `unsupported` when the provider doesn't know it or refuses it (e.g. JSON-RPC error -32601),
+//!   and `failed` on any other error.
+//! - `modules`: the namespaces advertised by `rpc_modules`, and `discovered` the methods listed
+//!   by the OpenRPC document of `rpc.discover`, where supported.
+//! - `advertised_but_broken`: probed methods that are advertised, by name or namespace, but
+//!   aren't supported. `unadvertised` the supported ones that aren't advertised, only when the
+//!   provider advertises anything at all.
+
+use crate::chain::{self, ChainConfig, ChainKind};
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::rpc_method::RpcMethod;
+
+use ethers::providers::{Authorization, JsonRpcClient, RpcError};
+use prometheus::Registry;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+/// JSON-RPC error code of unknown methods.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Error messages of providers refusing a method without the standard error code.
+const UNSUPPORTED_MESSAGES: [&str; 6] = [
+    "not found",
+    "not supported",
+    "unsupported",
+    "does not exist",
+    "not available",
+    "not allowed",
+];
+
+/// The methods probed for the support matrix, with the name of the variant if a method is probed
+/// several ways, and their parameters.
+fn probes() -> Vec<(RpcMethod, Option<&'static str>, Value)> {
+    let call = json!({ "to": ZERO_ADDRESS, "data": "0x" });
+    vec![
+        (RpcMethod::Web3ClientVersion, None, json!([])),
+        (RpcMethod::NetVersion, None, json!([])),
+        (RpcMethod::EthChainId, None, json!([])),
+        (RpcMethod::EthBlockNumber, None, json!([])),
+        (RpcMethod::EthSyncing, None, json!([])),
+        (RpcMethod::EthGasPrice, None, json!([])),
+        (RpcMethod::EthMaxPriorityFeePerGas, None, json!([])),
+        (RpcMethod::EthBlobBaseFee, None, json!([])),
+        (RpcMethod::EthFeeHistory, None, json!([4, "latest", [50]])),
+        (
+            RpcMethod::EthGetBlockByNumber,
+            None,
+            json!(["latest", false]),
+        ),
+        (RpcMethod::EthGetBlockReceipts, None, json!(["latest"])),
+        (
+            RpcMethod::EthGetBalance,
+            None,
+            json!([ZERO_ADDRESS, "latest"]),
+        ),
+        (RpcMethod::EthGetCode, None, json!([ZERO_ADDRESS, "latest"])),
+        (
+            RpcMethod::EthGetStorageAt,
+            None,
+            json!([ZERO_ADDRESS, "0x0", "latest"]),
+        ),
+        (
+            RpcMethod::EthGetTransactionCount,
+            None,
+            json!([ZERO_ADDRESS, "latest"]),
+        ),
+        (
+            RpcMethod::EthGetProof,
+            None,
+            json!([ZERO_ADDRESS, [], "latest"]),
+        ),
+        (RpcMethod::EthCall, None, json!([call, "latest"])),
+        (
+            RpcMethod::EthCall,
+            Some("state override"),
+            json!([call, "latest", { ZERO_ADDRESS: { "balance": "0x1" } }]),
+        ),
+        (
+            RpcMethod::EthCall,
+            Some("block override"),
+            json!([call, "latest", {}, { "number": "0x1" }]),
+        ),
+        (
+            RpcMethod::EthSimulateV1,
+            None,
+            json!([{ "blockStateCalls": [{ "calls": [call] }] }, "latest"]),
+        ),
+        (RpcMethod::EthEstimateGas, None, json!([call])),
+        (
+            RpcMethod::EthCreateAccessList,
+            None,
+            json!([call, "latest"]),
+        ),
+        (
+            RpcMethod::EthGetLogs,
+            None,
+            json!([{ "fromBlock": "latest", "toBlock": "latest" }]),
+        ),
+        (RpcMethod::DebugGetRawBlock, None, json!(["latest"])),
+        (RpcMethod::DebugGetRawReceipts, None, json!(["latest"])),
+        (RpcMethod::TxpoolStatus, None, json!([])),
+        (RpcMethod::OtsGetApiLevel, None, json!([])),
+        (RpcMethod::OtsGetBlockDetails, None, json!([1])),
+        (RpcMethod::OtsHasCode, None, json!([ZERO_ADDRESS, "latest"])),
+        (RpcMethod::ErigonBlockNumber, None, json!([])),
+        (RpcMethod::ErigonGetHeaderByNumber, None, json!([1])),
+        (RpcMethod::ErigonForks, None, json!([])),
+        (RpcMethod::TraceBlock, None, json!(["latest"])),
+        (
+            RpcMethod::DebugTraceBlockByNumber,
+            None,
+            json!(["latest", { "tracer": "callTracer" }]),
+        ),
+    ]
+}
+
+/// The method called by a probe, named `<method> (<variant>)` in reports.
+fn rpc_method(probe: &str) -> &str {
+    probe.split_whitespace().next().unwrap_or_default()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Support {
+    Supported,
+    Unsupported,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MethodSupport {
+    pub support: Support,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CapabilityReport {
+    pub chain: String,
+    pub rpc: String,
+    pub methods: BTreeMap<String, MethodSupport>,
+    /// Namespaces and their versions as advertised by `rpc_modules`.
+    pub modules: Option<BTreeMap<String, String>>,
+    /// Methods listed by `rpc.discover`.
+    pub discovered: Option<BTreeSet<String>>,
+    pub advertised_but_broken: Vec<String>,
+    pub unadvertised: Vec<String>,
+}
+
+impl CapabilityReport {
+    fn is_advertised(&self, method: &str) -> Option<bool> {
+        if self.modules.is_none() && self.discovered.is_none() {
+            return None;
+        }
+        let method = rpc_method(method);
+        let namespace = method.split('_').next().unwrap_or_default();
+        let in_modules = self
+            .modules
+            .as_ref()
+            .is_some_and(|modules| modules.contains_key(namespace));
+        let discovered = self
+            .discovered
+            .as_ref()
+            .is_some_and(|discovered| discovered.contains(method));
+        Some(in_modules || discovered)
+    }
+
+    /// Cross-check the probed support matrix against what the provider advertises.
+    fn cross_check(&mut self) {
+        for (method, support) in &self.methods {
+            match (self.is_advertised(method), support.support) {
+                (Some(true), Support::Unsupported | Support::Failed) => {
+                    self.advertised_but_broken.push(method.clone())
+                }
+                (Some(false), Support::Supported) => self.unadvertised.push(method.clone()),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Whether an error means the method isn't supported, or it failed otherwise.
+pub fn classify(e: &impl RpcError) -> Support {
+    match e.as_error_response() {
+        Some(error) if error.code == METHOD_NOT_FOUND => Support::Unsupported,
+        Some(error) => {
+            let message = error.message.to_lowercase();
+            if UNSUPPORTED_MESSAGES.iter().any(|m| message.contains(m)) {
+                Support::Unsupported
+            } else {
+                Support::Failed
+            }
+        }
+        None => Support::Failed,
+    }
+}
+
+/// Call `method`, classifying the outcome.
+async fn probe(
+    client: &MeasuredJsonRpc,
+    method: &RpcMethod,
+    params: Value,
+) -> (MethodSupport, Value) {
+    let start = Instant::now();
+    let res = client.request::<_, Value>(method.as_str(), params).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    match res {
+        Ok(result) => (
+            MethodSupport {
+                support: Support::Supported,
+                latency_ms,
+                error: None,
+            },
+            result,
+        ),
+        Err(e) => {
+            let support = classify(&e);
+            let error = e
+                .as_error_response()
+                .map(|error| format!("{}: {}", error.code, error.message))
+                .unwrap_or_else(|| e.to_string());
+            (
+                MethodSupport {
+                    support,
+                    latency_ms,
+                    error: Some(error),
+                },
+                Value::Null,
+            )
+        }
+    }
+}
+
+async fn report(chain: &ChainConfig, url: &reqwest::Url) -> CapabilityReport {
+    let registry = Registry::new();
+    let client = match &chain.basic_auth {
+        _ if url.scheme() == "file" => {
+            MeasuredJsonRpc::new_ipc(url.to_file_path().expect("Invalid RPC_URL"), &registry)
+        }
+        Some((username, password)) => MeasuredJsonRpc::new_with_auth(
+            url.as_str(),
+            Authorization::basic(username, password),
+            &registry,
+        ),
+        None => MeasuredJsonRpc::new(url.as_str(), &registry),
+    };
+
+    let mut methods = BTreeMap::new();
+    for (method, variant, params) in probes() {
+        let (support, _) = probe(&client, &method, params).await;
+        let name = match variant {
+            Some(variant) => format!("{} ({})", method, variant),
+            None => method.to_string(),
+        };
+        methods.insert(name, support);
+    }
+
+    let (modules, result) = probe(&client, &RpcMethod::RpcModules, json!([])).await;
+    let modules = (modules.support == Support::Supported)
+        .then(|| serde_json::from_value::<BTreeMap<String, Value>>(result).ok())
+        .flatten()
+        .map(|modules| {
+            modules
+                .into_iter()
+                .map(|(name, version)| (name, version.as_str().unwrap_or_default().to_string()))
+                .collect()
+        });
+
+    let (discover, result) = probe(&client, &RpcMethod::RpcDiscover, json!([])).await;
+    let discovered = (discover.support == Support::Supported)
+        .then(|| result["methods"].as_array().cloned())
+        .flatten()
+        .map(|methods| {
+            methods
+                .iter()
+                .filter_map(|method| method["name"].as_str().map(str::to_string))
+                .collect()
+        });
+
+    let mut report = CapabilityReport {
+        chain: chain.name.clone(),
+        rpc: chain::rpc_label(url).to_string(),
+        methods,
+        modules,
+        discovered,
+        advertised_but_broken: Vec::new(),
+        unadvertised: Vec::new(),
+    };
+    report.cross_check();
+    report
+}
+
+fn log_report(report: &CapabilityReport) {
+    let supported = report
+        .methods
+        .values()
+        .filter(|method| method.support == Support::Supported)
+        .count();
+    log::info!(
+        "[🧭][{}] {} supports {}/{} probed methods, advertises {}",
+        report.chain,
+        report.rpc,
+        supported,
+        report.methods.len(),
+        match (&report.modules, &report.discovered) {
+            (None, None) => "nothing".to_string(),
+            (modules, discovered) => format!(
+                "{} modules and {} methods",
+                modules.as_ref().map_or(0, BTreeMap::len),
+                discovered.as_ref().map_or(0, BTreeSet::len)
+            ),
+        }
+    );
+    if !report.advertised_but_broken.is_empty() {
+        log::warn!(
+            "[🧭][{}] {} advertises but fails {}",
+            report.chain,
+            report.rpc,
+            report.advertised_but_broken.join(", ")
+        );
+    }
+}
+
+/// Report the capabilities of every configured EVM endpoint.
+pub async fn run(chains: &[ChainConfig]) -> Vec<CapabilityReport> {
+    let mut reports = Vec::new();
+
+    for chain in chains {
+        if chain.kind != ChainKind::Evm {
+            log::warn!(
+                "[{}] Capability reports only support EVM chains, skipping",
+                chain.name
+            );
+            continue;
+        }
+
+        for url in &chain.rpc_urls {
+            let report = report(chain, url).await;
+            log_report(&report);
+            reports.push(report);
+        }
+    }
+
+    reports
+}
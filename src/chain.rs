@@ -0,0 +1,291 @@
+//! Chain configuration.
+//!
+//! A single BenchETH process can monitor several independent chains at once. Each chain has its
+//! own set of RPC providers, an optional expected chain id and its own polling settings.
+//!
+//! Chains are configured via environment variables:
+//!
+//! ```text
+//! CHAINS="ethereum,polygon"
+//! ETHEREUM_RPC_URLS="https://eth-a.example,https://eth-b.example"
+//! ETHEREUM_CHAIN_ID=1
+//! ETHEREUM_POLL_INTERVAL_MS=500
+//! ETHEREUM_EXPECTED_BLOCK_TIME_MS=12000
+//! POLYGON_RPC_URLS="https://polygon.example"
+//! POLYGON_CHAIN_ID=137
+//! POLYGON_EXPECTED_BLOCK_TIME_MS=2000
+//! ```
+//!
+//! `<CHAIN>_KIND` selects the RPC flavour of the chain (`evm` by default, `solana`, `tendermint`,
+//! `bitcoin`, `starknet` or `http`). `<CHAIN>_RPC_USERNAME` and `<CHAIN>_RPC_PASSWORD` enable HTTP
+//! basic authentication, as required by Bitcoin Core. `<CHAIN>_REFERENCE_URLS` lists independent
+//! endpoints used to estimate the canonical head.
+//!
+//! Every provider is labelled with an `endpoint` name, which is its host unless several providers
+//! share a host, in which case they are numbered, e.g. `lb.example-1` and `lb.example-2`.
+//! `<CHAIN>_ENDPOINT_NAMES` (`ENDPOINT_NAMES` for the single chain setup) names them instead, in
+//! the order of the RPC URLs.
+//!
+//! With several chains, `<CHAIN>_METRICS_PORT` serves the metrics of a chain on a port of its
+//! own as well, for scrape configs with a job per chain.
+//!
+//! Besides URLs, the RPC URLs of a chain can be paths to the IPC socket of a local node, e.g.
+//! `/var/lib/geth/geth.ipc`, which are kept as `file://` URLs.
+//!
+//! When `CHAINS` is not set, a single chain is built from `RPC_URL` (and the optional
+//! `CHAIN_NAME`, `CHAIN_KIND`, `CHAIN_ID`, `POLL_INTERVAL_MS` and `EXPECTED_BLOCK_TIME_MS`) so
+//! existing deployments keep working.
+
+use reqwest::Url;
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_CHAIN_NAME: &str = "ethereum";
+const DEFAULT_POLL_INTERVAL_MS: u64 = 500;
+const DEFAULT_EXPECTED_BLOCK_TIME_MS: u64 = 12_000;
+
+/// Parse a comma-separated list of URLs or absolute paths of IPC sockets.
+fn parse_urls(var: &str, urls: &str) -> Vec<Url> {
+    urls.split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            let path = Path::new(url);
+            if path.is_absolute() {
+                Url::from_file_path(path)
+            } else {
+                Url::parse(url).map_err(|_| ())
+            }
+            .unwrap_or_else(|_| panic!("Invalid {}", var))
+        })
+        .collect()
+}
+
+/// Name providers after their host, numbering the ones sharing a host.
+fn endpoint_names(urls: &[Url]) -> Vec<String> {
+    let labels: Vec<&str> = urls.iter().map(rpc_label).collect();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    labels
+        .iter()
+        .map(|label| {
+            if labels.iter().filter(|other| *other == label).count() == 1 {
+                return label.to_string();
+            }
+            let n = seen.entry(label).or_default();
+            *n += 1;
+            format!("{}-{}", label, n)
+        })
+        .collect()
+}
+
+/// The `rpc` label of a provider: the host of its URL, or the file name of its IPC socket.
+pub fn rpc_label(url: &Url) -> &str {
+    match url.host_str() {
+        Some(host) => host,
+        None => url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or("ipc"),
+    }
+}
+
+/// The RPC flavour spoken by the providers of a chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChainKind {
+    #[default]
+    Evm,
+    Solana,
+    Tendermint,
+    Bitcoin,
+    Starknet,
+    Http,
+}
+
+impl FromStr for ChainKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "evm" => Ok(ChainKind::Evm),
+            "solana" => Ok(ChainKind::Solana),
+            "tendermint" | "cometbft" | "cosmos" => Ok(ChainKind::Tendermint),
+            "bitcoin" => Ok(ChainKind::Bitcoin),
+            "starknet" => Ok(ChainKind::Starknet),
+            "http" | "rest" => Ok(ChainKind::Http),
+            _ => Err(format!("unknown chain kind {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChainConfig {
+    /// Name of the chain, used as the `chain` label on all metrics.
+    pub name: String,
+    /// RPC flavour of the chain.
+    pub kind: ChainKind,
+    /// Expected chain id. When set, every provider is checked against it on startup.
+    pub chain_id: Option<u64>,
+    /// RPC URLs of the providers to monitor for this chain.
+    pub rpc_urls: Vec<Url>,
+    /// Names of the providers in the order of `rpc_urls`, used as the `endpoint` label.
+    pub endpoints: Vec<String>,
+    /// Independent endpoints used to estimate the canonical head of the chain.
+    pub reference_urls: Vec<Url>,
+    /// Endpoint the providers' head lag is measured against, e.g. the node a reseller resells.
+    pub reference_rpc_url: Option<Url>,
+    /// Username and password sent with every request using HTTP basic authentication.
+    pub basic_auth: Option<(String, String)>,
+    /// How often to poll the providers for a new block.
+    pub poll_interval: Duration,
+    /// How often the chain is expected to produce a block, used to normalize freshness.
+    pub expected_block_time: Duration,
+    /// Port serving only the chain's metrics, besides the shared metrics port.
+    pub metrics_port: Option<u16>,
+    /// Prefix of the chain's environment variables, empty for the single chain setup.
+    env_prefix: String,
+}
+
+impl ChainConfig {
+    /// Load all chain configurations from the environment.
+    pub fn from_env() -> Vec<ChainConfig> {
+        match env::var("CHAINS") {
+            Ok(chains) => chains
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    let prefix = format!("{}_", name.to_uppercase().replace('-', "_"));
+                    ChainConfig::from_env_prefixed(name, &prefix, "RPC_URLS")
+                })
+                .collect(),
+            Err(_) => {
+                let name =
+                    env::var("CHAIN_NAME").unwrap_or_else(|_| DEFAULT_CHAIN_NAME.to_string());
+                vec![ChainConfig::from_env_prefixed(&name, "", "RPC_URL")]
+            }
+        }
+    }
+
+    fn from_env_prefixed(name: &str, prefix: &str, urls_var: &str) -> ChainConfig {
+        let urls_var = format!("{}{}", prefix, urls_var);
+        let rpc_urls = parse_urls(
+            &urls_var,
+            &env::var(&urls_var).unwrap_or_else(|_| panic!("Invalid {}", urls_var)),
+        );
+
+        if rpc_urls.is_empty() {
+            panic!("Invalid {}: no RPC URLs configured", urls_var);
+        }
+
+        let reference_urls_var = format!("{}REFERENCE_URLS", prefix);
+        let reference_urls = env::var(&reference_urls_var)
+            .map(|urls| parse_urls(&reference_urls_var, &urls))
+            .unwrap_or_default();
+        let reference_rpc_url_var = format!("{}REFERENCE_RPC_URL", prefix);
+        let reference_rpc_url = env::var(&reference_rpc_url_var)
+            .ok()
+            .and_then(|url| parse_urls(&reference_rpc_url_var, &url).pop());
+
+        let basic_auth = env::var(format!("{}RPC_USERNAME", prefix))
+            .ok()
+            .map(|username| {
+                let password = env::var(format!("{}RPC_PASSWORD", prefix)).unwrap_or_default();
+                (username, password)
+            });
+
+        // `KIND` alone is too generic for the un-prefixed single chain setup
+        let kind_var = match prefix {
+            "" => "CHAIN_KIND".to_string(),
+            prefix => format!("{}KIND", prefix),
+        };
+        let kind = env::var(kind_var)
+            .ok()
+            .map(|kind| kind.parse::<ChainKind>().expect("Invalid KIND"))
+            .unwrap_or_default();
+
+        let endpoints_var = format!("{}ENDPOINT_NAMES", prefix);
+        let endpoints = match env::var(&endpoints_var) {
+            Ok(names) => {
+                let names: Vec<String> = names.split(',').map(|name| name.trim().into()).collect();
+                if names.len() != rpc_urls.len() || names.iter().any(String::is_empty) {
+                    panic!("Invalid {}: must name every RPC URL", endpoints_var);
+                }
+                names
+            }
+            Err(_) => endpoint_names(&rpc_urls),
+        };
+
+        if matches!(kind, ChainKind::Bitcoin | ChainKind::Http)
+            && rpc_urls.iter().any(|url| url.scheme() == "file")
+        {
+            panic!("Invalid {}: IPC sockets require a JSON-RPC chain", urls_var);
+        }
+        if matches!(kind, ChainKind::Bitcoin | ChainKind::Http) {
+            if !reference_urls.is_empty() {
+                panic!(
+                    "Invalid {}: reference endpoints are not supported for {:?} chains",
+                    reference_urls_var, kind
+                );
+            }
+            if reference_rpc_url.is_some() {
+                panic!(
+                    "Invalid {}: reference endpoints are not supported for {:?} chains",
+                    reference_rpc_url_var, kind
+                );
+            }
+        }
+
+        let chain_id = env::var(format!("{}CHAIN_ID", prefix))
+            .ok()
+            .map(|id| id.parse::<u64>().expect("Invalid CHAIN_ID"));
+
+        let poll_interval = env::var(format!("{}POLL_INTERVAL_MS", prefix))
+            .ok()
+            .map(|ms| ms.parse::<u64>().expect("Invalid POLL_INTERVAL_MS"))
+            .unwrap_or(DEFAULT_POLL_INTERVAL_MS);
+        if poll_interval == 0 {
+            panic!("Invalid POLL_INTERVAL_MS: must be positive");
+        }
+
+        let expected_block_time = env::var(format!("{}EXPECTED_BLOCK_TIME_MS", prefix))
+            .ok()
+            .map(|ms| ms.parse::<u64>().expect("Invalid EXPECTED_BLOCK_TIME_MS"))
+            .unwrap_or(DEFAULT_EXPECTED_BLOCK_TIME_MS);
+
+        if expected_block_time == 0 {
+            panic!("Invalid EXPECTED_BLOCK_TIME_MS: must be greater than zero");
+        }
+
+        // the un-prefixed `METRICS_PORT` is the shared metrics port
+        let metrics_port = match prefix {
+            "" => None,
+            prefix => env::var(format!("{}METRICS_PORT", prefix))
+                .ok()
+                .map(|port| port.parse::<u16>().expect("Invalid METRICS_PORT")),
+        };
+
+        ChainConfig {
+            name: name.to_string(),
+            kind,
+            chain_id,
+            rpc_urls,
+            endpoints,
+            reference_urls,
+            reference_rpc_url,
+            basic_auth,
+            poll_interval: Duration::from_millis(poll_interval),
+            expected_block_time: Duration::from_millis(expected_block_time),
+            metrics_port,
+            env_prefix: prefix.to_string(),
+        }
+    }
+
+    /// Read a chain specific environment variable, e.g. `var("STARKNET_CALL_CONTRACT")` reads
+    /// `<CHAIN>_STARKNET_CALL_CONTRACT`.
+    pub fn var(&self, name: &str) -> Option<String> {
+        env::var(format!("{}{}", self.env_prefix, name)).ok()
+    }
+}
@@ -0,0 +1,228 @@
+//! Command line interface.
+//!
+//! BenchETH is configured through environment variables, which suit container deployments, or a
+//! configuration file standing for them, see [`config`](crate::config). The most common settings
+//! can be passed as flags as well, which take precedence over the environment variables they
+//! stand for, e.g. `--rpc-url` over `RPC_URL`, and the configuration file. Everything else is only
+//! configured through the environment, a `.env` file in the working directory or the
+//! configuration file.
+//!
+//! Without a subcommand, BenchETH runs the monitor like `bencheth monitor`.
+
+use bencheth::assertion::Assertion;
+use bencheth::config;
+use bencheth::load::Mix;
+use bencheth::report::Summary;
+
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
+
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "bencheth",
+    version,
+    about = "Benchmarks blockchain RPC providers"
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub options: Options,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Flags standing for environment variables.
+#[derive(Debug, Args)]
+pub struct Options {
+    /// TOML or YAML configuration file, overriding the environment
+    #[arg(long, global = true, env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+    /// RPC URLs to measure, comma-separated, or the IPC socket of a local node (RPC_URL)
+    #[arg(long, global = true)]
+    rpc_url: Option<String>,
+    /// Time between polls for new blocks, in milliseconds (POLL_INTERVAL_MS)
+    #[arg(long, global = true)]
+    poll_interval_ms: Option<u64>,
+    /// Port the metrics and healthcheck are served on (METRICS_PORT)
+    #[arg(long, global = true)]
+    metrics_port: Option<u16>,
+    /// Requests in flight at most, across all providers (MAX_IN_FLIGHT)
+    #[arg(long, visible_alias = "concurrency", global = true)]
+    max_in_flight: Option<usize>,
+}
+
+impl Options {
+    /// Load the configuration file and set the environment variables of the flags passed, so the
+    /// configuration read from the environment sees them.
+    pub fn apply(&self) {
+        if let Some(path) = &self.config {
+            config::load(path);
+        }
+        let vars = [
+            ("RPC_URL", self.rpc_url.clone()),
+            (
+                "POLL_INTERVAL_MS",
+                self.poll_interval_ms.map(|ms| ms.to_string()),
+            ),
+            (
+                "METRICS_PORT",
+                self.metrics_port.map(|port| port.to_string()),
+            ),
+            ("MAX_IN_FLIGHT", self.max_in_flight.map(|n| n.to_string())),
+        ];
+        for (var, value) in vars {
+            if let Some(value) = value {
+                env::set_var(var, value);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Measure the configured providers until stopped
+    Monitor,
+    /// Measure the configured providers for a while, then print the run report
+    Bench {
+        /// How long to measure, e.g. `300`, `300s`, `5m` or `1h`
+        #[arg(long, default_value = "5m", value_parser = parse_duration)]
+        duration: Duration,
+        /// Send requests at this rate to every provider instead of following the chain head,
+        /// then print the load report
+        #[arg(long)]
+        rps: Option<f64>,
+        /// Methods sent with `--rps` and their weights, e.g. `eth_blockNumber=4,eth_getBalance=1`
+        #[arg(long, default_value = "eth_blockNumber")]
+        mix: Mix,
+        /// TOML or YAML scenario file of the calls sent with `--rps`, instead of `--mix`
+        #[arg(long, conflicts_with = "mix")]
+        scenario: Option<PathBuf>,
+        /// Exit with an error unless every provider passes the check, e.g. `p95_latency<500ms`
+        /// or `error_rate<1%`. Can be repeated
+        #[arg(long = "assert", value_name = "CHECK")]
+        assertions: Vec<Assertion>,
+    },
+    /// Test the differences between the providers of one run report, or between two runs
+    Compare {
+        #[arg(value_name = "REPORT")]
+        a: String,
+        #[arg(value_name = "REPORT")]
+        b: Option<String>,
+    },
+    /// Print a summary of a run report
+    Report {
+        #[arg(value_name = "REPORT")]
+        path: String,
+    },
+    /// Query the healthcheck of the agent on this host
+    Healthcheck,
+    /// Measure BenchETH's own overhead against an in-process mock server
+    Calibrate,
+    /// Compare two endpoint configurations under identical conditions
+    Experiment,
+    /// Print the method support matrix of every EVM RPC URL
+    Capabilities,
+    /// Simulate client-side caching of head data
+    Cache,
+    /// Compare HTTP/2 multiplexing with HTTP/1.1 connections
+    Multiplex,
+    /// Benchmark an eth_call against every EVM RPC URL
+    Call,
+    /// Compare the responses of every EVM RPC URL to edge-case parameters
+    Fuzz,
+    /// Send JSON-RPC requests typed interactively through the measured transport
+    Repl,
+    /// Register BenchETH as a Windows service
+    #[cfg(windows)]
+    InstallService,
+    /// Remove the Windows service
+    #[cfg(windows)]
+    UninstallService,
+    /// Run as a Windows service, as started by the Service Control Manager
+    #[cfg(windows)]
+    #[command(hide = true)]
+    Service,
+}
+
+impl Command {
+    /// How long to measure before exiting, `None` to run until stopped.
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            Command::Bench { duration, .. } => Some(*duration),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a duration in seconds, or with an `s`, `m` or `h` suffix.
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, unit) = match duration.trim().find(|c: char| !c.is_ascii_digit()) {
+        Some(position) => duration.trim().split_at(position),
+        None => (duration.trim(), "s"),
+    };
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration {}", duration))?;
+    match unit {
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 60 * 60)),
+        _ => Err(format!("invalid duration {}, expected s, m or h", duration)),
+    }
+}
+
+/// The parts of a run report needed to summarize it.
+#[derive(Debug, Deserialize)]
+struct StoredReport {
+    geo: String,
+    providers: Vec<StoredProvider>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredProvider {
+    chain: String,
+    rpc: String,
+    propagation_delay_seconds: StoredRun,
+    request_latency_seconds: Option<Summary>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredRun {
+    run: Option<Summary>,
+}
+
+/// Summarize the run report at `path`, a line per provider.
+pub fn summarize(path: &str) -> String {
+    let json = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read report {}: {}", path, e));
+    let report: StoredReport =
+        serde_json::from_str(&json).unwrap_or_else(|e| panic!("Invalid report {}: {}", path, e));
+
+    let ms = |summary: Option<&Summary>, quantile: fn(&Summary) -> f64| {
+        summary
+            .map(|summary| format!("{:.0}ms", quantile(summary) * 1000.0))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let mut lines = vec![format!(
+        "{:<16} {:<32} {:>12} {:>12} {:>12} {:>12}",
+        "chain", "rpc", "delay p50", "delay p99", "latency p50", "latency p99"
+    )];
+    for provider in &report.providers {
+        let delay = provider.propagation_delay_seconds.run.as_ref();
+        let latency = provider.request_latency_seconds.as_ref();
+        lines.push(format!(
+            "{:<16} {:<32} {:>12} {:>12} {:>12} {:>12}",
+            provider.chain,
+            provider.rpc,
+            ms(delay, |s| s.p50),
+            ms(delay, |s| s.p99),
+            ms(latency, |s| s.p50),
+            ms(latency, |s| s.p99),
+        ));
+    }
+    lines.push(format!("measured from {}", report.geo));
+    lines.join("\n")
+}
@@ -0,0 +1,281 @@
+//! Comparison of providers and runs from run reports.
+//!
+//! A raw difference of two percentiles is mostly noise when the samples are few, and reporting it
+//! as a regression sends people chasing ghosts. `bencheth compare` tests the differences instead:
+//!
+//! - `bencheth compare <report.json>` compares every pair of providers of a chain in one run.
+//! - `bencheth compare <a.json> <b.json>` compares every provider present in both runs, B minus A.
+//!
+//! For the propagation delay and the detection interval, it prints the raw p50, p90 and p99
+//! differences and, when the reports were written with `REPORT_SAMPLES=true`, a Mann-Whitney U
+//! test and bootstrap confidence intervals at `COMPARE_CONFIDENCE` (default 0.95) of those
+//! differences. For the request latency, over all methods, it prints the raw differences of the
+//! percentiles estimated from the reports' sketches, which keep no samples to test.
+
+use crate::report::Summary;
+use crate::sketch::DDSketch;
+use crate::stats::{self, Delta, MannWhitney};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::env;
+
+const DEFAULT_COMPARE_CONFIDENCE: f64 = 0.95;
+
+const REQUEST_LATENCY: &str = "request_latency_seconds";
+
+/// The parts of a run report needed to compare it.
+#[derive(Debug, Deserialize)]
+struct StoredReport {
+    providers: Vec<StoredProvider>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredProvider {
+    chain: String,
+    rpc: String,
+    propagation_delay_seconds: StoredSamples,
+    detection_interval_seconds: StoredSamples,
+    #[serde(default)]
+    request_latency_sketches: BTreeMap<String, DDSketch>,
+}
+
+impl StoredProvider {
+    /// Request latency over all methods, from the sketches.
+    fn request_latency(&self) -> StoredSamples {
+        let mut all = DDSketch::default();
+        self.request_latency_sketches
+            .values()
+            .for_each(|sketch| all.merge(sketch));
+        StoredSamples {
+            run: Summary::from_sketch(&all),
+            samples: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredSamples {
+    run: Option<Summary>,
+    #[serde(default)]
+    samples: Option<Vec<f64>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Side {
+    pub report: String,
+    pub rpc: String,
+    pub run: Option<Summary>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Comparison {
+    pub chain: String,
+    pub metric: &'static str,
+    pub a: Side,
+    pub b: Side,
+    /// Differences of the run percentiles, B minus A, without any test.
+    pub raw_delta: BTreeMap<String, f64>,
+    /// Tested differences, B minus A, when both reports include samples.
+    pub delta: BTreeMap<String, Delta>,
+    pub mann_whitney: Option<MannWhitney>,
+    /// Whether the Mann-Whitney test rejects equal distributions at the configured confidence.
+    pub significant: Option<bool>,
+}
+
+fn load(path: &str) -> StoredReport {
+    let json = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read report {}: {}", path, e));
+    serde_json::from_str(&json).unwrap_or_else(|e| panic!("Invalid report {}: {}", path, e))
+}
+
+fn compare(
+    (report_a, a): (&str, &StoredProvider),
+    (report_b, b): (&str, &StoredProvider),
+    confidence: f64,
+) -> Vec<Comparison> {
+    let metrics = [
+        (
+            "propagation_delay_seconds",
+            &a.propagation_delay_seconds,
+            &b.propagation_delay_seconds,
+        ),
+        (
+            "detection_interval_seconds",
+            &a.detection_interval_seconds,
+            &b.detection_interval_seconds,
+        ),
+        (REQUEST_LATENCY, &a.request_latency(), &b.request_latency()),
+    ];
+
+    metrics
+        .into_iter()
+        .map(|(metric, samples_a, samples_b)| {
+            let raw_delta = match (&samples_a.run, &samples_b.run) {
+                (Some(a), Some(b)) => BTreeMap::from([
+                    ("p50".to_string(), b.p50 - a.p50),
+                    ("p90".to_string(), b.p90 - a.p90),
+                    ("p99".to_string(), b.p99 - a.p99),
+                ]),
+                _ => BTreeMap::new(),
+            };
+            let (delta, mann_whitney) = match (&samples_a.samples, &samples_b.samples) {
+                (Some(a), Some(b)) => (
+                    stats::quantile_deltas(a, b, confidence),
+                    stats::mann_whitney(a, b),
+                ),
+                _ => (BTreeMap::new(), None),
+            };
+            let significant = mann_whitney
+                .as_ref()
+                .map(|test| test.p_value < 1.0 - confidence);
+
+            Comparison {
+                chain: a.chain.clone(),
+                metric,
+                a: Side {
+                    report: report_a.to_string(),
+                    rpc: a.rpc.clone(),
+                    run: samples_a.run.clone(),
+                },
+                b: Side {
+                    report: report_b.to_string(),
+                    rpc: b.rpc.clone(),
+                    run: samples_b.run.clone(),
+                },
+                raw_delta,
+                delta,
+                mann_whitney,
+                significant,
+            }
+        })
+        .collect()
+}
+
+/// Compare the providers of the report at `a`, or the providers of `a` with those of `b`.
+pub fn run(a: &str, b: Option<&str>) -> Vec<Comparison> {
+    let confidence = env::var("COMPARE_CONFIDENCE")
+        .ok()
+        .map(|c| c.parse::<f64>().expect("Invalid COMPARE_CONFIDENCE"))
+        .unwrap_or(DEFAULT_COMPARE_CONFIDENCE);
+    if !(0.0..1.0).contains(&confidence) {
+        panic!("Invalid COMPARE_CONFIDENCE: must be between 0 and 1");
+    }
+
+    let report_a = load(a);
+    let mut comparisons = Vec::new();
+    match b {
+        Some(b) => {
+            let report_b = load(b);
+            for provider_a in &report_a.providers {
+                let provider_b = report_b
+                    .providers
+                    .iter()
+                    .find(|p| p.chain == provider_a.chain && p.rpc == provider_a.rpc);
+                match provider_b {
+                    Some(provider_b) => {
+                        comparisons.extend(compare((a, provider_a), (b, provider_b), confidence))
+                    }
+                    None => log::warn!(
+                        "[⚖️] {} {} is missing from {}, skipping",
+                        provider_a.chain,
+                        provider_a.rpc,
+                        b
+                    ),
+                }
+            }
+        }
+        None => {
+            for (i, provider_a) in report_a.providers.iter().enumerate() {
+                for provider_b in &report_a.providers[i + 1..] {
+                    if provider_a.chain == provider_b.chain {
+                        comparisons.extend(compare((a, provider_a), (a, provider_b), confidence));
+                    }
+                }
+            }
+        }
+    }
+
+    if comparisons
+        .iter()
+        .any(|c| c.metric != REQUEST_LATENCY && c.mann_whitney.is_none())
+    {
+        log::warn!("[⚖️] Reports without samples are compared without significance tests, write them with REPORT_SAMPLES=true");
+    }
+    for comparison in &comparisons {
+        log_comparison(comparison);
+    }
+    comparisons
+}
+
+fn log_comparison(comparison: &Comparison) {
+    let verdict = match comparison.significant {
+        Some(true) => "significant",
+        Some(false) => "not significant",
+        None => "untested",
+    };
+    log::info!(
+        "[⚖️][{}] {}: {} vs {}, p50 {:+.3}s, {}",
+        comparison.chain,
+        comparison.metric,
+        comparison.a.rpc,
+        comparison.b.rpc,
+        comparison.raw_delta.get("p50").copied().unwrap_or(f64::NAN),
+        verdict
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(rpc: &str, latencies: &[f64]) -> StoredProvider {
+        let mut sketch = DDSketch::default();
+        latencies.iter().for_each(|latency| sketch.add(*latency));
+        StoredProvider {
+            chain: "ethereum".to_string(),
+            rpc: rpc.to_string(),
+            propagation_delay_seconds: StoredSamples {
+                run: None,
+                samples: None,
+            },
+            detection_interval_seconds: StoredSamples {
+                run: None,
+                samples: None,
+            },
+            request_latency_sketches: BTreeMap::from([("eth_blockNumber".to_string(), sketch)]),
+        }
+    }
+
+    #[test]
+    fn request_latency_deltas() {
+        let a = provider("a", &[0.1; 100]);
+        let b = provider("b", &[0.3; 100]);
+        let comparisons = compare(("a.json", &a), ("b.json", &b), 0.95);
+        let latency = comparisons
+            .iter()
+            .find(|c| c.metric == REQUEST_LATENCY)
+            .unwrap();
+
+        for quantile in ["p50", "p90", "p99"] {
+            let delta = latency.raw_delta[quantile];
+            assert!((delta - 0.2).abs() < 0.01, "{}: {}", quantile, delta);
+        }
+        assert!(latency.mann_whitney.is_none());
+    }
+
+    #[test]
+    fn request_latency_without_sketches() {
+        let a = provider("a", &[]);
+        let b = provider("b", &[0.3]);
+        let comparisons = compare(("a.json", &a), ("b.json", &b), 0.95);
+        let latency = comparisons
+            .iter()
+            .find(|c| c.metric == REQUEST_LATENCY)
+            .unwrap();
+
+        assert!(latency.a.run.is_none());
+        assert!(latency.raw_delta.is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+//! Data completeness accounting.
+//!
+//! Indexers care less about how fast a provider is than about whether it served every block.
+//! `<CHAIN>_COMPLETENESS_WINDOWS` lists rolling windows in blocks, e.g. `100,1000`, over which
+//! the fraction of canonical blocks observed from an EVM provider is exported as
+//! `completeness_ratio`, labelled with the `window` and the `level` of the observation:
+//!
+//! - `header`: the block was fetched, by the monitor or the [backfill](crate::backfill) worker.
+//! - `body`: its transaction list was consistent with its header, i.e. empty exactly when the
+//!   transactions root is the root of an empty trie.
+//! - `transactions`: every one of its transactions was found by hash. Blocks whose transactions
+//!   weren't fetched, e.g. outside of `<CHAIN>_TRANSACTIONS_SCHEDULE`, count as unobserved.
+//!
+//! Canonical blocks are numbered up to the highest head seen across the chain's providers.
+//! Windows end `<CHAIN>_COMPLETENESS_DELAY` blocks (default 10) behind it, so blocks still being
+//! processed or backfilled aren't counted as missing yet, and start at the first block observed
+//! at the earliest, so blocks from before BenchETH started don't count either.
+
+use crate::block_metrics::ChainHead;
+use crate::chain::ChainConfig;
+
+use ethers::types::{Block, H256};
+use ethers::utils::{keccak256, rlp};
+use prometheus::{GaugeVec, Opts, Registry};
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+const DEFAULT_DELAY: u64 = 10;
+
+const HEADER: u8 = 1;
+const BODY: u8 = 2;
+const TRANSACTIONS: u8 = 4;
+
+const LEVELS: [(&str, u8); 3] = [
+    ("header", HEADER),
+    ("body", BODY),
+    ("transactions", TRANSACTIONS),
+];
+
+pub struct Completeness {
+    chain_head: ChainHead,
+    /// Windows, in blocks.
+    windows: Vec<u64>,
+    delay: u64,
+    empty_root: H256,
+    /// The first block observed, and the levels observed by block number.
+    observed: Mutex<(Option<u64>, BTreeMap<u64, u8>)>,
+    ratio: GaugeVec,
+}
+
+impl Completeness {
+    /// The completeness of a provider of `chain`, reporting to its `registry`. `None` unless
+    /// `<CHAIN>_COMPLETENESS_WINDOWS` is set.
+    pub fn from_chain(
+        chain: &ChainConfig,
+        chain_head: ChainHead,
+        registry: &Registry,
+    ) -> Option<Self> {
+        let windows = chain
+            .var("COMPLETENESS_WINDOWS")?
+            .split(',')
+            .map(str::trim)
+            .filter(|window| !window.is_empty())
+            .map(|window| match window.parse::<u64>() {
+                Ok(window) if window > 0 => window,
+                _ => panic!(
+                    "Invalid COMPLETENESS_WINDOWS: {} must be a number of blocks",
+                    window
+                ),
+            })
+            .collect();
+        let delay = chain
+            .var("COMPLETENESS_DELAY")
+            .map(|delay| delay.parse().expect("Invalid COMPLETENESS_DELAY"))
+            .unwrap_or(DEFAULT_DELAY);
+
+        let ratio = GaugeVec::new(
+            Opts::new(
+                "completeness_ratio",
+                "Fraction of the canonical blocks of a rolling window observed from the provider",
+            ),
+            &["window", "level"],
+        )
+        .unwrap();
+        registry.register(Box::new(ratio.clone())).unwrap();
+
+        Some(Self {
+            chain_head,
+            windows,
+            delay,
+            empty_root: H256(keccak256(rlp::NULL_RLP)),
+            observed: Mutex::new((None, BTreeMap::new())),
+            ratio,
+        })
+    }
+
+    fn observe(&self, number: u64, levels: u8) {
+        let (first, observed) = &mut *self.observed.lock().unwrap();
+        first.get_or_insert(number);
+        *observed.entry(number).or_default() |= levels;
+    }
+
+    /// Record a fetched block, with its body if consistent with the header.
+    pub fn observe_block<T>(&self, block: &Block<T>) {
+        let Some(number) = block.number else {
+            return;
+        };
+        let empty = block.transactions_root == self.empty_root;
+        let levels = match block.transactions.is_empty() == empty {
+            true => HEADER | BODY,
+            false => HEADER,
+        };
+        self.observe(number.as_u64(), levels);
+    }
+
+    /// Record that every transaction of block `number` was found.
+    pub fn observe_transactions(&self, number: u64) {
+        self.observe(number, TRANSACTIONS);
+    }
+
+    /// Update the ratios of every window, dropping observations older than all of them.
+    pub fn update(&self) {
+        let end = self.chain_head.highest().saturating_sub(self.delay);
+        let longest = self.windows.iter().max().copied().unwrap_or(1);
+        let (first, observed) = &mut *self.observed.lock().unwrap();
+        let Some(first) = *first else {
+            return;
+        };
+        *observed = observed.split_off(&end.saturating_sub(longest - 1));
+
+        for &window in &self.windows {
+            let start = end.saturating_sub(window - 1).max(first);
+            if start > end {
+                continue;
+            }
+            let blocks = end - start + 1;
+            for (level, flag) in LEVELS {
+                let count = observed
+                    .range(start..=end)
+                    .filter(|(_, levels)| *levels & flag != 0)
+                    .count();
+                self.ratio
+                    .with_label_values(&[&window.to_string(), level])
+                    .set(count as f64 / blocks as f64);
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Configuration files.
+//!
+//! Setups with many chains and providers take dozens of environment variables. They can be kept
+//! in a TOML or YAML file instead, loaded with `--config <path>` (or `CONFIG_FILE`), whose keys
+//! stand for the environment variables of the same name, lower-cased:
+//!
+//! ```toml
+//! metrics_port = 9090
+//! report_path = "report.json"
+//!
+//! [labels]
+//! team = "infra"
+//!
+//! [retry]
+//! rate_limit_retries = 5
+//! initial_backoff_ms = 250
+//!
+//! [chains.ethereum]
+//! rpc_urls = ["https://eth-a.example", "https://eth-b.example"]
+//! endpoint_names = ["a", "b"]
+//! poll_interval_ms = 500
+//! call_templates = ["transfer"]
+//! call.transfer.methods = ["eth_call", "eth_estimateGas"]
+//! call.transfer.tx = '{"from": "0x28C6c06298d514Db089934071355E5743bf21d60", "to": "0xdAC17F958D2ee523a2206206994597C13D831ec7"}'
+//! ```
+//!
+//! Nested tables join their keys with `_`, e.g. `retry.rate_limit_retries` sets
+//! `RETRY_RATE_LIMIT_RETRIES`, and lists are joined with commas. Two tables are special:
+//!
+//! - `chains` sets `CHAINS` to its keys and prefixes the variables of every chain with its name,
+//!   e.g. `ETHEREUM_RPC_URLS`. `tenants` does the same for `TENANTS`.
+//! - `labels`, at any level, becomes `key=value` pairs, e.g. `LABELS="team=infra"` or
+//!   `<TENANT>_LABELS`.
+//!
+//! The file takes precedence over the environment and `.env`, and command line flags over the
+//! file.
+
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+
+/// Tables whose keys name the entities configured by their prefixed variables.
+const PREFIX_TABLES: [&str; 2] = ["chains", "tenants"];
+
+/// Load the configuration file at `path` into the environment.
+pub fn load(path: &Path) {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read config {}: {}", path.display(), e));
+    let config: Value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Invalid config {}: {}", path.display(), e)),
+        _ => toml::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Invalid config {}: {}", path.display(), e)),
+    };
+
+    for (var, value) in vars(&config) {
+        env::set_var(var, value);
+    }
+}
+
+/// The environment variables set by `config`.
+fn vars(config: &Value) -> BTreeMap<String, String> {
+    let Value::Object(table) = config else {
+        panic!("Invalid config: expected a table of settings");
+    };
+
+    let mut vars = BTreeMap::new();
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            (key, Value::Object(entities)) if PREFIX_TABLES.contains(&key) => {
+                let names = entities.keys().cloned().collect::<Vec<_>>();
+                vars.insert(key.to_uppercase(), names.join(","));
+                for (name, settings) in entities {
+                    flatten(&mut vars, &name.to_uppercase().replace('-', "_"), settings);
+                }
+            }
+            (key, value) => flatten(&mut vars, &key.to_uppercase(), value),
+        }
+    }
+    vars
+}
+
+/// Set the variable `var` to `value`, or the variables of its keys if it is a table.
+fn flatten(vars: &mut BTreeMap<String, String>, var: &str, value: &Value) {
+    match value {
+        Value::Object(labels) if var == "LABELS" || var.ends_with("_LABELS") => {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, scalar(var, value)))
+                .collect::<Vec<_>>();
+            vars.insert(var.to_string(), labels.join(","));
+        }
+        Value::Object(table) => {
+            for (key, value) in table {
+                flatten(vars, &format!("{}_{}", var, key.to_uppercase()), value);
+            }
+        }
+        Value::Array(values) => {
+            let values = values
+                .iter()
+                .map(|value| scalar(var, value))
+                .collect::<Vec<_>>();
+            vars.insert(var.to_string(), values.join(","));
+        }
+        value => {
+            vars.insert(var.to_string(), scalar(var, value));
+        }
+    }
+}
+
+fn scalar(var: &str, value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Number(value) => value.to_string(),
+        Value::Bool(value) => value.to_string(),
+        _ => panic!(
+            "Invalid config: {} must be a string, number or boolean",
+            var
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toml(config: &str) -> BTreeMap<String, String> {
+        vars(&toml::from_str(config).unwrap())
+    }
+
+    fn expected(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn flatten_tables_and_lists() {
+        let vars = toml(
+            r#"
+            metrics_port = 9090
+            report_path = "report.json"
+            log_bodies = true
+
+            [labels]
+            team = "infra"
+            region = "eu"
+
+            [retry]
+            rate_limit_retries = 5
+
+            [chains.ethereum]
+            rpc_urls = ["https://eth-a.example", "https://eth-b.example"]
+            poll_interval_ms = 500
+            call.transfer.methods = ["eth_call", "eth_estimateGas"]
+
+            [chains.base-sepolia]
+            rpc_urls = ["https://base.example"]
+            labels = { tier = "free" }
+            "#,
+        );
+        assert_eq!(
+            vars,
+            expected(&[
+                ("BASE_SEPOLIA_LABELS", "tier=free"),
+                ("BASE_SEPOLIA_RPC_URLS", "https://base.example"),
+                ("CHAINS", "base-sepolia,ethereum"),
+                ("ETHEREUM_CALL_TRANSFER_METHODS", "eth_call,eth_estimateGas"),
+                ("ETHEREUM_POLL_INTERVAL_MS", "500"),
+                (
+                    "ETHEREUM_RPC_URLS",
+                    "https://eth-a.example,https://eth-b.example"
+                ),
+                ("LABELS", "region=eu,team=infra"),
+                ("LOG_BODIES", "true"),
+                ("METRICS_PORT", "9090"),
+                ("REPORT_PATH", "report.json"),
+                ("RETRY_RATE_LIMIT_RETRIES", "5"),
+            ])
+        );
+    }
+
+    #[test]
+    fn flatten_yaml_tenants() {
+        let config = "tenants:\n  acme:\n    chains: [ethereum]\n    labels:\n      plan: pro\n";
+        assert_eq!(
+            vars(&serde_yaml::from_str(config).unwrap()),
+            expected(&[
+                ("ACME_CHAINS", "ethereum"),
+                ("ACME_LABELS", "plan=pro"),
+                ("TENANTS", "acme"),
+            ])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid config: RPC_URLS must be a string, number or boolean")]
+    fn nested_lists() {
+        toml("rpc_urls = [[\"https://eth.example\"]]");
+    }
+}
@@ -0,0 +1,279 @@
+//! Block body cross-validation between providers.
+//!
+//! A provider can be at the head and still serve a stale or partially indexed body for it, e.g.
+//! a block from before a reorg or one whose transactions aren't all indexed yet, which neither
+//! latency nor lag metrics catch. With `<CHAIN>_CROSS_VALIDATION=true` and more than one RPC URL,
+//! every new head of an EVM chain is fetched from all of its providers at once and the bodies
+//! compared pairwise: the block hash, the transaction hashes and the receipts root.
+//!
+//! Differences are counted in `cross_validation_divergences_total` by `field` and the pair of
+//! endpoints, `endpoint_a` and `endpoint_b` in the order of the RPC URLs, and validated blocks in
+//! `cross_validation_blocks_total`. Providers that don't have the block yet are left out of the
+//! comparison, their lag is measured already. Heads differing across providers for a moment are
+//! expected during reorgs, so `<CHAIN>_CROSS_VALIDATION_DEPTH` (default 0) validates the block
+//! that many blocks behind the head instead.
+//!
+//! Pairwise differences don't say who is wrong. Providers are also grouped by the block hash and
+//! transaction count they served, and every provider outside the group of the majority counts as
+//! diverged from consensus in `consensus_mismatch_total` by `diverged_endpoint`, as the
+//! cross-validation's own metrics already carry an `endpoint` label. Without a single largest
+//! group, e.g. two providers disagreeing, each of them counts as diverged.
+//!
+//! Bodies are requested outside of the measured transports, so they don't count toward the
+//! providers' latency and error metrics, but they do count toward the request limits and the
+//! providers' budgets.
+
+use crate::block_metrics::ChainHead;
+use crate::budget::Budget;
+use crate::chain::{ChainConfig, ChainKind};
+use crate::limits::{Limited, RequestLimits};
+use crate::rpc_method::RpcMethod;
+
+use ethers::providers::{Authorization, Http, JsonRpcClient};
+use ethers::types::{Block, H256, U64};
+use prometheus::{IntCounter, IntCounterVec, Opts, Registry};
+use serde_json::json;
+use tokio::time;
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+type Equal = fn(&Block<H256>, &Block<H256>) -> bool;
+
+/// The fields compared between bodies.
+const FIELDS: [(&str, Equal); 3] = [
+    ("hash", |a, b| a.hash == b.hash),
+    ("transactions", |a, b| a.transactions == b.transactions),
+    ("receiptsRoot", |a, b| a.receipts_root == b.receipts_root),
+];
+
+struct Endpoint {
+    name: String,
+    client: Limited<Http>,
+}
+
+pub struct CrossValidation {
+    chain: Arc<ChainConfig>,
+    chain_head: ChainHead,
+    depth: u64,
+    endpoints: Vec<Endpoint>,
+    blocks: IntCounter,
+    divergences: IntCounterVec,
+    mismatches: IntCounterVec,
+}
+
+impl CrossValidation {
+    /// `None` unless `<CHAIN>_CROSS_VALIDATION=true` on an EVM chain with more than one HTTP RPC
+    /// URL. Bodies are requested within the `budgets` of the providers, in the order of the RPC
+    /// URLs, and the `limits` of the chain.
+    pub fn from_chain(
+        chain: Arc<ChainConfig>,
+        chain_head: ChainHead,
+        budgets: &[Budget],
+        limits: &RequestLimits,
+        registry: &Registry,
+    ) -> Option<Self> {
+        if chain.var("CROSS_VALIDATION").as_deref() != Some("true") {
+            return None;
+        }
+        if chain.kind != ChainKind::Evm {
+            log::warn!(
+                "[{}] Cross-validation is only supported for EVM chains",
+                chain.name
+            );
+            return None;
+        }
+        let endpoints = chain
+            .rpc_urls
+            .iter()
+            .zip(&chain.endpoints)
+            .zip(budgets)
+            .filter(|((url, _), _)| url.scheme().starts_with("http"))
+            .map(|((url, name), budget)| {
+                let client = match &chain.basic_auth {
+                    Some((username, password)) => {
+                        Http::new_with_auth(url.clone(), Authorization::basic(username, password))
+                            .expect("could not initialize http")
+                    }
+                    None => Http::from_str(url.as_str()).expect("could not initialize http"),
+                };
+                Endpoint {
+                    name: name.clone(),
+                    client: Limited::new(client)
+                        .with_budget(budget.clone())
+                        .with_limits(limits.clone()),
+                }
+            })
+            .collect::<Vec<_>>();
+        if endpoints.len() < 2 {
+            log::warn!(
+                "[{}] Cross-validation needs more than one HTTP RPC URL",
+                chain.name
+            );
+            return None;
+        }
+        let depth = chain
+            .var("CROSS_VALIDATION_DEPTH")
+            .map(|depth| {
+                depth
+                    .parse::<u64>()
+                    .expect("Invalid CROSS_VALIDATION_DEPTH")
+            })
+            .unwrap_or_default();
+
+        let blocks = IntCounter::new(
+            "cross_validation_blocks_total",
+            "Number of blocks whose bodies were compared across providers",
+        )
+        .unwrap();
+        let divergences = IntCounterVec::new(
+            Opts::new(
+                "cross_validation_divergences_total",
+                "Number of blocks whose bodies differ between two providers",
+            ),
+            &["field", "endpoint_a", "endpoint_b"],
+        )
+        .unwrap();
+        let mismatches = IntCounterVec::new(
+            Opts::new(
+                "consensus_mismatch_total",
+                "Number of blocks for which the provider diverged from the majority of providers",
+            ),
+            &["diverged_endpoint"],
+        )
+        .unwrap();
+        registry.register(Box::new(blocks.clone())).unwrap();
+        registry.register(Box::new(divergences.clone())).unwrap();
+        registry.register(Box::new(mismatches.clone())).unwrap();
+
+        Some(Self {
+            chain,
+            chain_head,
+            depth,
+            endpoints,
+            blocks,
+            divergences,
+            mismatches,
+        })
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = time::interval(self.chain.poll_interval);
+        let mut validated = 0;
+
+        loop {
+            interval.tick().await;
+
+            // only the newest block when the head advanced by several
+            let height = self.chain_head.highest().saturating_sub(self.depth);
+            if height <= validated {
+                continue;
+            }
+            validated = height;
+            self.validate(height).await;
+        }
+    }
+
+    /// Fetch block `height` from every provider and compare the bodies pairwise.
+    async fn validate(&self, height: u64) {
+        let bodies = futures::future::join_all(self.endpoints.iter().map(|endpoint| {
+            // a slow provider must not hold back the next block
+            time::timeout(
+                self.chain.poll_interval * 4,
+                self.get_block(endpoint, height),
+            )
+        }))
+        .await;
+        let bodies = self
+            .endpoints
+            .iter()
+            .zip(bodies)
+            .filter_map(|(endpoint, body)| Some((endpoint, body.ok()??)))
+            .collect::<Vec<_>>();
+        if bodies.len() < 2 {
+            return;
+        }
+        self.blocks.inc();
+
+        for (i, (a, body_a)) in bodies.iter().enumerate() {
+            for (b, body_b) in &bodies[i + 1..] {
+                for (field, equal) in FIELDS {
+                    if equal(body_a, body_b) {
+                        continue;
+                    }
+                    log::warn!(
+                        "[{}] {} and {} disagree on the {} of block {}",
+                        self.chain.name,
+                        a.name,
+                        b.name,
+                        field,
+                        height
+                    );
+                    self.divergences
+                        .with_label_values(&[field, &a.name, &b.name])
+                        .inc();
+                }
+            }
+        }
+
+        self.check_consensus(height, &bodies);
+    }
+
+    /// Count the providers diverging from the majority on the hash and transaction count.
+    fn check_consensus(&self, height: u64, bodies: &[(&Endpoint, Block<H256>)]) {
+        let mut groups: Vec<((Option<H256>, usize), usize)> = Vec::new();
+        for (_, body) in bodies {
+            let key = (body.hash, body.transactions.len());
+            match groups.iter_mut().find(|(group, _)| *group == key) {
+                Some((_, size)) => *size += 1,
+                None => groups.push((key, 1)),
+            }
+        }
+        if groups.len() < 2 {
+            return;
+        }
+        groups.sort_by(|(_, a), (_, b)| b.cmp(a));
+        // a tie for the largest group leaves no consensus
+        let consensus = (groups[0].1 > groups[1].1).then_some(groups[0].0);
+
+        for (endpoint, body) in bodies {
+            let key = (body.hash, body.transactions.len());
+            if Some(key) == consensus {
+                continue;
+            }
+            log::warn!(
+                "[{}] {} diverged from consensus on block {}: hash {:?} with {} transactions",
+                self.chain.name,
+                endpoint.name,
+                height,
+                body.hash.unwrap_or_default(),
+                body.transactions.len()
+            );
+            self.mismatches.with_label_values(&[&endpoint.name]).inc();
+        }
+    }
+
+    /// The body of block `height`, `None` if the provider doesn't have it or failed.
+    async fn get_block(&self, endpoint: &Endpoint, height: u64) -> Option<Block<H256>> {
+        let res = endpoint
+            .client
+            .request::<_, Option<Block<H256>>>(
+                RpcMethod::EthGetBlockByNumber.as_str(),
+                json!([U64::from(height), false]),
+            )
+            .await;
+        match res {
+            Ok(block) => block,
+            Err(e) => {
+                log::debug!(
+                    "[{}] Failed to get block {} from {} for cross-validation: {:?}",
+                    self.chain.name,
+                    height,
+                    endpoint.name,
+                    e
+                );
+                None
+            }
+        }
+    }
+}
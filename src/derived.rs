@@ -0,0 +1,487 @@
+//! Derived metrics computed in-process from the exported ones.
+//!
+//! Consumers without a query engine (status pages, the run report, simple scrapers) still want
+//! ratios such as an error rate. `DERIVED_METRICS` defines gauges from PromQL-like expressions,
+//! evaluated for every provider every `DERIVED_INTERVAL_SECS` (default 15):
+//!
+//! ```text
+//! DERIVED_METRICS="error_rate=increase(request_errors[5m]) / increase(request_total[5m]);mean_latency_seconds=rate(request_latency_sum[5m]) / rate(request_latency_count[5m])"
+//! ```
+//!
+//! An expression combines numbers, metric names, `rate(<metric>[<window>])` and
+//! `increase(<metric>[<window>])` with `+`, `-`, `*`, `/` and parentheses. A metric name stands
+//! for the sum of all of the provider's series of that metric; histograms are referenced as
+//! `<name>_sum` and `<name>_count`. Windows are given in `s`, `m` or `h`. A derived gauge isn't
+//! updated while its expression is undefined, e.g. before a window has any history or when
+//! dividing by zero.
+
+use crate::limits;
+
+use prometheus::proto::MetricType;
+use prometheus::{Gauge, Registry};
+use tokio::time;
+
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(f64),
+    Metric(String),
+    Rate(String, Duration),
+    Increase(String, Duration),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+impl Expr {
+    /// Names of the metrics the expression reads and the longest window it needs.
+    fn references(&self, metrics: &mut Vec<String>) -> Duration {
+        match self {
+            Expr::Number(_) => Duration::ZERO,
+            Expr::Metric(name) => {
+                metrics.push(name.clone());
+                Duration::ZERO
+            }
+            Expr::Rate(name, window) | Expr::Increase(name, window) => {
+                metrics.push(name.clone());
+                *window
+            }
+            Expr::Binary(lhs, _, rhs) => lhs.references(metrics).max(rhs.references(metrics)),
+        }
+    }
+
+    fn eval(&self, history: &History) -> Option<f64> {
+        let value = match self {
+            Expr::Number(value) => *value,
+            Expr::Metric(name) => history.latest(name)?,
+            Expr::Rate(name, window) => {
+                let (increase, elapsed) = history.increase(name, *window)?;
+                increase / elapsed.as_secs_f64()
+            }
+            Expr::Increase(name, window) => history.increase(name, *window)?.0,
+            Expr::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(history)?, rhs.eval(history)?);
+                match op {
+                    '+' => lhs + rhs,
+                    '-' => lhs - rhs,
+                    '*' => lhs * rhs,
+                    _ => lhs / rhs,
+                }
+            }
+        };
+        value.is_finite().then_some(value)
+    }
+}
+
+/// A recursive descent parser for derived metric expressions.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(input: &'a str) -> Result<Expr, String> {
+        let mut parser = Parser { input, pos: 0 };
+        let expr = parser.expr()?;
+        parser.skip_whitespace();
+        if parser.pos < input.len() {
+            return Err(format!("unexpected {:?}", &input[parser.pos..]));
+        }
+        Ok(expr)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected {:?} at {}", c, self.pos))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = if self.eat('+') {
+                '+'
+            } else if self.eat('-') {
+                '-'
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.factor()?;
+        loop {
+            let op = if self.eat('*') {
+                '*'
+            } else if self.eat('/') {
+                '/'
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.factor()?));
+        }
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        if self.eat('(') {
+            let expr = self.expr()?;
+            self.expect(')')?;
+            return Ok(expr);
+        }
+
+        let token = self.token();
+        if token.is_empty() {
+            return Err(format!("expected a number or metric at {}", self.pos));
+        }
+        if let Ok(value) = token.parse::<f64>() {
+            return Ok(Expr::Number(value));
+        }
+
+        if !self.eat('(') {
+            return Ok(Expr::Metric(token.to_string()));
+        }
+        let metric = self.token().to_string();
+        self.expect('[')?;
+        let window = self.token();
+        let window = parse_window(window).ok_or_else(|| format!("invalid window {}", window))?;
+        self.expect(']')?;
+        self.expect(')')?;
+
+        match token {
+            "rate" => Ok(Expr::Rate(metric, window)),
+            "increase" => Ok(Expr::Increase(metric, window)),
+            _ => Err(format!("unknown function {}", token)),
+        }
+    }
+
+    /// A metric name, function name, number or window.
+    fn token(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == ':')
+        {
+            self.pos += 1;
+        }
+        &self.input[start..self.pos]
+    }
+}
+
+fn parse_window(window: &str) -> Option<Duration> {
+    let unit = match window.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        _ => return None,
+    };
+    let value = window[..window.len() - 1].parse::<u64>().ok()?;
+    Some(Duration::from_secs(value * unit))
+}
+
+#[derive(Clone, Debug)]
+struct Definition {
+    name: String,
+    expr: Expr,
+}
+
+/// Timestamped values of the metrics referenced by the definitions.
+#[derive(Default)]
+struct History {
+    samples: VecDeque<(Instant, HashMap<String, f64>)>,
+}
+
+impl History {
+    fn latest(&self, name: &str) -> Option<f64> {
+        self.samples.back()?.1.get(name).copied()
+    }
+
+    /// Increase of a counter since the newest sample covering all of `window`, or the oldest one
+    /// while the history is shorter, with the time it took.
+    fn increase(&self, name: &str, window: Duration) -> Option<(f64, Duration)> {
+        let (now, latest) = self.samples.back()?;
+        let (then, earliest) = self
+            .samples
+            .iter()
+            .rev()
+            .find(|(at, _)| now.duration_since(*at) >= window)
+            .or_else(|| self.samples.front())?;
+        if then == now {
+            return None;
+        }
+        Some((
+            latest.get(name)? - earliest.get(name)?,
+            now.duration_since(*then),
+        ))
+    }
+}
+
+/// Sum the series of the `metrics` in `registry`.
+fn sample(registry: &Registry, metrics: &[String]) -> HashMap<String, f64> {
+    let mut values = HashMap::new();
+    for family in registry.gather() {
+        let name = family.get_name();
+        let series = family.get_metric();
+        match family.get_field_type() {
+            MetricType::HISTOGRAM => {
+                let sum = series.iter().map(|m| m.get_histogram().get_sample_sum());
+                values.insert(format!("{}_sum", name), sum.sum());
+                let count = series.iter().map(|m| m.get_histogram().get_sample_count());
+                values.insert(format!("{}_count", name), count.sum::<u64>() as f64);
+            }
+            MetricType::COUNTER => {
+                let value = series.iter().map(|m| m.get_counter().get_value()).sum();
+                values.insert(name.to_string(), value);
+            }
+            MetricType::GAUGE => {
+                let value = series.iter().map(|m| m.get_gauge().get_value()).sum();
+                values.insert(name.to_string(), value);
+            }
+            _ => {}
+        }
+    }
+    values.retain(|name, _| metrics.contains(name));
+    values
+}
+
+struct DerivedProvider {
+    registry: Registry,
+    history: History,
+    gauges: Vec<Gauge>,
+}
+
+/// Evaluates the derived metrics of every watched provider.
+pub struct DerivedMetrics {
+    definitions: Vec<Definition>,
+    metrics: Vec<String>,
+    retention: Duration,
+    interval: Duration,
+    providers: Vec<DerivedProvider>,
+}
+
+impl DerivedMetrics {
+    /// `None` unless `DERIVED_METRICS` is set.
+    pub fn from_env() -> Option<Self> {
+        let definitions = env::var("DERIVED_METRICS").ok()?;
+        let definitions = definitions
+            .split(';')
+            .filter(|definition| !definition.trim().is_empty())
+            .map(|definition| {
+                let (name, expr) = definition
+                    .split_once('=')
+                    .unwrap_or_else(|| panic!("Invalid DERIVED_METRICS entry {}", definition));
+                let expr = Parser::parse(expr)
+                    .unwrap_or_else(|e| panic!("Invalid DERIVED_METRICS entry {}: {}", name, e));
+                Definition {
+                    name: name.trim().to_string(),
+                    expr,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut metrics = Vec::new();
+        let retention = definitions
+            .iter()
+            .map(|definition| definition.expr.references(&mut metrics))
+            .max()
+            .unwrap_or_default();
+
+        let interval = env::var("DERIVED_INTERVAL_SECS")
+            .ok()
+            .map(|secs| secs.parse::<u64>().expect("Invalid DERIVED_INTERVAL_SECS"))
+            .unwrap_or(15);
+        if interval == 0 {
+            panic!("Invalid DERIVED_INTERVAL_SECS: must be positive");
+        }
+
+        Some(Self {
+            definitions,
+            metrics,
+            retention,
+            interval: Duration::from_secs(interval),
+            providers: Vec::new(),
+        })
+    }
+
+    /// Derive metrics from, and export them to, a provider's `registry`.
+    pub fn watch(&mut self, registry: &Registry) {
+        let gauges = self
+            .definitions
+            .iter()
+            .map(|definition| {
+                let gauge = Gauge::new(&definition.name, "Derived metric")
+                    .unwrap_or_else(|e| panic!("Invalid DERIVED_METRICS name: {}", e));
+                registry
+                    .register(Box::new(gauge.clone()))
+                    .unwrap_or_else(|e| panic!("Invalid DERIVED_METRICS name: {}", e));
+                gauge
+            })
+            .collect();
+
+        self.providers.push(DerivedProvider {
+            registry: registry.clone(),
+            history: History::default(),
+            gauges,
+        });
+    }
+
+    pub async fn run(mut self) {
+        let mut interval = time::interval(self.interval);
+        loop {
+            interval.tick().await;
+
+            let now = Instant::now();
+            for provider in &mut self.providers {
+                let samples = &mut provider.history.samples;
+                if limits::memory_exceeded() && samples.len() > 2 {
+                    samples.pop_front();
+                }
+                samples.push_back((now, sample(&provider.registry, &self.metrics)));
+                // keep one sample older than the longest window so it can be covered entirely
+                while samples.len() > 2 && now.duration_since(samples[1].0) >= self.retention {
+                    samples.pop_front();
+                }
+
+                for (definition, gauge) in self.definitions.iter().zip(&provider.gauges) {
+                    if let Some(value) = definition.expr.eval(&provider.history) {
+                        gauge.set(value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A history of `request_total` sampled every 10 seconds.
+    fn history(values: &[f64]) -> History {
+        let start = Instant::now();
+        History {
+            samples: values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    let at = start + Duration::from_secs(10 * i as u64);
+                    (at, HashMap::from([("request_total".to_string(), *value)]))
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn increase_covers_the_window() {
+        let history = history(&[0.0, 5.0, 10.0, 20.0, 40.0]);
+        let window = Duration::from_secs(25);
+        assert_eq!(
+            history.increase("request_total", window),
+            Some((35.0, Duration::from_secs(30)))
+        );
+        assert_eq!(
+            history.increase("request_total", Duration::from_secs(30)),
+            Some((35.0, Duration::from_secs(30)))
+        );
+    }
+
+    #[test]
+    fn increase_of_a_short_history() {
+        let history = history(&[0.0, 5.0, 10.0]);
+        assert_eq!(
+            history.increase("request_total", Duration::from_secs(300)),
+            Some((10.0, Duration::from_secs(20)))
+        );
+        assert_eq!(
+            self::history(&[1.0]).increase("request_total", Duration::from_secs(300)),
+            None
+        );
+        assert_eq!(
+            history.increase("request_errors", Duration::from_secs(300)),
+            None
+        );
+    }
+
+    fn eval(expr: &str, history: &History) -> Option<f64> {
+        Parser::parse(expr).unwrap().eval(history)
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let history = History::default();
+        assert_eq!(eval("1 + 2 * 3", &history), Some(7.0));
+        assert_eq!(eval("(1 + 2) * 3", &history), Some(9.0));
+        assert_eq!(eval("8 / 4 / 2", &history), Some(1.0));
+        assert_eq!(eval("10 - 4 - 3", &history), Some(3.0));
+        assert_eq!(eval(" 1.5*2 ", &history), Some(3.0));
+        // undefined when dividing by zero
+        assert_eq!(eval("1 / 0", &history), None);
+    }
+
+    #[test]
+    fn parse_functions() {
+        let expr = Parser::parse(
+            "increase(request_errors[5m]) / increase(request_total[1h]) + rate(x:y[30s])",
+        )
+        .unwrap();
+        let mut metrics = Vec::new();
+        assert_eq!(expr.references(&mut metrics), Duration::from_secs(3600));
+        assert_eq!(metrics, ["request_errors", "request_total", "x:y"]);
+
+        let history = history(&[0.0, 5.0, 10.0, 20.0, 40.0]);
+        assert_eq!(eval("request_total * 2", &history), Some(80.0));
+        assert_eq!(eval("rate(request_total[20s])", &history), Some(1.5));
+        assert_eq!(eval("increase(request_total[20s])", &history), Some(30.0));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for expr in [
+            "",
+            "1 +",
+            "(1 + 2",
+            "1 2",
+            "sum(request_total[5m])",
+            "rate(request_total)",
+            "rate(request_total[5d])",
+            "rate(request_total[m])",
+            "request_total[5m]",
+        ] {
+            assert!(Parser::parse(expr).is_err(), "{} parsed", expr);
+        }
+    }
+
+    #[test]
+    fn windows() {
+        assert_eq!(parse_window("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_window("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_window("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_window(""), None);
+        assert_eq!(parse_window("1.5m"), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Canonical error taxonomy.
+//!
+//! Providers report the same conditions with different codes and messages, and reuse codes for
+//! unrelated ones: Infura's `-32005` is a rate limit, a used up daily quota or a log query
+//! returning too many results depending on the message. Every error is normalized into a kind,
+//! exported as the `kind` label of `request_errors` next to the raw `code`:
+//!
+//! - `rate_limited`: the provider throttled the request, which is retried with backoff.
+//! - `capacity`: a quota of the plan is used up, e.g. daily requests or monthly compute units.
+//! - `unavailable`: the provider failed in a transient way, e.g. `header not found` from a node
+//!   behind its load balancer or an HTTP `503`, which is retried as well.
+//! - `limit_exceeded`: the request asks too much, e.g. a block range or result count above the
+//!   provider's limits.
+//! - `unsupported`: the method isn't served.
+//! - `invalid`: the request is malformed, e.g. invalid parameters.
+//! - `execution`: the call reverted.
+//! - `transport`: the request didn't get an answer, e.g. a timeout or a dropped connection.
+//! - `other`: anything else.
+//!
+//! The mapping is a table of rules, the rules of the provider's [vendor](crate::vendor) first,
+//! then the generic ones. Providers whose vendor isn't recognized, e.g. behind a proxy, get the
+//! rules of every vendor.
+
+use crate::vendor::Vendor;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    RateLimited,
+    Capacity,
+    Unavailable,
+    LimitExceeded,
+    Unsupported,
+    Invalid,
+    Execution,
+    Transport,
+    Other,
+}
+
+/// A rule of the table: the vendor it applies to (every vendor if `None`), the JSON-RPC code and
+/// a lower-case part of the message it matches, if any, and the kind of the errors it matches.
+type Rule = (Option<Vendor>, Option<i64>, Option<&'static str>, ErrorKind);
+
+/// Vendor rules come first, and specific rules before the broad ones of the same code.
+const RULES: [Rule; 19] = [
+    (
+        Some(Vendor::Alchemy),
+        None,
+        Some("capacity limit exceeded"),
+        ErrorKind::Capacity,
+    ),
+    // for rate limits of specific IPs
+    (
+        Some(Vendor::Alchemy),
+        Some(-32016),
+        Some("rate limit"),
+        ErrorKind::RateLimited,
+    ),
+    (
+        Some(Vendor::Infura),
+        Some(-32005),
+        Some("query returned more than"),
+        ErrorKind::LimitExceeded,
+    ),
+    (
+        Some(Vendor::Infura),
+        Some(-32005),
+        Some("daily request count exceeded"),
+        ErrorKind::Capacity,
+    ),
+    // `exceeded project rate limit`
+    (
+        Some(Vendor::Infura),
+        Some(-32005),
+        None,
+        ErrorKind::RateLimited,
+    ),
+    // e.g. `15/second request limit reached`
+    (
+        Some(Vendor::QuickNode),
+        Some(-32007),
+        None,
+        ErrorKind::RateLimited,
+    ),
+    // e.g. `eth_getLogs is limited to a 10,000 range`
+    (
+        Some(Vendor::QuickNode),
+        Some(-32614),
+        None,
+        ErrorKind::LimitExceeded,
+    ),
+    (
+        None,
+        None,
+        Some("daily request count exceeded"),
+        ErrorKind::Capacity,
+    ),
+    // alchemy throws it this way, and others followed
+    (None, Some(429), None, ErrorKind::RateLimited),
+    (None, None, Some("rate limit"), ErrorKind::RateLimited),
+    // commonly thrown by nodes behind a load balancer that haven't seen the block yet, see also
+    // <https://github.com/MetaMask/metamask-extension/issues/7234>
+    (None, None, Some("header not found"), ErrorKind::Unavailable),
+    (
+        None,
+        None,
+        Some("query returned more than"),
+        ErrorKind::LimitExceeded,
+    ),
+    (None, None, Some("block range"), ErrorKind::LimitExceeded),
+    (None, None, Some("response size"), ErrorKind::LimitExceeded),
+    (None, Some(-32601), None, ErrorKind::Unsupported),
+    (None, None, Some("not supported"), ErrorKind::Unsupported),
+    (None, None, Some("execution reverted"), ErrorKind::Execution),
+    (None, Some(-32602), None, ErrorKind::Invalid),
+    (None, Some(-32600), None, ErrorKind::Invalid),
+];
+
+impl ErrorKind {
+    /// The kind of the JSON-RPC error with `code` and `message` from a provider of `vendor`.
+    pub fn classify(code: i64, message: &str, vendor: Option<Vendor>) -> Self {
+        let message = message.to_lowercase();
+        RULES
+            .iter()
+            .filter(|(rule_vendor, ..)| match (rule_vendor, vendor) {
+                (Some(rule_vendor), Some(vendor)) => *rule_vendor == vendor,
+                _ => true,
+            })
+            .find(|(_, rule_code, rule_message, _)| {
+                rule_code.is_none_or(|rule_code| rule_code == code)
+                    && rule_message.is_none_or(|rule_message| message.contains(rule_message))
+            })
+            .map_or(ErrorKind::Other, |(.., kind)| *kind)
+    }
+
+    /// The kind of an HTTP error with `status`, `None` if there was no response.
+    pub fn from_status(status: Option<u16>) -> Self {
+        match status {
+            None => ErrorKind::Transport,
+            Some(429) => ErrorKind::RateLimited,
+            Some(402) => ErrorKind::Capacity,
+            Some(413) => ErrorKind::LimitExceeded,
+            Some(404 | 405 | 501) => ErrorKind::Unsupported,
+            Some(502..=504) => ErrorKind::Unavailable,
+            Some(_) => ErrorKind::Other,
+        }
+    }
+
+    /// Whether requests failing this way are retried.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ErrorKind::RateLimited | ErrorKind::Unavailable)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::RateLimited => "rate_limited",
+            ErrorKind::Capacity => "capacity",
+            ErrorKind::Unavailable => "unavailable",
+            ErrorKind::LimitExceeded => "limit_exceeded",
+            ErrorKind::Unsupported => "unsupported",
+            ErrorKind::Invalid => "invalid",
+            ErrorKind::Execution => "execution",
+            ErrorKind::Transport => "transport",
+            ErrorKind::Other => "other",
+        }
+    }
+}
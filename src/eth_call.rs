@@ -0,0 +1,322 @@
+//! `eth_call` benchmark.
+//!
+//! Read-heavy dApps mostly call view functions of their contracts. `bencheth call` executes one
+//! such call repeatedly against every configured EVM RPC URL and reports its latency and
+//! failures:
+//!
+//! - `ETH_CALL_TO` and `ETH_CALL_DATA`: the contract and the calldata, e.g. `0x18160ddd` for
+//!   `totalSupply()`. `ETH_CALL_FROM` sets the sender.
+//! - `ETH_CALL_BLOCKS`: comma separated blocks to call at, as tags or numbers, e.g.
+//!   `latest,18000000` (default `latest`). Every block is benchmarked on its own, so recent and
+//!   historical state can be compared.
+//! - `ETH_CALL_HISTORICAL_DEPTH`: also call at a block picked at random for every request among
+//!   the last `n` blocks, reported as `historical`, to exercise the provider's archive state
+//!   rather than its caches.
+//! - `ETH_CALL_REQUESTS` (default 200) calls per block, `ETH_CALL_CONCURRENCY` (default 8) at
+//!   once.
+//!
+//! Failures are broken down by [kind](crate::error_kind), e.g. `execution` for reverts or
+//! `unsupported` for pruned state. Calls wait for the [request limits](crate::limits) and the
+//! provider's [budget](crate::budget) before they are timed.
+
+use crate::budget::Budget;
+use crate::chain::{self, ChainConfig, ChainKind};
+use crate::error_kind::ErrorKind;
+use crate::limits;
+use crate::report::Summary;
+use crate::rpc_method::RpcMethod;
+use crate::tls;
+use crate::vendor::Vendor;
+
+use ethers::core::rand::{thread_rng, Rng};
+use ethers::types::{Address, Bytes, U64};
+use futures::StreamExt;
+use prometheus::Registry;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+const DEFAULT_REQUESTS: usize = 200;
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Calls taking longer than this count as failures.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The block of a benchmarked call.
+#[derive(Clone, Debug)]
+enum Block {
+    /// A tag or number, as configured.
+    Fixed(String),
+    /// A random block among the last blocks.
+    Historical(u64),
+}
+
+impl Block {
+    fn label(&self) -> String {
+        match self {
+            Block::Fixed(block) => block.clone(),
+            Block::Historical(_) => "historical".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BlockResult {
+    pub block: String,
+    pub requests: usize,
+    pub errors: usize,
+    pub error_rate: f64,
+    /// Errors by kind.
+    pub error_kinds: BTreeMap<String, usize>,
+    /// Latency of the successful calls, in milliseconds.
+    pub latency_ms: Option<Summary>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CallReport {
+    pub chain: String,
+    pub rpc: String,
+    pub to: Address,
+    pub results: Vec<BlockResult>,
+}
+
+struct CallBenchmark {
+    call: Value,
+    blocks: Vec<Block>,
+    requests: usize,
+    concurrency: usize,
+}
+
+impl CallBenchmark {
+    fn from_env() -> Self {
+        let to = env::var("ETH_CALL_TO")
+            .expect("ETH_CALL_TO is required")
+            .parse::<Address>()
+            .expect("Invalid ETH_CALL_TO");
+        let data = env::var("ETH_CALL_DATA")
+            .expect("ETH_CALL_DATA is required")
+            .parse::<Bytes>()
+            .expect("Invalid ETH_CALL_DATA");
+        let mut call = json!({ "to": to, "data": data });
+        if let Ok(from) = env::var("ETH_CALL_FROM") {
+            call["from"] = json!(from.parse::<Address>().expect("Invalid ETH_CALL_FROM"));
+        }
+
+        let mut blocks = env::var("ETH_CALL_BLOCKS")
+            .unwrap_or_else(|_| "latest".to_string())
+            .split(',')
+            .map(str::trim)
+            .filter(|block| !block.is_empty())
+            .map(|block| Block::Fixed(block.to_string()))
+            .collect::<Vec<_>>();
+        if let Ok(depth) = env::var("ETH_CALL_HISTORICAL_DEPTH") {
+            match depth.parse::<u64>() {
+                Ok(depth) if depth > 0 => blocks.push(Block::Historical(depth)),
+                _ => panic!("Invalid ETH_CALL_HISTORICAL_DEPTH: must be a number of blocks"),
+            }
+        }
+
+        let var = |name: &str, default: usize| {
+            env::var(name)
+                .ok()
+                .map(|n| match n.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => panic!("Invalid {}: must be greater than zero", name),
+                })
+                .unwrap_or(default)
+        };
+
+        Self {
+            call,
+            blocks,
+            requests: var("ETH_CALL_REQUESTS", DEFAULT_REQUESTS),
+            concurrency: var("ETH_CALL_CONCURRENCY", DEFAULT_CONCURRENCY),
+        }
+    }
+
+    /// The parameters of a call at `block`, on a chain at `head`.
+    fn params(&self, block: &Block, head: u64) -> Value {
+        let block = match block {
+            Block::Fixed(block) => match block.parse::<u64>() {
+                Ok(number) => json!(U64::from(number)),
+                Err(_) => json!(block),
+            },
+            Block::Historical(depth) => {
+                let back = thread_rng().gen_range(0..*depth).min(head);
+                json!(U64::from(head - back))
+            }
+        };
+        json!([self.call, block])
+    }
+
+    async fn run(&self, chain: &ChainConfig, url: &Url) -> CallReport {
+        let client = tls::client_builder(&Registry::new())
+            .timeout(TIMEOUT)
+            .build()
+            .expect("could not initialize http");
+        let vendor = url.host_str().and_then(Vendor::detect);
+        let budget = Budget::from_chain(chain, vendor, &Registry::new());
+        budget.admit().await;
+        let permit = limits::acquire().await;
+        let head = match send(
+            &client,
+            url,
+            &chain.basic_auth,
+            RpcMethod::EthBlockNumber.as_str(),
+            json!([]),
+        )
+        .await
+        {
+            Ok(head) => serde_json::from_value::<U64>(head)
+                .map(|head| head.as_u64())
+                .unwrap_or_default(),
+            Err((kind, e)) => {
+                log::warn!(
+                    "[📞][{}] Failed to get the head of {} ({}): {}",
+                    chain.name,
+                    chain::rpc_label(url),
+                    kind.as_str(),
+                    e
+                );
+                0
+            }
+        };
+        drop(permit);
+
+        let mut results = Vec::new();
+        for block in &self.blocks {
+            let calls = futures::stream::iter(0..self.requests)
+                .map(|_| {
+                    let params = self.params(block, head);
+                    async {
+                        budget.admit().await;
+                        let _permit = limits::acquire().await;
+                        let start = Instant::now();
+                        let res = send(
+                            &client,
+                            url,
+                            &chain.basic_auth,
+                            RpcMethod::EthCall.as_str(),
+                            params,
+                        )
+                        .await;
+                        res.map(|_| start.elapsed().as_secs_f64() * 1000.0)
+                    }
+                })
+                .buffer_unordered(self.concurrency)
+                .collect::<Vec<_>>()
+                .await;
+
+            let mut latencies = Vec::with_capacity(calls.len());
+            let mut error_kinds = BTreeMap::new();
+            for res in calls {
+                match res {
+                    Ok(latency) => latencies.push(latency),
+                    Err((kind, e)) => {
+                        // vendor codes only become meaningful with the vendor known
+                        let kind = match e.get("code").and_then(Value::as_i64) {
+                            Some(code) => ErrorKind::classify(
+                                code,
+                                e["message"].as_str().unwrap_or_default(),
+                                vendor,
+                            ),
+                            None => kind,
+                        };
+                        log::debug!("[📞][{}] eth_call failed: {}", chain.name, e);
+                        *error_kinds.entry(kind.as_str().to_string()).or_default() += 1;
+                    }
+                }
+            }
+            let errors = error_kinds.values().sum::<usize>();
+            results.push(BlockResult {
+                block: block.label(),
+                requests: self.requests,
+                errors,
+                error_rate: errors as f64 / self.requests as f64,
+                error_kinds,
+                latency_ms: Summary::from_samples(latencies),
+            });
+        }
+
+        CallReport {
+            chain: chain.name.clone(),
+            rpc: chain::rpc_label(url).to_string(),
+            to: serde_json::from_value(self.call["to"].clone()).unwrap_or_default(),
+            results,
+        }
+    }
+}
+
+/// Send a JSON-RPC request, failing with the kind of the error and the error object, or the
+/// transport error as a string.
+async fn send(
+    client: &Client,
+    url: &Url,
+    basic_auth: &Option<(String, String)>,
+    method: &str,
+    params: Value,
+) -> Result<Value, (ErrorKind, Value)> {
+    let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    let mut request = client.post(url.clone()).json(&body);
+    if let Some((username, password)) = basic_auth {
+        request = request.basic_auth(username, Some(password));
+    }
+    let response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| {
+            let status = e.status().map(|status| status.as_u16());
+            (ErrorKind::from_status(status), json!(e.to_string()))
+        })?;
+    let mut response: Value = response
+        .json()
+        .await
+        .map_err(|e| (ErrorKind::Transport, json!(e.to_string())))?;
+    match response.get("error") {
+        Some(error) => Err((ErrorKind::Other, error.clone())),
+        None => Ok(response["result"].take()),
+    }
+}
+
+/// Benchmark the call against every configured EVM RPC URL.
+pub async fn run(chains: &[ChainConfig]) -> Vec<CallReport> {
+    let benchmark = CallBenchmark::from_env();
+    let mut reports = Vec::new();
+
+    for chain in chains {
+        if chain.kind != ChainKind::Evm {
+            log::warn!(
+                "[{}] The eth_call benchmark only supports EVM chains, skipping",
+                chain.name
+            );
+            continue;
+        }
+        for url in chain
+            .rpc_urls
+            .iter()
+            .filter(|url| url.scheme().starts_with("http"))
+        {
+            let report = benchmark.run(chain, url).await;
+            for result in &report.results {
+                log::info!(
+                    "[📞][{}] {} at {}: p50 {:.1}ms p99 {:.1}ms, {} of {} failed",
+                    report.chain,
+                    report.rpc,
+                    result.block,
+                    result.latency_ms.as_ref().map_or(0.0, |l| l.p50),
+                    result.latency_ms.as_ref().map_or(0.0, |l| l.p99),
+                    result.errors,
+                    result.requests
+                );
+            }
+            reports.push(report);
+        }
+    }
+
+    reports
+}
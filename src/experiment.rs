@@ -0,0 +1,226 @@
+//! A/B experiments between two endpoint configurations.
+//!
+//! Whether a provider's new API tier or another regional endpoint is faster is hard to tell from
+//! two dashboards, as the network and the provider's load change over time. An experiment sends
+//! the same request to both configurations in randomly ordered pairs, so both see the same
+//! conditions, and tests the difference statistically.
+//!
+//! Run it with `bencheth experiment`. `EXPERIMENT_A_URL` and `EXPERIMENT_B_URL` are the two
+//! endpoints, each measured `EXPERIMENT_REQUESTS` times (default 1000) with a pause of
+//! `EXPERIMENT_INTERVAL_MS` (default 100) between pairs. The request is `EXPERIMENT_METHOD`
+//! (default `eth_blockNumber`) with `EXPERIMENT_PARAMS` (a JSON array, default `[]`).
+//!
+//! The report contains a Mann-Whitney U test of the latencies and bootstrap confidence intervals
+//! at `EXPERIMENT_CONFIDENCE` (default 0.95) of the p50, p90 and p99 differences, B minus A.
+//! Requests failing, with an HTTP error status or a JSON-RPC error, are counted as `errors`
+//! rather than measured.
+
+use crate::redact;
+use crate::report::Summary;
+use crate::rpc_method::RpcMethod;
+use crate::stats::{self, Delta, MannWhitney};
+use crate::tls;
+
+use ethers::core::rand::{thread_rng, Rng};
+use prometheus::Registry;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+const DEFAULT_EXPERIMENT_REQUESTS: usize = 1000;
+const DEFAULT_EXPERIMENT_INTERVAL_MS: u64 = 100;
+const DEFAULT_EXPERIMENT_CONFIDENCE: f64 = 0.95;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ArmResult {
+    pub url: String,
+    pub errors: usize,
+    /// Latency of the successful requests, in milliseconds.
+    pub latency_ms: Summary,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ExperimentReport {
+    pub method: String,
+    pub requests: usize,
+    pub confidence: f64,
+    pub a: ArmResult,
+    pub b: ArmResult,
+    pub mann_whitney: Option<MannWhitney>,
+    /// Latency quantile differences, B minus A, in milliseconds.
+    pub delta_ms: BTreeMap<String, Delta>,
+    /// Whether the Mann-Whitney test rejects equal latencies at the configured confidence.
+    pub significant: bool,
+}
+
+struct Arm {
+    url: Url,
+    client: Client,
+    latencies: Vec<f64>,
+    errors: usize,
+}
+
+impl Arm {
+    fn new(var: &str) -> Self {
+        let url = env::var(var)
+            .ok()
+            .and_then(|url| Url::parse(&url).ok())
+            .unwrap_or_else(|| panic!("Invalid {}", var));
+        let client = tls::client_builder(&Registry::new())
+            .build()
+            .expect("could not initialize http");
+
+        Self {
+            url,
+            client,
+            latencies: Vec::new(),
+            errors: 0,
+        }
+    }
+
+    /// Send the request, returning its latency in milliseconds. JSON-RPC errors are failures too,
+    /// as an endpoint answering them fast would otherwise look faster.
+    async fn send(&self, body: &str) -> Result<f64, String> {
+        let start = Instant::now();
+        let response = self
+            .client
+            .post(self.url.clone())
+            .header("content-type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?;
+        let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+        let latency = start.elapsed().as_secs_f64() * 1000.0;
+
+        let response: Value = serde_json::from_slice(&bytes).map_err(|e| e.to_string())?;
+        if let Some(error) = response.get("error") {
+            return Err(format!("JSON-RPC error {}", error));
+        }
+        Ok(latency)
+    }
+
+    async fn measure(&mut self, body: &str) {
+        match self.send(body).await {
+            Ok(latency) => self.latencies.push(latency),
+            Err(e) => {
+                log::debug!("[🧪] Request to {} failed: {}", redact::url(&self.url), e);
+                self.errors += 1;
+            }
+        }
+    }
+
+    fn result(&self) -> ArmResult {
+        ArmResult {
+            url: redact::url(&self.url),
+            errors: self.errors,
+            latency_ms: Summary::from_samples(self.latencies.iter().copied()).unwrap_or_default(),
+        }
+    }
+}
+
+/// Run the experiment configured in the environment.
+pub async fn run() -> ExperimentReport {
+    let requests = env::var("EXPERIMENT_REQUESTS")
+        .ok()
+        .map(|n| n.parse::<usize>().expect("Invalid EXPERIMENT_REQUESTS"))
+        .unwrap_or(DEFAULT_EXPERIMENT_REQUESTS);
+    let interval = env::var("EXPERIMENT_INTERVAL_MS")
+        .ok()
+        .map(|ms| ms.parse::<u64>().expect("Invalid EXPERIMENT_INTERVAL_MS"))
+        .unwrap_or(DEFAULT_EXPERIMENT_INTERVAL_MS);
+    let confidence = env::var("EXPERIMENT_CONFIDENCE")
+        .ok()
+        .map(|c| c.parse::<f64>().expect("Invalid EXPERIMENT_CONFIDENCE"))
+        .unwrap_or(DEFAULT_EXPERIMENT_CONFIDENCE);
+    if !(0.0..1.0).contains(&confidence) {
+        panic!("Invalid EXPERIMENT_CONFIDENCE: must be between 0 and 1");
+    }
+    let method = env::var("EXPERIMENT_METHOD")
+        .map(|method| {
+            RpcMethod::repeatable(&method)
+                .unwrap_or_else(|e| panic!("Invalid EXPERIMENT_METHOD: {}", e))
+        })
+        .unwrap_or(RpcMethod::EthBlockNumber)
+        .to_string();
+    let params = env::var("EXPERIMENT_PARAMS")
+        .ok()
+        .map(|params| serde_json::from_str::<Value>(&params).expect("Invalid EXPERIMENT_PARAMS"))
+        .unwrap_or_else(|| json!([]));
+    let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+
+    let mut a = Arm::new("EXPERIMENT_A_URL");
+    let mut b = Arm::new("EXPERIMENT_B_URL");
+
+    // establish the connections up front so the handshakes aren't part of the comparison
+    for arm in [&a, &b] {
+        if let Err(e) = arm.send(&body).await {
+            log::warn!(
+                "[🧪] Warm-up request to {} failed: {}",
+                redact::url(&arm.url),
+                e
+            );
+        }
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_millis(interval.max(1)));
+    for _ in 0..requests {
+        interval.tick().await;
+        // random order within a pair so neither arm benefits from going first
+        if thread_rng().gen::<bool>() {
+            a.measure(&body).await;
+            b.measure(&body).await;
+        } else {
+            b.measure(&body).await;
+            a.measure(&body).await;
+        }
+    }
+
+    let mann_whitney = stats::mann_whitney(&a.latencies, &b.latencies);
+    let delta_ms = stats::quantile_deltas(&a.latencies, &b.latencies, confidence);
+    let significant = mann_whitney
+        .as_ref()
+        .is_some_and(|test| test.p_value < 1.0 - confidence);
+
+    let report = ExperimentReport {
+        method,
+        requests,
+        confidence,
+        a: a.result(),
+        b: b.result(),
+        mann_whitney,
+        delta_ms,
+        significant,
+    };
+    log_report(&report);
+    report
+}
+
+fn log_report(report: &ExperimentReport) {
+    for (name, delta) in &report.delta_ms {
+        log::info!(
+            "[🧪] {} B - A: {:+.3}ms ({:.0}% CI {:+.3}ms to {:+.3}ms)",
+            name,
+            delta.estimate,
+            report.confidence * 100.0,
+            delta.ci_low,
+            delta.ci_high
+        );
+    }
+    if let Some(test) = &report.mann_whitney {
+        log::info!(
+            "[🧪] Mann-Whitney p = {:.4}: {}",
+            test.p_value,
+            if report.significant {
+                "the configurations differ"
+            } else {
+                "no significant difference"
+            }
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Client specific namespace scenarios.
+//!
+//! Erigon-backed providers serve extended APIs besides the standard `eth` namespace, e.g. the
+//! `ots` namespace Otterscan is built on. `<CHAIN>_EXTENDED_NAMESPACES` lists the namespaces
+//! whose methods are called at every new block of an EVM chain:
+//!
+//! - `ots`: `ots_getBlockDetails` and the first page of `ots_getBlockTransactions`.
+//! - `erigon`: `erigon_getHeaderByNumber` and `erigon_getLogsByHash`.
+//!
+//! Latencies are exported as `extended_method_latency_seconds` and failures as
+//! `extended_method_errors_total`, labelled with the `method`. `extended_method_supported` is 0
+//! once the provider refused a method, which isn't called again. Extended methods follow
+//! `<CHAIN>_EXTENDED_SCHEDULE`.
+
+use crate::capabilities::{self, Support};
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::rpc_method::RpcMethod;
+use crate::schedule::Schedule;
+
+use ethers::prelude::*;
+use prometheus::{GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Instant;
+
+const NAMESPACES: [&str; 2] = ["ots", "erigon"];
+
+/// The calls of `namespace` at a block, with their parameters.
+fn calls(namespace: &str, number: u64, hash: H256) -> Vec<(RpcMethod, Value)> {
+    match namespace {
+        "ots" => vec![
+            (RpcMethod::OtsGetBlockDetails, json!([number])),
+            (RpcMethod::OtsGetBlockTransactions, json!([number, 0, 10])),
+        ],
+        "erigon" => vec![
+            (RpcMethod::ErigonGetHeaderByNumber, json!([number])),
+            (RpcMethod::ErigonGetLogsByHash, json!([hash])),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+pub struct ExtendedMethods {
+    chain: String,
+    namespaces: Vec<String>,
+    schedule: Schedule,
+    /// Methods the provider refused.
+    unsupported: Mutex<HashSet<RpcMethod>>,
+    latency: HistogramVec,
+    errors: IntCounterVec,
+    supported: GaugeVec,
+}
+
+impl ExtendedMethods {
+    /// The extended methods of a provider of `chain`, reporting to its `registry`. `None` unless
+    /// `<CHAIN>_EXTENDED_NAMESPACES` is set.
+    pub fn from_chain(chain: &ChainConfig, registry: &Registry) -> Option<Self> {
+        let namespaces = chain
+            .var("EXTENDED_NAMESPACES")?
+            .split(',')
+            .map(|namespace| namespace.trim().to_lowercase())
+            .filter(|namespace| !namespace.is_empty())
+            .inspect(|namespace| {
+                if !NAMESPACES.contains(&namespace.as_str()) {
+                    panic!(
+                        "Invalid EXTENDED_NAMESPACES: {} must be one of {}",
+                        namespace,
+                        NAMESPACES.join(", ")
+                    );
+                }
+            })
+            .collect();
+
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "extended_method_latency_seconds",
+                "Time taken for RPC URL to respond to a client specific method",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "extended_method_errors_total",
+                "Number of failed calls of a client specific method",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let supported = GaugeVec::new(
+            Opts::new(
+                "extended_method_supported",
+                "Whether RPC URL supports a client specific method",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(supported.clone())).unwrap();
+
+        Some(Self {
+            chain: chain.name.clone(),
+            namespaces,
+            schedule: Schedule::from_chain(chain, "EXTENDED"),
+            unsupported: Mutex::new(HashSet::new()),
+            latency,
+            errors,
+            supported,
+        })
+    }
+
+    /// Call the methods of every namespace at the block `number` with `hash`.
+    pub async fn run(&self, provider: &Provider<MeasuredJsonRpc>, number: u64, hash: H256) {
+        if !self.schedule.is_active() {
+            return;
+        }
+
+        for namespace in &self.namespaces {
+            for (method, params) in calls(namespace, number, hash) {
+                self.call(provider, method, params).await;
+            }
+        }
+    }
+
+    async fn call(&self, provider: &Provider<MeasuredJsonRpc>, method: RpcMethod, params: Value) {
+        if self.unsupported.lock().unwrap().contains(&method) {
+            return;
+        }
+
+        let start = Instant::now();
+        let res = provider
+            .request::<_, Box<RawValue>>(method.as_str(), params)
+            .await;
+        let elapsed = start.elapsed();
+
+        match res {
+            Ok(_) => {
+                self.supported
+                    .with_label_values(&[method.as_str()])
+                    .set(1.0);
+                self.latency
+                    .with_label_values(&[method.as_str()])
+                    .observe(elapsed.as_secs_f64());
+            }
+            Err(e) if capabilities::classify(&e) == Support::Unsupported => {
+                log::warn!(
+                    "[{}] {} is not supported, not calling it: {:?}",
+                    self.chain,
+                    method,
+                    e
+                );
+                self.supported
+                    .with_label_values(&[method.as_str()])
+                    .set(0.0);
+                self.unsupported.lock().unwrap().insert(method);
+            }
+            Err(e) => {
+                log::warn!("[{}] Failed to call {}: {:?}", self.chain, method, e);
+                self.errors.with_label_values(&[method.as_str()]).inc();
+            }
+        }
+    }
+}
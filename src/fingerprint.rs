@@ -0,0 +1,248 @@
+//! Backend node fingerprinting.
+//!
+//! A provider endpoint is usually a load balancer in front of several nodes, and how many and
+//! how consistent they are decides how often consecutive requests see different chain states.
+//! With `<CHAIN>_BACKEND_FINGERPRINT=true`, an EVM provider is sent `web3_clientVersion`,
+//! `eth_syncing` and `eth_blockNumber` in rapid succession, `<CHAIN>_BACKEND_FINGERPRINT_ROUNDS`
+//! times (default 20), and the responses are clustered into the nodes which must have sent them:
+//!
+//! - every distinct client version, and every distinct syncing state, is a different node;
+//! - a node's head never goes backwards, so heads sorted in the order they were returned are
+//!   covered by at least as many nodes as the longest run of strictly decreasing heads in them.
+//!
+//! The largest of these is a lower bound of the number of nodes, exported as
+//! `backend_nodes_estimated`. `backend_inconsistency_ratio` is the share of rounds contradicting
+//! the round before: another client version or syncing state, or a lower head. A fingerprint is
+//! taken every `<CHAIN>_BACKEND_FINGERPRINT_INTERVAL_SECS` (default 300) following
+//! `<CHAIN>_BACKEND_FINGERPRINT_SCHEDULE`, with requests sent outside of the measured transports,
+//! though under the request limits and the provider's budget.
+
+use crate::budget::Budget;
+use crate::chain::{ChainConfig, ChainKind};
+use crate::limits::{Limited, RequestLimits};
+use crate::rpc_method::RpcMethod;
+use crate::schedule::Schedule;
+
+use ethers::providers::{Authorization, Http, HttpClientError, JsonRpcClient};
+use ethers::types::U64;
+use prometheus::{Gauge, Registry};
+use reqwest::Url;
+use serde_json::Value;
+use tokio::time;
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_INTERVAL_SECS: u64 = 300;
+const DEFAULT_ROUNDS: usize = 20;
+
+/// The responses of a round.
+#[derive(Debug)]
+struct Round {
+    client_version: String,
+    /// Whether the node reported itself syncing.
+    syncing: bool,
+    head: u64,
+}
+
+/// The length of the longest strictly decreasing subsequence of `heads`, the fewest
+/// non-decreasing sequences they can be split into.
+fn longest_decreasing(heads: &[u64]) -> usize {
+    // tails[i]: the largest last element of a decreasing subsequence of length i + 1
+    let mut tails: Vec<u64> = Vec::new();
+    for &head in heads {
+        let position = tails.partition_point(|&tail| tail > head);
+        match tails.get_mut(position) {
+            Some(tail) => *tail = head,
+            None => tails.push(head),
+        }
+    }
+    tails.len()
+}
+
+/// The estimated number of nodes and share of inconsistent rounds of `rounds`.
+fn cluster(rounds: &[Round]) -> (usize, f64) {
+    let versions = rounds
+        .iter()
+        .map(|round| round.client_version.as_str())
+        .collect::<BTreeSet<_>>()
+        .len();
+    let syncing = rounds
+        .iter()
+        .map(|round| round.syncing)
+        .collect::<BTreeSet<_>>()
+        .len();
+    let heads = rounds.iter().map(|round| round.head).collect::<Vec<_>>();
+    let nodes = versions.max(syncing).max(longest_decreasing(&heads));
+
+    let inconsistent = rounds
+        .windows(2)
+        .filter(|pair| {
+            pair[1].client_version != pair[0].client_version
+                || pair[1].syncing != pair[0].syncing
+                || pair[1].head < pair[0].head
+        })
+        .count();
+    let ratio = inconsistent as f64 / rounds.len().saturating_sub(1).max(1) as f64;
+    (nodes, ratio)
+}
+
+pub struct BackendFingerprintProbe {
+    chain: Arc<ChainConfig>,
+    endpoint: String,
+    client: Limited<Http>,
+    rounds: usize,
+    interval: Duration,
+    schedule: Schedule,
+    nodes: Gauge,
+    inconsistency: Gauge,
+}
+
+impl BackendFingerprintProbe {
+    /// The probe of the provider at `rpc_url`, sending requests within its `budget` and `limits`
+    /// and reporting to its `registry`. `None` unless
+    /// `<CHAIN>_BACKEND_FINGERPRINT=true` on an EVM chain and an HTTP RPC URL.
+    pub fn from_chain(
+        chain: Arc<ChainConfig>,
+        rpc_url: &Url,
+        endpoint: &str,
+        budget: Budget,
+        limits: RequestLimits,
+        registry: &Registry,
+    ) -> Option<Self> {
+        if chain.var("BACKEND_FINGERPRINT").as_deref() != Some("true") {
+            return None;
+        }
+        if chain.kind != ChainKind::Evm || !rpc_url.scheme().starts_with("http") {
+            log::warn!(
+                "[{}] The backend fingerprint probe only supports HTTP RPC URLs of EVM chains",
+                chain.name
+            );
+            return None;
+        }
+        let client = match &chain.basic_auth {
+            Some((username, password)) => {
+                Http::new_with_auth(rpc_url.clone(), Authorization::basic(username, password))
+                    .expect("could not initialize http")
+            }
+            None => Http::from_str(rpc_url.as_str()).expect("could not initialize http"),
+        };
+        let client = Limited::new(client).with_budget(budget).with_limits(limits);
+        let rounds = chain
+            .var("BACKEND_FINGERPRINT_ROUNDS")
+            .map(|rounds| {
+                rounds
+                    .parse::<usize>()
+                    .expect("Invalid BACKEND_FINGERPRINT_ROUNDS")
+            })
+            .unwrap_or(DEFAULT_ROUNDS);
+        if rounds < 2 {
+            panic!("Invalid BACKEND_FINGERPRINT_ROUNDS: must be at least 2");
+        }
+        let interval = chain
+            .var("BACKEND_FINGERPRINT_INTERVAL_SECS")
+            .map(|secs| {
+                secs.parse::<u64>()
+                    .expect("Invalid BACKEND_FINGERPRINT_INTERVAL_SECS")
+            })
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+        let nodes = Gauge::new(
+            "backend_nodes_estimated",
+            "Lower bound of the number of nodes behind the provider's endpoint",
+        )
+        .unwrap();
+        let inconsistency = Gauge::new(
+            "backend_inconsistency_ratio",
+            "Share of fingerprint rounds contradicting the round before",
+        )
+        .unwrap();
+        registry.register(Box::new(nodes.clone())).unwrap();
+        registry.register(Box::new(inconsistency.clone())).unwrap();
+
+        Some(Self {
+            schedule: Schedule::from_chain(&chain, "BACKEND_FINGERPRINT"),
+            chain,
+            endpoint: endpoint.to_string(),
+            client,
+            rounds,
+            interval: Duration::from_secs(interval),
+            nodes,
+            inconsistency,
+        })
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = time::interval(self.interval);
+
+        loop {
+            interval.tick().await;
+            if !self.schedule.is_active() {
+                continue;
+            }
+
+            let mut rounds = Vec::with_capacity(self.rounds);
+            for _ in 0..self.rounds {
+                match self.round().await {
+                    Ok(round) => rounds.push(round),
+                    Err(e) => log::debug!(
+                        "[{}] Failed a backend fingerprint round of {}: {}",
+                        self.chain.name,
+                        self.endpoint,
+                        e
+                    ),
+                }
+            }
+            if rounds.len() < 2 {
+                log::warn!(
+                    "[{}] Failed to fingerprint the backends of {}, {} of {} rounds answered",
+                    self.chain.name,
+                    self.endpoint,
+                    rounds.len(),
+                    self.rounds
+                );
+                continue;
+            }
+
+            let (nodes, inconsistency) = cluster(&rounds);
+            let versions = rounds
+                .iter()
+                .map(|round| round.client_version.as_str())
+                .collect::<BTreeSet<_>>();
+            log::info!(
+                "[{}] {} has at least {} backend nodes, {:.0}% of rounds inconsistent, running {:?}",
+                self.chain.name,
+                self.endpoint,
+                nodes,
+                inconsistency * 100.0,
+                versions
+            );
+            self.nodes.set(nodes as f64);
+            self.inconsistency.set(inconsistency);
+        }
+    }
+
+    async fn round(&self) -> Result<Round, HttpClientError> {
+        let client_version = self
+            .client
+            .request::<_, String>(RpcMethod::Web3ClientVersion.as_str(), ())
+            .await?;
+        // `false`, or the sync progress while syncing
+        let syncing = self
+            .client
+            .request::<_, Value>(RpcMethod::EthSyncing.as_str(), ())
+            .await?;
+        let head = self
+            .client
+            .request::<_, U64>(RpcMethod::EthBlockNumber.as_str(), ())
+            .await?
+            .as_u64();
+        Ok(Round {
+            client_version,
+            syncing: syncing != Value::Bool(false),
+            head,
+        })
+    }
+}
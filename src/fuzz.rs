@@ -0,0 +1,517 @@
+//! Differential fuzzing of edge-case parameters.
+//!
+//! The JSON-RPC specification leaves room for interpretation, and clients and providers differ on
+//! the parameters at its edges: block tags like `safe` and `finalized`, EIP-1898 block objects,
+//! hex quantities with leading zeros or upper case digits, inverted or unbounded log ranges,
+//! boundary addresses and storage slots. `bencheth fuzz` sends the same set of valid but unusual
+//! requests to every RPC URL of each configured EVM chain, and to its `<CHAIN>_REFERENCE_RPC_URL`
+//! when set, and reports where the responses differ.
+//!
+//! Requests are pinned to a block `FUZZ_DEPTH` (default 16) blocks below the head, so providers
+//! at slightly different heights still agree. A case is compared either `exact`ly, the results
+//! or the failure to return one, or by `outcome` only, whether a result was returned, for
+//! parameters whose result moves with the head, like `latest`. Responses are compared with the
+//! reference client's, or with the most common one without a reference, and the providers
+//! answering otherwise are listed as `divergent`. Results are reported as a short digest, errors
+//! with their code and message.
+
+use crate::chain::{self, ChainConfig, ChainKind};
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::rpc_method::RpcMethod;
+
+use ethers::providers::{Authorization, JsonRpcClient, RpcError};
+use ethers::types::U64;
+use ethers::utils::keccak256;
+use prometheus::Registry;
+use reqwest::Url;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::env;
+
+const DEFAULT_DEPTH: u64 = 16;
+
+/// Blocks covered by the wide log range cases, beyond the limits of most providers.
+const WIDE_LOG_RANGE: u64 = 100_000;
+
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+const MAX_ADDRESS: &str = "0xffffffffffffffffffffffffffffffffffffffff";
+/// The `ecrecover` precompile.
+const PRECOMPILE_ADDRESS: &str = "0x0000000000000000000000000000000000000001";
+/// A topic no log has, so wide ranges don't return huge results.
+const UNMATCHED_TOPIC: &str = "0x00000000000000000000000000000000000000000000000000000000deadbeef";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Comparison {
+    /// The results, or the failure to return one.
+    Exact,
+    /// Whether a result was returned.
+    Outcome,
+}
+
+struct Case {
+    name: &'static str,
+    method: RpcMethod,
+    params: Value,
+    comparison: Comparison,
+}
+
+fn case(name: &'static str, method: RpcMethod, params: Value, comparison: Comparison) -> Case {
+    Case {
+        name,
+        method,
+        params,
+        comparison,
+    }
+}
+
+/// The cases of `block`, with hash `hash`.
+fn cases(block: u64, hash: &str) -> Vec<Case> {
+    use Comparison::{Exact, Outcome};
+    use RpcMethod::*;
+
+    let number = format!("{:#x}", block);
+    let digits = &number[2..];
+    let call = json!({ "to": ZERO_ADDRESS, "data": "0x" });
+    vec![
+        // block tags
+        case(
+            "earliest tag",
+            EthGetBlockByNumber,
+            json!(["earliest", false]),
+            Exact,
+        ),
+        case(
+            "latest tag",
+            EthGetBlockByNumber,
+            json!(["latest", false]),
+            Outcome,
+        ),
+        case(
+            "safe tag",
+            EthGetBlockByNumber,
+            json!(["safe", false]),
+            Outcome,
+        ),
+        case(
+            "finalized tag",
+            EthGetBlockByNumber,
+            json!(["finalized", false]),
+            Outcome,
+        ),
+        case(
+            "pending tag",
+            EthGetBlockByNumber,
+            json!(["pending", false]),
+            Outcome,
+        ),
+        case(
+            "pending nonce",
+            EthGetTransactionCount,
+            json!([ZERO_ADDRESS, "pending"]),
+            Outcome,
+        ),
+        case(
+            "block beyond the head",
+            EthGetBlockByNumber,
+            json!([format!("{:#x}", block + 1_000_000), false]),
+            Exact,
+        ),
+        case(
+            "omitted full transactions flag",
+            EthGetBlockByNumber,
+            json!([number]),
+            Exact,
+        ),
+        // hex quantities
+        case(
+            "leading zeros",
+            EthGetBlockByNumber,
+            json!([format!("0x00{}", digits), false]),
+            Exact,
+        ),
+        case(
+            "upper case digits",
+            EthGetBlockByNumber,
+            json!([format!("0x{}", digits.to_uppercase()), false]),
+            Exact,
+        ),
+        case(
+            "upper case prefix",
+            EthGetBlockByNumber,
+            json!([format!("0X{}", digits), false]),
+            Exact,
+        ),
+        case(
+            "decimal number",
+            EthGetBlockByNumber,
+            json!([block.to_string(), false]),
+            Exact,
+        ),
+        // EIP-1898 block parameters
+        case(
+            "block number object",
+            EthGetBalance,
+            json!([ZERO_ADDRESS, { "blockNumber": number }]),
+            Exact,
+        ),
+        case(
+            "block hash object",
+            EthGetBalance,
+            json!([ZERO_ADDRESS, { "blockHash": hash }]),
+            Exact,
+        ),
+        case(
+            "canonical block hash object",
+            EthGetBalance,
+            json!([ZERO_ADDRESS, { "blockHash": hash, "requireCanonical": true }]),
+            Exact,
+        ),
+        // boundary addresses and slots
+        case(
+            "max address balance",
+            EthGetBalance,
+            json!([MAX_ADDRESS, number]),
+            Exact,
+        ),
+        case(
+            "precompile code",
+            EthGetCode,
+            json!([PRECOMPILE_ADDRESS, number]),
+            Exact,
+        ),
+        case(
+            "upper case address",
+            EthGetBalance,
+            json!([format!("0x{}", MAX_ADDRESS[2..].to_uppercase()), number]),
+            Exact,
+        ),
+        case(
+            "short storage slot",
+            EthGetStorageAt,
+            json!([ZERO_ADDRESS, "0x0", number]),
+            Exact,
+        ),
+        case(
+            "padded storage slot",
+            EthGetStorageAt,
+            json!([ZERO_ADDRESS, format!("0x{}", "0".repeat(64)), number]),
+            Exact,
+        ),
+        case(
+            "max storage slot",
+            EthGetStorageAt,
+            json!([ZERO_ADDRESS, format!("0x{}", "f".repeat(64)), number]),
+            Exact,
+        ),
+        // calls
+        case("empty call", EthCall, json!([call, number]), Exact),
+        case(
+            "call with input and data",
+            EthCall,
+            json!([{ "to": ZERO_ADDRESS, "input": "0x", "data": "0x" }, number]),
+            Exact,
+        ),
+        case(
+            "call without recipient",
+            EthCall,
+            json!([{ "data": "0x" }, number]),
+            Exact,
+        ),
+        case(
+            "zero block fee history",
+            EthFeeHistory,
+            json!(["0x0", number, []]),
+            Exact,
+        ),
+        case(
+            "decimal fee history count",
+            EthFeeHistory,
+            json!([4, number, [25, 75]]),
+            Exact,
+        ),
+        // log ranges
+        case(
+            "single block logs",
+            EthGetLogs,
+            json!([{ "fromBlock": number, "toBlock": number, "address": ZERO_ADDRESS }]),
+            Exact,
+        ),
+        case(
+            "inverted log range",
+            EthGetLogs,
+            json!([{ "fromBlock": number, "toBlock": format!("{:#x}", block.saturating_sub(1)) }]),
+            Exact,
+        ),
+        case(
+            "wide log range",
+            EthGetLogs,
+            json!([{
+                "fromBlock": format!("{:#x}", block.saturating_sub(WIDE_LOG_RANGE)),
+                "toBlock": number,
+                "topics": [UNMATCHED_TOPIC],
+            }]),
+            Exact,
+        ),
+        case(
+            "unbounded log range",
+            EthGetLogs,
+            json!([{ "fromBlock": "earliest", "toBlock": number, "topics": [UNMATCHED_TOPIC] }]),
+            Exact,
+        ),
+        case(
+            "log block hash and range",
+            EthGetLogs,
+            json!([{ "blockHash": hash, "fromBlock": number }]),
+            Exact,
+        ),
+        case(
+            "empty topic alternatives",
+            EthGetLogs,
+            json!([{ "fromBlock": number, "toBlock": number, "topics": [[]] }]),
+            Exact,
+        ),
+    ]
+}
+
+/// A provider's response to a case.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Response {
+    pub ok: bool,
+    /// The first bytes of the keccak256 of the result.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    /// What is compared of the response.
+    fn key(&self, comparison: Comparison) -> (bool, Option<&str>) {
+        match comparison {
+            Comparison::Exact => (self.ok, self.digest.as_deref()),
+            Comparison::Outcome => (self.ok, None),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CaseReport {
+    pub name: &'static str,
+    pub method: String,
+    pub params: Value,
+    pub comparison: Comparison,
+    /// Responses by RPC host.
+    pub responses: BTreeMap<String, Response>,
+    /// The providers answering unlike the reference, or the majority.
+    pub divergent: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FuzzReport {
+    pub chain: String,
+    pub block: u64,
+    pub reference: Option<String>,
+    /// Cases some provider answered differently.
+    pub differences: usize,
+    pub cases: Vec<CaseReport>,
+}
+
+fn client(chain: &ChainConfig, url: &Url, registry: &Registry) -> MeasuredJsonRpc {
+    match &chain.basic_auth {
+        _ if url.scheme() == "file" => {
+            MeasuredJsonRpc::new_ipc(url.to_file_path().expect("Invalid RPC_URL"), registry)
+        }
+        Some((username, password)) => MeasuredJsonRpc::new_with_auth(
+            url.as_str(),
+            Authorization::basic(username, password),
+            registry,
+        ),
+        None => MeasuredJsonRpc::new(url.as_str(), registry),
+    }
+}
+
+async fn send(client: &MeasuredJsonRpc, method: &RpcMethod, params: Value) -> Response {
+    match client.request::<_, Value>(method.as_str(), params).await {
+        Ok(result) => {
+            let encoded = serde_json::to_vec(&result).unwrap_or_default();
+            Response {
+                ok: true,
+                digest: Some(hex::encode(&keccak256(encoded)[..8])),
+                error: None,
+            }
+        }
+        Err(e) => Response {
+            ok: false,
+            digest: None,
+            error: Some(
+                e.as_error_response()
+                    .map(|error| format!("{}: {}", error.code, error.message))
+                    .unwrap_or_else(|| e.to_string()),
+            ),
+        },
+    }
+}
+
+/// The providers answering unlike the reference's response, or the most common one.
+fn divergent(
+    responses: &BTreeMap<String, Response>,
+    reference: Option<&Response>,
+    comparison: Comparison,
+) -> Vec<String> {
+    let baseline = match reference {
+        Some(reference) => reference.key(comparison),
+        None => {
+            let mut counts = BTreeMap::new();
+            for response in responses.values() {
+                *counts.entry(response.key(comparison)).or_insert(0) += 1;
+            }
+            match counts.into_iter().max_by_key(|(_, count)| *count) {
+                Some((key, _)) => key,
+                None => return Vec::new(),
+            }
+        }
+    };
+    responses
+        .iter()
+        .filter(|(_, response)| response.key(comparison) != baseline)
+        .map(|(rpc, _)| rpc.clone())
+        .collect()
+}
+
+async fn fuzz(chain: &ChainConfig, depth: u64) -> Result<FuzzReport, String> {
+    let registry = Registry::new();
+    let clients = chain
+        .rpc_urls
+        .iter()
+        .map(|url| {
+            (
+                chain::rpc_label(url).to_string(),
+                client(chain, url, &registry),
+            )
+        })
+        .collect::<Vec<_>>();
+    // the reference is a public endpoint, without the providers' credentials
+    let reference = chain.reference_rpc_url.as_ref().map(|url| {
+        (
+            chain::rpc_label(url).to_string(),
+            MeasuredJsonRpc::new(url.as_str(), &registry),
+        )
+    });
+
+    // pin the block on the reference, or the first provider
+    let (_, pinning) = reference
+        .as_ref()
+        .or(clients.first())
+        .ok_or("no RPC URL configured")?;
+    let head = pinning
+        .request::<_, U64>(RpcMethod::EthBlockNumber.as_str(), ())
+        .await
+        .map_err(|e| format!("failed to get the head: {}", e))?
+        .as_u64();
+    let block = head.saturating_sub(depth);
+    let hash = pinning
+        .request::<_, Value>(
+            RpcMethod::EthGetBlockByNumber.as_str(),
+            json!([format!("{:#x}", block), false]),
+        )
+        .await
+        .map_err(|e| format!("failed to get block {}: {}", block, e))?["hash"]
+        .as_str()
+        .ok_or_else(|| format!("block {} has no hash", block))?
+        .to_string();
+
+    let mut reports = Vec::new();
+    for case in cases(block, &hash) {
+        let requests = clients
+            .iter()
+            .map(|(_, client)| send(client, &case.method, case.params.clone()));
+        let responses = futures::future::join_all(requests).await;
+        let responses = clients
+            .iter()
+            .map(|(rpc, _)| rpc.clone())
+            .zip(responses)
+            .collect::<BTreeMap<_, _>>();
+        let reference_response = match &reference {
+            Some((_, client)) => Some(send(client, &case.method, case.params.clone()).await),
+            None => None,
+        };
+
+        let divergent = divergent(&responses, reference_response.as_ref(), case.comparison);
+        let mut responses = responses;
+        if let (Some((rpc, _)), Some(response)) = (&reference, reference_response) {
+            responses.insert(rpc.clone(), response);
+        }
+        reports.push(CaseReport {
+            name: case.name,
+            method: case.method.to_string(),
+            params: case.params,
+            comparison: case.comparison,
+            responses,
+            divergent,
+        });
+    }
+
+    Ok(FuzzReport {
+        chain: chain.name.clone(),
+        block,
+        reference: reference.map(|(rpc, _)| rpc),
+        differences: reports
+            .iter()
+            .filter(|report| !report.divergent.is_empty())
+            .count(),
+        cases: reports,
+    })
+}
+
+fn log_report(report: &FuzzReport) {
+    log::info!(
+        "[🎲][{}] {} of {} edge cases answered differently at block {}",
+        report.chain,
+        report.differences,
+        report.cases.len(),
+        report.block
+    );
+    for case in &report.cases {
+        if !case.divergent.is_empty() {
+            log::warn!(
+                "[🎲][{}] {} ({}) differs on {}",
+                report.chain,
+                case.name,
+                case.method,
+                case.divergent.join(", ")
+            );
+        }
+    }
+}
+
+/// Fuzz every configured EVM chain.
+pub async fn run(chains: &[ChainConfig]) -> Vec<FuzzReport> {
+    let depth = env::var("FUZZ_DEPTH")
+        .ok()
+        .map(|depth| depth.parse::<u64>().expect("Invalid FUZZ_DEPTH"))
+        .unwrap_or(DEFAULT_DEPTH);
+    let mut reports = Vec::new();
+
+    for chain in chains {
+        if chain.kind != ChainKind::Evm {
+            log::warn!(
+                "[{}] Differential fuzzing only supports EVM chains, skipping",
+                chain.name
+            );
+            continue;
+        }
+
+        match fuzz(chain, depth).await {
+            Ok(report) => {
+                log_report(&report);
+                reports.push(report);
+            }
+            Err(e) => log::error!("[{}] Failed to fuzz the providers: {}", chain.name, e),
+        }
+    }
+
+    reports
+}
@@ -0,0 +1,157 @@
+//! Block header hash verification.
+//!
+//! A block's hash is the keccak256 of its RLP encoded header, so a provider returning corrupted
+//! or fabricated header fields, or a hash that isn't the block's, can be caught without another
+//! source. For every block fetched from an EVM provider the header is encoded from the returned
+//! fields, including those added by forks when present, and its hash compared with the reported
+//! one. Mismatches are logged and counted in `header_hash_mismatch_total`.
+//!
+//! Blocks lacking fields of the header, e.g. the `miner` or `nonce`, can't be verified and are
+//! skipped. Chains whose headers don't follow Ethereum's, e.g. with extra fields, can turn the
+//! verification off with `<CHAIN>_HEADER_HASH=false`.
+
+use crate::chain::ChainConfig;
+
+use ethers::types::{Block, H256};
+use ethers::utils::keccak256;
+use ethers::utils::rlp::RlpStream;
+use prometheus::{IntCounter, Registry};
+
+pub struct HeaderHash {
+    chain: String,
+    endpoint: String,
+    mismatches: IntCounter,
+}
+
+/// The RLP encoded header of `block`, `None` if fields of the header are missing.
+fn encode(block: &Block<H256>) -> Option<Vec<u8>> {
+    let mut stream = RlpStream::new();
+    stream.begin_unbounded_list();
+    stream.append(&block.parent_hash);
+    stream.append(&block.uncles_hash);
+    stream.append(&block.author?);
+    stream.append(&block.state_root);
+    stream.append(&block.transactions_root);
+    stream.append(&block.receipts_root);
+    stream.append(&block.logs_bloom?);
+    stream.append(&block.difficulty);
+    stream.append(&block.number?);
+    stream.append(&block.gas_limit);
+    stream.append(&block.gas_used);
+    stream.append(&block.timestamp);
+    stream.append(&block.extra_data.as_ref());
+    stream.append(&block.mix_hash?);
+    stream.append(&block.nonce?);
+
+    // fields added by forks, in the order they were added
+    if let Some(base_fee_per_gas) = block.base_fee_per_gas {
+        stream.append(&base_fee_per_gas);
+    }
+    if let Some(withdrawals_root) = block.withdrawals_root {
+        stream.append(&withdrawals_root);
+    }
+    if let Some(blob_gas_used) = block.blob_gas_used {
+        stream.append(&blob_gas_used);
+    }
+    if let Some(excess_blob_gas) = block.excess_blob_gas {
+        stream.append(&excess_blob_gas);
+    }
+    if let Some(parent_beacon_block_root) = block.parent_beacon_block_root {
+        stream.append(&parent_beacon_block_root);
+    }
+    if let Some(requests_hash) = block.other.get_deserialized::<H256>("requestsHash") {
+        stream.append(&requests_hash.ok()?);
+    }
+
+    stream.finalize_unbounded_list();
+    Some(stream.out().to_vec())
+}
+
+impl HeaderHash {
+    /// The verification of the headers of `endpoint`, reporting to its `registry`. `None` with
+    /// `<CHAIN>_HEADER_HASH=false`.
+    pub fn from_chain(chain: &ChainConfig, endpoint: &str, registry: &Registry) -> Option<Self> {
+        if chain.var("HEADER_HASH").as_deref() == Some("false") {
+            return None;
+        }
+        let mismatches = IntCounter::new(
+            "header_hash_mismatch_total",
+            "Number of blocks whose hash isn't the hash of their header",
+        )
+        .unwrap();
+        registry.register(Box::new(mismatches.clone())).unwrap();
+
+        Some(Self {
+            chain: chain.name.clone(),
+            endpoint: endpoint.to_string(),
+            mismatches,
+        })
+    }
+
+    pub fn verify(&self, block: &Block<H256>) {
+        let (Some(hash), Some(header)) = (block.hash, encode(block)) else {
+            return;
+        };
+        let computed = H256(keccak256(header));
+        if computed != hash {
+            log::warn!(
+                "[{}] Block {} from {} has hash {:?}, but its header hashes to {:?}",
+                self.chain,
+                block.number.unwrap_or_default(),
+                self.endpoint,
+                hash,
+                computed
+            );
+            self.mismatches.inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn zeros(bytes: usize) -> String {
+        format!("0x{}", "00".repeat(bytes))
+    }
+
+    #[test]
+    fn mainnet_genesis() {
+        let block: Block<H256> = serde_json::from_value(json!({
+            "hash": "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3",
+            "parentHash": zeros(32),
+            "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "miner": zeros(20),
+            "stateRoot": "0xd7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544",
+            "transactionsRoot": "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+            "receiptsRoot": "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+            "logsBloom": zeros(256),
+            "difficulty": "0x400000000",
+            "number": "0x0",
+            "gasLimit": "0x1388",
+            "gasUsed": "0x0",
+            "timestamp": "0x0",
+            "extraData": "0x11bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82fa",
+            "mixHash": zeros(32),
+            "nonce": "0x0000000000000042",
+            "transactions": [],
+            "uncles": [],
+        }))
+        .unwrap();
+        assert_eq!(
+            H256(keccak256(encode(&block).unwrap())),
+            block.hash.unwrap()
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        let block = Block::<H256> {
+            nonce: None,
+            ..Default::default()
+        };
+        assert_eq!(encode(&block), None);
+    }
+}
@@ -0,0 +1,142 @@
+//! Provider health endpoint checks.
+//!
+//! Many gateways expose a dedicated health endpoint next to their RPC. Polling it separately
+//! tells a gateway that is up but failing RPC requests apart from a full outage.
+//!
+//! `<CHAIN>_HEALTH_PATH` (e.g. `/health`) is resolved against every RPC URL of the chain, so an
+//! absolute path replaces the RPC URL's path, which usually carries the API key. Gateways serving
+//! their health endpoint elsewhere are given `<CHAIN>_HEALTH_URLS` instead, a comma-separated
+//! list in the order of the RPC URLs, where an empty entry skips the provider. Requests carry the
+//! chain's basic auth credentials, and any 2xx response counts as healthy.
+
+use crate::chain::ChainConfig;
+use crate::schedule::Schedule;
+use crate::tls;
+
+use prometheus::{Gauge, Registry};
+use reqwest::Url;
+use tokio::time;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+pub struct HealthCheck {
+    chain: Arc<ChainConfig>,
+    url: Url,
+    client: reqwest::Client,
+    schedule: Schedule,
+    health_up: Gauge,
+    latency_seconds: Gauge,
+}
+
+impl HealthCheck {
+    /// The check of the provider at `rpc_url` named `endpoint`, reporting to its `registry`.
+    /// `None` unless `<CHAIN>_HEALTH_URLS` has an entry for it or `<CHAIN>_HEALTH_PATH` is set.
+    pub fn from_chain(
+        chain: Arc<ChainConfig>,
+        rpc_url: &Url,
+        endpoint: &str,
+        registry: &Registry,
+    ) -> Option<Self> {
+        let url = match (chain.var("HEALTH_URLS"), chain.var("HEALTH_PATH")) {
+            (Some(urls), _) => {
+                let urls: Vec<&str> = urls.split(',').map(str::trim).collect();
+                if urls.len() != chain.rpc_urls.len() {
+                    panic!("Invalid HEALTH_URLS: must have an entry for every RPC URL");
+                }
+                let index = chain.endpoints.iter().position(|name| name == endpoint)?;
+                match urls[index] {
+                    "" => return None,
+                    url => Url::parse(url).expect("Invalid HEALTH_URLS"),
+                }
+            }
+            (None, Some(path)) => rpc_url.join(&path).expect("Invalid HEALTH_PATH"),
+            (None, None) => return None,
+        };
+        let client = tls::client_builder(&Registry::new())
+            .build()
+            .expect("could not initialize http");
+        let schedule = Schedule::from_chain(&chain, "HEALTH");
+
+        let health_up = Gauge::new(
+            "health_check_up",
+            "Whether the provider's health endpoint reports it as healthy",
+        )
+        .unwrap();
+        let latency_seconds = Gauge::new(
+            "health_check_latency_seconds",
+            "Time taken for the provider's health endpoint to respond",
+        )
+        .unwrap();
+        registry.register(Box::new(health_up.clone())).unwrap();
+        registry
+            .register(Box::new(latency_seconds.clone()))
+            .unwrap();
+
+        Some(Self {
+            chain,
+            url,
+            client,
+            schedule,
+            health_up,
+            latency_seconds,
+        })
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let chain = &self.chain.name;
+        // health endpoints are cheap but not meant to be hammered on every poll
+        let period = self.chain.poll_interval.max(self.chain.expected_block_time);
+        let mut interval = time::interval(period);
+
+        loop {
+            interval.tick().await;
+            if !self.schedule.is_active() {
+                continue;
+            }
+
+            let mut request = self.client.get(self.url.clone());
+            if let Some((username, password)) = &self.chain.basic_auth {
+                request = request.basic_auth(username, Some(password));
+            }
+
+            let start = Instant::now();
+            let res = time::timeout(period, request.send()).await;
+            let elapsed = start.elapsed();
+
+            match res {
+                Ok(Ok(response)) if response.status().is_success() => {
+                    self.health_up.set(1.0);
+                    self.latency_seconds.set(elapsed.as_secs_f64());
+                }
+                Ok(Ok(response)) => {
+                    log::warn!(
+                        "[{}] Health endpoint {} reports {}",
+                        chain,
+                        self.url.host_str().unwrap_or_default(),
+                        response.status()
+                    );
+                    self.health_up.set(0.0);
+                    self.latency_seconds.set(elapsed.as_secs_f64());
+                }
+                Ok(Err(e)) => {
+                    log::warn!(
+                        "[{}] Health endpoint {} is unreachable: {:?}",
+                        chain,
+                        self.url.host_str().unwrap_or_default(),
+                        e
+                    );
+                    self.health_up.set(0.0);
+                }
+                Err(_) => {
+                    log::warn!(
+                        "[{}] Health endpoint {} timed out",
+                        chain,
+                        self.url.host_str().unwrap_or_default()
+                    );
+                    self.health_up.set(0.0);
+                }
+            }
+        }
+    }
+}
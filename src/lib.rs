@@ -0,0 +1,764 @@
+//! BenchETH measures the latency, freshness and reliability of blockchain RPC providers and
+//! exports the measurements as Prometheus metrics.
+//!
+//! The `bencheth` binary is a command line wrapper around this library. Services embedding the
+//! measurements can run the whole agent with the [`Bencheth`] builder, or use its parts on their
+//! own: [`MeasuredJsonRpc`](measured_json_rpc_client::MeasuredJsonRpc) is an ethers transport
+//! recording the latency and errors of every request, [`Monitor`](monitor::Monitor) watches a
+//! provider for new blocks, and [`metrics_server`] serves the metrics of their registries.
+//!
+//! ```no_run
+//! use bencheth::chain::ChainConfig;
+//! use bencheth::Bencheth;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let report = Bencheth::new(ChainConfig::from_env())
+//!     .duration(Some(std::time::Duration::from_secs(60)))
+//!     .run()
+//!     .await?;
+//! println!("{:?}", report.build());
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod adapters;
+pub mod alert;
+pub mod archival;
+pub mod assertion;
+pub mod backend;
+pub mod backfill;
+pub mod block_content;
+pub mod block_metrics;
+pub mod block_trace;
+pub mod block_webhook;
+pub mod body_log;
+pub mod budget;
+pub mod cache;
+pub mod calibration;
+pub mod canary;
+pub mod capabilities;
+pub mod chain;
+pub mod compare;
+pub mod completeness;
+pub mod config;
+pub mod cross_validation;
+pub mod derived;
+pub mod error_kind;
+pub mod eth_call;
+pub mod experiment;
+pub mod extended;
+pub mod fingerprint;
+pub mod fuzz;
+pub mod header_hash;
+pub mod health;
+pub mod limits;
+pub mod liveness;
+pub mod load;
+pub mod logs;
+pub mod maintenance;
+pub mod manifest;
+pub mod measured_http_client;
+pub mod measured_json_rpc_client;
+pub mod metrics_server;
+pub mod monitor;
+#[cfg(feature = "sinks")]
+pub mod mqtt;
+pub mod multiplex;
+pub mod openmetrics;
+pub mod outliers;
+pub mod placement;
+pub mod poll;
+pub mod pushgateway;
+pub mod raw;
+pub mod receipts_root;
+pub mod recent_blocks;
+pub mod recommendations;
+pub mod redact;
+pub mod reference;
+pub mod reorg;
+pub mod repl;
+pub mod report;
+#[cfg(feature = "sinks")]
+pub mod rollup;
+pub mod rpc_method;
+pub mod runtime;
+pub mod schedule;
+pub mod score;
+pub mod self_test;
+pub mod sequencer;
+pub mod server_timing;
+#[cfg(windows)]
+pub mod service;
+pub mod simulation;
+pub mod sketch;
+pub mod slo;
+pub mod stale;
+pub mod stats;
+pub mod supervisor;
+pub mod tenant;
+pub mod tls;
+pub mod transactions_root;
+pub mod trie;
+pub mod vendor;
+pub mod watchdog;
+
+use adapters::bitcoin::{BitcoinMonitor, BitcoinRpc};
+use adapters::http::HttpMonitor;
+use adapters::solana::SolanaMonitor;
+use adapters::starknet::StarknetMonitor;
+use adapters::tendermint::TendermintMonitor;
+use alert::Alerts;
+use archival::ArchivalDepthProbe;
+use block_metrics::{BlockMetrics, ChainHead};
+use block_trace::BlockTracer;
+use block_webhook::{BlockEvents, BlockWebhook};
+use budget::Budget;
+use canary::{Canary, CanaryLock};
+use chain::{ChainConfig, ChainKind};
+use cross_validation::CrossValidation;
+use derived::DerivedMetrics;
+use fingerprint::BackendFingerprintProbe;
+use health::HealthCheck;
+use liveness::Liveness;
+use maintenance::Maintenance;
+use manifest::Manifest;
+use measured_http_client::MeasuredHttp;
+use measured_json_rpc_client::MeasuredJsonRpc;
+use metrics_server::{Endpoints, FinalScrape};
+use monitor::Monitor;
+#[cfg(feature = "sinks")]
+use mqtt::Mqtt;
+use placement::Placement;
+use pushgateway::Pushgateway;
+use recent_blocks::RecentBlocks;
+use recommendations::Recommendations;
+use reference::ReferenceHeads;
+use report::Report;
+#[cfg(feature = "sinks")]
+use rollup::Rollup;
+use score::Scores;
+use sequencer::{ArbitrumFeedProbe, OpSyncStatusProbe};
+use slo::Slos;
+use supervisor::Supervisor;
+use tenant::{Tenant, Tenants};
+use vendor::Vendor;
+use watchdog::Watchdog;
+
+use ethers::prelude::*;
+use prometheus::Registry;
+use reqwest::Url;
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builds and runs the agent: a monitor per provider of every chain, the metrics server, alerts,
+/// scores and reports, configured through the environment like the `bencheth` binary.
+pub struct Bencheth {
+    chains: Vec<ChainConfig>,
+    duration: Option<Duration>,
+    shutdown: Pin<Box<dyn Future<Output = ()> + Send>>,
+    auxiliary: Option<tokio::runtime::Handle>,
+    calibration_requests: usize,
+}
+
+impl Bencheth {
+    /// Measure the providers of `chains`.
+    pub fn new(chains: Vec<ChainConfig>) -> Self {
+        Self {
+            chains,
+            duration: None,
+            shutdown: Box::pin(shutdown_signal()),
+            auxiliary: None,
+            calibration_requests: calibration::requests(),
+        }
+    }
+
+    /// Measure the chains configured in the environment.
+    pub fn from_env() -> Self {
+        Self::new(ChainConfig::from_env())
+    }
+
+    /// Stop measuring after `duration`, `None` (the default) to run until shut down.
+    pub fn duration(mut self, duration: Option<Duration>) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Shut down when `signal` resolves rather than on Ctrl+C or SIGTERM.
+    pub fn shutdown(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown = Box::pin(signal);
+        self
+    }
+
+    /// Serve metrics, alert and write reports on `handle`, so they don't stall the measurements.
+    /// Defaults to the runtime the agent runs on.
+    pub fn auxiliary(mut self, handle: tokio::runtime::Handle) -> Self {
+        self.auxiliary = Some(handle);
+        self
+    }
+
+    /// Run until shut down or the duration has passed, returning the report of the run.
+    ///
+    /// Credentials are redacted from logs and panic messages with [`redact::init`], which installs
+    /// the logger unless one is installed already.
+    pub async fn run(self) -> Result<Report, Box<dyn Error>> {
+        redact::init();
+        supervisor::install_panic_hook();
+        let chains = self.chains;
+        // chains built in code rather than from the environment
+        for chain in &chains {
+            chain.rpc_urls.iter().for_each(redact::register_url);
+            if let Some((_, password)) = &chain.basic_auth {
+                redact::register(password);
+            }
+        }
+        if self_test::enabled() {
+            self_test::run(&chains).await?;
+        }
+        let auxiliary = self
+            .auxiliary
+            .unwrap_or_else(tokio::runtime::Handle::current);
+
+        // get the cloud placement or geo region
+        let placement = Placement::detect().await;
+        let geo_region = placement.geo.clone();
+
+        let manifest = Manifest::new(&chains);
+        let tenants = Tenants::from_env(&chains);
+        let report = Report::new(&geo_region, manifest.clone()).with_outliers();
+        let tenant_reports: HashMap<String, (String, Report)> = tenants
+            .iter()
+            .filter_map(|tenant| {
+                let path = tenant.report_path.clone()?;
+                let report = Report::new(&geo_region, manifest.clone());
+                Some((tenant.name.clone(), (path, report)))
+            })
+            .collect();
+        if env::var("CALIBRATE").map(|v| v == "true").unwrap_or(false) {
+            let calibration = calibration::calibrate(self.calibration_requests).await;
+            calibration::log_calibration(&calibration);
+            for (_, report) in tenant_reports.values() {
+                report.set_calibration(calibration.clone());
+            }
+            report.set_calibration(calibration);
+        }
+
+        let maintenance = Maintenance::from_chains(&chains);
+        let mut alerts = Alerts::from_env(maintenance.clone());
+        let mut scores = Scores::from_env();
+        let mut recommendations = Recommendations::from_env();
+        let mut derived = DerivedMetrics::from_env();
+        let mut watchdog = Watchdog::from_env();
+        let mut slos = Slos::from_env();
+        let block_webhook = BlockWebhook::from_env();
+        let block_tracer = BlockTracer::from_env();
+        let recent_blocks = RecentBlocks::from_env();
+        #[cfg(feature = "sinks")]
+        let mqtt = Mqtt::from_env();
+        #[cfg(feature = "sinks")]
+        if let Some(mqtt) = &mqtt {
+            alerts.add_sink(Box::new(mqtt.sink()));
+        }
+        #[cfg(not(feature = "sinks"))]
+        for var in ["MQTT_URL", "ROLLUP_SLACK_WEBHOOK_URL", "ROLLUP_SMTP_URL"] {
+            if env::var(var).is_ok() {
+                log::warn!(
+                    "{} is ignored, this build was made without the sinks feature",
+                    var
+                );
+            }
+        }
+
+        let chain_ports = chains
+            .iter()
+            .map(|chain| (chain.name.clone(), chain.metrics_port))
+            .collect();
+        let mut registries = Vec::new();
+        let mut monitors = Vec::new();
+
+        for chain in chains {
+            let chain = Arc::new(chain);
+            let chain_head = ChainHead::default();
+            let canary_lock = CanaryLock::default();
+            let tenant = tenants.of(&chain.name);
+            let limits = tenant
+                .map(|tenant| tenant.limits.clone())
+                .unwrap_or_default();
+            let mut budgets = Vec::new();
+
+            for (rpc_url, endpoint) in chain.rpc_urls.iter().zip(&chain.endpoints) {
+                let rpc_host = chain::rpc_label(rpc_url);
+
+                log::info!(
+                    "[😛][🗺️-{}][⛓️-{}] ➡️ {}:  {:?}",
+                    geo_region,
+                    chain.name,
+                    rpc_host,
+                    env!("CARGO_PKG_VERSION")
+                );
+
+                let registry = new_registry(
+                    &chain.name,
+                    rpc_host,
+                    endpoint,
+                    &placement,
+                    &manifest,
+                    tenant,
+                );
+                maintenance.register(&chain.name, endpoint, &registry);
+                let supervisor = Supervisor::new(&chain.name, endpoint, alerts.events(), &registry);
+                let stats = report.provider(&chain.name, endpoint);
+                stats.track_requests(&registry);
+                if let Some((_, tenant_report)) =
+                    tenant.and_then(|tenant| tenant_reports.get(&tenant.name))
+                {
+                    tenant_report.include(&chain.name, endpoint, stats.clone());
+                }
+                let mut events: Vec<BlockEvents> = block_webhook
+                    .iter()
+                    .map(|webhook| webhook.provider(&chain.name, endpoint))
+                    .collect();
+                events.extend(
+                    recent_blocks
+                        .iter()
+                        .map(|recent_blocks| recent_blocks.provider(&chain.name, endpoint)),
+                );
+                #[cfg(feature = "sinks")]
+                events.extend(mqtt.iter().map(|mqtt| mqtt.provider(&chain.name, endpoint)));
+                let block_metrics = BlockMetrics::new(
+                    &registry,
+                    &chain.name,
+                    endpoint,
+                    chain.expected_block_time,
+                    chain_head.clone(),
+                    stats.clone(),
+                    events,
+                );
+                let budget = Budget::from_chain(&chain, Vendor::detect(rpc_host), &registry);
+                budgets.push(budget.clone());
+                alerts.watch(
+                    &chain.name,
+                    endpoint,
+                    chain.expected_block_time,
+                    block_metrics.clone(),
+                    budget.clone(),
+                    &registry,
+                );
+                let score = scores.watch(&registry, block_metrics.clone());
+                recommendations.watch(&chain.name, endpoint, score, &registry);
+                watchdog.watch(
+                    chain.expected_block_time,
+                    block_metrics.clone(),
+                    budget.clone(),
+                );
+                if let Some(slos) = &mut slos {
+                    slos.watch(&chain.name, endpoint, &registry, block_metrics.clone());
+                }
+                if let Some(derived) = &mut derived {
+                    derived.watch(&registry);
+                }
+
+                let transport = || {
+                    let transport = match &chain.basic_auth {
+                        // the IPC socket of a local node
+                        _ if rpc_url.scheme() == "file" => MeasuredJsonRpc::new_ipc(
+                            rpc_url.to_file_path().expect("Invalid RPC_URL"),
+                            &registry,
+                        ),
+                        Some((username, password)) => MeasuredJsonRpc::new_with_auth(
+                            rpc_url.as_str(),
+                            Authorization::basic(username, password),
+                            &registry,
+                        ),
+                        None => MeasuredJsonRpc::new(rpc_url.as_str(), &registry),
+                    }
+                    .with_budget(budget.clone())
+                    .with_limits(limits.clone());
+                    stats.track_latency(transport.latency_sketches());
+                    transport
+                };
+
+                let monitor = match chain.kind {
+                    ChainKind::Evm => {
+                        let transport = transport();
+                        let mut provider = Provider::new(transport);
+                        provider.set_interval(chain.poll_interval);
+
+                        let mut monitor = Monitor::new(
+                            chain.clone(),
+                            endpoint,
+                            Arc::new(provider),
+                            &registry,
+                            block_metrics,
+                        );
+                        if let Some(block_tracer) = &block_tracer {
+                            monitor =
+                                monitor.with_tracer(block_tracer.provider(&chain.name, endpoint));
+                        }
+                        supervisor.spawn("monitor", monitor, Monitor::run)
+                    }
+                    ChainKind::Solana => {
+                        let monitor = SolanaMonitor::new(
+                            chain.clone(),
+                            transport(),
+                            &registry,
+                            block_metrics,
+                        );
+                        supervisor.spawn("monitor", monitor, SolanaMonitor::run)
+                    }
+                    ChainKind::Tendermint => {
+                        let monitor =
+                            TendermintMonitor::new(chain.clone(), transport(), block_metrics);
+                        supervisor.spawn("monitor", monitor, TendermintMonitor::run)
+                    }
+                    ChainKind::Starknet => {
+                        let monitor =
+                            StarknetMonitor::new(chain.clone(), transport(), block_metrics);
+                        supervisor.spawn("monitor", monitor, StarknetMonitor::run)
+                    }
+                    ChainKind::Http => {
+                        let http =
+                            MeasuredHttp::new(rpc_url.clone(), chain.basic_auth.clone(), &registry)
+                                .with_budget(budget.clone())
+                                .with_limits(limits.clone());
+                        stats.track_latency(http.latency_sketches());
+                        let monitor =
+                            HttpMonitor::new(chain.clone(), http, &registry, block_metrics);
+                        supervisor.spawn("monitor", monitor, HttpMonitor::run)
+                    }
+                    ChainKind::Bitcoin => {
+                        let client =
+                            BitcoinRpc::new(rpc_url.clone(), chain.basic_auth.clone(), &registry)
+                                .with_budget(budget.clone())
+                                .with_limits(limits.clone());
+                        stats.track_latency(client.latency_sketches());
+                        let monitor = BitcoinMonitor::new(chain.clone(), client, block_metrics);
+                        supervisor.spawn("monitor", monitor, BitcoinMonitor::run)
+                    }
+                };
+                monitors.push(monitor);
+
+                if let Some(health) =
+                    HealthCheck::from_chain(chain.clone(), rpc_url, endpoint, &registry)
+                {
+                    monitors.push(supervisor.spawn("health_check", health, HealthCheck::run));
+                }
+                if let Some(probe) = ArchivalDepthProbe::from_chain(
+                    chain.clone(),
+                    rpc_url,
+                    endpoint,
+                    budget.clone(),
+                    limits.clone(),
+                    &registry,
+                ) {
+                    monitors.push(supervisor.spawn(
+                        "archival_depth",
+                        probe,
+                        ArchivalDepthProbe::run,
+                    ));
+                }
+                if let Some(probe) = BackendFingerprintProbe::from_chain(
+                    chain.clone(),
+                    rpc_url,
+                    endpoint,
+                    budget.clone(),
+                    limits.clone(),
+                    &registry,
+                ) {
+                    monitors.push(supervisor.spawn(
+                        "backend_fingerprint",
+                        probe,
+                        BackendFingerprintProbe::run,
+                    ));
+                }
+                if let Some(canary) = Canary::from_chain(
+                    chain.clone(),
+                    rpc_url,
+                    endpoint,
+                    canary_lock.clone(),
+                    budget.clone(),
+                    limits.clone(),
+                    &registry,
+                ) {
+                    monitors.push(supervisor.spawn("canary", canary, Canary::run));
+                }
+
+                registries.push(registry);
+            }
+
+            if !chain.reference_urls.is_empty() || chain.reference_rpc_url.is_some() {
+                let registry = new_registry(
+                    &chain.name,
+                    "reference",
+                    "reference",
+                    &placement,
+                    &manifest,
+                    tenant,
+                );
+                let references = ReferenceHeads::new(
+                    chain.clone(),
+                    &chain.reference_urls,
+                    chain.reference_rpc_url.as_ref(),
+                    chain_head.clone(),
+                    &registry,
+                );
+                let supervisor =
+                    Supervisor::new(&chain.name, "reference", alerts.events(), &registry);
+                monitors.push(supervisor.spawn("references", references, ReferenceHeads::run));
+                registries.push(registry);
+            }
+
+            let registry = new_registry(
+                &chain.name,
+                "cross-validation",
+                "cross-validation",
+                &placement,
+                &manifest,
+                tenant,
+            );
+            if let Some(validation) = CrossValidation::from_chain(
+                chain.clone(),
+                chain_head.clone(),
+                &budgets,
+                &limits,
+                &registry,
+            ) {
+                let supervisor =
+                    Supervisor::new(&chain.name, "cross-validation", alerts.events(), &registry);
+                monitors.push(supervisor.spawn(
+                    "cross_validation",
+                    validation,
+                    CrossValidation::run,
+                ));
+                registries.push(registry);
+            }
+
+            if let Some(url) = chain.var("OP_NODE_URL") {
+                let url = Url::parse(&url).expect("Invalid OP_NODE_URL");
+                let registry = new_registry(
+                    &chain.name,
+                    url.host_str().unwrap(),
+                    url.host_str().unwrap(),
+                    &placement,
+                    &manifest,
+                    tenant,
+                );
+                let probe = OpSyncStatusProbe::new(
+                    chain.clone(),
+                    MeasuredJsonRpc::new(url.as_str(), &registry).with_limits(limits.clone()),
+                    &registry,
+                );
+                let supervisor = Supervisor::new(
+                    &chain.name,
+                    url.host_str().unwrap(),
+                    alerts.events(),
+                    &registry,
+                );
+                monitors.push(supervisor.spawn("op_sync_status", probe, OpSyncStatusProbe::run));
+                registries.push(registry);
+            }
+
+            if let Some(url) = chain.var("ARBITRUM_FEED_URL") {
+                let url = Url::parse(&url).expect("Invalid ARBITRUM_FEED_URL");
+                let registry = new_registry(
+                    &chain.name,
+                    url.host_str().unwrap(),
+                    url.host_str().unwrap(),
+                    &placement,
+                    &manifest,
+                    tenant,
+                );
+                let supervisor = Supervisor::new(
+                    &chain.name,
+                    url.host_str().unwrap(),
+                    alerts.events(),
+                    &registry,
+                );
+                let probe = ArbitrumFeedProbe::new(chain.clone(), url, &registry);
+                monitors.push(supervisor.spawn("arbitrum_feed", probe, ArbitrumFeedProbe::run));
+                registries.push(registry);
+            }
+        }
+
+        // the agent's own tasks are only watched, their metrics aren't a provider's
+        let agent_registry = Registry::new();
+        let agent = Supervisor::new("", "agent", alerts.events(), &agent_registry);
+        registries.push(agent_registry);
+
+        let (recommendation_feed, recommendation_sink, recommendation_updates) =
+            recommendations.start();
+        alerts.add_sink(Box::new(recommendation_sink));
+
+        // serving metrics, alerting and writing reports must not stall the measurements
+        #[cfg(feature = "sinks")]
+        if let Some(rollup) = Rollup::from_env(&geo_region, registries.clone()) {
+            auxiliary.spawn(agent.watch("rollup", rollup.run()));
+        }
+        let pushgateway = Pushgateway::from_env(registries.clone()).map(Arc::new);
+        let health = watchdog.clone();
+        let final_scrape = FinalScrape::from_env();
+        let metrics_server = auxiliary.spawn(agent.watch(
+            "metrics_server",
+            crate::metrics_server::start_metrics_server(
+                registries,
+                chain_ports,
+                Endpoints {
+                    history: recent_blocks.as_ref().map(RecentBlocks::history),
+                    slos: slos.clone(),
+                    recommendations: recommendation_feed,
+                },
+                health,
+                final_scrape.clone(),
+            ),
+        ));
+        auxiliary.spawn(agent.watch("alerts", alerts.run()));
+        if let Some(liveness) = Liveness::from_env() {
+            auxiliary
+                .spawn(agent.watch("liveness", liveness.run(tokio::runtime::Handle::current())));
+        }
+        auxiliary.spawn(agent.watch("scores", scores.run()));
+        auxiliary.spawn(agent.watch("recommendations", recommendation_updates.run()));
+        if let Some(derived) = derived {
+            auxiliary.spawn(agent.watch("derived", derived.run()));
+        }
+        if let Some(slos) = slos {
+            auxiliary.spawn(agent.watch("slos", slos.run()));
+        }
+        if let Some(block_webhook) = block_webhook {
+            auxiliary.spawn(agent.watch("block_webhook", block_webhook.run()));
+        }
+        if let Some(block_tracer) = block_tracer {
+            auxiliary.spawn(agent.watch("block_tracer", block_tracer.run()));
+        }
+        if let Some(recent_blocks) = recent_blocks {
+            auxiliary.spawn(agent.watch("recent_blocks", recent_blocks.run()));
+        }
+        #[cfg(feature = "sinks")]
+        if let Some(mqtt) = mqtt {
+            auxiliary.spawn(agent.watch("mqtt", mqtt.run()));
+        }
+        if let Some(pushgateway) = &pushgateway {
+            auxiliary.spawn(agent.watch("pushgateway", pushgateway.clone().run()));
+        }
+        auxiliary.spawn(agent.watch("watchdog", watchdog.run()));
+
+        let report_path = env::var("REPORT_PATH").ok();
+        let report_interval = env::var("REPORT_INTERVAL_SECS")
+            .ok()
+            .map(|secs| secs.parse::<u64>().expect("Invalid REPORT_INTERVAL_SECS"))
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(60));
+        if let Some(path) = &report_path {
+            auxiliary
+                .spawn(agent.watch("report", report.clone().run(path.clone(), report_interval)));
+        }
+        for (path, report) in tenant_reports.values() {
+            auxiliary
+                .spawn(agent.watch("report", report.clone().run(path.clone(), report_interval)));
+        }
+
+        watchdog::ready();
+        let measurements = monitors
+            .iter()
+            .map(|monitor| monitor.abort_handle())
+            .collect::<Vec<_>>();
+        let duration = self.duration;
+        tokio::select! {
+            _ = futures::future::join_all(monitors) => {}
+            _ = self.shutdown => {
+                log::info!("Shutting down");
+            }
+            _ = tokio::time::sleep(duration.unwrap_or_default()), if duration.is_some() => {
+                log::info!("Finished measuring");
+            }
+        }
+        watchdog::stopping();
+        // the final reports and scrape see the state the measurements stopped in
+        for measurement in measurements {
+            measurement.abort();
+        }
+
+        report.finish();
+        if let Some(path) = &report_path {
+            report.write(path);
+        }
+        for (path, report) in tenant_reports.values() {
+            report.finish();
+            report.write(path);
+        }
+
+        if let Some(pushgateway) = &pushgateway {
+            pushgateway.finish().await;
+        }
+        final_scrape.wait().await;
+        let _ = metrics_server.await;
+
+        Ok(report)
+    }
+}
+
+/// Resolve on Ctrl+C or, on unix, SIGTERM as sent by `docker stop`. On Windows, closing the
+/// console, logging off, shutting down, Ctrl+Break and a service stop request shut down as well.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows;
+        let mut ctrl_break = windows::ctrl_break().expect("Failed to install Ctrl+Break handler");
+        let mut ctrl_close = windows::ctrl_close().expect("Failed to install close handler");
+        let mut ctrl_logoff = windows::ctrl_logoff().expect("Failed to install logoff handler");
+        let mut ctrl_shutdown =
+            windows::ctrl_shutdown().expect("Failed to install shutdown handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = ctrl_break.recv() => {}
+            _ = ctrl_close.recv() => {}
+            _ = ctrl_logoff.recv() => {}
+            _ = ctrl_shutdown.recv() => {}
+            _ = service::stopped() => {}
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install Ctrl+C handler");
+}
+
+fn new_registry(
+    chain: &str,
+    rpc_host: &str,
+    endpoint: &str,
+    placement: &Placement,
+    manifest: &Manifest,
+    tenant: Option<&Tenant>,
+) -> Registry {
+    let mut labels = manifest.const_labels();
+    labels.extend(placement.const_labels());
+    labels.extend(tenant::global_labels());
+    if let Some(tenant) = tenant {
+        labels.extend(tenant.const_labels());
+    }
+    labels.insert("chain".to_string(), chain.to_string());
+    labels.insert("rpc".to_string(), rpc_host.to_string());
+    labels.insert("endpoint".to_string(), endpoint.to_string());
+    labels.insert("vendor".to_string(), Vendor::label(rpc_host).to_string());
+    labels.insert("geo".to_string(), placement.geo.clone());
+    let registry = Registry::new_custom(None, Some(labels)).expect("Failed to create registry");
+    manifest.register_info(&registry);
+    registry
+}
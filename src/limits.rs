@@ -0,0 +1,306 @@
+//! Global resource limits.
+//!
+//! A misconfigured scenario, say many chains with short poll intervals, shouldn't be able to OOM
+//! the agent or run up a surprise bill with the providers. Across all providers:
+//!
+//! - `MAX_IN_FLIGHT`: requests in flight at once. Further requests wait for one to complete.
+//! - `MAX_QPS`: requests started per second. Requests are spaced evenly, further ones wait.
+//! - `MAX_MEMORY_MB`: memory allocated by the agent. Above it, buffers stop growing until usage
+//!   falls back under the cap: the run report's rolling windows and the derived metrics' history
+//!   drop their oldest samples, block events are dropped and request bodies aren't logged.
+//!
+//! Requests wait for the limits before their latency is measured, so throttling doesn't skew the
+//! measurements, and a retried request counts once. No limits are enforced by default.
+//!
+//! The same request limits can be set for a group of providers with [`RequestLimits`], e.g. for
+//! a [tenant](crate::tenant).
+//!
+//! Probes whose requests aren't measured, e.g. [cross-validation](crate::cross_validation), send
+//! them through [`Limited`] so they count toward the limits and the providers' budgets all the
+//! same.
+
+use crate::budget::Budget;
+
+use async_trait::async_trait;
+use ethers::providers::JsonRpcClient;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Instant};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// Bytes currently allocated, counted by [`CountingAllocator`].
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the memory cap was exceeded when last checked, to log crossings only once.
+static OVER_MEMORY: AtomicBool = AtomicBool::new(false);
+
+/// The system allocator, counting the bytes allocated through it.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// Spaces requests evenly at a rate.
+#[derive(Debug)]
+struct Pacer {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    async fn wait(&self) {
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        time::sleep_until(at).await;
+    }
+}
+
+#[derive(Debug)]
+struct RequestCaps {
+    in_flight: Option<Arc<Semaphore>>,
+    pacer: Option<Pacer>,
+}
+
+/// Caps on the requests in flight and started per second, shared by every clone.
+#[derive(Clone, Debug, Default)]
+pub struct RequestLimits(Option<Arc<RequestCaps>>);
+
+impl RequestLimits {
+    /// Read `<prefix>MAX_IN_FLIGHT` and `<prefix>MAX_QPS`, unlimited unless either is set.
+    pub fn from_env_prefixed(prefix: &str) -> Self {
+        let in_flight = env::var(format!("{}MAX_IN_FLIGHT", prefix)).ok().map(|n| {
+            let n = n.parse::<usize>().expect("Invalid MAX_IN_FLIGHT");
+            Arc::new(Semaphore::new(n.max(1)))
+        });
+        let pacer = env::var(format!("{}MAX_QPS", prefix)).ok().map(|qps| {
+            let qps = qps.parse::<f64>().expect("Invalid MAX_QPS");
+            assert!(qps > 0.0, "Invalid MAX_QPS: must be positive");
+            Pacer {
+                interval: Duration::from_secs_f64(1.0 / qps),
+                next: Mutex::new(Instant::now()),
+            }
+        });
+        if in_flight.is_none() && pacer.is_none() {
+            return Self(None);
+        }
+
+        Self(Some(Arc::new(RequestCaps { in_flight, pacer })))
+    }
+
+    /// Wait until a request may be sent under the limits. The request counts as in flight until
+    /// the returned permit is dropped.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let caps = self.0.as_ref()?;
+        let permit = match &caps.in_flight {
+            Some(in_flight) => Some(
+                in_flight
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("Semaphore closed"),
+            ),
+            None => None,
+        };
+        if let Some(pacer) = &caps.pacer {
+            pacer.wait().await;
+        }
+        permit
+    }
+}
+
+struct Limits {
+    requests: RequestLimits,
+    max_memory_bytes: Option<usize>,
+}
+
+/// Read the limits from the environment.
+pub fn init() {
+    let requests = RequestLimits::from_env_prefixed("");
+    let max_memory_bytes = env::var("MAX_MEMORY_MB").ok().map(|mb| {
+        let mb = mb.parse::<usize>().expect("Invalid MAX_MEMORY_MB");
+        mb * 1024 * 1024
+    });
+
+    LIMITS
+        .set(Limits {
+            requests,
+            max_memory_bytes,
+        })
+        .ok();
+}
+
+/// Wait until a request may be sent under the limits. The request counts as in flight until the
+/// returned permit is dropped.
+pub async fn acquire() -> Option<OwnedSemaphorePermit> {
+    LIMITS.get()?.requests.acquire().await
+}
+
+/// A transport sending requests under the global limits, the group `limits` and the provider's
+/// `budget`, without measuring them.
+#[derive(Debug)]
+pub struct Limited<C> {
+    inner: C,
+    budget: Budget,
+    limits: RequestLimits,
+}
+
+impl<C> Limited<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            budget: Budget::default(),
+            limits: RequestLimits::default(),
+        }
+    }
+
+    /// Count every request against `budget`, waiting while it is used up.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Send requests under `limits` as well as the global ones.
+    pub fn with_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+#[async_trait]
+impl<C: JsonRpcClient> JsonRpcClient for Limited<C> {
+    type Error = C::Error;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        self.budget.admit().await;
+        let _group_permit = self.limits.acquire().await;
+        let _permit = acquire().await;
+        self.inner.request(method, params).await
+    }
+}
+
+/// Whether the agent allocated more than `MAX_MEMORY_MB`, in which case buffers must not grow.
+pub fn memory_exceeded() -> bool {
+    let Some(max) = LIMITS.get().and_then(|limits| limits.max_memory_bytes) else {
+        return false;
+    };
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    let exceeded = allocated > max;
+    if OVER_MEMORY.swap(exceeded, Ordering::Relaxed) != exceeded {
+        if exceeded {
+            log::warn!(
+                "Allocated {} MB, above MAX_MEMORY_MB, shedding buffered data",
+                allocated / 1024 / 1024
+            );
+        } else {
+            log::info!("Memory usage is back under MAX_MEMORY_MB");
+        }
+    }
+    exceeded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ethers::providers::MockProvider;
+    use ethers::types::U64;
+
+    fn limits(in_flight: Option<usize>, qps: Option<f64>) -> RequestLimits {
+        RequestLimits(Some(Arc::new(RequestCaps {
+            in_flight: in_flight.map(|n| Arc::new(Semaphore::new(n))),
+            pacer: qps.map(|qps| Pacer {
+                interval: Duration::from_secs_f64(1.0 / qps),
+                next: Mutex::new(Instant::now()),
+            }),
+        })))
+    }
+
+    #[tokio::test]
+    async fn unlimited() {
+        assert!(RequestLimits::default().acquire().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn in_flight() {
+        let limits = limits(Some(2), None);
+        let first = limits.acquire().await;
+        let _second = limits.acquire().await;
+        assert!(time::timeout(Duration::from_millis(20), limits.acquire())
+            .await
+            .is_err());
+
+        // a completed request makes room for the next
+        drop(first);
+        assert!(time::timeout(Duration::from_millis(20), limits.acquire())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn paced() {
+        let limits = limits(None, Some(100.0));
+        let start = Instant::now();
+        for _ in 0..5 {
+            limits.acquire().await;
+        }
+        // the first request goes right away, the others 10ms apart
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn limited_requests() {
+        let mock = MockProvider::new();
+        mock.push(U64::from(7)).unwrap();
+        let client = Limited::new(mock).with_limits(limits(Some(1), None));
+        let number: U64 = client.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(number, U64::from(7));
+        // the permit was released with the response
+        assert!(client.limits.acquire().await.is_some());
+    }
+}
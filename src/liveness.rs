@@ -0,0 +1,93 @@
+//! Dead man's switch pings.
+//!
+//! A benchmark agent that died silently looks identical to one with nothing to report. With
+//! `LIVENESS_PING_URL` set (e.g. a healthchecks.io check URL), the agent pings it every
+//! `LIVENESS_PING_INTERVAL_SECS` (default 60) while it is healthy, so the monitoring service
+//! alerts when the pings stop.
+//!
+//! The agent counts as healthy while the measurement runtime keeps making progress. A stalled
+//! runtime pings `<LIVENESS_PING_URL>/fail` instead, which healthchecks.io treats as an
+//! immediate failure.
+
+use tokio::runtime::Handle;
+use tokio::time;
+
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub struct Liveness {
+    client: reqwest::Client,
+    url: String,
+    interval: Duration,
+}
+
+impl Liveness {
+    /// `None` unless `LIVENESS_PING_URL` is set.
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("LIVENESS_PING_URL").ok()?;
+        reqwest::Url::parse(&url).expect("Invalid LIVENESS_PING_URL");
+        let interval = env::var("LIVENESS_PING_INTERVAL_SECS")
+            .ok()
+            .map(|secs| {
+                secs.parse::<u64>()
+                    .expect("Invalid LIVENESS_PING_INTERVAL_SECS")
+            })
+            .unwrap_or(60);
+
+        Some(Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            interval: Duration::from_secs(interval.max(1)),
+        })
+    }
+
+    /// Ping while the runtime of `measurement` keeps beating.
+    pub async fn run(self, measurement: Handle) {
+        let heartbeat = Arc::new(AtomicU64::new(now_secs()));
+        let beat = heartbeat.clone();
+        let beat_interval = self.interval / 2;
+        measurement.spawn(async move {
+            let mut interval = time::interval(beat_interval);
+            loop {
+                interval.tick().await;
+                beat.store(now_secs(), Ordering::Relaxed);
+            }
+        });
+
+        let mut interval = time::interval(self.interval);
+        loop {
+            interval.tick().await;
+
+            let since_beat = now_secs().saturating_sub(heartbeat.load(Ordering::Relaxed));
+            let url = if since_beat <= 2 * self.interval.as_secs() {
+                self.url.clone()
+            } else {
+                log::warn!(
+                    "Measurement runtime hasn't made progress for {}s",
+                    since_beat
+                );
+                format!("{}/fail", self.url)
+            };
+
+            let res = self
+                .client
+                .get(url)
+                .timeout(self.interval)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = res {
+                log::warn!("Failed to send liveness ping: {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,532 @@
+//! Constant-rate load generation.
+//!
+//! Following the chain head only measures a provider at the rate blocks come in. `bencheth bench
+//! --rps <n>` sends a request mix to every configured JSON-RPC endpoint at a target rate instead,
+//! for the run's `--duration`, e.g. `bencheth bench --rps 200 --duration 5m`.
+//!
+//! The scheduler is open-loop: requests are sent on schedule whether or not earlier ones have
+//! been answered, like independent clients would, and a request's latency counts from when it
+//! was due rather than when it was sent. A slow provider therefore shows up as latency instead
+//! of a lower request rate hiding it (coordinated omission). Requests due while `LOAD_MAX_IN_FLIGHT`
+//! (default 1000) are unanswered are dropped and counted.
+//!
+//! `--mix` weighs the methods sent, e.g. `eth_blockNumber=4,eth_getBalance=1` (default
+//! `eth_blockNumber`), and requests go out in a fixed rotation that follows the weights exactly.
+//! Methods are sent with their parameters in `LOAD_PARAMS`, a JSON object by method, e.g.
+//! `{"eth_call": [{"to": "0x..."}, "latest"]}`, or defaults for methods of the latest block and
+//! the zero address.
+//!
+//! `--scenario <path>` replaces the mix with a scenario file, TOML or YAML, modelling the traffic
+//! of an application instead:
+//!
+//! ```toml
+//! [variables]
+//! holder = ["28c6c06298d514db089934071355e5743bf21d60", "dfd5293d8e347dfe59e90efd55b2956a1343963d"]
+//!
+//! [[calls]]
+//! method = "eth_call"
+//! name = "balanceOf"
+//! params = [{ to = "0xdAC17F958D2ee523a2206206994597C13D831ec7", data = "0x70a08231000000000000000000000000{{holder}}" }, "latest"]
+//! weight = 4
+//! concurrency = 50
+//!
+//! [[calls]]
+//! method = "eth_getBlockByNumber"
+//! params = ["latest", true]
+//! ```
+//!
+//! Every call has a `method`, `params` (the same defaults otherwise) and a `weight` (default 1),
+//! and is reported under its `name`, the method by default. `{{<variable>}}` in a string of the
+//! parameters is replaced with a value of the variable picked at random for every request, a
+//! whole string `"{{<variable>}}"` with the value as is, e.g. an object. `concurrency` caps the
+//! call's requests in flight, requests due above it are dropped like above `LOAD_MAX_IN_FLIGHT`.
+//!
+//! For every endpoint, the report contains the achieved rate of answered requests, the latency
+//! percentiles and the number of errors, overall and by method.
+
+use crate::chain::{self, ChainConfig, ChainKind};
+use crate::report::Summary;
+use crate::rpc_method::RpcMethod;
+use crate::sketch::DDSketch;
+use crate::tls;
+
+use ethers::core::rand::{thread_rng, Rng};
+use prometheus::Registry;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+use tokio::time::{self, Instant, MissedTickBehavior};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_MAX_IN_FLIGHT: usize = 1000;
+
+/// Requests taking longer than this count as errors.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+/// Parameters of the methods sent without `LOAD_PARAMS`.
+fn default_params(method: &RpcMethod) -> Value {
+    match method {
+        RpcMethod::EthGetBlockByNumber => json!(["latest", false]),
+        RpcMethod::EthGetBlockReceipts => json!(["latest"]),
+        RpcMethod::EthGetBalance | RpcMethod::EthGetCode | RpcMethod::EthGetTransactionCount => {
+            json!([ZERO_ADDRESS, "latest"])
+        }
+        RpcMethod::EthGetStorageAt => json!([ZERO_ADDRESS, "0x0", "latest"]),
+        RpcMethod::EthFeeHistory => json!(["0x4", "latest", []]),
+        _ => json!([]),
+    }
+}
+
+/// A request mix, e.g. `eth_blockNumber=4,eth_getBalance=1`. A method without a weight weighs 1.
+#[derive(Clone, Debug)]
+pub struct Mix(Vec<(RpcMethod, u32)>);
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(mix: &str) -> Result<Self, Self::Err> {
+        let mix = mix
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (method, weight) = entry.split_once('=').unwrap_or((entry, "1"));
+                let method = RpcMethod::repeatable(method)?;
+                let weight = weight
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid weight of {}: {}", method, weight))?;
+                Ok((method, weight))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if mix.iter().all(|(_, weight)| *weight == 0) {
+            return Err("the mix needs a method with a weight above zero".to_string());
+        }
+        Ok(Self(mix))
+    }
+}
+
+/// A call of a scenario file.
+#[derive(Clone, Debug, Deserialize)]
+struct ScenarioCall {
+    method: String,
+    name: Option<String>,
+    params: Option<Value>,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    concurrency: Option<usize>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// The weighted calls sent by a load test, from a `--mix` or a scenario file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    variables: BTreeMap<String, Vec<Value>>,
+    calls: Vec<ScenarioCall>,
+}
+
+impl Scenario {
+    /// Load the scenario file at `path`, TOML or YAML.
+    pub fn load(path: &Path) -> Self {
+        let contents = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read scenario {}: {}", path.display(), e));
+        let scenario: Scenario = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&contents)
+                .unwrap_or_else(|e| panic!("Invalid scenario {}: {}", path.display(), e)),
+            _ => toml::from_str(&contents)
+                .unwrap_or_else(|e| panic!("Invalid scenario {}: {}", path.display(), e)),
+        };
+
+        if scenario.calls.iter().all(|call| call.weight == 0) {
+            panic!(
+                "Invalid scenario {}: needs a call with a weight above zero",
+                path.display()
+            );
+        }
+        for call in &scenario.calls {
+            if let Err(e) = RpcMethod::repeatable(&call.method) {
+                panic!("Invalid scenario {}: {}", path.display(), e);
+            }
+            if let Some(params) = &call.params {
+                fill(
+                    params,
+                    &mut |variable| match scenario.variables.get(variable) {
+                        Some(values) if !values.is_empty() => values[0].clone(),
+                        _ => panic!(
+                            "Invalid scenario {}: {} has no values",
+                            path.display(),
+                            variable
+                        ),
+                    },
+                );
+            }
+        }
+        scenario
+    }
+}
+
+impl From<Mix> for Scenario {
+    /// The methods of the mix with their parameters in `LOAD_PARAMS`.
+    fn from(mix: Mix) -> Self {
+        let params = env::var("LOAD_PARAMS")
+            .ok()
+            .map(|params| {
+                serde_json::from_str::<BTreeMap<String, Value>>(&params)
+                    .expect("Invalid LOAD_PARAMS")
+            })
+            .unwrap_or_default();
+        let calls = mix
+            .0
+            .into_iter()
+            .map(|(method, weight)| ScenarioCall {
+                method: method.to_string(),
+                name: None,
+                params: params.get(method.as_str()).cloned(),
+                weight,
+                concurrency: None,
+            })
+            .collect();
+
+        Self {
+            variables: BTreeMap::new(),
+            calls,
+        }
+    }
+}
+
+/// Fill the `{{<variable>}}` placeholders in the strings of `template` with `pick`.
+fn fill(template: &Value, pick: &mut impl FnMut(&str) -> Value) -> Value {
+    match template {
+        Value::String(string) => {
+            if let Some(variable) = string
+                .strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .filter(|variable| !variable.contains('{'))
+            {
+                return pick(variable);
+            }
+            let mut filled = String::new();
+            let mut rest = string.as_str();
+            while let Some((before, after)) = rest.split_once("{{") {
+                let Some((variable, after)) = after.split_once("}}") else {
+                    break;
+                };
+                filled.push_str(before);
+                match pick(variable) {
+                    Value::String(value) => filled.push_str(&value),
+                    value => filled.push_str(&value.to_string()),
+                }
+                rest = after;
+            }
+            filled.push_str(rest);
+            Value::String(filled)
+        }
+        Value::Array(values) => {
+            Value::Array(values.iter().map(|value| fill(value, pick)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), fill(value, pick)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MethodResult {
+    pub requests: u64,
+    pub errors: u64,
+    /// Latency of the answered requests from when they were due.
+    pub latency_seconds: Option<Summary>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LoadReport {
+    pub chain: String,
+    pub rpc: String,
+    pub target_rps: f64,
+    /// Requests answered successfully per second.
+    pub achieved_rps: f64,
+    pub elapsed_secs: f64,
+    pub requests: u64,
+    pub errors: u64,
+    /// Requests due while `LOAD_MAX_IN_FLIGHT` were unanswered, not sent.
+    pub dropped: u64,
+    pub latency_seconds: Option<Summary>,
+    pub methods: BTreeMap<String, MethodResult>,
+    /// Latency over all methods, for `--assert`.
+    #[serde(skip)]
+    pub latency_sketch: DDSketch,
+}
+
+#[derive(Debug, Default)]
+struct MethodSamples {
+    requests: u64,
+    errors: u64,
+    latency: DDSketch,
+}
+
+/// A call of the load test, ready to send.
+struct Call {
+    name: String,
+    method: String,
+    params: Value,
+    /// The request body, unless the parameters have placeholders to fill for every request.
+    body: Option<Arc<String>>,
+    concurrency: Option<usize>,
+}
+
+impl Call {
+    fn body(&self, variables: &BTreeMap<String, Vec<Value>>) -> String {
+        if let Some(body) = &self.body {
+            return body.as_ref().clone();
+        }
+        let mut rng = thread_rng();
+        let params = fill(&self.params, &mut |variable| {
+            let values = &variables[variable];
+            values[rng.gen_range(0..values.len())].clone()
+        });
+        request_body(&self.method, params)
+    }
+}
+
+fn request_body(method: &str, params: Value) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    })
+    .to_string()
+}
+
+pub struct LoadTest {
+    rps: f64,
+    duration: Duration,
+    max_in_flight: usize,
+    variables: BTreeMap<String, Vec<Value>>,
+    calls: Vec<Call>,
+    /// The calls in rotation by index, as many times as they weigh.
+    rotation: Vec<usize>,
+}
+
+impl LoadTest {
+    pub fn new(rps: f64, duration: Duration, scenario: Scenario) -> Self {
+        if !(rps > 0.0 && rps.is_finite()) {
+            panic!("Invalid --rps: must be greater than zero");
+        }
+        let max_in_flight = env::var("LOAD_MAX_IN_FLIGHT")
+            .ok()
+            .map(|n| n.parse::<usize>().expect("Invalid LOAD_MAX_IN_FLIGHT"))
+            .unwrap_or(DEFAULT_MAX_IN_FLIGHT);
+
+        let mut calls = Vec::new();
+        let mut rotation = Vec::new();
+        for call in scenario.calls {
+            let method = RpcMethod::repeatable(&call.method).expect("Invalid scenario");
+            let params = call.params.unwrap_or_else(|| default_params(&method));
+            // only scenario files have variables, `LOAD_PARAMS` are sent as they are
+            let mut templated = false;
+            fill(&params, &mut |variable| {
+                templated |= scenario.variables.contains_key(variable);
+                Value::Null
+            });
+            rotation.extend(std::iter::repeat_n(calls.len(), call.weight as usize));
+            calls.push(Call {
+                name: call.name.unwrap_or_else(|| method.to_string()),
+                method: method.as_str().to_string(),
+                body: (!templated).then(|| Arc::new(request_body(method.as_str(), params.clone()))),
+                params,
+                concurrency: call.concurrency,
+            });
+        }
+
+        Self {
+            rps,
+            duration,
+            max_in_flight,
+            variables: scenario.variables,
+            calls,
+            rotation,
+        }
+    }
+
+    /// Send the mix to `url` at the target rate for the duration.
+    async fn run(&self, chain: &ChainConfig, url: &Url) -> LoadReport {
+        let client = tls::client_builder(&Registry::new())
+            .timeout(TIMEOUT)
+            .build()
+            .expect("could not initialize http");
+        let samples: Arc<Mutex<BTreeMap<String, MethodSamples>>> = Default::default();
+        let in_flight = Arc::new(Semaphore::new(self.max_in_flight));
+        let call_in_flight = self
+            .calls
+            .iter()
+            .map(|call| call.concurrency.map(|n| Arc::new(Semaphore::new(n))))
+            .collect::<Vec<_>>();
+        let mut dropped = 0;
+        let mut requests = Vec::new();
+
+        // an open loop, catching up on the ticks missed by a busy runtime
+        let mut interval = time::interval(Duration::from_secs_f64(1.0 / self.rps));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Burst);
+        let start = Instant::now();
+        for &index in self.rotation.iter().cycle() {
+            let due = interval.tick().await;
+            if due.duration_since(start) >= self.duration {
+                break;
+            }
+            let call_permit = match &call_in_flight[index] {
+                Some(call_in_flight) => match call_in_flight.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        dropped += 1;
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            let Ok(permit) = in_flight.clone().try_acquire_owned() else {
+                dropped += 1;
+                continue;
+            };
+
+            let call = &self.calls[index];
+            let client = client.clone();
+            let url = url.clone();
+            let basic_auth = chain.basic_auth.clone();
+            let body = call.body(&self.variables);
+            let samples = samples.clone();
+            let method = call.name.clone();
+            let chain = chain.name.clone();
+            requests.push(tokio::spawn(async move {
+                let res = send(&client, &url, &basic_auth, body).await;
+                let latency = due.elapsed().as_secs_f64();
+                drop(permit);
+                drop(call_permit);
+
+                let mut samples = samples.lock().unwrap();
+                let samples = samples.entry(method.clone()).or_default();
+                samples.requests += 1;
+                match res {
+                    Ok(()) => samples.latency.add(latency),
+                    Err(e) => {
+                        log::debug!("[📈][{}] {} request failed: {}", chain, method, e);
+                        samples.errors += 1;
+                    }
+                }
+            }));
+        }
+        futures::future::join_all(requests).await;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let samples = std::mem::take(&mut *samples.lock().unwrap());
+        let mut all = DDSketch::default();
+        let mut methods = BTreeMap::new();
+        for (method, samples) in samples {
+            all.merge(&samples.latency);
+            methods.insert(
+                method,
+                MethodResult {
+                    requests: samples.requests,
+                    errors: samples.errors,
+                    latency_seconds: Summary::from_sketch(&samples.latency),
+                },
+            );
+        }
+        let requests = methods.values().map(|method| method.requests).sum::<u64>();
+        let errors = methods.values().map(|method| method.errors).sum::<u64>();
+
+        LoadReport {
+            chain: chain.name.clone(),
+            rpc: chain::rpc_label(url).to_string(),
+            target_rps: self.rps,
+            achieved_rps: (requests - errors) as f64 / elapsed,
+            elapsed_secs: elapsed,
+            requests,
+            errors,
+            dropped,
+            latency_seconds: Summary::from_sketch(&all),
+            methods,
+            latency_sketch: all,
+        }
+    }
+}
+
+/// Send a JSON-RPC request, failing on HTTP and JSON-RPC errors.
+async fn send(
+    client: &Client,
+    url: &Url,
+    basic_auth: &Option<(String, String)>,
+    body: String,
+) -> Result<(), String> {
+    let mut request = client
+        .post(url.clone())
+        .header("content-type", "application/json")
+        .body(body);
+    if let Some((username, password)) = basic_auth {
+        request = request.basic_auth(username, Some(password));
+    }
+    let response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let response: Value = response.json().await.map_err(|e| e.to_string())?;
+    match response.get("error") {
+        Some(error) => Err(error.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Load every configured JSON-RPC endpoint at once, so they are measured over the same period.
+pub async fn run(chains: &[ChainConfig], test: &LoadTest) -> Vec<LoadReport> {
+    let mut runs = Vec::new();
+    for chain in chains {
+        if chain.kind == ChainKind::Http {
+            log::warn!(
+                "[{}] Load generation only supports JSON-RPC chains, skipping",
+                chain.name
+            );
+            continue;
+        }
+        for url in chain
+            .rpc_urls
+            .iter()
+            .filter(|url| url.scheme().starts_with("http"))
+        {
+            runs.push(test.run(chain, url));
+        }
+    }
+
+    let reports = futures::future::join_all(runs).await;
+    for report in &reports {
+        log::info!(
+            "[📈][{}] {}: {:.1} of {} req/s, p50 {:.3}s p99 {:.3}s, {} errors, {} dropped",
+            report.chain,
+            report.rpc,
+            report.achieved_rps,
+            report.target_rps,
+            report.latency_seconds.as_ref().map_or(0.0, |l| l.p50),
+            report.latency_seconds.as_ref().map_or(0.0, |l| l.p99),
+            report.errors,
+            report.dropped
+        );
+    }
+    reports
+}
@@ -0,0 +1,172 @@
+//! Log query scenarios.
+//!
+//! `eth_getLogs` scans every block of its range, which makes it the most expensive method
+//! providers serve, and their limits on ranges and results differ the most. For EVM chains,
+//! `<CHAIN>_LOGS_RANGES` lists block ranges, e.g. `1,100,1000`, queried at every new block, each
+//! ending at the new block:
+//!
+//! - `<CHAIN>_LOGS_ADDRESSES`: comma separated contract addresses to filter by, every contract by
+//!   default.
+//! - `<CHAIN>_LOGS_TOPICS`: the topics filter as a JSON array, e.g.
+//!   `["0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"]` for ERC-20
+//!   transfers, every log by default.
+//!
+//! Latencies are exported as `logs_latency_seconds`, the number of logs returned as
+//! `logs_results` and failures, e.g. a range or result limit of the provider, as
+//! `logs_errors_total`, all labelled with the `range` in blocks. Log queries follow
+//! `<CHAIN>_LOGS_SCHEDULE`.
+
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::{latency_buckets, MeasuredJsonRpc};
+use crate::rpc_method::RpcMethod;
+use crate::schedule::Schedule;
+
+use ethers::prelude::*;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+
+use std::time::Instant;
+
+pub struct LogQueries {
+    chain: String,
+    /// Block ranges, in blocks.
+    ranges: Vec<u64>,
+    addresses: Vec<Address>,
+    topics: Option<Value>,
+    schedule: Schedule,
+    latency: HistogramVec,
+    results: HistogramVec,
+    errors: IntCounterVec,
+}
+
+impl LogQueries {
+    /// The log queries of a provider of `chain`, reporting to its `registry`. `None` unless
+    /// `<CHAIN>_LOGS_RANGES` is set.
+    pub fn from_chain(chain: &ChainConfig, registry: &Registry) -> Option<Self> {
+        let ranges = chain
+            .var("LOGS_RANGES")?
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(|range| match range.parse::<u64>() {
+                Ok(range) if range > 0 => range,
+                _ => panic!("Invalid LOGS_RANGES: {} must be a number of blocks", range),
+            })
+            .collect();
+        let addresses = chain
+            .var("LOGS_ADDRESSES")
+            .map(|addresses| {
+                addresses
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|address| !address.is_empty())
+                    .map(|address| address.parse().expect("Invalid LOGS_ADDRESSES"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let topics = chain.var("LOGS_TOPICS").map(|topics| {
+            match serde_json::from_str::<Value>(&topics).expect("Invalid LOGS_TOPICS") {
+                topics @ Value::Array(_) => topics,
+                _ => panic!("Invalid LOGS_TOPICS: must be a JSON array"),
+            }
+        });
+
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "logs_latency_seconds",
+                "Time taken for RPC URL to answer a log query over a range of blocks",
+            )
+            .buckets(latency_buckets()),
+            &["range"],
+        )
+        .unwrap();
+        let results = HistogramVec::new(
+            HistogramOpts::new(
+                "logs_results",
+                "Number of logs returned by a log query over a range of blocks",
+            )
+            .buckets(prometheus::exponential_buckets(1.0, 4.0, 10).unwrap()),
+            &["range"],
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "logs_errors_total",
+                "Number of failed log queries over a range of blocks",
+            ),
+            &["range"],
+        )
+        .unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(results.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+
+        Some(Self {
+            chain: chain.name.clone(),
+            ranges,
+            addresses,
+            topics,
+            schedule: Schedule::from_chain(chain, "LOGS"),
+            latency,
+            results,
+            errors,
+        })
+    }
+
+    /// The filter of the range of `range` blocks ending at block `to`.
+    fn filter(&self, range: u64, to: u64) -> Value {
+        let from = to.saturating_sub(range - 1);
+        let mut filter = json!({
+            "fromBlock": U64::from(from),
+            "toBlock": U64::from(to),
+        });
+        if !self.addresses.is_empty() {
+            filter["address"] = json!(self.addresses);
+        }
+        if let Some(topics) = &self.topics {
+            filter["topics"] = topics.clone();
+        }
+        filter
+    }
+
+    /// Query the logs of every range ending at block `number`.
+    pub async fn run(&self, provider: &Provider<MeasuredJsonRpc>, number: u64) {
+        if !self.schedule.is_active() {
+            return;
+        }
+
+        for &range in &self.ranges {
+            let label = range.to_string();
+            let start = Instant::now();
+            let res = provider
+                .request::<_, Vec<Box<RawValue>>>(
+                    RpcMethod::EthGetLogs.as_str(),
+                    [self.filter(range, number)],
+                )
+                .await;
+            let elapsed = start.elapsed();
+
+            match res {
+                Ok(logs) => {
+                    self.latency
+                        .with_label_values(&[&label])
+                        .observe(elapsed.as_secs_f64());
+                    self.results
+                        .with_label_values(&[&label])
+                        .observe(logs.len() as f64);
+                }
+                Err(e) => {
+                    log::warn!(
+                        "[{}] Failed to query the logs of {} blocks up to {}: {:?}",
+                        self.chain,
+                        range,
+                        number,
+                        e
+                    );
+                    self.errors.with_label_values(&[&label]).inc();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Scheduled provider maintenance windows.
+//!
+//! During a provider's maintenance window its alerts are suppressed and its `provider_maintenance`
+//! gauge is 1, 0 otherwise. The labels of its other series don't change, so counters and
+//! histograms carry on through the window, and scheduled vendor maintenance is excluded from
+//! error budgets by joining on the gauge:
+//!
+//! ```text
+//! rate(request_errors[5m]) unless on (chain, endpoint) provider_maintenance == 1
+//! ```
+//!
+//! Windows are configured per chain with `<CHAIN>_MAINTENANCE_WINDOWS`, a `;` separated list of
+//! `<endpoint>=<window>` entries, where the endpoint is the provider's `endpoint` name (its host
+//! unless named otherwise, see [`chain`](crate::chain)) or `*` for every provider of the chain. A
+//! window is either a one-off RFC 3339 range or a weekly recurring UTC time range:
+//!
+//! ```text
+//! ETHEREUM_MAINTENANCE_WINDOWS="eth-a.example=2024-05-01T02:00:00Z/2024-05-01T04:00:00Z;*=Sun 03:00-03:30"
+//! ```
+
+use crate::chain::ChainConfig;
+
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{IntGauge, Registry};
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MaintenanceWindow {
+    Once {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+    Weekly {
+        weekday: Weekday,
+        start: NaiveTime,
+        end: NaiveTime,
+    },
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some((start, end)) = s.split_once('/') {
+            let parse = |time: &str| {
+                DateTime::parse_from_rfc3339(time.trim())
+                    .map(|time| time.with_timezone(&Utc))
+                    .map_err(|e| format!("invalid maintenance window {}: {}", s, e))
+            };
+            return Ok(MaintenanceWindow::Once {
+                start: parse(start)?,
+                end: parse(end)?,
+            });
+        }
+
+        let (weekday, range) = s
+            .split_once(' ')
+            .ok_or_else(|| format!("invalid maintenance window {}", s))?;
+        let weekday = weekday
+            .parse::<Weekday>()
+            .map_err(|_| format!("invalid maintenance window {}: unknown weekday", s))?;
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| format!("invalid maintenance window {}", s))?;
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|e| format!("invalid maintenance window {}: {}", s, e))
+        };
+
+        Ok(MaintenanceWindow::Weekly {
+            weekday,
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        match self {
+            MaintenanceWindow::Once { start, end } => *start <= at && at < *end,
+            MaintenanceWindow::Weekly {
+                weekday,
+                start,
+                end,
+            } => {
+                let time = at.time();
+                if start <= end {
+                    at.weekday() == *weekday && *start <= time && time < *end
+                } else {
+                    // the window runs past midnight into the next day
+                    (at.weekday() == *weekday && *start <= time)
+                        || (at.weekday() == weekday.succ() && time < *end)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ProviderWindow {
+    chain: String,
+    /// Endpoint name of the provider, `*` for every provider of the chain.
+    endpoint: String,
+    window: MaintenanceWindow,
+}
+
+/// The maintenance windows of all providers.
+#[derive(Clone, Debug, Default)]
+pub struct Maintenance {
+    windows: Arc<Vec<ProviderWindow>>,
+}
+
+impl Maintenance {
+    pub fn from_chains(chains: &[ChainConfig]) -> Self {
+        let mut windows = Vec::new();
+        for chain in chains {
+            let Some(entries) = chain.var("MAINTENANCE_WINDOWS") else {
+                continue;
+            };
+
+            for entry in entries.split(';').filter(|entry| !entry.trim().is_empty()) {
+                let (endpoint, window) = entry
+                    .split_once('=')
+                    .unwrap_or_else(|| panic!("Invalid MAINTENANCE_WINDOWS entry {}", entry));
+                let window = window
+                    .parse::<MaintenanceWindow>()
+                    .unwrap_or_else(|e| panic!("Invalid MAINTENANCE_WINDOWS: {}", e));
+                windows.push(ProviderWindow {
+                    chain: chain.name.clone(),
+                    endpoint: endpoint.trim().to_string(),
+                    window,
+                });
+            }
+        }
+
+        Self {
+            windows: Arc::new(windows),
+        }
+    }
+
+    /// Whether the provider `endpoint` of `chain` is in a maintenance window right now.
+    pub fn is_active(&self, chain: &str, endpoint: &str) -> bool {
+        let now = Utc::now();
+        self.windows.iter().any(|provider| {
+            provider.chain == chain
+                && (provider.endpoint == "*" || provider.endpoint == endpoint)
+                && provider.window.contains(now)
+        })
+    }
+
+    /// Export whether the provider `endpoint` of `chain` is in a maintenance window as
+    /// `provider_maintenance` in its `registry`.
+    pub fn register(&self, chain: &str, endpoint: &str, registry: &Registry) {
+        let gauge = IntGauge::new(
+            "provider_maintenance",
+            "Whether the provider is in a scheduled maintenance window",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(MaintenanceGauge {
+                maintenance: self.clone(),
+                chain: chain.to_string(),
+                endpoint: endpoint.to_string(),
+                gauge,
+            }))
+            .unwrap();
+    }
+}
+
+/// `provider_maintenance`, brought up to date whenever the registry is gathered.
+struct MaintenanceGauge {
+    maintenance: Maintenance,
+    chain: String,
+    endpoint: String,
+    gauge: IntGauge,
+}
+
+impl Collector for MaintenanceGauge {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauge.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let active = self.maintenance.is_active(&self.chain, &self.endpoint);
+        self.gauge.set(active as i64);
+        self.gauge.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_once() {
+        assert_eq!(
+            "2024-05-01T02:00:00Z/2024-05-01T04:00:00+02:00".parse(),
+            Ok(MaintenanceWindow::Once {
+                start: at("2024-05-01T02:00:00Z"),
+                end: at("2024-05-01T02:00:00Z"),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_weekly() {
+        assert_eq!(
+            " Sun 03:00-03:30 ".parse(),
+            Ok(MaintenanceWindow::Weekly {
+                weekday: Weekday::Sun,
+                start: NaiveTime::from_hms_opt(3, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(3, 30, 0).unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for window in [
+            "",
+            "Sun",
+            "Someday 03:00-03:30",
+            "Sun 03:00",
+            "Sun 25:00-26:00",
+            "2024-05-01/2024-05-02",
+        ] {
+            assert!(
+                window.parse::<MaintenanceWindow>().is_err(),
+                "{} parsed",
+                window
+            );
+        }
+    }
+
+    #[test]
+    fn contains_once() {
+        let window = "2024-05-01T02:00:00Z/2024-05-01T04:00:00Z"
+            .parse::<MaintenanceWindow>()
+            .unwrap();
+        assert!(!window.contains(at("2024-05-01T01:59:59Z")));
+        assert!(window.contains(at("2024-05-01T02:00:00Z")));
+        assert!(window.contains(at("2024-05-01T03:59:59Z")));
+        assert!(!window.contains(at("2024-05-01T04:00:00Z")));
+    }
+
+    #[test]
+    fn contains_weekly() {
+        let window = "Sun 03:00-03:30".parse::<MaintenanceWindow>().unwrap();
+        // 2024-05-05 is a Sunday
+        assert!(window.contains(at("2024-05-05T03:00:00Z")));
+        assert!(window.contains(at("2024-05-12T03:29:59Z")));
+        assert!(!window.contains(at("2024-05-05T03:30:00Z")));
+        assert!(!window.contains(at("2024-05-06T03:15:00Z")));
+    }
+
+    #[test]
+    fn contains_past_midnight() {
+        let window = "Sat 23:00-01:00".parse::<MaintenanceWindow>().unwrap();
+        assert!(!window.contains(at("2024-05-04T22:59:59Z")));
+        assert!(window.contains(at("2024-05-04T23:30:00Z")));
+        assert!(window.contains(at("2024-05-05T00:59:59Z")));
+        assert!(!window.contains(at("2024-05-05T01:00:00Z")));
+        // the early hours of Saturday belong to Friday's night
+        assert!(!window.contains(at("2024-05-04T00:30:00Z")));
+    }
+
+    #[test]
+    fn provider_maintenance() {
+        let maintenance = Maintenance {
+            windows: Arc::new(vec![ProviderWindow {
+                chain: "ethereum".to_string(),
+                endpoint: "a".to_string(),
+                window: "2000-01-01T00:00:00Z/2100-01-01T00:00:00Z".parse().unwrap(),
+            }]),
+        };
+        let gauge = |chain: &str, endpoint: &str| {
+            let registry = Registry::new();
+            maintenance.register(chain, endpoint, &registry);
+            let families = registry.gather();
+            assert_eq!(families[0].get_name(), "provider_maintenance");
+            families[0].get_metric()[0].get_gauge().get_value()
+        };
+        assert_eq!(gauge("ethereum", "a"), 1.0);
+        assert_eq!(gauge("ethereum", "b"), 0.0);
+        assert_eq!(gauge("base", "a"), 0.0);
+    }
+}
@@ -0,0 +1,190 @@
+//! Run manifests.
+//!
+//! Results are only comparable, and only trustworthy when shared, if it is known what produced
+//! them. Every run report carries a manifest with the version and git commit of the binary, a
+//! hash of the configuration, the start and end of the run and the machine it ran on.
+//!
+//! The configuration hash covers everything that changes what is measured (chains, providers,
+//! intervals) with credentials redacted from the URLs, so two runs with the same hash measured
+//! the same thing even if their API keys differ.
+//!
+//! Each run gets a `run_id`, `RUN_ID` or generated from the start time, exported along with the
+//! configuration hash as the `run_info` metric. With `RUN_LABELS=true` both are also attached as
+//! labels to every metric, so overlapping runs from the same host stay apart during analysis at
+//! the cost of new series on every restart.
+//!
+//! With `MANIFEST_SIGNING_KEY` set to a hex encoded 32 byte ed25519 secret key, every report is
+//! signed and the signature written next to it as `<REPORT_PATH>.sig`.
+
+use crate::chain::ChainConfig;
+use crate::redact;
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signer as _, SigningKey};
+use ethers::core::rand::{thread_rng, Rng};
+use prometheus::{IntGauge, Opts, Registry};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Machine {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub cpus: usize,
+}
+
+impl Machine {
+    fn current() -> Self {
+        Self {
+            hostname: hostname::get()
+                .map(|hostname| hostname.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            os: env::consts::OS.to_string(),
+            arch: env::consts::ARCH.to_string(),
+            cpus: std::thread::available_parallelism()
+                .map(|cpus| cpus.get())
+                .unwrap_or(1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Manifest {
+    /// Identifies the run in metrics and reports.
+    pub run_id: String,
+    pub version: String,
+    /// Commit the binary was built from, when known at build time.
+    pub git_commit: Option<String>,
+    /// SHA-256 of the configuration, see [`config_hash`].
+    pub config_hash: String,
+    pub started_at: DateTime<Utc>,
+    /// Set by the report written on shutdown.
+    pub ended_at: Option<DateTime<Utc>>,
+    pub machine: Machine,
+    /// Whether to label every metric with the run id and configuration hash.
+    #[serde(skip)]
+    run_labels: bool,
+}
+
+impl Manifest {
+    pub fn new(chains: &[ChainConfig]) -> Self {
+        let started_at = Utc::now();
+        let run_id = env::var("RUN_ID").unwrap_or_else(|_| {
+            format!(
+                "{}-{:04x}",
+                started_at.format("%Y%m%dT%H%M%SZ"),
+                thread_rng().gen::<u16>()
+            )
+        });
+        let run_labels = env::var("RUN_LABELS").map(|v| v == "true").unwrap_or(false);
+
+        Self {
+            run_id,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: option_env!("BENCHETH_GIT_COMMIT").map(str::to_string),
+            config_hash: config_hash(chains),
+            started_at,
+            ended_at: None,
+            machine: Machine::current(),
+            run_labels,
+        }
+    }
+
+    /// Labels to attach to every metric of the run.
+    pub fn const_labels(&self) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        if self.run_labels {
+            labels.insert("run_id".to_string(), self.run_id.clone());
+            labels.insert("config_hash".to_string(), self.config_hash.clone());
+        }
+        labels
+    }
+
+    /// Export the `run_info` metric to `registry`.
+    pub fn register_info(&self, registry: &Registry) {
+        let mut opts = Opts::new("run_info", "Run metadata, always 1");
+        // the labels may already be attached to the registry
+        if !self.run_labels {
+            opts = opts
+                .const_label("run_id", &self.run_id)
+                .const_label("config_hash", &self.config_hash);
+        }
+        let info = IntGauge::with_opts(opts.const_label("version", &self.version).const_label(
+            "git_commit",
+            self.git_commit.as_deref().unwrap_or("unknown"),
+        ))
+        .unwrap();
+        info.set(1);
+        registry.register(Box::new(info)).unwrap();
+    }
+}
+
+/// Hex encoded SHA-256 of the chain configurations, with credentials redacted.
+pub fn config_hash(chains: &[ChainConfig]) -> String {
+    let mut hasher = Sha256::new();
+    for chain in chains {
+        hasher.update(format!(
+            "chain={} kind={:?} chain_id={:?} poll_interval={:?} expected_block_time={:?}\n",
+            chain.name, chain.kind, chain.chain_id, chain.poll_interval, chain.expected_block_time
+        ));
+        for url in &chain.rpc_urls {
+            hasher.update(format!("rpc={}\n", redact::url(url)));
+        }
+        for url in &chain.reference_urls {
+            hasher.update(format!("reference={}\n", redact::url(url)));
+        }
+        if let Some(url) = &chain.reference_rpc_url {
+            hasher.update(format!("reference_rpc={}\n", redact::url(url)));
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Detached signature of a report, written to `<report>.sig`.
+#[derive(Debug, Serialize)]
+pub struct Signature {
+    pub algorithm: &'static str,
+    pub public_key: String,
+    pub signature: String,
+}
+
+pub struct Signer {
+    key: SigningKey,
+}
+
+impl Signer {
+    /// `None` unless `MANIFEST_SIGNING_KEY` is set.
+    pub fn from_env() -> Option<Self> {
+        let key = env::var("MANIFEST_SIGNING_KEY").ok()?;
+        let key: [u8; 32] = hex::decode(key.trim())
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .expect("Invalid MANIFEST_SIGNING_KEY");
+        Some(Self {
+            key: SigningKey::from_bytes(&key),
+        })
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        Signature {
+            algorithm: "ed25519",
+            public_key: hex::encode(self.key.verifying_key().as_bytes()),
+            signature: hex::encode(self.key.sign(message).to_bytes()),
+        }
+    }
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signer")
+            .field(
+                "public_key",
+                &hex::encode(self.key.verifying_key().as_bytes()),
+            )
+            .finish()
+    }
+}
@@ -0,0 +1,185 @@
+//! A measured plain HTTP transport for chain APIs that aren't JSON-RPC 2.0.
+//!
+//! REST-style APIs (Tron, sequencer health endpoints) and legacy JSON-RPC dialects (Bitcoin Core)
+//! can't go through [`MeasuredJsonRpc`](crate::measured_json_rpc_client::MeasuredJsonRpc), but
+//! should still report the same `request_total`, `request_latency` and `request_errors` series.
+//! [`MeasuredHttp`] records latency and request counts for every call and leaves it to the caller
+//! to decide what counts as an error, since a non-2xx status can still carry a valid response.
+
+use crate::body_log::BodyLog;
+use crate::budget::Budget;
+use crate::error_kind::ErrorKind;
+use crate::limits::{self, RequestLimits};
+use crate::measured_json_rpc_client::Metrics;
+use crate::sketch::LatencySketches;
+use crate::tls;
+
+use prometheus::Registry;
+use reqwest::{Method, StatusCode, Url};
+use serde::Deserialize;
+use serde_json::Value;
+
+use std::time::Instant;
+
+/// The raw response of a measured HTTP request.
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct MeasuredHttp {
+    client: reqwest::Client,
+    base_url: Url,
+    basic_auth: Option<(String, String)>,
+    metrics: Metrics,
+    body_log: BodyLog,
+    budget: Budget,
+    limits: RequestLimits,
+}
+
+impl MeasuredHttp {
+    pub fn new(base_url: Url, basic_auth: Option<(String, String)>, registry: &Registry) -> Self {
+        Self {
+            client: tls::client_builder(registry)
+                .build()
+                .expect("could not initialize http"),
+            base_url,
+            basic_auth,
+            metrics: Metrics::new(registry),
+            body_log: BodyLog::from_env(),
+            budget: Budget::default(),
+            limits: RequestLimits::default(),
+        }
+    }
+
+    /// Count every request against `budget`, waiting while it is used up.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Send requests under `limits` as well as the global ones.
+    pub fn with_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn latency_sketches(&self) -> LatencySketches {
+        self.metrics.latency_sketches.clone()
+    }
+
+    /// Send a request to `path`, relative to the base URL. An empty path targets the base URL
+    /// itself.
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<HttpResponse, reqwest::Error> {
+        let url = self.url(path);
+        log::trace!("request: {} {}, body: {:?}", method, path, body);
+
+        let label = Self::method_label(path, body);
+
+        let mut request = self.client.request(method, url);
+        if let Some((username, password)) = &self.basic_auth {
+            request = request.basic_auth(username, Some(password));
+        }
+        let mut sent = 0;
+        if let Some(body) = body {
+            let body = serde_json::to_vec(body).unwrap_or_default();
+            sent = body.len();
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body);
+        }
+
+        let sampled = self.body_log.sample();
+        let request = request.build();
+        if let (true, Ok(request)) = (sampled, &request) {
+            let body = request.body().and_then(|body| body.as_bytes());
+            self.body_log
+                .log_request(request.url(), request.headers(), body.unwrap_or_default());
+        }
+
+        self.budget.admit().await;
+        let _group_permit = self.limits.acquire().await;
+        let _permit = limits::acquire().await;
+        let timer = self
+            .metrics
+            .request_latency
+            .with_label_values(&[&label])
+            .start_timer();
+        let start = Instant::now();
+        let res = match request {
+            Ok(request) => self.client.execute(request).await,
+            Err(e) => Err(e),
+        };
+        let res = match res {
+            Ok(response) => {
+                let (status, headers) = (response.status(), response.headers().clone());
+                let url = response.url().clone();
+                response.text().await.map(|body| {
+                    let elapsed = start.elapsed();
+                    self.metrics.request_io.observe(elapsed.as_secs_f64());
+                    self.metrics.record_bytes(&label, sent, body.len());
+                    self.metrics.record_server_timing(elapsed, &headers);
+                    if sampled {
+                        self.body_log
+                            .log_response(&url, status, &headers, body.as_bytes());
+                    }
+                    HttpResponse { status, body }
+                })
+            }
+            Err(e) => Err(e),
+        };
+        let latency = timer.stop_and_record();
+        self.metrics.latency_sketches.record(&label, latency);
+        self.metrics
+            .request_total
+            .with_label_values(&[&label])
+            .inc();
+
+        if let Err(e) = &res {
+            log::debug!("Reqwest error: {:?}", e);
+            let status = e.status().map(|s| s.as_u16());
+            self.record_error(
+                &label,
+                &status.map(|s| s.to_string()).unwrap_or_default(),
+                ErrorKind::from_status(status),
+            );
+        }
+
+        res
+    }
+
+    /// Deserialize a response body, timing it like the results of JSON-RPC requests.
+    pub fn deserialize<'a, R: Deserialize<'a>>(&self, body: &'a str) -> serde_json::Result<R> {
+        self.metrics.deserialize(body)
+    }
+
+    /// The `method` label of a request to `path` with `body`: JSON-RPC dialects are broken down
+    /// by their method, REST APIs by their path.
+    pub fn method_label(path: &str, body: Option<&Value>) -> String {
+        body.and_then(|body| body["method"].as_str())
+            .unwrap_or(if path.is_empty() { "/" } else { path })
+            .to_string()
+    }
+
+    /// Count an error returned by the API for a request of `method`, labelled with an HTTP status
+    /// or API error code and its kind.
+    pub fn record_error(&self, method: &str, code: &str, kind: ErrorKind) {
+        self.metrics.record_error(method, code, kind);
+    }
+
+    fn url(&self, path: &str) -> Url {
+        if path.is_empty() {
+            return self.base_url.clone();
+        }
+        let base = self.base_url.as_str().trim_end_matches('/');
+        let path = path.trim_start_matches('/');
+        Url::parse(&format!("{}/{}", base, path)).unwrap_or_else(|_| self.base_url.clone())
+    }
+}
@@ -1,19 +1,65 @@
 //! Create a custom data transport to use with a Provider.
 
+use crate::backend::{Backend, RpcBackend};
+use crate::body_log::BodyLog;
+use crate::budget::Budget;
+use crate::error_kind::ErrorKind;
+use crate::limits::{self, RequestLimits};
+use crate::outliers::Attempt;
+use crate::server_timing;
+use crate::sketch::LatencySketches;
+use crate::tls;
+use crate::vendor::Vendor;
+
 use async_trait::async_trait;
 use ethers::{
-    prelude::{Http, JsonRpcClient, ProviderError, RetryClientError, RpcError},
+    prelude::{JsonRpcClient, ProviderError, RetryClientError, RpcError},
     providers::{
-        HttpClientError, HttpRateLimitRetryPolicy, JsonRpcError, RetryClient, RetryClientBuilder,
-        RetryPolicy,
+        Authorization, ConnectionDetails, HttpClientError, HttpRateLimitRetryPolicy, Ipc, IpcError,
+        JsonRpcError, RetryClientBuilder, RetryPolicy, Ws, WsClientError,
     },
 };
-use prometheus::{histogram_opts, Histogram, IntCounter, IntCounterVec, Opts, Registry};
+use prometheus::{histogram_opts, Gauge, Histogram, HistogramVec, IntCounterVec, Opts, Registry};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::cell::Cell;
+use std::env;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use std::{fmt::Debug, str::FromStr};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::sync::Mutex;
+
+const SECONDS_PER_MONTH: f64 = 30.0 * 24.0 * 60.0 * 60.0;
+
+/// `request_latency` buckets in seconds, from fast local nodes to slow providers' tail.
+const DEFAULT_LATENCY_BUCKETS: [f64; 14] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// The `request_latency` and block latency budget buckets, `LATENCY_BUCKETS` if set: upper
+/// bounds in seconds, comma-separated and increasing.
+pub fn latency_buckets() -> Vec<f64> {
+    let Ok(buckets) = env::var("LATENCY_BUCKETS") else {
+        return DEFAULT_LATENCY_BUCKETS.to_vec();
+    };
+    let buckets = buckets
+        .split(',')
+        .map(|bucket| {
+            bucket
+                .trim()
+                .parse::<f64>()
+                .expect("Invalid LATENCY_BUCKETS")
+        })
+        .collect::<Vec<_>>();
+    if buckets.is_empty() || buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+        panic!("Invalid LATENCY_BUCKETS: must be increasing");
+    }
+    buckets
+}
 
 /// First we must create an error type, and implement [`From`] for
 /// [`ProviderError`].
@@ -28,6 +74,21 @@ use thiserror::Error;
 pub enum MeasuredJsonRpcError {
     #[error(transparent)]
     Http(#[from] RetryClientError),
+    /// Boxed, as it is several times larger than the other errors.
+    #[error(transparent)]
+    Ws(Box<WsClientError>),
+    #[error(transparent)]
+    Ipc(#[from] IpcError),
+    /// An error response received with the alloy backend.
+    #[cfg(feature = "alloy")]
+    #[error(transparent)]
+    JsonRpc(JsonRpcError),
+    /// Any other error of the alloy backend.
+    #[cfg(feature = "alloy")]
+    #[error("{0}")]
+    Transport(String),
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
 }
 
 /// In order to use our `InstrumentedJsonRpcError` in the RPC client, we have to implement
@@ -45,12 +106,20 @@ impl RpcError for MeasuredJsonRpcError {
     fn as_error_response(&self) -> Option<&ethers::providers::JsonRpcError> {
         match self {
             MeasuredJsonRpcError::Http(e) => e.as_error_response(),
+            MeasuredJsonRpcError::Ws(e) => e.as_error_response(),
+            MeasuredJsonRpcError::Ipc(e) => e.as_error_response(),
+            #[cfg(feature = "alloy")]
+            MeasuredJsonRpcError::JsonRpc(e) => Some(e),
+            _ => None,
         }
     }
 
     fn as_serde_error(&self) -> Option<&serde_json::Error> {
         match self {
             MeasuredJsonRpcError::Http(RetryClientError::SerdeJson(err)) => Some(err),
+            MeasuredJsonRpcError::Ws(e) => e.as_serde_error(),
+            MeasuredJsonRpcError::Ipc(e) => e.as_serde_error(),
+            MeasuredJsonRpcError::SerdeJson(err) => Some(err),
             _ => None,
         }
     }
@@ -65,26 +134,103 @@ impl From<MeasuredJsonRpcError> for ProviderError {
 }
 
 /// Define a struct to hold the metrics we want to track. For this example, we will track:
-/// - `request_total`: the total number of requests made to the RPC URL
-/// - `request_latency`: the time taken for the RPC URL to respond
-/// - `request_errors`: the total number of errors from the RPC URL
+/// - `request_total`: the total number of requests made to the RPC URL, per method
+/// - `request_latency`: the time taken for the RPC URL to respond, per method
+/// - `request_errors`: the total number of errors from the RPC URL, per method, error code and
+///   [kind](crate::error_kind)
+/// - `request_bytes_total` / `response_bytes_total`: the bytes sent and received per method,
+///   along with an estimate of the monthly traffic at the current rate
+/// - `server_processing_seconds` / `network_seconds`: the latency split by the processing time
+///   the provider reports, see [`server_timing`]
+/// - `request_io_seconds` / `response_deserialization_seconds`: the time spent awaiting network
+///   I/O and deserializing responses, to tell the provider's latency from our own parsing
+///
+/// The metrics are shared by every transport so all chains report the same series.
 #[derive(Clone, Debug)]
 pub struct Metrics {
-    request_total: IntCounter,
-    request_latency: Histogram,
+    pub request_total: IntCounterVec,
+    pub request_latency: HistogramVec,
+    /// Latency by method, for the run report.
+    pub latency_sketches: LatencySketches,
+    pub request_errors: IntCounterVec,
+    pub request_bytes: IntCounterVec,
+    pub response_bytes: IntCounterVec,
+    pub server_processing: Histogram,
+    pub network: Histogram,
+    pub request_io: Histogram,
+    pub response_deserialization: Histogram,
+    egress: EgressEstimate,
+    /// The vendor of the RPC URL, whose rate limit errors are retried.
+    pub vendor: Option<Vendor>,
+}
+
+/// Extrapolates the traffic since startup to a month, optionally priced per GB with
+/// `EGRESS_COST_PER_GB`.
+#[derive(Clone, Debug)]
+struct EgressEstimate {
+    started: Instant,
+    total_bytes: Arc<AtomicU64>,
+    monthly_bytes: Gauge,
+    monthly_cost: Option<(Gauge, f64)>,
+}
+
+impl EgressEstimate {
+    fn new(registry: &Registry) -> Self {
+        let monthly_bytes = Gauge::new(
+            "egress_monthly_bytes_estimate",
+            "Bytes sent and received in 30 days at the rate since startup",
+        )
+        .expect("could not create egress_monthly_bytes_estimate gauge");
+        registry
+            .register(Box::new(monthly_bytes.clone()))
+            .expect("could not register egress_monthly_bytes_estimate gauge");
+
+        let monthly_cost = env::var("EGRESS_COST_PER_GB").ok().map(|cost| {
+            let cost = cost.parse::<f64>().expect("Invalid EGRESS_COST_PER_GB");
+            let gauge = Gauge::new(
+                "egress_monthly_cost_estimate",
+                "Cost of the estimated monthly traffic at EGRESS_COST_PER_GB",
+            )
+            .expect("could not create egress_monthly_cost_estimate gauge");
+            registry
+                .register(Box::new(gauge.clone()))
+                .expect("could not register egress_monthly_cost_estimate gauge");
+            (gauge, cost)
+        });
+
+        Self {
+            started: Instant::now(),
+            total_bytes: Default::default(),
+            monthly_bytes,
+            monthly_cost,
+        }
+    }
+
+    fn record(&self, bytes: u64) {
+        let total = self.total_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let elapsed = self.started.elapsed().as_secs_f64().max(1.0);
+        let monthly = total as f64 / elapsed * SECONDS_PER_MONTH;
+        self.monthly_bytes.set(monthly);
+        if let Some((gauge, cost_per_gb)) = &self.monthly_cost {
+            gauge.set(monthly / 1e9 * cost_per_gb);
+        }
+    }
 }
 
 /// We implement a constructor method for our metrics, which will initialize the metrics and
 /// register them with the provided [`Registry`].
 impl Metrics {
-    fn new(registry: &Registry) -> Self {
-        let request_total =
-            IntCounter::new("request_total", "Total number of requests made to RPC URL")
-                .expect("could not create request_total counter");
-        let request_latency = Histogram::with_opts(histogram_opts!(
-            "request_latency",
-            "The time taken for RPC URL to respond"
-        ))
+    pub fn new(registry: &Registry) -> Self {
+        let request_total = IntCounterVec::new(
+            Opts::new("request_total", "Total number of requests made to RPC URL"),
+            &["method"],
+        )
+        .expect("could not create request_total counter");
+        let request_latency = HistogramVec::new(
+            histogram_opts!("request_latency", "The time taken for RPC URL to respond")
+                .buckets(latency_buckets()),
+            &["method"],
+        )
         .expect("could not create request_latency histogram");
         registry
             .register(Box::new(request_total.clone()))
@@ -92,87 +238,544 @@ impl Metrics {
         registry
             .register(Box::new(request_latency.clone()))
             .expect("could not register request_latency histogram");
+        let request_errors = IntCounterVec::new(
+            Opts::new("request_errors", "Total number of errors from RPC URL"),
+            &["method", "code", "kind"],
+        )
+        .expect("could not create request_errors counter");
+        registry
+            .register(Box::new(request_errors.clone()))
+            .expect("could not register request_errors counter");
+        let request_bytes = IntCounterVec::new(
+            Opts::new(
+                "request_bytes_total",
+                "Total bytes of request bodies sent to RPC URL",
+            ),
+            &["method"],
+        )
+        .expect("could not create request_bytes_total counter");
+        registry
+            .register(Box::new(request_bytes.clone()))
+            .expect("could not register request_bytes_total counter");
+        let response_bytes = IntCounterVec::new(
+            Opts::new(
+                "response_bytes_total",
+                "Total bytes of response bodies received from RPC URL",
+            ),
+            &["method"],
+        )
+        .expect("could not create response_bytes_total counter");
+        registry
+            .register(Box::new(response_bytes.clone()))
+            .expect("could not register response_bytes_total counter");
+        let server_processing = Histogram::with_opts(histogram_opts!(
+            "server_processing_seconds",
+            "Processing time reported by the RPC URL in its Server-Timing or X-Response-Time header"
+        ))
+        .expect("could not create server_processing_seconds histogram");
+        registry
+            .register(Box::new(server_processing.clone()))
+            .expect("could not register server_processing_seconds histogram");
+        let network = Histogram::with_opts(histogram_opts!(
+            "network_seconds",
+            "Time taken for a response beyond the processing time reported by the RPC URL"
+        ))
+        .expect("could not create network_seconds histogram");
+        registry
+            .register(Box::new(network.clone()))
+            .expect("could not register network_seconds histogram");
+        let request_io = Histogram::with_opts(histogram_opts!(
+            "request_io_seconds",
+            "Time spent awaiting network I/O for a request to RPC URL, including retries"
+        ))
+        .expect("could not create request_io_seconds histogram");
+        registry
+            .register(Box::new(request_io.clone()))
+            .expect("could not register request_io_seconds histogram");
+        let response_deserialization = Histogram::with_opts(
+            histogram_opts!(
+                "response_deserialization_seconds",
+                "Time taken to deserialize the responses to a request to RPC URL"
+            )
+            .buckets(prometheus::exponential_buckets(0.00001, 4.0, 10).unwrap()),
+        )
+        .expect("could not create response_deserialization_seconds histogram");
+        registry
+            .register(Box::new(response_deserialization.clone()))
+            .expect("could not register response_deserialization_seconds histogram");
         Self {
             request_total,
             request_latency,
+            latency_sketches: LatencySketches::default(),
+            request_errors,
+            request_bytes,
+            response_bytes,
+            server_processing,
+            network,
+            request_io,
+            response_deserialization,
+            egress: EgressEstimate::new(registry),
+            vendor: None,
         }
     }
+
+    /// Retry the rate limit errors of the vendor of `url` only, if it is known.
+    pub fn with_vendor(mut self, url: &reqwest::Url) -> Self {
+        self.vendor = url.host_str().and_then(Vendor::detect);
+        self
+    }
+
+    /// Deserialize a result from `json`, timing it. Results borrowing from `json`, e.g.
+    /// [`RawValue`], skip the copy.
+    pub fn deserialize<'a, R: Deserialize<'a>>(&self, json: &'a str) -> serde_json::Result<R> {
+        let _timer = self.response_deserialization.start_timer();
+        serde_json::from_str(json)
+    }
+
+    /// Split the `elapsed` time of a response into the processing time reported in its
+    /// `headers`, if any, and the network time.
+    pub fn record_server_timing(&self, elapsed: Duration, headers: &HeaderMap) {
+        let Some(server_time) = server_timing::server_time(headers) else {
+            return;
+        };
+        self.server_processing.observe(server_time.as_secs_f64());
+        self.network
+            .observe(elapsed.saturating_sub(server_time).as_secs_f64());
+    }
+
+    /// Count an error of a request of `method`, labelled with an HTTP status or error code and
+    /// its kind.
+    pub fn record_error(&self, method: &str, code: &str, kind: ErrorKind) {
+        self.request_errors
+            .with_label_values(&[method, code, kind.as_str()])
+            .inc();
+    }
+
+    /// Count the bytes of a request body sent and its response body received.
+    pub fn record_bytes(&self, method: &str, sent: usize, received: usize) {
+        self.request_bytes
+            .with_label_values(&[method])
+            .inc_by(sent as u64);
+        self.response_bytes
+            .with_label_values(&[method])
+            .inc_by(received as u64);
+        self.egress.record((sent + received) as u64);
+    }
 }
 
-/// Create a measured retry policy that will track the number of errors from the RPC URL.
+/// Time spent by the attempts of a measured request awaiting network I/O and parsing responses.
+#[derive(Debug, Default)]
+struct RequestTiming {
+    io: Cell<Duration>,
+    parse: Cell<Duration>,
+}
+
+tokio::task_local! {
+    static TIMING: RequestTiming;
+    /// The method of the request being measured, for the errors counted by the retry policy,
+    /// which doesn't see it.
+    static METHOD: String;
+}
+
+/// Count an error of the request being measured, if any, labelled with an HTTP status or error
+/// code and its kind.
+pub fn count_error(request_errors: &IntCounterVec, code: &str, kind: ErrorKind) {
+    let method = METHOD.try_with(Clone::clone).unwrap_or_default();
+    request_errors
+        .with_label_values(&[&method, code, kind.as_str()])
+        .inc();
+}
+
+/// Add time spent awaiting network I/O to the request being measured, if any.
+pub fn record_io(elapsed: Duration) {
+    let _ = TIMING.try_with(|timing| timing.io.set(timing.io.get() + elapsed));
+}
+
+/// Add time spent parsing a response to the request being measured, if any.
+pub fn record_parse(elapsed: Duration) {
+    let _ = TIMING.try_with(|timing| timing.parse.set(timing.parse.get() + elapsed));
+}
+
+/// A JSON-RPC over HTTP transport like ethers' [`Http`], which also counts the bytes on the
+/// wire. Every attempt of a retried request is counted, as it is paid for all the same.
 #[derive(Debug)]
-pub struct MeasuredHttpRateLimitRetryPolicy {
-    request_errors: Arc<IntCounterVec>,
-    default_policy: HttpRateLimitRetryPolicy,
+pub struct MeteredHttp {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    id: AtomicU64,
+    metrics: Metrics,
+    body_log: BodyLog,
+    /// Whether requests carry an `Authorization` header.
+    authenticated: bool,
 }
 
-impl MeasuredHttpRateLimitRetryPolicy {
-    pub fn new(registry: &Registry) -> Self {
-        let request_errors = IntCounterVec::new(
-            Opts::new("request_errors", "Total number of errors from RPC URL"),
-            &["code"],
-        )
-        .expect("could not create request_errors counter");
+#[async_trait]
+impl JsonRpcClient for MeteredHttp {
+    type Error = HttpClientError;
 
-        registry
-            .register(Box::new(request_errors.clone()))
-            .expect("could not register request_errors counter");
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        #[derive(Serialize)]
+        struct Request<'a, T> {
+            id: u64,
+            jsonrpc: &'a str,
+            method: &'a str,
+            // like ethers, omit the params of parameterless calls instead of sending `null`
+            #[serde(skip_serializing_if = "is_zst")]
+            params: T,
+        }
+
+        fn is_zst<T>(_: &T) -> bool {
+            std::mem::size_of::<T>() == 0
+        }
+
+        // the result is deserialized in the same pass as the envelope, a `Box<RawValue>` result
+        // is only copied out of the body
+        #[derive(Deserialize)]
+        struct Response<R> {
+            result: Option<R>,
+            #[serde(default)]
+            error: Option<JsonRpcError>,
+        }
+
+        let id = self.id.fetch_add(1, Ordering::SeqCst);
+        let payload = serde_json::to_vec(&Request {
+            id,
+            jsonrpc: "2.0",
+            method,
+            params,
+        })
+        .map_err(|err| HttpClientError::SerdeJson {
+            err,
+            text: String::new(),
+        })?;
+        let sent = payload.len();
+
+        let request = self
+            .client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(payload)
+            .build()?;
+        let sampled = self.body_log.sample();
+        if sampled {
+            let body = request.body().and_then(|body| body.as_bytes());
+            self.body_log
+                .log_request(&self.url, request.headers(), body.unwrap_or_default());
+        }
+
+        let attempt = Attempt::capture(method, &request, self.authenticated);
+        let start = Instant::now();
+        let mut response_status = None;
+        let res = async {
+            let res = self.client.execute(request).await?;
+            let (status, headers) = (res.status(), res.headers().clone());
+            response_status = Some(status);
+            let body = res.bytes().await?;
+            let elapsed = start.elapsed();
+            record_io(elapsed);
+            self.metrics.record_bytes(method, sent, body.len());
+            self.metrics.record_server_timing(elapsed, &headers);
+            if sampled {
+                self.body_log
+                    .log_response(&self.url, status, &headers, &body);
+            }
+
+            let start = Instant::now();
+            let text = || String::from_utf8_lossy(&body).to_string();
+            let response = serde_json::from_slice::<Response<R>>(&body);
+            record_parse(start.elapsed());
+            let response =
+                response.map_err(|err| HttpClientError::SerdeJson { err, text: text() })?;
+            if let Some(error) = response.error {
+                return Err(HttpClientError::JsonRpcError(error));
+            }
+
+            match response.result {
+                Some(result) => Ok(result),
+                // a `null` or missing result, for results which may be absent
+                None => serde_json::from_str("null")
+                    .map_err(|err| HttpClientError::SerdeJson { err, text: text() }),
+            }
+        }
+        .await;
+        if let Some(attempt) = attempt {
+            attempt.finish(start.elapsed(), response_status, &res);
+        }
+        res
+    }
+}
 
+/// How rate limited and timed out requests are retried: up to `RETRY_RATE_LIMIT_RETRIES`
+/// (default 10) times when rate limited and `RETRY_TIMEOUT_RETRIES` (default 3, HTTP only) times
+/// when timed out, waiting `RETRY_INITIAL_BACKOFF_MS` (default 500) before the first retry and
+/// twice as long before every further one.
+#[derive(Clone, Copy, Debug)]
+pub struct RetrySettings {
+    pub rate_limit_retries: u32,
+    pub timeout_retries: u32,
+    pub initial_backoff: Duration,
+}
+
+impl RetrySettings {
+    pub fn from_env() -> Self {
+        let var = |var: &str, default: u64| {
+            env::var(var)
+                .ok()
+                .map(|value| {
+                    value
+                        .parse::<u64>()
+                        .unwrap_or_else(|_| panic!("Invalid {}", var))
+                })
+                .unwrap_or(default)
+        };
         Self {
-            request_errors: Arc::new(request_errors),
-            default_policy: HttpRateLimitRetryPolicy::default(),
+            rate_limit_retries: var("RETRY_RATE_LIMIT_RETRIES", 10) as u32,
+            timeout_retries: var("RETRY_TIMEOUT_RETRIES", 3) as u32,
+            initial_backoff: Duration::from_millis(var("RETRY_INITIAL_BACKOFF_MS", 500)),
         }
     }
 }
 
-/// We implement the [`HttpRateLimitRetryPolicy`] trait for our measured retry policy.
-/// This will allow us to use our custom retry policy with the [`RetryClient`].
-/// We will simply increment the counter for the error code, then return the default
-/// retry policy.
-impl RetryPolicy<HttpClientError> for MeasuredHttpRateLimitRetryPolicy {
-    fn should_retry(&self, error: &HttpClientError) -> bool {
-        fn should_retry_json_rpc_error(err: &JsonRpcError, req_errs: Arc<IntCounterVec>) -> bool {
-            let JsonRpcError { code, message, .. } = err;
+/// Reconnects of a WebSocket transport before its requests fail.
+const WS_RECONNECTS: usize = 10;
 
-            log::debug!("JSON RPC error: code={}, message={}", code, message);
-            req_errs.with_label_values(&[&code.to_string()]).inc();
+/// A JSON-RPC over WebSocket transport wrapping ethers' [`Ws`], selected for `ws://` and
+/// `wss://` URLs. It connects on its first request, so it can be created like the HTTP transports,
+/// and connects again on the next request once ethers gave up reconnecting a closed connection.
+/// The frames on the wire aren't exposed, so the bytes counted are those of the JSON-RPC payloads.
+/// Rate limited requests are retried like with [`RetryClient`](ethers::providers::RetryClient).
+#[derive(Debug)]
+pub struct MeasuredWs {
+    conn: ConnectionDetails,
+    ws: Mutex<Option<Ws>>,
+    metrics: Metrics,
+    retry: RetrySettings,
+}
 
-            // alchemy throws it this way
-            if *code == 429 {
-                return true;
-            }
+impl MeasuredWs {
+    fn new(url: reqwest::Url, auth: Option<Authorization>, metrics: Metrics) -> Self {
+        Self {
+            conn: ConnectionDetails::new(url, auth),
+            ws: Mutex::new(None),
+            metrics,
+            retry: RetrySettings::from_env(),
+        }
+    }
+
+    async fn ws(&self) -> Result<Ws, WsClientError> {
+        let mut ws = self.ws.lock().await;
+        if let Some(ws) = ws.as_ref() {
+            return Ok(ws.clone());
+        }
+        let connected = Ws::connect_with_reconnects(self.conn.clone(), WS_RECONNECTS).await?;
+        *ws = Some(connected.clone());
+        Ok(connected)
+    }
+}
+
+#[async_trait]
+impl Backend for MeasuredWs {
+    async fn request(
+        &self,
+        method: &str,
+        params: Option<Box<RawValue>>,
+    ) -> Result<Box<RawValue>, MeasuredJsonRpcError> {
+        let sent = method.len() + params.as_ref().map_or(0, |params| params.get().len());
+        let mut backoff = self.retry.initial_backoff;
+        let mut retries = 0;
+
+        loop {
+            let start = Instant::now();
+            let res = match self.ws().await {
+                Ok(ws) => match &params {
+                    Some(params) => {
+                        JsonRpcClient::request::<_, Box<RawValue>>(&ws, method, params).await
+                    }
+                    None => JsonRpcClient::request::<_, Box<RawValue>>(&ws, method, ()).await,
+                },
+                Err(e) => Err(e),
+            };
+            record_io(start.elapsed());
 
-            // This is an infura error code for `exceeded project rate limit`
-            if *code == -32005 {
-                return true;
+            let e = match res {
+                Ok(result) => {
+                    self.metrics.record_bytes(method, sent, result.get().len());
+                    return Ok(result);
+                }
+                Err(e) => e,
+            };
+            self.metrics.record_bytes(method, sent, 0);
+            if matches!(
+                e,
+                WsClientError::UnexpectedClose
+                    | WsClientError::DeadChannel
+                    | WsClientError::TooManyReconnects
+            ) {
+                // the connection is gone for good, the next request connects again
+                *self.ws.lock().await = None;
             }
 
-            // alternative alchemy error for specific IPs
-            if *code == -32016 && message.contains("rate limit") {
-                return true;
+            let should_retry = match &e {
+                WsClientError::JsonRpcError(error) => should_retry_json_rpc_error(
+                    error,
+                    &self.metrics.request_errors,
+                    self.metrics.vendor,
+                ),
+                _ => {
+                    count_error(&self.metrics.request_errors, "ws", ErrorKind::Transport);
+                    false
+                }
+            };
+            if !should_retry || retries >= self.retry.rate_limit_retries {
+                return Err(MeasuredJsonRpcError::Ws(Box::new(e)));
             }
+            retries += 1;
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
+
+/// A JSON-RPC transport over the IPC socket (or named pipe on Windows) of a local node, wrapping
+/// ethers' [`Ipc`], to measure a node's baseline latency without the HTTP stack. It connects on
+/// its first request, and connects again on the next request once the node closed the socket,
+/// e.g. when it restarted. The bytes counted are those of the JSON-RPC payloads. Requests aren't
+/// retried, as a local node doesn't rate limit.
+#[derive(Debug)]
+pub struct MeasuredIpc {
+    path: PathBuf,
+    ipc: Mutex<Option<Ipc>>,
+    metrics: Metrics,
+}
+
+impl MeasuredIpc {
+    async fn ipc(&self) -> Result<Ipc, IpcError> {
+        let mut ipc = self.ipc.lock().await;
+        if let Some(ipc) = ipc.as_ref() {
+            return Ok(ipc.clone());
+        }
+        let connected = Ipc::connect(&self.path).await?;
+        *ipc = Some(connected.clone());
+        Ok(connected)
+    }
+}
+
+#[async_trait]
+impl Backend for MeasuredIpc {
+    async fn request(
+        &self,
+        method: &str,
+        params: Option<Box<RawValue>>,
+    ) -> Result<Box<RawValue>, MeasuredJsonRpcError> {
+        let sent = method.len() + params.as_ref().map_or(0, |params| params.get().len());
 
-            match message.as_str() {
-                // this is commonly thrown by infura and is apparently a load balancer issue, see also <https://github.com/MetaMask/metamask-extension/issues/7234>
-                "header not found" => true,
-                // also thrown by infura if out of budget for the day and ratelimited
-                "daily request count exceeded, request rate limited" => true,
-                _ => false,
+        let start = Instant::now();
+        let res = match self.ipc().await {
+            Ok(ipc) => match params {
+                Some(params) => {
+                    JsonRpcClient::request::<_, Box<RawValue>>(&ipc, method, params).await
+                }
+                None => JsonRpcClient::request::<_, Box<RawValue>>(&ipc, method, ()).await,
+            },
+            Err(e) => Err(e),
+        };
+        record_io(start.elapsed());
+
+        match res {
+            Ok(result) => {
+                self.metrics.record_bytes(method, sent, result.get().len());
+                Ok(result)
+            }
+            Err(e) => {
+                self.metrics.record_bytes(method, sent, 0);
+                if matches!(
+                    e,
+                    IpcError::IoError(_)
+                        | IpcError::ChannelError(_)
+                        | IpcError::RequestCancelled(_)
+                        | IpcError::ServerExit
+                ) {
+                    // the connection is gone, the next request connects again
+                    *self.ipc.lock().await = None;
+                }
+                match &e {
+                    IpcError::JsonRpcError(error) => {
+                        should_retry_json_rpc_error(
+                            error,
+                            &self.metrics.request_errors,
+                            self.metrics.vendor,
+                        );
+                    }
+                    _ => count_error(&self.metrics.request_errors, "ipc", ErrorKind::Transport),
+                }
+                Err(e.into())
             }
         }
+    }
+}
+
+/// Count a JSON-RPC error by its code and kind, and tell whether the request should be retried,
+/// because the provider rate limited it or failed in a known transient way.
+pub fn should_retry_json_rpc_error(
+    err: &JsonRpcError,
+    request_errors: &IntCounterVec,
+    vendor: Option<Vendor>,
+) -> bool {
+    let JsonRpcError { code, message, .. } = err;
+
+    let kind = ErrorKind::classify(*code, message, vendor);
+    log::debug!(
+        "JSON RPC error: code={}, message={}, kind={}",
+        code,
+        message,
+        kind.as_str()
+    );
+    count_error(request_errors, &code.to_string(), kind);
+    kind.is_retryable()
+}
+
+/// Create a measured retry policy that will track the number of errors from the RPC URL.
+#[derive(Debug)]
+pub struct MeasuredHttpRateLimitRetryPolicy {
+    request_errors: Arc<IntCounterVec>,
+    vendor: Option<Vendor>,
+    default_policy: HttpRateLimitRetryPolicy,
+}
+
+impl MeasuredHttpRateLimitRetryPolicy {
+    pub fn new(request_errors: IntCounterVec, vendor: Option<Vendor>) -> Self {
+        Self {
+            request_errors: Arc::new(request_errors),
+            vendor,
+            default_policy: HttpRateLimitRetryPolicy,
+        }
+    }
+}
 
+/// We implement the [`HttpRateLimitRetryPolicy`] trait for our measured retry policy.
+/// This will allow us to use our custom retry policy with the [`RetryClient`].
+/// We will simply increment the counter for the error code, then return the default
+/// retry policy.
+impl RetryPolicy<HttpClientError> for MeasuredHttpRateLimitRetryPolicy {
+    fn should_retry(&self, error: &HttpClientError) -> bool {
         match error {
             HttpClientError::ReqwestError(err) => {
-                let status = err
-                    .status()
-                    .map(|s| s.as_u16().to_string())
-                    .unwrap_or_default();
+                let status = err.status().map(|s| s.as_u16());
+                let kind = ErrorKind::from_status(status);
                 log::debug!("Reqwest error: {:?}", err);
-                self.request_errors.with_label_values(&[&status]).inc();
-                err.status() == Some(http::StatusCode::TOO_MANY_REQUESTS)
+                count_error(
+                    &self.request_errors,
+                    &status.map(|s| s.to_string()).unwrap_or_default(),
+                    kind,
+                );
+                kind.is_retryable()
             }
             HttpClientError::JsonRpcError(err) => {
-                should_retry_json_rpc_error(err, self.request_errors.clone())
+                should_retry_json_rpc_error(err, &self.request_errors, self.vendor)
             }
             HttpClientError::SerdeJson { text, .. } => {
                 // some providers send invalid JSON RPC in the error case (no `id:u64`), but the
@@ -186,9 +789,13 @@ impl RetryPolicy<HttpClientError> for MeasuredHttpRateLimitRetryPolicy {
                 log::debug!("SerdeJSON error: {}", &text);
 
                 if let Ok(resp) = serde_json::from_str::<Resp>(text) {
-                    return should_retry_json_rpc_error(&resp.error, self.request_errors.clone());
+                    return should_retry_json_rpc_error(
+                        &resp.error,
+                        &self.request_errors,
+                        self.vendor,
+                    );
                 }
-                self.request_errors.with_label_values(&["unknown"]).inc();
+                count_error(&self.request_errors, "unknown", ErrorKind::Other);
                 false
             }
         }
@@ -200,11 +807,14 @@ impl RetryPolicy<HttpClientError> for MeasuredHttpRateLimitRetryPolicy {
 }
 
 /// Next, we create our transport type, which in this case will be a struct that contains
-/// only [`RetryClient<Http>`] and our metrics.
+/// only the [`Backend`] sending the requests, [`RetryClient<MeteredHttp>`] by default, and our
+/// metrics.
 #[derive(Clone, Debug)]
 pub struct MeasuredJsonRpc {
-    client: Arc<RetryClient<Http>>,
+    client: Arc<dyn Backend>,
     metrics: Metrics,
+    budget: Budget,
+    limits: RequestLimits,
 }
 
 // We implement a convenience "constructor" method, to easily initialize the transport.
@@ -212,21 +822,130 @@ pub struct MeasuredJsonRpc {
 // It will also bind the metrics to the registry.
 impl MeasuredJsonRpc {
     pub fn new(url: impl Into<String>, registry: &Registry) -> Self {
-        let http = Http::from_str(url.into().as_str()).expect("could not initialize http");
+        let url: reqwest::Url = url.into().parse().expect("could not parse url");
+        Self::connect(url, None, registry)
+    }
 
+    /// Connect to the IPC socket of a local node at `path`.
+    pub fn new_ipc(path: impl Into<PathBuf>, registry: &Registry) -> Self {
+        let metrics = Metrics::new(registry);
+        Self {
+            client: Arc::new(MeasuredIpc {
+                path: path.into(),
+                ipc: Mutex::new(None),
+                metrics: metrics.clone(),
+            }),
+            metrics,
+            budget: Budget::default(),
+            limits: RequestLimits::default(),
+        }
+    }
+
+    /// Same as [`MeasuredJsonRpc::new`], but authenticates every request with `auth`.
+    pub fn new_with_auth(url: impl Into<String>, auth: Authorization, registry: &Registry) -> Self {
+        let url: reqwest::Url = url.into().parse().expect("could not parse url");
+        Self::connect(url, Some(auth), registry)
+    }
+
+    /// Connect over WebSocket for `ws://` and `wss://` URLs, and otherwise with the backend
+    /// selected by `RPC_BACKEND`.
+    fn connect(url: reqwest::Url, auth: Option<Authorization>, registry: &Registry) -> Self {
+        if matches!(url.scheme(), "ws" | "wss") {
+            let metrics = Metrics::new(registry).with_vendor(&url);
+            return Self {
+                client: Arc::new(MeasuredWs::new(url, auth, metrics.clone())),
+                metrics,
+                budget: Budget::default(),
+                limits: RequestLimits::default(),
+            };
+        }
+
+        match RpcBackend::from_env() {
+            RpcBackend::Ethers => {
+                let authenticated = auth.is_some();
+                let mut builder = tls::client_builder(registry);
+                if let Some(auth) = auth {
+                    let mut auth_value =
+                        HeaderValue::from_str(&auth.to_string()).expect("invalid auth");
+                    auth_value.set_sensitive(true);
+                    let mut headers = HeaderMap::new();
+                    headers.insert(AUTHORIZATION, auth_value);
+                    builder = builder.default_headers(headers);
+                }
+                let client = builder.build().expect("could not initialize http");
+                Self::from_http(url, client, authenticated, registry)
+            }
+            #[cfg(feature = "alloy")]
+            RpcBackend::Alloy => {
+                let metrics = Metrics::new(registry).with_vendor(&url);
+                Self {
+                    client: Arc::new(crate::backend::AlloyBackend::new(
+                        &url,
+                        auth,
+                        metrics.clone(),
+                    )),
+                    metrics,
+                    budget: Budget::default(),
+                    limits: RequestLimits::default(),
+                }
+            }
+            #[cfg(not(feature = "alloy"))]
+            RpcBackend::Alloy => unreachable!("RPC_BACKEND=alloy requires the alloy feature"),
+        }
+    }
+
+    fn from_http(
+        url: reqwest::Url,
+        client: reqwest::Client,
+        authenticated: bool,
+        registry: &Registry,
+    ) -> Self {
+        let metrics = Metrics::new(registry).with_vendor(&url);
+        let http = MeteredHttp {
+            client,
+            url,
+            id: AtomicU64::new(1),
+            metrics: metrics.clone(),
+            body_log: BodyLog::from_env(),
+            authenticated,
+        };
+        let retry = RetrySettings::from_env();
         let client = Arc::new(
             RetryClientBuilder::default()
-                .rate_limit_retries(10)
-                .timeout_retries(3)
-                .initial_backoff(Duration::from_millis(500))
+                .rate_limit_retries(retry.rate_limit_retries)
+                .timeout_retries(retry.timeout_retries)
+                .initial_backoff(retry.initial_backoff)
                 .build(
                     http,
-                    Box::new(MeasuredHttpRateLimitRetryPolicy::new(registry)),
+                    Box::new(MeasuredHttpRateLimitRetryPolicy::new(
+                        metrics.request_errors.clone(),
+                        metrics.vendor,
+                    )),
                 ),
         );
 
-        let metrics = Metrics::new(registry);
-        Self { client, metrics }
+        Self {
+            client,
+            metrics,
+            budget: Budget::default(),
+            limits: RequestLimits::default(),
+        }
+    }
+
+    /// Count every request against `budget`, waiting while it is used up.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Send requests under `limits` as well as the global ones.
+    pub fn with_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn latency_sketches(&self) -> LatencySketches {
+        self.metrics.latency_sketches.clone()
     }
 }
 
@@ -248,10 +967,47 @@ impl JsonRpcClient for MeasuredJsonRpc {
         R: DeserializeOwned + Send,
     {
         log::trace!("request: method: {}, params: {:?}", method, params);
-        let timer = self.metrics.request_latency.start_timer();
-        let res = self.client.request(method, params).await;
-        timer.observe_duration();
-        self.metrics.request_total.inc();
-        res.map_err(Into::into)
+        self.budget.admit().await;
+        let _group_permit = self.limits.acquire().await;
+        let _permit = limits::acquire().await;
+        // like ethers, parameterless calls are told apart by their zero-sized params
+        let params = if std::mem::size_of::<T>() == 0 {
+            None
+        } else {
+            Some(serde_json::value::to_raw_value(&params)?)
+        };
+        let timer = self
+            .metrics
+            .request_latency
+            .with_label_values(&[method])
+            .start_timer();
+        let (res, timing) = METHOD
+            .scope(
+                method.to_string(),
+                TIMING.scope(RequestTiming::default(), async {
+                    let res = self.client.request(method, params).await;
+                    (
+                        res,
+                        TIMING.with(|timing| (timing.io.get(), timing.parse.get())),
+                    )
+                }),
+            )
+            .await;
+        let latency = timer.stop_and_record();
+        self.metrics.latency_sketches.record(method, latency);
+        self.metrics
+            .request_total
+            .with_label_values(&[method])
+            .inc();
+
+        let (io, mut parse) = timing;
+        let start = Instant::now();
+        let result = res.and_then(|raw| Ok(serde_json::from_str(raw.get())?));
+        parse += start.elapsed();
+        self.metrics.request_io.observe(io.as_secs_f64());
+        self.metrics
+            .response_deserialization
+            .observe(parse.as_secs_f64());
+        result
     }
 }
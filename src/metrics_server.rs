@@ -1,37 +1,513 @@
+use crate::openmetrics;
+use crate::outliers;
+use crate::recent_blocks::BlockHistory;
+use crate::recommendations::RecommendationFeed;
+use crate::slo::Slos;
+use crate::watchdog::Watchdog;
+
+use std::collections::BTreeMap;
 use std::env;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Response, Server};
-use prometheus::{Encoder, Registry, TextEncoder};
-
-pub async fn start_metrics_server(registry: Registry) {
-    let make_svc = make_service_fn(|_| {
-        let registry = registry.clone();
-        async {
-            Ok::<_, hyper::Error>(service_fn(move |_req| {
-                let metric_families = registry.gather();
-                let mut buffer = vec![];
-                let encoder = TextEncoder::new();
-                encoder.encode(&metric_families, &mut buffer).unwrap();
-
-                let response = Response::builder()
-                    .status(200)
-                    .body(Body::from(buffer))
+use hyper::{Body, Request, Response, Server};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    histogram_opts, Encoder, Gauge, Histogram, IntCounter, Registry, TextEncoder, TEXT_FORMAT,
+};
+use tokio::sync::{broadcast, watch, Notify};
+
+const DEFAULT_FINAL_SCRAPE_TIMEOUT_SECS: u64 = 10;
+
+/// The prefix of the per-chain metrics paths, `/metrics/<chain>`.
+const CHAIN_PATH_PREFIX: &str = "/metrics/";
+
+/// Keeps the metrics server up for a final scrape when the agent shuts down, so the state at the
+/// end of the run isn't lost between the last scrape and the exit. Once the measurements have
+/// stopped, [`FinalScrape::wait`] waits up to `FINAL_SCRAPE_TIMEOUT_SECS` (default 10, 0 to
+/// skip) for the metrics to be scraped, then stops the server, letting scrapes in progress
+/// complete.
+#[derive(Clone, Debug)]
+pub struct FinalScrape {
+    timeout: Duration,
+    stopping: Arc<AtomicBool>,
+    scraped: Arc<Notify>,
+    closed: Arc<watch::Sender<bool>>,
+}
+
+impl FinalScrape {
+    pub fn from_env() -> Self {
+        let timeout = env::var("FINAL_SCRAPE_TIMEOUT_SECS")
+            .ok()
+            .map(|secs| {
+                secs.parse::<u64>()
+                    .expect("Invalid FINAL_SCRAPE_TIMEOUT_SECS")
+            })
+            .unwrap_or(DEFAULT_FINAL_SCRAPE_TIMEOUT_SECS);
+        Self {
+            timeout: Duration::from_secs(timeout),
+            stopping: Default::default(),
+            scraped: Default::default(),
+            closed: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Wait for a scrape of the final state, then stop the metrics server.
+    pub async fn wait(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if !self.timeout.is_zero() {
+            log::info!(
+                "Waiting up to {}s for a final scrape",
+                self.timeout.as_secs()
+            );
+            if tokio::time::timeout(self.timeout, self.scraped.notified())
+                .await
+                .is_err()
+            {
+                log::warn!("The metrics weren't scraped before shutting down");
+            }
+        }
+        self.closed.send_replace(true);
+    }
+
+    fn record_scrape(&self) {
+        if self.stopping.load(Ordering::SeqCst) {
+            self.scraped.notify_one();
+        }
+    }
+}
+
+/// The exporter's own metrics, as the exposition grows slow and large with many providers and
+/// labels. `METRICS_MAX_BYTES` caps the size of a scrape response: the metric families that don't
+/// fit are left out, with a warning.
+#[derive(Clone)]
+struct ScrapeMetrics {
+    registry: Registry,
+    duration: Histogram,
+    response_bytes: Gauge,
+    scrapes: IntCounter,
+    truncations: IntCounter,
+    max_bytes: Option<usize>,
+}
+
+impl ScrapeMetrics {
+    fn from_env() -> Self {
+        let registry = Registry::new();
+        let duration = Histogram::with_opts(
+            histogram_opts!(
+                "exporter_scrape_duration_seconds",
+                "Time taken to gather and encode the metrics for a scrape"
+            )
+            .buckets(prometheus::exponential_buckets(0.0005, 4.0, 8).unwrap()),
+        )
+        .expect("could not create exporter_scrape_duration_seconds histogram");
+        registry
+            .register(Box::new(duration.clone()))
+            .expect("could not register exporter_scrape_duration_seconds histogram");
+        let response_bytes = Gauge::new(
+            "exporter_scrape_response_bytes",
+            "Size of the last scrape response",
+        )
+        .expect("could not create exporter_scrape_response_bytes gauge");
+        registry
+            .register(Box::new(response_bytes.clone()))
+            .expect("could not register exporter_scrape_response_bytes gauge");
+        let scrapes = IntCounter::new("exporter_scrapes_total", "Number of scrapes served")
+            .expect("could not create exporter_scrapes_total counter");
+        registry
+            .register(Box::new(scrapes.clone()))
+            .expect("could not register exporter_scrapes_total counter");
+        let truncations = IntCounter::new(
+            "exporter_scrape_truncations_total",
+            "Number of scrape responses cut at METRICS_MAX_BYTES",
+        )
+        .expect("could not create exporter_scrape_truncations_total counter");
+        registry
+            .register(Box::new(truncations.clone()))
+            .expect("could not register exporter_scrape_truncations_total counter");
+
+        let max_bytes = env::var("METRICS_MAX_BYTES")
+            .ok()
+            .map(|bytes| bytes.parse::<usize>().expect("Invalid METRICS_MAX_BYTES"));
+
+        Self {
+            registry,
+            duration,
+            response_bytes,
+            scrapes,
+            truncations,
+            max_bytes,
+        }
+    }
+
+    /// Encode `families` in the text format, or OpenMetrics, up to the size cap.
+    fn encode(&self, families: &[MetricFamily], openmetrics: bool, start: Instant) -> Vec<u8> {
+        let encoder = TextEncoder::new();
+        let mut buffer = vec![];
+        let mut family_buffer = vec![];
+        // OpenMetrics must end with `# EOF` even if cut
+        let reserved = if openmetrics {
+            openmetrics::EOF.len()
+        } else {
+            0
+        };
+        for (i, family) in families.iter().enumerate() {
+            family_buffer.clear();
+            if openmetrics {
+                openmetrics::encode(family, &mut family_buffer);
+            } else {
+                encoder
+                    .encode(std::slice::from_ref(family), &mut family_buffer)
                     .unwrap();
-                async { Ok::<_, hyper::Error>(response) }
-            }))
+            }
+            if let Some(max_bytes) = self.max_bytes {
+                if buffer.len() + family_buffer.len() + reserved > max_bytes {
+                    log::warn!(
+                        "Metrics exceed METRICS_MAX_BYTES ({} bytes), leaving out {} of {} families",
+                        max_bytes,
+                        families.len() - i,
+                        families.len()
+                    );
+                    self.truncations.inc();
+                    break;
+                }
+            }
+            buffer.extend_from_slice(&family_buffer);
         }
-    });
+        if openmetrics {
+            buffer.extend_from_slice(openmetrics::EOF);
+        }
+
+        self.duration.observe(start.elapsed().as_secs_f64());
+        self.response_bytes.set(buffer.len() as f64);
+        self.scrapes.inc();
+        buffer
+    }
+}
+
+/// Gather the metrics of all registries, merging families that share a name so each family is
+/// only exposed once.
+pub fn gather(registries: &[Registry]) -> Vec<MetricFamily> {
+    let mut families: BTreeMap<String, MetricFamily> = BTreeMap::new();
+    for registry in registries {
+        for mut family in registry.gather() {
+            match families.get_mut(family.get_name()) {
+                Some(existing) => existing.mut_metric().extend(family.take_metric()),
+                None => {
+                    families.insert(family.get_name().to_string(), family);
+                }
+            }
+        }
+    }
+    families.into_values().collect()
+}
 
-    // pull port from env or default to 9090
-    let port = env::var("METRICS_PORT")
+/// The path of the healthcheck endpoint, `HEALTHCHECK_PATH` (default `/healthz`).
+pub fn healthcheck_path() -> String {
+    env::var("HEALTHCHECK_PATH").unwrap_or_else(|_| "/healthz".to_string())
+}
+
+/// The port metrics are served on, `METRICS_PORT` (default 9090).
+pub fn port() -> u16 {
+    env::var("METRICS_PORT")
         .unwrap_or_else(|_| "9090".to_string())
         .parse::<u16>()
-        .unwrap();
-    let addr = ([0, 0, 0, 0], port).into();
-    let server = Server::bind(&addr).serve(make_svc);
+        .unwrap()
+}
+
+/// `200 OK` while some provider's head is advancing, `503 Service Unavailable` otherwise, for
+/// container and load balancer healthchecks.
+fn healthcheck_response(watchdog: &Watchdog) -> Response<Body> {
+    let status = if watchdog.healthy() { 200 } else { 503 };
+    Response::builder()
+        .status(status)
+        .body(Body::from(watchdog.status()))
+        .unwrap()
+}
+
+/// Query the healthcheck endpoint of the agent on this host, for `bencheth healthcheck` in images
+/// without curl.
+pub async fn check_health() -> Result<String, Box<dyn Error>> {
+    let url = format!("http://127.0.0.1:{}{}", port(), healthcheck_path());
+    let response = reqwest::Client::new()
+        .get(url)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, body).into());
+    }
+    Ok(body)
+}
 
-    if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
+/// The value of parameter `name` in `query`.
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Answer `/slo` with `200 OK` when the providers asked about, of `chain` only on the port of a
+/// chain, meet the SLOs, `503 Service Unavailable` otherwise and `404 Not Found` when there are
+/// none to judge.
+fn slo_response(slos: Option<&Slos>, chain: Option<&str>, query: Option<&str>) -> Response<Body> {
+    let Some(slos) = slos else {
+        return Response::builder()
+            .status(404)
+            .body(Body::from("No SLOs are configured"))
+            .unwrap();
+    };
+    let chain = query_param(query, "chain").or(chain);
+    match slos.status(chain, query_param(query, "provider")) {
+        Some((met, status)) => Response::builder()
+            .status(if met { 200 } else { 503 })
+            .body(Body::from(status))
+            .unwrap(),
+        None => Response::builder()
+            .status(404)
+            .body(Body::from("Unknown provider"))
+            .unwrap(),
+    }
+}
+
+/// Answer `/recommendations` with the current recommendations as JSON, or with `stream=true` as
+/// newline-delimited JSON followed by every change until the client disconnects.
+fn recommendations_response(feed: &RecommendationFeed, query: Option<&str>) -> Response<Body> {
+    if query_param(query, "stream") != Some("true") {
+        return Response::builder()
+            .status(200)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(feed.to_json()))
+            .unwrap();
     }
+
+    // subscribed before the snapshot is taken so no change falls in between
+    let mut changes = feed.subscribe();
+    let current = feed.current();
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let line = |recommendation| {
+            let mut line = serde_json::to_vec(&recommendation).unwrap_or_default();
+            line.push(b'\n');
+            hyper::body::Bytes::from(line)
+        };
+        for recommendation in current {
+            if sender.send_data(line(recommendation)).await.is_err() {
+                return;
+            }
+        }
+        loop {
+            match changes.recv().await {
+                Ok(recommendation) => {
+                    if sender.send_data(line(recommendation)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("A recommendations stream missed {} changes", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+    Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, "application/x-ndjson")
+        .body(body)
+        .unwrap()
+}
+
+/// Answer `/blocks` with the recent blocks, of `chain` only on the port of a chain, `404 Not
+/// Found` when they aren't kept.
+fn blocks_response(
+    history: Option<&BlockHistory>,
+    chain: Option<&str>,
+    query: Option<&str>,
+) -> Response<Body> {
+    let Some(history) = history else {
+        return Response::builder()
+            .status(404)
+            .body(Body::from("Recent blocks are disabled"))
+            .unwrap();
+    };
+    let param = |name: &str| query_param(query, name);
+    let limit = match param("limit").map(str::parse::<usize>) {
+        Some(Ok(limit)) => Some(limit),
+        Some(Err(_)) => {
+            return Response::builder()
+                .status(400)
+                .body(Body::from("Invalid limit"))
+                .unwrap()
+        }
+        None => None,
+    };
+    Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(history.to_json(param("chain").or(chain), limit)))
+        .unwrap()
+}
+
+/// What the metrics server serves besides the metrics.
+#[derive(Clone)]
+pub struct Endpoints {
+    /// `/blocks`, `None` when recent blocks aren't kept.
+    pub history: Option<BlockHistory>,
+    /// `/slo`, `None` when no SLOs are configured.
+    pub slos: Option<Slos>,
+    /// `/recommendations`.
+    pub recommendations: RecommendationFeed,
+}
+
+/// Keep the metrics of `chain` only, dropping the families left empty.
+fn only_chain(families: Vec<MetricFamily>, chain: &str) -> Vec<MetricFamily> {
+    families
+        .into_iter()
+        .filter_map(|mut family| {
+            let metrics = family
+                .take_metric()
+                .into_iter()
+                .filter(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_name() == "chain" && label.get_value() == chain)
+                })
+                .collect::<Vec<_>>();
+            if metrics.is_empty() {
+                return None;
+            }
+            family.set_metric(metrics.into());
+            Some(family)
+        })
+        .collect()
+}
+
+/// Serve the metrics of all chains on `METRICS_PORT`, and the metrics of a single chain on the
+/// ports given in `chain_ports`, for scrape configs with a job per chain. With `METRICS_PER_CHAIN`
+/// set to `true`, `/metrics/<chain>` serves the metrics of a single chain on any port as well.
+pub async fn start_metrics_server(
+    registries: Vec<Registry>,
+    chain_ports: Vec<(String, Option<u16>)>,
+    endpoints: Endpoints,
+    watchdog: Watchdog,
+    final_scrape: FinalScrape,
+) {
+    let per_chain = env::var("METRICS_PER_CHAIN").is_ok_and(|v| v == "true");
+    let chains = Arc::new(
+        chain_ports
+            .iter()
+            .map(|(chain, _)| chain.clone())
+            .collect::<Vec<_>>(),
+    );
+    let healthcheck_path = healthcheck_path();
+    let scrape_metrics = ScrapeMetrics::from_env();
+    let mut registries = registries;
+    registries.push(scrape_metrics.registry.clone());
+
+    let ports = std::iter::once((port(), None)).chain(
+        chain_ports
+            .into_iter()
+            .filter_map(|(chain, port)| Some((port?, Some(chain)))),
+    );
+    let servers = ports.map(|(port, port_chain)| {
+        let mut closed = final_scrape.closed.subscribe();
+        let registries = registries.clone();
+        let scrape_metrics = scrape_metrics.clone();
+        let watchdog = watchdog.clone();
+        let healthcheck_path = healthcheck_path.clone();
+        let final_scrape = final_scrape.clone();
+        let chains = chains.clone();
+        let endpoints = endpoints.clone();
+        let make_svc = make_service_fn(move |_| {
+            let registries = registries.clone();
+            let scrape_metrics = scrape_metrics.clone();
+            let watchdog = watchdog.clone();
+            let healthcheck_path = healthcheck_path.clone();
+            let final_scrape = final_scrape.clone();
+            let chains = chains.clone();
+            let port_chain = port_chain.clone();
+            let endpoints = endpoints.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                    let path = req.uri().path();
+                    // the chain whose metrics are requested, all chains if `None`
+                    let chain = match path.strip_prefix(CHAIN_PATH_PREFIX) {
+                        Some(chain) if per_chain => Some(chain.to_string()),
+                        _ => port_chain.clone(),
+                    };
+                    let response = if path == healthcheck_path {
+                        healthcheck_response(&watchdog)
+                    } else if path == "/blocks" {
+                        blocks_response(
+                            endpoints.history.as_ref(),
+                            chain.as_deref(),
+                            req.uri().query(),
+                        )
+                    } else if path == "/slo" {
+                        slo_response(endpoints.slos.as_ref(), chain.as_deref(), req.uri().query())
+                    } else if path == "/recommendations" {
+                        recommendations_response(&endpoints.recommendations, req.uri().query())
+                    } else if path == "/outliers" {
+                        Response::builder()
+                            .status(200)
+                            .header(hyper::header::CONTENT_TYPE, "application/json")
+                            .body(Body::from(outliers::to_json()))
+                            .unwrap()
+                    } else if chain.as_ref().is_some_and(|chain| !chains.contains(chain)) {
+                        Response::builder()
+                            .status(404)
+                            .body(Body::from("Unknown chain"))
+                            .unwrap()
+                    } else {
+                        let start = Instant::now();
+                        let openmetrics = req
+                            .headers()
+                            .get(hyper::header::ACCEPT)
+                            .and_then(|accept| accept.to_str().ok())
+                            .is_some_and(openmetrics::preferred);
+                        let mut metric_families = gather(&registries);
+                        if let Some(chain) = &chain {
+                            metric_families = only_chain(metric_families, chain);
+                        }
+                        let buffer = scrape_metrics.encode(&metric_families, openmetrics, start);
+                        final_scrape.record_scrape();
+
+                        let content_type = if openmetrics {
+                            openmetrics::CONTENT_TYPE
+                        } else {
+                            TEXT_FORMAT
+                        };
+                        Response::builder()
+                            .status(200)
+                            .header(hyper::header::CONTENT_TYPE, content_type)
+                            .body(Body::from(buffer))
+                            .unwrap()
+                    };
+                    async { Ok::<_, hyper::Error>(response) }
+                }))
+            }
+        });
+
+        let addr = ([0, 0, 0, 0], port).into();
+        let server = Server::bind(&addr)
+            .serve(make_svc)
+            .with_graceful_shutdown(async move {
+                let _ = closed.wait_for(|closed| *closed).await;
+            });
+        async move {
+            if let Err(e) = server.await {
+                eprintln!("server error on port {}: {}", port, e);
+            }
+        }
+    });
+
+    futures::future::join_all(servers).await;
 }
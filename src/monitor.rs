@@ -0,0 +1,495 @@
+//! Block monitoring loop for a single provider of a chain.
+//!
+//! New heads are detected, their blocks fetched and their reorgs observed on the detection path,
+//! which must keep polling at its interval for the propagation and lag measurements to hold.
+//! Verifications of a block that wait on further requests run concurrently off that path, block
+//! after block. When `<CHAIN>_VERIFICATION_QUEUE` (default 16) blocks are waiting to be verified,
+//! the verifications of new blocks are skipped until the queue has room again.
+
+use crate::backfill::Backfill;
+use crate::block_content::BlockContent;
+use crate::block_metrics::BlockMetrics;
+use crate::block_trace::{BlockTrace, ProviderTracer};
+use crate::chain::ChainConfig;
+use crate::completeness::Completeness;
+use crate::extended::ExtendedMethods;
+use crate::header_hash::HeaderHash;
+use crate::logs::LogQueries;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::raw::RawRetrieval;
+use crate::receipts_root::ReceiptsRoot;
+use crate::reorg::Reorgs;
+use crate::rpc_method::RpcMethod;
+use crate::schedule::Schedule;
+use crate::simulation::Simulations;
+use crate::stale::StaleData;
+use crate::transactions_root::TransactionsRoot;
+
+use chrono::{DateTime, Utc};
+use ethers::prelude::*;
+use futures::future::BoxFuture;
+use prometheus::Registry;
+use serde_json::value::RawValue;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+const DEFAULT_VERIFICATION_QUEUE: usize = 16;
+
+/// A new block left to the verifications.
+struct Verification {
+    number: U64,
+    block: Block<H256>,
+    trace: Option<BlockTrace>,
+}
+
+pub struct Monitor {
+    chain: Arc<ChainConfig>,
+    endpoint: String,
+    provider: Arc<Provider<MeasuredJsonRpc>>,
+    block_metrics: BlockMetrics,
+    block_content: BlockContent,
+    header_hash: Option<HeaderHash>,
+    transactions: Schedule,
+    simulations: Option<Simulations>,
+    raw: Option<RawRetrieval>,
+    transactions_root: Option<TransactionsRoot>,
+    receipts_root: Option<ReceiptsRoot>,
+    extended: Option<ExtendedMethods>,
+    logs: Option<LogQueries>,
+    backfill: Option<Arc<Backfill>>,
+    completeness: Option<Arc<Completeness>>,
+    reorgs: Reorgs,
+    stale: StaleData,
+    verification_queue: usize,
+    tracer: Option<ProviderTracer>,
+}
+
+impl Monitor {
+    /// Monitor the provider named `endpoint` through `provider`, reporting to its `registry`.
+    pub fn new(
+        chain: Arc<ChainConfig>,
+        endpoint: impl Into<String>,
+        provider: Arc<Provider<MeasuredJsonRpc>>,
+        registry: &Registry,
+        block_metrics: BlockMetrics,
+    ) -> Self {
+        let endpoint = endpoint.into();
+        let block_content = BlockContent::from_chain(&chain, registry);
+        let header_hash = HeaderHash::from_chain(&chain, &endpoint, registry);
+        let transactions = Schedule::from_chain(&chain, "TRANSACTIONS");
+        let simulations = Simulations::from_chain(&chain, registry);
+        let raw = RawRetrieval::from_chain(&chain, registry);
+        let transactions_root = TransactionsRoot::from_chain(&chain, &endpoint, registry);
+        let receipts_root = ReceiptsRoot::from_chain(&chain, &endpoint, registry);
+        let extended = ExtendedMethods::from_chain(&chain, registry);
+        let logs = LogQueries::from_chain(&chain, registry);
+        let backfill = Backfill::from_chain(&chain, registry).map(Arc::new);
+        let completeness =
+            Completeness::from_chain(&chain, block_metrics.chain_head().clone(), registry)
+                .map(Arc::new);
+        let reorgs = Reorgs::new(&chain.name, &endpoint, registry);
+        let stale = StaleData::new(&chain.name, &endpoint, registry);
+        let verification_queue = chain
+            .var("VERIFICATION_QUEUE")
+            .map(|n| n.parse::<usize>().expect("Invalid VERIFICATION_QUEUE"))
+            .unwrap_or(DEFAULT_VERIFICATION_QUEUE)
+            .max(1);
+
+        Self {
+            chain,
+            endpoint,
+            provider,
+            block_metrics,
+            block_content,
+            header_hash,
+            transactions,
+            simulations,
+            raw,
+            transactions_root,
+            receipts_root,
+            extended,
+            logs,
+            backfill,
+            completeness,
+            reorgs,
+            stale,
+            verification_queue,
+            tracer: None,
+        }
+    }
+
+    /// Trace the processing of sampled blocks with `tracer`.
+    pub fn with_tracer(mut self, tracer: ProviderTracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Make sure the provider serves the chain we expect before we start measuring it.
+    async fn check_chain_id(&self) -> bool {
+        let Some(expected) = self.chain.chain_id else {
+            return true;
+        };
+
+        match self.provider.get_chainid().await {
+            Ok(chain_id) if chain_id == U256::from(expected) => true,
+            Ok(chain_id) => {
+                log::error!(
+                    "[{}] {} reports chain id {} but {} was expected, not monitoring it",
+                    self.chain.name,
+                    self.endpoint,
+                    chain_id,
+                    expected
+                );
+                false
+            }
+            Err(e) => {
+                // don't give up on a provider because of a transient error
+                log::warn!(
+                    "[{}] Failed to get chain id from {}: {:?}",
+                    self.chain.name,
+                    self.endpoint,
+                    e
+                );
+                true
+            }
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        if !self.check_chain_id().await {
+            return;
+        }
+
+        let (verifications, queue) = mpsc::channel(self.verification_queue);
+        let monitor = async {
+            tokio::join!(self.watch(verifications), self.verify(queue));
+        };
+
+        // the backfill worker goes down and is restarted with the monitor
+        match &self.backfill {
+            Some(backfill) => {
+                let backfill = backfill
+                    .clone()
+                    .run(self.provider.clone(), self.completeness.clone());
+                tokio::select! {
+                    _ = monitor => {}
+                    _ = backfill => {}
+                }
+            }
+            None => monitor.await,
+        }
+    }
+
+    /// Poll the provider for new blocks and process them, leaving their verifications to
+    /// `verifications`.
+    async fn watch(&self, verifications: mpsc::Sender<Verification>) {
+        let chain = &self.chain.name;
+        let provider = &self.provider;
+
+        // This uses eth_getFilterChanges underneath the hood which does not work well with RPC providers that load balance 😿
+        // let mut stream = provider
+        //     .watch_blocks()
+        //     .await
+        //     .expect("Failed to watch blocks");
+
+        // check for new blocks every poll interval
+        let mut interval = self.block_metrics.poll_interval(self.chain.poll_interval);
+
+        loop {
+            interval.tick().await;
+
+            let mut curr_block_height = match provider.get_block_number().await {
+                Ok(b) => b,
+                Err(e) => {
+                    log::warn!("[{}] Failed to get block number: {:?}", chain, e);
+                    continue;
+                }
+            };
+
+            if curr_block_height == U64::zero() {
+                continue;
+            }
+
+            self.block_metrics.observe_head(curr_block_height.as_u64());
+
+            log::info!("[{}] Current block height: {}", chain, curr_block_height);
+            self.block_metrics.set_height(curr_block_height.as_u64());
+
+            loop {
+                interval.tick().await;
+
+                let detect_start = SystemTime::now();
+                let latest_block_height = match provider.get_block_number().await {
+                    Ok(b) => b,
+                    Err(e) => {
+                        log::warn!("[{}] Failed to get block number: {:?}", chain, e);
+                        continue;
+                    }
+                };
+
+                self.block_metrics
+                    .observe_head(latest_block_height.as_u64());
+
+                if latest_block_height == curr_block_height {
+                    continue;
+                }
+
+                if latest_block_height < curr_block_height {
+                    self.stale
+                        .regression(curr_block_height.as_u64(), latest_block_height.as_u64());
+                    continue;
+                }
+                let detected_at = SystemTime::now();
+
+                log::info!(
+                    "[{}] Current block height: {} ({} new blocks)",
+                    chain,
+                    latest_block_height,
+                    latest_block_height - curr_block_height
+                );
+
+                // leave the blocks in between to the backfill worker to get back to the head
+                if let Some(backfill) = &self.backfill {
+                    let behind = (latest_block_height - curr_block_height).as_u64();
+                    if behind > backfill.max_catch_up() {
+                        backfill.skipped(
+                            curr_block_height.as_u64() + 1,
+                            latest_block_height.as_u64() - 1,
+                        );
+                        curr_block_height = latest_block_height - 1;
+                    }
+                }
+
+                while curr_block_height < latest_block_height {
+                    curr_block_height += U64::one();
+                    let mut trace = self.tracer.as_ref().and_then(|tracer| {
+                        tracer.start(curr_block_height.as_u64(), detect_start, detected_at)
+                    });
+
+                    let fetch_start = SystemTime::now();
+                    let block = match provider.get_block(curr_block_height).await {
+                        Ok(b) => b,
+                        Err(e) => {
+                            log::warn!(
+                                "[{}] Failed to get block {:?}: {:?}",
+                                chain,
+                                curr_block_height,
+                                e
+                            );
+                            if let Some(backfill) = &self.backfill {
+                                backfill.missed(curr_block_height.as_u64());
+                            }
+                            continue;
+                        }
+                    };
+
+                    let Some(block) = block else {
+                        self.stale
+                            .unavailable(curr_block_height.as_u64(), latest_block_height.as_u64());
+                        if let Some(backfill) = &self.backfill {
+                            backfill.missed(curr_block_height.as_u64());
+                        }
+                        continue;
+                    };
+                    let fetched_at = SystemTime::now();
+                    if let Some(trace) = &mut trace {
+                        trace.span("fetch_block", fetch_start, fetched_at);
+                    }
+
+                    let timestamp =
+                        DateTime::<Utc>::from_timestamp(block.timestamp.as_u64() as i64, 0)
+                            .expect("Invalid block timestamp");
+                    self.block_metrics.observe_block(
+                        block.number.unwrap().as_u64(),
+                        &format!("{:?}", block.hash.unwrap_or_default()),
+                        timestamp,
+                        Some(block.transactions.len()),
+                    );
+                    self.block_content.observe(&block);
+                    if let Some(header_hash) = &self.header_hash {
+                        header_hash.verify(&block);
+                    }
+                    if let Some(hash) = block.hash {
+                        self.reorgs
+                            .observe(
+                                provider,
+                                curr_block_height.as_u64(),
+                                hash,
+                                block.parent_hash,
+                            )
+                            .await;
+                    }
+                    if let Some(completeness) = &self.completeness {
+                        completeness.observe_block(&block);
+                    }
+
+                    let mut transactions = block.transactions.clone();
+                    let fetch_transactions = self.transactions.is_active();
+                    if !fetch_transactions {
+                        transactions.clear();
+                    }
+                    let fan_out_start = SystemTime::now();
+                    let transactions = tokio_stream::iter(transactions)
+                        .map(|tx_hsh| {
+                            let tx_provider = provider.clone();
+                            async move {
+                                let start = SystemTime::now();
+                                let found = get_transaction(&tx_hsh, tx_provider).await;
+                                (tx_hsh, start, SystemTime::now(), found)
+                            }
+                        })
+                        .buffer_unordered(num_cpus::get())
+                        .collect::<Vec<_>>()
+                        .await;
+                    let fan_out_end = SystemTime::now();
+                    let since = |start: SystemTime, end: SystemTime| {
+                        end.duration_since(start).unwrap_or_default()
+                    };
+                    self.block_metrics.observe_budget(
+                        timestamp,
+                        detected_at.into(),
+                        since(fetch_start, fetched_at),
+                        fetch_transactions.then(|| since(fan_out_start, fan_out_end)),
+                    );
+                    if let Some(trace) = &mut trace {
+                        let fan_out = trace.span("transactions", fan_out_start, fan_out_end);
+                        for (tx_hsh, start, end, _) in &transactions {
+                            trace.child(
+                                fan_out,
+                                "fetch_transaction",
+                                *start,
+                                *end,
+                                vec![("tx.hash", format!("{:?}", tx_hsh))],
+                            );
+                        }
+                    }
+
+                    if let Some(completeness) = &self.completeness {
+                        if fetch_transactions && transactions.iter().all(|(.., found)| *found) {
+                            completeness.observe_transactions(curr_block_height.as_u64());
+                        }
+                        completeness.update();
+                    }
+
+                    log::info!(
+                        "[{}] New block height {} at {} with timestamp {} with {} txs found after {}.",
+                        chain,
+                        block.number.unwrap().as_u64(),
+                        block.hash.unwrap(),
+                        timestamp,
+                        transactions.len(),
+                        Utc::now() - timestamp
+                    );
+
+                    let verification = Verification {
+                        number: curr_block_height,
+                        block,
+                        trace,
+                    };
+                    if let Err(TrySendError::Full(verification)) =
+                        verifications.try_send(verification)
+                    {
+                        log::warn!(
+                            "[{}] {} blocks of {} are waiting to be verified, skipping block {}",
+                            chain,
+                            self.verification_queue,
+                            self.endpoint,
+                            curr_block_height
+                        );
+                        if let Some(trace) = verification.trace {
+                            trace.finish();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run the verifications of new blocks, block after block, each block's concurrently.
+    async fn verify(&self, mut queue: mpsc::Receiver<Verification>) {
+        let provider = &self.provider;
+
+        while let Some(Verification {
+            number,
+            block,
+            trace,
+        }) = queue.recv().await
+        {
+            let first_tx = block.transactions.first().copied();
+            let mut verifications: Vec<(&'static str, BoxFuture<'_, ()>)> = Vec::new();
+            if let Some(simulations) = &self.simulations {
+                verifications.push(("simulations", Box::pin(simulations.run(provider, number))));
+            }
+            if let Some(raw) = &self.raw {
+                verifications.push((
+                    "raw_retrieval",
+                    Box::pin(raw.run(provider, number, first_tx)),
+                ));
+            }
+            if let Some(transactions_root) = &self.transactions_root {
+                verifications.push((
+                    "transactions_root",
+                    Box::pin(transactions_root.run(provider, number)),
+                ));
+            }
+            if let Some(receipts_root) = &self.receipts_root {
+                verifications.push((
+                    "receipts_root",
+                    Box::pin(receipts_root.run(provider, &block)),
+                ));
+            }
+            if let Some(extended) = &self.extended {
+                verifications.push((
+                    "extended_methods",
+                    Box::pin(extended.run(
+                        provider,
+                        number.as_u64(),
+                        block.hash.unwrap_or_default(),
+                    )),
+                ));
+            }
+            if let Some(logs) = &self.logs {
+                verifications.push(("logs", Box::pin(logs.run(provider, number.as_u64()))));
+            }
+
+            let spans = futures::future::join_all(verifications.into_iter().map(
+                |(name, verification)| async move {
+                    let start = SystemTime::now();
+                    verification.await;
+                    (name, start, SystemTime::now())
+                },
+            ))
+            .await;
+            if let Some(mut trace) = trace {
+                for (name, start, end) in spans {
+                    trace.span(name, start, end);
+                }
+                trace.finish();
+            }
+        }
+    }
+}
+
+/// Whether the transaction is found.
+async fn get_transaction(tx_hsh: &H256, provider: Arc<Provider<MeasuredJsonRpc>>) -> bool {
+    // only whether it's found matters, so the transaction is left unparsed
+    let tx = match provider
+        .request::<_, Option<Box<RawValue>>>(RpcMethod::EthGetTransactionByHash.as_str(), [tx_hsh])
+        .await
+    {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::warn!("Failed to get transaction {:?}: {:?}", tx_hsh, e);
+            return false;
+        }
+    };
+
+    if tx.is_none() {
+        return false;
+    }
+
+    log::trace!("Transaction {:?} found at {}", tx_hsh, Utc::now());
+    true
+}
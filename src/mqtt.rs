@@ -0,0 +1,189 @@
+//! MQTT publishing for edge deployments.
+//!
+//! Agents at the edge often report over constrained links where MQTT is the standard transport.
+//! With `MQTT_URL` set (`mqtt://[user:password@]host[:port]`, or `mqtts://` for TLS), the agent
+//! publishes under `MQTT_TOPIC_PREFIX` (default `bencheth`):
+//!
+//! - `<prefix>/<chain>/<rpc>/blocks`: every observed block, as sent by the
+//!   [block webhook](crate::block_webhook).
+//! - `<prefix>/<chain>/<rpc>/alerts`: alerts as they fire and resolve.
+//! - `<prefix>/<chain>/<rpc>/status`: the provider's status on every change, retained.
+//! - `<prefix>/agents/<client id>/online`: `true` while connected, retained, and set to `false`
+//!   by the broker when the agent disappears.
+//!
+//! Messages are published with QoS `MQTT_QOS` (0, 1 or 2, default 1) by the client
+//! `MQTT_CLIENT_ID` (default `bencheth-<hostname>`). Block events queue up like those of the
+//! webhook, so a slow link never holds up the measurements, while alerts and status changes are
+//! dropped when the client can't take them, so it never holds up alerting either.
+
+use crate::alert::{Alert, AlertSink, StatusChange};
+use crate::block_webhook::{BlockEvent, BlockEvents};
+
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS, Transport};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use std::env;
+use std::time::Duration;
+
+const DEFAULT_TOPIC_PREFIX: &str = "bencheth";
+const DEFAULT_QUEUE: usize = 1024;
+
+/// Requests buffered by the MQTT client before publishing waits.
+const CLIENT_CAPACITY: usize = 64;
+
+/// Publishes alerts and status changes of the [`Mqtt`] connection.
+#[derive(Clone)]
+pub struct MqttSink {
+    client: AsyncClient,
+    prefix: String,
+    qos: QoS,
+}
+
+impl MqttSink {
+    /// Publish a message, waiting while the client's buffer is full.
+    async fn publish(&self, topic: String, retain: bool, message: &impl Serialize) {
+        let payload = serde_json::to_vec(message).expect("Failed to serialize MQTT message");
+        if let Err(e) = self.client.publish(topic, self.qos, retain, payload).await {
+            log::warn!("Failed to publish MQTT message: {:?}", e);
+        }
+    }
+
+    /// Publish a message, dropping it while the client's buffer is full, e.g. when the broker is
+    /// unreachable.
+    fn try_publish(&self, topic: String, retain: bool, message: &impl Serialize) {
+        let payload = serde_json::to_vec(message).expect("Failed to serialize MQTT message");
+        if let Err(e) = self.client.try_publish(topic, self.qos, retain, payload) {
+            log::warn!("Failed to publish MQTT message, dropping it: {:?}", e);
+        }
+    }
+}
+
+// alerts are sent in line with the evaluation of the rules, so they're never waited on
+#[async_trait]
+impl AlertSink for MqttSink {
+    async fn alert(&self, alert: &Alert) {
+        let topic = format!("{}/{}/{}/alerts", self.prefix, alert.chain, alert.rpc);
+        self.try_publish(topic, false, alert);
+    }
+
+    async fn status_changed(&self, change: &StatusChange) {
+        let topic = format!("{}/{}/{}/status", self.prefix, change.chain, change.rpc);
+        self.try_publish(topic, true, change);
+    }
+}
+
+pub struct Mqtt {
+    sink: MqttSink,
+    eventloop: EventLoop,
+    online_topic: String,
+    sender: mpsc::Sender<BlockEvent>,
+    receiver: mpsc::Receiver<BlockEvent>,
+}
+
+impl Mqtt {
+    /// `None` unless `MQTT_URL` is set.
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("MQTT_URL").ok()?;
+        let url = reqwest::Url::parse(&url).expect("Invalid MQTT_URL");
+        let tls = match url.scheme() {
+            "mqtt" | "tcp" => false,
+            "mqtts" | "ssl" => true,
+            _ => panic!("Invalid MQTT_URL: the scheme must be mqtt or mqtts"),
+        };
+        let host = url.host_str().expect("Invalid MQTT_URL: missing host");
+        let port = url.port().unwrap_or(if tls { 8883 } else { 1883 });
+
+        let client_id = env::var("MQTT_CLIENT_ID").unwrap_or_else(|_| {
+            let hostname = hostname::get().unwrap_or_default();
+            format!("bencheth-{}", hostname.to_string_lossy())
+        });
+        let prefix = env::var("MQTT_TOPIC_PREFIX")
+            .unwrap_or_else(|_| DEFAULT_TOPIC_PREFIX.to_string())
+            .trim_end_matches('/')
+            .to_string();
+        let qos = match env::var("MQTT_QOS").as_deref() {
+            Ok("0") => QoS::AtMostOnce,
+            Ok("1") | Err(_) => QoS::AtLeastOnce,
+            Ok("2") => QoS::ExactlyOnce,
+            Ok(_) => panic!("Invalid MQTT_QOS: must be 0, 1 or 2"),
+        };
+        let online_topic = format!("{}/agents/{}/online", prefix, client_id);
+
+        let mut options = MqttOptions::new(client_id, host, port);
+        options
+            .set_keep_alive(Duration::from_secs(30))
+            .set_last_will(LastWill::new(&online_topic, "false", qos, true));
+        if !url.username().is_empty() {
+            options.set_credentials(url.username(), url.password().unwrap_or_default());
+        }
+        if tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+
+        let (client, eventloop) = AsyncClient::new(options, CLIENT_CAPACITY);
+        let (sender, receiver) = mpsc::channel(DEFAULT_QUEUE);
+
+        Some(Self {
+            sink: MqttSink {
+                client,
+                prefix,
+                qos,
+            },
+            eventloop,
+            online_topic,
+            sender,
+            receiver,
+        })
+    }
+
+    /// The sink publishing alerts and status changes.
+    pub fn sink(&self) -> MqttSink {
+        self.sink.clone()
+    }
+
+    /// The handle to publish the block events of a provider with.
+    pub fn provider(&self, chain: &str, rpc: &str) -> BlockEvents {
+        BlockEvents::new(chain, rpc, self.sender.clone())
+    }
+
+    pub async fn run(self) {
+        let Self {
+            sink,
+            mut eventloop,
+            online_topic,
+            sender,
+            mut receiver,
+        } = self;
+        // only the providers' handles keep the channel open
+        drop(sender);
+
+        let client = sink.client.clone();
+        let qos = sink.qos;
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        log::info!("Connected to the MQTT broker");
+                        let online = client.try_publish(&online_topic, qos, true, "true");
+                        if let Err(e) = online {
+                            log::warn!("Failed to publish MQTT message: {:?}", e);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // polling again reconnects
+                        log::warn!("MQTT connection error: {}", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        while let Some(event) = receiver.recv().await {
+            let topic = format!("{}/{}/{}/blocks", sink.prefix, event.chain, event.rpc);
+            sink.publish(topic, false, &event).await;
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! HTTP/2 multiplexing versus HTTP/1.1 connection stress scenario.
+//!
+//! Gateways can either multiplex concurrent requests as streams over a single HTTP/2 connection
+//! or spread them over a pool of HTTP/1.1 connections. Which one performs better depends on the
+//! provider's load balancers, so the scenario sends the same batch of requests to a provider
+//! both ways, with the same number of requests in flight, and compares throughput and latency.
+//!
+//! Run it with `bencheth multiplex`. Every configured RPC URL is measured with
+//! `MULTIPLEX_REQUESTS` requests (default 1000) and `MULTIPLEX_STREAMS` concurrent streams or
+//! connections (default 32). The request is `MULTIPLEX_METHOD` (default `eth_blockNumber`) with
+//! `MULTIPLEX_PARAMS` (a JSON array, default `[]`).
+//!
+//! Both modes use rustls so the TLS library doesn't skew the comparison. Plain `http://` URLs
+//! are spoken to with HTTP/2 prior knowledge (h2c), which not every server supports.
+
+use crate::chain::{ChainConfig, ChainKind};
+use crate::report::Summary;
+use crate::rpc_method::RpcMethod;
+
+use futures::StreamExt;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::env;
+use std::time::Instant;
+
+const DEFAULT_MULTIPLEX_REQUESTS: usize = 1000;
+const DEFAULT_MULTIPLEX_STREAMS: usize = 32;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScenarioResult {
+    /// `HTTP/2` or `HTTP/1.1`.
+    pub protocol: &'static str,
+    pub requests: usize,
+    pub errors: usize,
+    pub elapsed_secs: f64,
+    /// Successful requests per second.
+    pub throughput_rps: f64,
+    /// Latency of the successful requests, in milliseconds.
+    pub latency_ms: Summary,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MultiplexReport {
+    pub chain: String,
+    pub rpc: String,
+    pub method: String,
+    pub streams: usize,
+    pub http2: ScenarioResult,
+    pub http1: ScenarioResult,
+}
+
+struct Scenario {
+    requests: usize,
+    streams: usize,
+    method: String,
+    body: String,
+}
+
+impl Scenario {
+    fn from_env() -> Self {
+        let requests = env::var("MULTIPLEX_REQUESTS")
+            .ok()
+            .map(|n| n.parse::<usize>().expect("Invalid MULTIPLEX_REQUESTS"))
+            .unwrap_or(DEFAULT_MULTIPLEX_REQUESTS);
+        let streams = env::var("MULTIPLEX_STREAMS")
+            .ok()
+            .map(|n| n.parse::<usize>().expect("Invalid MULTIPLEX_STREAMS"))
+            .unwrap_or(DEFAULT_MULTIPLEX_STREAMS);
+        if streams == 0 {
+            panic!("Invalid MULTIPLEX_STREAMS: must be greater than zero");
+        }
+        let method = env::var("MULTIPLEX_METHOD")
+            .map(|method| {
+                RpcMethod::repeatable(&method)
+                    .unwrap_or_else(|e| panic!("Invalid MULTIPLEX_METHOD: {}", e))
+            })
+            .unwrap_or(RpcMethod::EthBlockNumber)
+            .to_string();
+        let params = env::var("MULTIPLEX_PARAMS")
+            .ok()
+            .map(|params| serde_json::from_str::<Value>(&params).expect("Invalid MULTIPLEX_PARAMS"))
+            .unwrap_or_else(|| json!([]));
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+
+        Self {
+            requests,
+            streams,
+            method,
+            body: body.to_string(),
+        }
+    }
+
+    async fn run(
+        &self,
+        protocol: &'static str,
+        client: Client,
+        url: &Url,
+        basic_auth: &Option<(String, String)>,
+    ) -> ScenarioResult {
+        let send = || {
+            let mut request = client
+                .post(url.clone())
+                .header("content-type", "application/json")
+                .body(self.body.clone());
+            if let Some((username, password)) = basic_auth {
+                request = request.basic_auth(username, Some(password));
+            }
+            async move {
+                let start = Instant::now();
+                let res = match request.send().await {
+                    Ok(response) if response.status().is_success() => {
+                        response.bytes().await.map(|_| ())
+                    }
+                    Ok(response) => response.error_for_status().map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                res.map(|_| start.elapsed().as_secs_f64() * 1000.0)
+            }
+        };
+
+        // establish the connection up front so the handshake isn't part of the comparison
+        if let Err(e) = send().await {
+            log::warn!("[🔀] {} warm-up request to {} failed: {}", protocol, url, e);
+        }
+
+        let start = Instant::now();
+        let results = futures::stream::iter(0..self.requests)
+            .map(|_| send())
+            .buffer_unordered(self.streams)
+            .collect::<Vec<_>>()
+            .await;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let mut latencies = Vec::with_capacity(results.len());
+        let mut errors = 0;
+        for res in results {
+            match res {
+                Ok(latency) => latencies.push(latency),
+                Err(e) => {
+                    log::debug!("[🔀] {} request to {} failed: {}", protocol, url, e);
+                    errors += 1;
+                }
+            }
+        }
+
+        ScenarioResult {
+            protocol,
+            requests: self.requests,
+            errors,
+            elapsed_secs: elapsed,
+            throughput_rps: latencies.len() as f64 / elapsed,
+            latency_ms: Summary::from_samples(latencies).unwrap_or_default(),
+        }
+    }
+}
+
+/// Run the scenario against every configured JSON-RPC endpoint.
+pub async fn run(chains: &[ChainConfig]) -> Vec<MultiplexReport> {
+    let scenario = Scenario::from_env();
+    let mut reports = Vec::new();
+
+    for chain in chains {
+        if chain.kind == ChainKind::Http {
+            log::warn!(
+                "[{}] The multiplexing scenario only supports JSON-RPC chains, skipping",
+                chain.name
+            );
+            continue;
+        }
+
+        for url in &chain.rpc_urls {
+            let http2 = Client::builder()
+                .use_rustls_tls()
+                .http2_prior_knowledge()
+                .build()
+                .expect("could not initialize http");
+            let http1 = Client::builder()
+                .use_rustls_tls()
+                .http1_only()
+                .build()
+                .expect("could not initialize http");
+
+            let report = MultiplexReport {
+                chain: chain.name.clone(),
+                rpc: url.host_str().unwrap_or_default().to_string(),
+                method: scenario.method.clone(),
+                streams: scenario.streams,
+                http2: scenario.run("HTTP/2", http2, url, &chain.basic_auth).await,
+                http1: scenario
+                    .run("HTTP/1.1", http1, url, &chain.basic_auth)
+                    .await,
+            };
+            log_report(&report);
+            reports.push(report);
+        }
+    }
+
+    reports
+}
+
+fn log_report(report: &MultiplexReport) {
+    for result in [&report.http2, &report.http1] {
+        log::info!(
+            "[🔀][{}] {} {} with {} streams: {:.1} req/s, p50 {:.3}ms p99 {:.3}ms, {} errors",
+            report.chain,
+            report.rpc,
+            result.protocol,
+            report.streams,
+            result.throughput_rps,
+            result.latency_ms.p50,
+            result.latency_ms.p99,
+            result.errors
+        );
+    }
+}
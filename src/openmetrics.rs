@@ -0,0 +1,149 @@
+//! OpenMetrics exposition of the metrics.
+//!
+//! The metrics endpoint serves the classic Prometheus text format unless the scraper prefers
+//! OpenMetrics in its `Accept` header, as Prometheus and Grafana Agent do. The metrics are the
+//! same in both formats: counters whose names end in `_total` are OpenMetrics counters, while the
+//! others keep their names as `unknown` metrics, since OpenMetrics requires the suffix and renaming
+//! them would break existing queries.
+
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+
+use std::io::Write;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Terminates every exposition.
+pub const EOF: &[u8] = b"# EOF\n";
+
+/// Whether an `Accept` header prefers OpenMetrics over the text format.
+pub fn preferred(accept: &str) -> bool {
+    let mut openmetrics = 0.0;
+    let mut text = 0.0;
+    for media_range in accept.split(',') {
+        let mut params = media_range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default();
+        let q = params
+            .filter_map(|param| param.strip_prefix("q="))
+            .find_map(|q| q.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        match media_type {
+            "application/openmetrics-text" => openmetrics = f64::max(openmetrics, q),
+            "text/plain" | "text/*" | "*/*" => text = f64::max(text, q),
+            _ => {}
+        }
+    }
+    openmetrics > 0.0 && openmetrics >= text
+}
+
+/// Encode a metric family in the OpenMetrics text format.
+pub fn encode(family: &MetricFamily, buffer: &mut Vec<u8>) {
+    let name = family.get_name();
+    // the suffix of counter samples
+    let (name, kind, total) = match family.get_field_type() {
+        MetricType::COUNTER => match name.strip_suffix("_total") {
+            Some(name) => (name, "counter", "_total"),
+            None => (name, "unknown", ""),
+        },
+        MetricType::GAUGE => (name, "gauge", ""),
+        MetricType::HISTOGRAM => (name, "histogram", ""),
+        MetricType::SUMMARY => (name, "summary", ""),
+        MetricType::UNTYPED => (name, "unknown", ""),
+    };
+
+    if !family.get_help().is_empty() {
+        let _ = writeln!(buffer, "# HELP {} {}", name, escape(family.get_help()));
+    }
+    let _ = writeln!(buffer, "# TYPE {} {}", name, kind);
+
+    for metric in family.get_metric() {
+        match family.get_field_type() {
+            MetricType::COUNTER => {
+                let value = metric.get_counter().get_value();
+                sample(buffer, name, total, metric, None, value);
+            }
+            MetricType::GAUGE => {
+                let value = metric.get_gauge().get_value();
+                sample(buffer, name, "", metric, None, value);
+            }
+            MetricType::UNTYPED => {
+                let value = metric.get_untyped().get_value();
+                sample(buffer, name, "", metric, None, value);
+            }
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                let mut inf = false;
+                for bucket in histogram.get_bucket() {
+                    let upper_bound = bucket.get_upper_bound();
+                    inf |= upper_bound == f64::INFINITY;
+                    let le = ("le", number(upper_bound));
+                    let count = bucket.get_cumulative_count() as f64;
+                    sample(buffer, name, "_bucket", metric, Some(le), count);
+                }
+                // the +Inf bucket is required
+                if !inf {
+                    let le = ("le", number(f64::INFINITY));
+                    let count = histogram.get_sample_count() as f64;
+                    sample(buffer, name, "_bucket", metric, Some(le), count);
+                }
+                let count = histogram.get_sample_count() as f64;
+                sample(buffer, name, "_count", metric, None, count);
+                let sum = histogram.get_sample_sum();
+                sample(buffer, name, "_sum", metric, None, sum);
+            }
+            MetricType::SUMMARY => {
+                let summary = metric.get_summary();
+                for quantile in summary.get_quantile() {
+                    let label = ("quantile", number(quantile.get_quantile()));
+                    sample(buffer, name, "", metric, Some(label), quantile.get_value());
+                }
+                let count = summary.get_sample_count() as f64;
+                sample(buffer, name, "_count", metric, None, count);
+                sample(buffer, name, "_sum", metric, None, summary.get_sample_sum());
+            }
+        }
+    }
+}
+
+fn sample(
+    buffer: &mut Vec<u8>,
+    name: &str,
+    suffix: &str,
+    metric: &Metric,
+    extra_label: Option<(&str, String)>,
+    value: f64,
+) {
+    let mut labels = metric
+        .get_label()
+        .iter()
+        .map(|label| format!("{}=\"{}\"", label.get_name(), escape(label.get_value())))
+        .collect::<Vec<_>>();
+    if let Some((label, value)) = extra_label {
+        labels.push(format!("{}=\"{}\"", label, value));
+    }
+
+    let _ = write!(buffer, "{}{}", name, suffix);
+    if !labels.is_empty() {
+        let _ = write!(buffer, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(buffer, " {}", number(value));
+}
+
+/// Format a number the way OpenMetrics expects, e.g. `1.0` and `+Inf`.
+fn number(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
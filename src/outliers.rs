@@ -0,0 +1,157 @@
+//! Reproducible outlier and failed requests.
+//!
+//! When a provider misbehaves, its support wants a request they can run themselves rather than a
+//! metric. With `OUTLIER_LATENCY_MS` set, every attempt of a JSON-RPC request over HTTP that took
+//! longer, or failed, is recorded with a ready-to-run `curl` command sending the exact same body.
+//! Credentials are left out: keys in the URL are replaced like in logs and an `Authorization`
+//! header, when the provider needs one, is a placeholder to fill in.
+//!
+//! The last `OUTLIERS_MAX` (default 100) are served as JSON on `/outliers` and included in the run
+//! report.
+
+use crate::redact;
+
+use chrono::{DateTime, Utc};
+use reqwest::{Request, StatusCode, Url};
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::env;
+use std::fmt::Display;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const DEFAULT_MAX: usize = 100;
+
+static OUTLIERS: OnceLock<Outliers> = OnceLock::new();
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Outlier {
+    pub at: DateTime<Utc>,
+    /// The RPC URL with credentials redacted.
+    pub url: String,
+    pub method: String,
+    /// `slow` or `error`.
+    pub reason: &'static str,
+    pub latency_ms: f64,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    /// A `curl` command sending the same request.
+    pub curl: String,
+}
+
+struct Outliers {
+    latency: Duration,
+    max: usize,
+    recent: Mutex<VecDeque<Outlier>>,
+}
+
+/// Read the outlier settings from the environment. Recording is off unless `OUTLIER_LATENCY_MS`
+/// is set.
+pub fn init() {
+    let Ok(latency) = env::var("OUTLIER_LATENCY_MS") else {
+        return;
+    };
+    let latency = latency.parse::<u64>().expect("Invalid OUTLIER_LATENCY_MS");
+    let max = env::var("OUTLIERS_MAX")
+        .ok()
+        .map(|max| max.parse::<usize>().expect("Invalid OUTLIERS_MAX"))
+        .unwrap_or(DEFAULT_MAX);
+    let _ = OUTLIERS.set(Outliers {
+        latency: Duration::from_millis(latency),
+        max,
+        recent: Mutex::new(VecDeque::new()),
+    });
+}
+
+/// The recorded outliers, oldest first.
+pub fn recent() -> Vec<Outlier> {
+    OUTLIERS
+        .get()
+        .map(|outliers| outliers.recent.lock().unwrap().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Serve `/outliers`.
+pub fn to_json() -> String {
+    serde_json::to_string(&recent()).unwrap_or_default()
+}
+
+/// Quote `text` for a POSIX shell.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+fn curl(url: &Url, authenticated: bool, body: &[u8]) -> String {
+    let mut curl = format!(
+        "curl -sS -X POST {} -H 'Content-Type: application/json'",
+        quote(&redact::url(url))
+    );
+    if authenticated {
+        curl.push_str(" -H 'Authorization: <credentials>'");
+    }
+    curl.push_str(" --data-raw ");
+    curl.push_str(&quote(&redact::text(&String::from_utf8_lossy(body))));
+    curl
+}
+
+/// An attempt of a request being sent, recorded when it turns out to be an outlier.
+pub struct Attempt {
+    url: Url,
+    method: String,
+    authenticated: bool,
+    body: Vec<u8>,
+}
+
+impl Attempt {
+    /// Keep what is needed to reproduce `request`, `None` unless outliers are recorded.
+    pub fn capture(method: &str, request: &Request, authenticated: bool) -> Option<Self> {
+        OUTLIERS.get()?;
+        Some(Self {
+            url: request.url().clone(),
+            method: method.to_string(),
+            authenticated,
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .unwrap_or_default()
+                .to_vec(),
+        })
+    }
+
+    /// Record the attempt if it took longer than `OUTLIER_LATENCY_MS` or failed.
+    pub fn finish<T, E: Display>(
+        self,
+        latency: Duration,
+        status: Option<StatusCode>,
+        res: &Result<T, E>,
+    ) {
+        let Some(outliers) = OUTLIERS.get() else {
+            return;
+        };
+        let reason = match res {
+            Err(_) => "error",
+            Ok(_) if latency > outliers.latency => "slow",
+            Ok(_) => return,
+        };
+
+        let outlier = Outlier {
+            at: Utc::now(),
+            url: redact::url(&self.url),
+            method: self.method,
+            reason,
+            latency_ms: latency.as_secs_f64() * 1000.0,
+            status: status.map(|status| status.as_u16()),
+            error: res
+                .as_ref()
+                .err()
+                .map(|e| redact::text(&e.to_string()).into_owned()),
+            curl: curl(&self.url, self.authenticated, &self.body),
+        };
+        let mut recent = outliers.recent.lock().unwrap();
+        recent.push_back(outlier);
+        while recent.len() > outliers.max {
+            recent.pop_front();
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Placement of the agent.
+//!
+//! Latencies depend on where the agent runs relative to the providers. On a cloud instance, the
+//! provider, region and availability zone are read from the instance metadata service, trying
+//! AWS (IMDSv2), GCP and Azure at once with a short timeout. The `geo` label is then the cloud
+//! region, e.g. `us-east-1` or `westeurope`, which matches the network topology better than a
+//! geo IP lookup, and every metric is also labelled with `cloud` (`aws`, `gcp` or `azure`) and,
+//! where the instance has one, `zone`.
+//!
+//! Elsewhere, or with `CLOUD_METADATA=false`, `geo` falls back to the country and region of the
+//! agent's public IP address as reported by ipinfo.io.
+
+use reqwest::Client;
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+/// Link-local address of the AWS and Azure metadata services.
+const METADATA_IP: &str = "169.254.169.254";
+const GCP_METADATA_HOST: &str = "metadata.google.internal";
+const METADATA_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Clone, Debug)]
+pub struct Placement {
+    /// Value of the `geo` label, the cloud region or the geo IP region.
+    pub geo: String,
+    pub cloud: Option<&'static str>,
+    pub zone: Option<String>,
+}
+
+impl Placement {
+    /// Detect the placement from the instance metadata, falling back to a geo IP lookup.
+    pub async fn detect() -> Self {
+        let enabled = env::var("CLOUD_METADATA")
+            .map(|v| v.parse::<bool>().expect("Invalid CLOUD_METADATA"))
+            .unwrap_or(true);
+        if enabled {
+            let client = Client::builder()
+                .timeout(METADATA_TIMEOUT)
+                .no_proxy()
+                .build()
+                .expect("could not initialize http");
+            let (aws, gcp, azure) = tokio::join!(aws(&client), gcp(&client), azure(&client));
+            if let Some(placement) = aws.or(gcp).or(azure) {
+                log::info!(
+                    "Running on {} in {}{}",
+                    placement.cloud.unwrap_or_default(),
+                    placement.geo,
+                    placement
+                        .zone
+                        .as_ref()
+                        .map(|zone| format!(", zone {}", zone))
+                        .unwrap_or_default()
+                );
+                return placement;
+            }
+            log::debug!("No instance metadata service found, looking up the geo region");
+        }
+
+        Self {
+            geo: get_geo_region().await,
+            cloud: None,
+            zone: None,
+        }
+    }
+
+    /// Labels to attach to every metric besides `geo`.
+    pub fn const_labels(&self) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        if let Some(cloud) = self.cloud {
+            labels.insert("cloud".to_string(), cloud.to_string());
+        }
+        if let Some(zone) = &self.zone {
+            labels.insert("zone".to_string(), zone.clone());
+        }
+        labels
+    }
+}
+
+async fn aws(client: &Client) -> Option<Placement> {
+    let token = client
+        .put(format!("http://{}/latest/api/token", METADATA_IP))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    let get = |path: &'static str| {
+        let request = client
+            .get(format!(
+                "http://{}/latest/meta-data/placement/{}",
+                METADATA_IP, path
+            ))
+            .header("X-aws-ec2-metadata-token", &token);
+        async move {
+            request
+                .send()
+                .await
+                .ok()?
+                .error_for_status()
+                .ok()?
+                .text()
+                .await
+                .ok()
+        }
+    };
+
+    Some(Placement {
+        geo: get("region").await?,
+        cloud: Some("aws"),
+        zone: get("availability-zone").await,
+    })
+}
+
+async fn gcp(client: &Client) -> Option<Placement> {
+    // projects/<project number>/zones/<zone>
+    let zone = client
+        .get(format!(
+            "http://{}/computeMetadata/v1/instance/zone",
+            GCP_METADATA_HOST
+        ))
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    let zone = zone.rsplit('/').next()?.to_string();
+    // zones are named after their region, e.g. us-central1-a
+    let (region, _) = zone.rsplit_once('-')?;
+
+    Some(Placement {
+        geo: region.to_string(),
+        cloud: Some("gcp"),
+        zone: Some(zone),
+    })
+}
+
+async fn azure(client: &Client) -> Option<Placement> {
+    let compute: Value = client
+        .get(format!(
+            "http://{}/metadata/instance/compute?api-version=2021-02-01",
+            METADATA_IP
+        ))
+        .header("Metadata", "true")
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .json()
+        .await
+        .ok()?;
+    let location = compute["location"].as_str()?;
+    // availability zones are numbered within a region, and empty without one
+    let zone = compute["zone"]
+        .as_str()
+        .filter(|zone| !zone.is_empty())
+        .map(|zone| format!("{}-{}", location, zone));
+
+    Some(Placement {
+        geo: location.to_string(),
+        cloud: Some("azure"),
+        zone,
+    })
+}
+
+async fn get_geo_region() -> String {
+    let region = reqwest::get("https://ipinfo.io/json")
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let region: serde_json::Value = serde_json::from_str(&region).unwrap();
+    let country = region["country"].as_str().unwrap();
+    let region = region["region"].as_str().unwrap();
+    format!("{}-{}", country, region)
+}
@@ -0,0 +1,83 @@
+//! Polling interval that detects loop overruns.
+//!
+//! When the work done between two ticks takes longer than the polling interval, the next poll
+//! happens late. An overloaded agent therefore silently stretches its polling intervals, which
+//! biases every propagation measurement. [`PollInterval`] counts these overruns. The ticks missed
+//! during an overrun aren't made up for, so a slow poll isn't followed by a burst of back to back
+//! polls: the next one waits a full interval.
+
+use prometheus::{Counter, IntCounter, Registry};
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct PollMetrics {
+    overruns: IntCounter,
+    overrun_seconds: Counter,
+}
+
+impl PollMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        let overruns = IntCounter::new(
+            "poll_loop_overruns_total",
+            "Number of polling ticks whose work took longer than the polling interval",
+        )
+        .unwrap();
+        let overrun_seconds = Counter::new(
+            "poll_loop_overrun_seconds_total",
+            "Total time by which polling ticks exceeded the polling interval",
+        )
+        .unwrap();
+        registry.register(Box::new(overruns.clone())).unwrap();
+        registry
+            .register(Box::new(overrun_seconds.clone()))
+            .unwrap();
+
+        Self {
+            overruns,
+            overrun_seconds,
+        }
+    }
+
+    pub fn interval(&self, period: Duration) -> PollInterval {
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        PollInterval {
+            interval,
+            period,
+            last_tick: None,
+            metrics: self.clone(),
+        }
+    }
+}
+
+pub struct PollInterval {
+    interval: Interval,
+    period: Duration,
+    last_tick: Option<Instant>,
+    metrics: PollMetrics,
+}
+
+impl PollInterval {
+    /// Wait for the next tick, counting an overrun if the work since the previous tick took
+    /// longer than the polling interval.
+    pub async fn tick(&mut self) {
+        if let Some(last_tick) = self.last_tick {
+            let work = last_tick.elapsed();
+            if work > self.period {
+                let overrun = work - self.period;
+                log::debug!(
+                    "Polling loop overrun: tick took {:?} for a {:?} interval",
+                    work,
+                    self.period
+                );
+                self.metrics.overruns.inc();
+                self.metrics.overrun_seconds.inc_by(overrun.as_secs_f64());
+            }
+        }
+
+        self.interval.tick().await;
+        self.last_tick = Some(Instant::now());
+    }
+}
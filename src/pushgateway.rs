@@ -0,0 +1,103 @@
+//! Pushes to a Prometheus Pushgateway.
+//!
+//! A one-shot benchmark in CI has no scraper to collect its metrics before it exits. With
+//! `PUSHGATEWAY_URL` set (e.g. `http://pushgateway:9091`), the metrics of every provider are
+//! pushed to the group `job=<PUSHGATEWAY_JOB>` (default `bencheth`),
+//! `instance=<PUSHGATEWAY_INSTANCE>` (default the hostname) every `PUSHGATEWAY_INTERVAL_SECS`
+//! (default 15), and once more when the measurements stop, so the group holds the final state of
+//! the run. Every push replaces the whole group. The metrics are still served as well.
+
+use crate::metrics_server;
+
+use prometheus::{Encoder, Registry, TextEncoder};
+use reqwest::Url;
+use tokio::time;
+
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_JOB: &str = "bencheth";
+const DEFAULT_INTERVAL_SECS: u64 = 15;
+
+pub struct Pushgateway {
+    client: reqwest::Client,
+    /// `<PUSHGATEWAY_URL>/metrics/job/<job>/instance/<instance>`.
+    url: Url,
+    interval: Duration,
+    registries: Vec<Registry>,
+}
+
+impl Pushgateway {
+    /// Push the metrics of `registries`. `None` unless `PUSHGATEWAY_URL` is set.
+    pub fn from_env(registries: Vec<Registry>) -> Option<Self> {
+        let url = env::var("PUSHGATEWAY_URL").ok()?;
+        let mut url = Url::parse(&url).expect("Invalid PUSHGATEWAY_URL");
+        let job = env::var("PUSHGATEWAY_JOB").unwrap_or_else(|_| DEFAULT_JOB.to_string());
+        let instance = env::var("PUSHGATEWAY_INSTANCE").unwrap_or_else(|_| {
+            hostname::get()
+                .map(|hostname| hostname.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        if job.is_empty() || instance.is_empty() {
+            panic!("Invalid PUSHGATEWAY_JOB or PUSHGATEWAY_INSTANCE: must not be empty");
+        }
+        url.path_segments_mut()
+            .expect("Invalid PUSHGATEWAY_URL")
+            .pop_if_empty()
+            .extend(["metrics", "job", &job, "instance", &instance]);
+        let interval = env::var("PUSHGATEWAY_INTERVAL_SECS")
+            .ok()
+            .map(|secs| {
+                secs.parse::<u64>()
+                    .expect("Invalid PUSHGATEWAY_INTERVAL_SECS")
+            })
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+        Some(Self {
+            client: reqwest::Client::new(),
+            url,
+            interval: Duration::from_secs(interval.max(1)),
+            registries,
+        })
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = time::interval(self.interval);
+        // the first tick completes right away, there is nothing measured yet
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.push().await {
+                log::warn!("Failed to push metrics to the Pushgateway: {:?}", e);
+            }
+        }
+    }
+
+    /// Push the final state of the run.
+    pub async fn finish(&self) {
+        match self.push().await {
+            Ok(()) => log::info!("Pushed the final metrics to the Pushgateway"),
+            Err(e) => log::warn!(
+                "Failed to push the final metrics to the Pushgateway: {:?}",
+                e
+            ),
+        }
+    }
+
+    async fn push(&self) -> Result<(), reqwest::Error> {
+        let families = metrics_server::gather(&self.registries);
+        let encoder = TextEncoder::new();
+        let mut body = vec![];
+        encoder.encode(&families, &mut body).unwrap();
+        self.client
+            .put(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, encoder.format_type())
+            .body(body)
+            .timeout(self.interval)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
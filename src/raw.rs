@@ -0,0 +1,178 @@
+//! Raw RLP retrieval scenarios.
+//!
+//! Pipelines decoding RLP themselves can fetch blocks, transactions and receipts with the
+//! `debug_getRaw*` methods instead of their JSON counterparts. With `<CHAIN>_RAW_RETRIEVAL=true`,
+//! every new block of an EVM chain is fetched both ways:
+//!
+//! - `block`: `debug_getRawBlock` versus `eth_getBlockByNumber` with full transactions.
+//! - `receipts`: `debug_getRawReceipts` versus `eth_getBlockReceipts`.
+//! - `transaction`: `debug_getRawTransaction` versus `eth_getTransactionByHash`, for the block's
+//!   first transaction.
+//!
+//! Latencies are exported as `raw_retrieval_latency_seconds` and the size of the results as
+//! `raw_retrieval_result_bytes`, labelled with the `payload` and its `encoding` (`raw` or
+//! `json`). A payload is no longer fetched once the provider refused either method. Raw
+//! retrieval follows `<CHAIN>_RAW_SCHEDULE`.
+
+use crate::capabilities::{self, Support};
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::rpc_method::RpcMethod;
+use crate::schedule::Schedule;
+
+use ethers::prelude::*;
+use prometheus::{exponential_buckets, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub struct RawRetrieval {
+    chain: String,
+    schedule: Schedule,
+    /// Payloads the provider refused.
+    unsupported: Mutex<HashSet<&'static str>>,
+    latency: HistogramVec,
+    result_bytes: HistogramVec,
+    errors: IntCounterVec,
+}
+
+impl RawRetrieval {
+    /// Raw retrieval for a provider of `chain`, reporting to its `registry`. `None` unless
+    /// `<CHAIN>_RAW_RETRIEVAL` is `true`.
+    pub fn from_chain(chain: &ChainConfig, registry: &Registry) -> Option<Self> {
+        let enabled = chain
+            .var("RAW_RETRIEVAL")
+            .map(|enabled| enabled.parse::<bool>().expect("Invalid RAW_RETRIEVAL"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let labels = &["payload", "encoding"];
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "raw_retrieval_latency_seconds",
+                "Time taken for RPC URL to return a payload as RLP or JSON",
+            ),
+            labels,
+        )
+        .unwrap();
+        let result_bytes = HistogramVec::new(
+            HistogramOpts::new(
+                "raw_retrieval_result_bytes",
+                "Size of a payload returned by RPC URL as RLP or JSON",
+            )
+            .buckets(exponential_buckets(256.0, 4.0, 10).unwrap()),
+            labels,
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "raw_retrieval_errors_total",
+                "Number of failures to return a payload as RLP or JSON",
+            ),
+            labels,
+        )
+        .unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(result_bytes.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+
+        Some(Self {
+            chain: chain.name.clone(),
+            schedule: Schedule::from_chain(chain, "RAW"),
+            unsupported: Mutex::new(HashSet::new()),
+            latency,
+            result_bytes,
+            errors,
+        })
+    }
+
+    /// Fetch `block`, its receipts and its first transaction `tx` both as RLP and JSON.
+    pub async fn run(&self, provider: &Provider<MeasuredJsonRpc>, block: U64, tx: Option<H256>) {
+        if !self.schedule.is_active() {
+            return;
+        }
+
+        self.compare(
+            provider,
+            "block",
+            (RpcMethod::DebugGetRawBlock, json!([block])),
+            (RpcMethod::EthGetBlockByNumber, json!([block, true])),
+        )
+        .await;
+        self.compare(
+            provider,
+            "receipts",
+            (RpcMethod::DebugGetRawReceipts, json!([block])),
+            (RpcMethod::EthGetBlockReceipts, json!([block])),
+        )
+        .await;
+        if let Some(tx) = tx {
+            self.compare(
+                provider,
+                "transaction",
+                (RpcMethod::DebugGetRawTransaction, json!([tx])),
+                (RpcMethod::EthGetTransactionByHash, json!([tx])),
+            )
+            .await;
+        }
+    }
+
+    async fn compare(
+        &self,
+        provider: &Provider<MeasuredJsonRpc>,
+        payload: &'static str,
+        raw: (RpcMethod, Value),
+        json: (RpcMethod, Value),
+    ) {
+        if self.unsupported.lock().unwrap().contains(payload) {
+            return;
+        }
+
+        for (encoding, (method, params)) in [("raw", raw), ("json", json)] {
+            let labels = &[payload, encoding];
+
+            let start = Instant::now();
+            let res = provider
+                .request::<_, Box<RawValue>>(method.as_str(), params)
+                .await;
+            let elapsed = start.elapsed();
+
+            match res {
+                Ok(result) => {
+                    self.latency
+                        .with_label_values(labels)
+                        .observe(elapsed.as_secs_f64());
+                    self.result_bytes
+                        .with_label_values(labels)
+                        .observe(result.get().len() as f64);
+                }
+                Err(e) if capabilities::classify(&e) == Support::Unsupported => {
+                    log::warn!(
+                        "[{}] {} is not supported, not fetching {} as RLP: {:?}",
+                        self.chain,
+                        method,
+                        payload,
+                        e
+                    );
+                    self.unsupported.lock().unwrap().insert(payload);
+                    return;
+                }
+                Err(e) => {
+                    log::warn!(
+                        "[{}] Failed to fetch {} with {}: {:?}",
+                        self.chain,
+                        payload,
+                        method,
+                        e
+                    );
+                    self.errors.with_label_values(labels).inc();
+                }
+            }
+        }
+    }
+}
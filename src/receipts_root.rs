@@ -0,0 +1,292 @@
+//! Receipts root verification.
+//!
+//! Receipts are what indexers read events from, and a provider serving stale or partial ones
+//! breaks them silently. With `<CHAIN>_RECEIPTS_ROOT=true`, the receipts of every new block of an
+//! EVM chain are fetched with `eth_getBlockReceipts`, or one by one with
+//! `eth_getTransactionReceipt` when the provider doesn't support it, the trie of their encodings is
+//! rebuilt and its root compared with the block's `receiptsRoot`. The time taken to fetch them is
+//! exported as `receipts_latency_seconds`, labelled with the `method`, and mismatches are logged
+//! and counted in `receipts_root_mismatch_total`.
+//!
+//! Receipts of legacy, access list, dynamic fee, blob and set code transactions are encoded, as
+//! well as those of OP Stack deposits. A block with a receipt of another type can't be verified
+//! and is skipped. Verification follows `<CHAIN>_RECEIPTS_ROOT_SCHEDULE`.
+
+use crate::capabilities::{self, Support};
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::rpc_method::RpcMethod;
+use crate::schedule::Schedule;
+use crate::trie;
+
+use ethers::prelude::*;
+use ethers::utils::rlp::RlpStream;
+use futures::StreamExt;
+use prometheus::{HistogramOpts, HistogramVec, IntCounter, Registry};
+use serde_json::json;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// The encoding of `receipt` in the receipts trie, `None` for unsupported types.
+fn encode(receipt: &TransactionReceipt) -> Option<Vec<u8>> {
+    let kind = receipt
+        .transaction_type
+        .map(|kind| kind.as_u64())
+        .unwrap_or(0);
+    if !matches!(kind, 0..=4 | 0x7e) {
+        return None;
+    }
+
+    let mut stream = RlpStream::new();
+    stream.begin_unbounded_list();
+    // the state root before Byzantium
+    match (receipt.status, receipt.root) {
+        (Some(status), _) => stream.append(&status),
+        (None, Some(root)) => stream.append(&root),
+        (None, None) => return None,
+    };
+    stream.append(&receipt.cumulative_gas_used);
+    stream.append(&receipt.logs_bloom);
+    stream.append_list(&receipt.logs);
+    // OP Stack deposits since Regolith and Canyon
+    if kind == 0x7e {
+        for name in ["depositNonce", "depositReceiptVersion"] {
+            if let Some(value) = receipt.other.get_deserialized::<U64>(name) {
+                stream.append(&value.ok()?);
+            }
+        }
+    }
+    stream.finalize_unbounded_list();
+
+    let payload = stream.out().to_vec();
+    if kind == 0 {
+        return Some(payload);
+    }
+    Some([&[kind as u8], payload.as_slice()].concat())
+}
+
+pub struct ReceiptsRoot {
+    chain: String,
+    endpoint: String,
+    schedule: Schedule,
+    /// Whether the provider refused `eth_getBlockReceipts`.
+    per_transaction: AtomicBool,
+    latency: HistogramVec,
+    mismatches: IntCounter,
+}
+
+impl ReceiptsRoot {
+    /// The verification of the receipts of `endpoint`, reporting to its `registry`. `None` unless
+    /// `<CHAIN>_RECEIPTS_ROOT` is `true`.
+    pub fn from_chain(chain: &ChainConfig, endpoint: &str, registry: &Registry) -> Option<Self> {
+        let enabled = chain
+            .var("RECEIPTS_ROOT")
+            .map(|enabled| enabled.parse::<bool>().expect("Invalid RECEIPTS_ROOT"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "receipts_latency_seconds",
+                "Time taken for RPC URL to return all the receipts of a block",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let mismatches = IntCounter::new(
+            "receipts_root_mismatch_total",
+            "Number of blocks whose receipts don't hash to their receipts root",
+        )
+        .unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(mismatches.clone())).unwrap();
+
+        Some(Self {
+            chain: chain.name.clone(),
+            endpoint: endpoint.to_string(),
+            schedule: Schedule::from_chain(chain, "RECEIPTS_ROOT"),
+            per_transaction: AtomicBool::new(false),
+            latency,
+            mismatches,
+        })
+    }
+
+    /// The receipts of `block`, in the order of its transactions.
+    async fn receipts(
+        &self,
+        provider: &Provider<MeasuredJsonRpc>,
+        block: &Block<H256>,
+    ) -> Result<Vec<TransactionReceipt>, ProviderError> {
+        let number = block.number.unwrap_or_default();
+        if !self.per_transaction.load(Ordering::Relaxed) {
+            let start = Instant::now();
+            let res = provider
+                .request::<_, Vec<TransactionReceipt>>(
+                    RpcMethod::EthGetBlockReceipts.as_str(),
+                    json!([number]),
+                )
+                .await;
+            match res {
+                Ok(receipts) => {
+                    self.latency
+                        .with_label_values(&[RpcMethod::EthGetBlockReceipts.as_str()])
+                        .observe(start.elapsed().as_secs_f64());
+                    return Ok(receipts);
+                }
+                Err(e) if capabilities::classify(&e) == Support::Unsupported => {
+                    log::warn!(
+                        "[{}] {} is not supported by {}, fetching receipts one by one: {:?}",
+                        self.chain,
+                        RpcMethod::EthGetBlockReceipts,
+                        self.endpoint,
+                        e
+                    );
+                    self.per_transaction.store(true, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let start = Instant::now();
+        let requests = block
+            .transactions
+            .iter()
+            .map(|tx| {
+                provider.request::<_, Option<TransactionReceipt>>(
+                    RpcMethod::EthGetTransactionReceipt.as_str(),
+                    json!([tx]),
+                )
+            })
+            .collect::<Vec<_>>();
+        let receipts = futures::stream::iter(requests)
+            .buffered(num_cpus::get())
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+        self.latency
+            .with_label_values(&[RpcMethod::EthGetTransactionReceipt.as_str()])
+            .observe(start.elapsed().as_secs_f64());
+        Ok(receipts)
+    }
+
+    /// Fetch the receipts of `block` and verify its receipts root.
+    pub async fn run(&self, provider: &Provider<MeasuredJsonRpc>, block: &Block<H256>) {
+        if !self.schedule.is_active() {
+            return;
+        }
+        let number = block.number.unwrap_or_default();
+        let receipts = match self.receipts(provider, block).await {
+            Ok(receipts) => receipts,
+            Err(e) => {
+                log::warn!(
+                    "[{}] Failed to get the receipts of block {} from {}: {:?}",
+                    self.chain,
+                    number,
+                    self.endpoint,
+                    e
+                );
+                return;
+            }
+        };
+
+        let mut encoded = Vec::with_capacity(receipts.len());
+        for receipt in &receipts {
+            match encode(receipt) {
+                Some(receipt_encoded) => encoded.push(receipt_encoded),
+                None => {
+                    log::debug!(
+                        "[{}] Can't verify the receipts root of block {}, receipt of {:?} of type {:?} can't be encoded",
+                        self.chain,
+                        number,
+                        receipt.transaction_hash,
+                        receipt.transaction_type
+                    );
+                    return;
+                }
+            }
+        }
+
+        let root = trie::ordered_root(&encoded);
+        if root != block.receipts_root {
+            log::warn!(
+                "[{}] Block {} from {} has receipts root {:?}, but its {} receipts hash to {:?}",
+                self.chain,
+                number,
+                self.endpoint,
+                block.receipts_root,
+                encoded.len(),
+                root
+            );
+            self.mismatches.inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn receipt(json: serde_json::Value) -> TransactionReceipt {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn transfer() -> serde_json::Value {
+        json!({
+            "transactionHash": "0xc3c5f700243de37ae986082fd2af88d2a7c2752a0c0f7b9d6ac47c729d45e067",
+            "transactionIndex": "0x0",
+            "blockHash": "0xda53da08ef6a3cbde84c33e51c04f68c3853b6a3731f10baa2324968eee63972",
+            "blockNumber": "0x3",
+            "from": "0xfdcedc3bfca10ecb0890337fbdd1977aba84807a",
+            "to": "0xdca8ce283150ab773bcbeb8d38289bdb5661de1e",
+            "cumulativeGasUsed": "0x5208",
+            "gasUsed": "0x5208",
+            "contractAddress": null,
+            "logs": [],
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "status": "0x1",
+        })
+    }
+
+    #[test]
+    fn legacy() {
+        let encoded = encode(&receipt(transfer())).unwrap();
+        assert_eq!(
+            trie::ordered_root(&[encoded]),
+            "0x056b23fbba480696b65fe5a59b8f2148a1299103c4f57df839233af2cf4ca2d2"
+                .parse::<H256>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn typed() {
+        let legacy = encode(&receipt(transfer())).unwrap();
+        let mut typed = transfer();
+        typed["type"] = json!("0x2");
+        assert_eq!(
+            encode(&receipt(typed)).unwrap(),
+            [&[2], legacy.as_slice()].concat()
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut typed = transfer();
+        typed["type"] = json!("0x42");
+        assert_eq!(encode(&receipt(typed)), None);
+
+        // neither a status nor a state root
+        let mut transfer = transfer();
+        transfer["status"] = json!(null);
+        assert_eq!(encode(&receipt(transfer)), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Recent blocks, for forensics.
+//!
+//! "What happened in the last 10 minutes" shouldn't need a Prometheus query per provider. The
+//! agent keeps the last `RECENT_BLOCKS` (default 256, 0 to disable) blocks observed on every
+//! chain in memory, and the metrics server answers `/blocks` with them as JSON, newest first:
+//!
+//! ```json
+//! [{"chain": "ethereum", "height": 19000000, "hash": "0x...",
+//!   "timestamp": "2024-01-01T00:00:00Z", "transactions": 150,
+//!   "first_seen_at": "2024-01-01T00:00:00.850Z", "first_seen_by": "eth.example",
+//!   "propagation_delay_seconds": 0.85,
+//!   "providers": [{"rpc": "eth.example", "observed_at": "2024-01-01T00:00:00.850Z",
+//!                  "propagation_delay_seconds": 0.85}, ...]}]
+//! ```
+//!
+//! `/blocks?chain=<chain>&limit=<n>` returns the last `n` blocks of a chain only. Blocks are
+//! recorded from the auxiliary runtime like [block webhooks](crate::block_webhook), and are
+//! dropped rather than slowing down the measurements when many are waiting.
+
+use crate::block_webhook::{BlockEvent, BlockEvents};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use std::collections::VecDeque;
+use std::env;
+use std::sync::{Arc, Mutex};
+
+const DEFAULT_RECENT_BLOCKS: usize = 256;
+const QUEUE: usize = 1024;
+
+#[derive(Clone, Debug, Serialize)]
+struct Sighting {
+    rpc: String,
+    observed_at: DateTime<Utc>,
+    propagation_delay_seconds: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct RecentBlock {
+    chain: String,
+    height: u64,
+    hash: String,
+    timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transactions: Option<usize>,
+    first_seen_at: DateTime<Utc>,
+    first_seen_by: String,
+    propagation_delay_seconds: f64,
+    /// Every provider that observed the block, in the order they did.
+    providers: Vec<Sighting>,
+}
+
+/// The blocks recently observed across all chains, oldest first, as served on `/blocks`.
+#[derive(Clone, Debug, Default)]
+pub struct BlockHistory(Arc<Mutex<VecDeque<RecentBlock>>>);
+
+impl BlockHistory {
+    fn record(&self, event: BlockEvent, capacity: usize) {
+        let sighting = Sighting {
+            rpc: event.rpc,
+            observed_at: event.observed_at,
+            propagation_delay_seconds: event.propagation_delay_seconds,
+        };
+
+        let mut blocks = self.0.lock().unwrap();
+        // providers see a block within seconds of each other, so it is near the end if known
+        let known = blocks
+            .iter_mut()
+            .rev()
+            .find(|block| block.chain == event.chain && block.hash == event.hash);
+        match known {
+            Some(block) => block.providers.push(sighting),
+            None => {
+                if blocks.len() == capacity {
+                    blocks.pop_front();
+                }
+                blocks.push_back(RecentBlock {
+                    chain: event.chain,
+                    height: event.height,
+                    hash: event.hash,
+                    timestamp: event.timestamp,
+                    transactions: event.transactions,
+                    first_seen_at: sighting.observed_at,
+                    first_seen_by: sighting.rpc.clone(),
+                    propagation_delay_seconds: sighting.propagation_delay_seconds,
+                    providers: vec![sighting],
+                });
+            }
+        }
+    }
+
+    /// The last `limit` blocks, of `chain` only if given, newest first, as JSON.
+    pub fn to_json(&self, chain: Option<&str>, limit: Option<usize>) -> String {
+        let blocks = self.0.lock().unwrap();
+        let blocks = blocks
+            .iter()
+            .rev()
+            .filter(|block| chain.is_none_or(|chain| block.chain == chain))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect::<Vec<_>>();
+        serde_json::to_string(&blocks).expect("Failed to serialize recent blocks")
+    }
+}
+
+pub struct RecentBlocks {
+    capacity: usize,
+    history: BlockHistory,
+    sender: mpsc::Sender<BlockEvent>,
+    receiver: mpsc::Receiver<BlockEvent>,
+}
+
+impl RecentBlocks {
+    /// `None` with `RECENT_BLOCKS=0`.
+    pub fn from_env() -> Option<Self> {
+        let capacity = env::var("RECENT_BLOCKS")
+            .ok()
+            .map(|n| n.parse::<usize>().expect("Invalid RECENT_BLOCKS"))
+            .unwrap_or(DEFAULT_RECENT_BLOCKS);
+        if capacity == 0 {
+            return None;
+        }
+        let (sender, receiver) = mpsc::channel(QUEUE);
+
+        Some(Self {
+            capacity,
+            history: Default::default(),
+            sender,
+            receiver,
+        })
+    }
+
+    /// The handle serving the recorded blocks.
+    pub fn history(&self) -> BlockHistory {
+        self.history.clone()
+    }
+
+    /// The handle to record the blocks of a provider with.
+    pub fn provider(&self, chain: &str, rpc: &str) -> BlockEvents {
+        BlockEvents::new(chain, rpc, self.sender.clone())
+    }
+
+    pub async fn run(mut self) {
+        // only the providers' handles keep the channel open
+        drop(self.sender);
+        while let Some(event) = self.receiver.recv().await {
+            self.history.record(event, self.capacity);
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! Quarantine recommendations for routing gateways.
+//!
+//! A gateway routing requests across providers needs decisions, not metrics. From the status
+//! given by the [alerting rules](crate::alert) and the decayed [`provider_score`](crate::score),
+//! every provider is recommended to be quarantined or restored, with the reasons:
+//!
+//! - `quarantine` while the provider is `down`, or its score is below
+//!   `RECOMMEND_QUARANTINE_SCORE` (default 50).
+//! - `restore` once it is no longer down and, if it was quarantined, its score is back to
+//!   `RECOMMEND_RESTORE_SCORE` (default 80), so a provider hovering around the threshold doesn't
+//!   flap in and out of rotation.
+//!
+//! Providers are evaluated on every status change and every `RECOMMEND_INTERVAL_SECS` (default
+//! 15). The metrics server answers `/recommendations` with the current recommendation of every
+//! provider as JSON, and `/recommendations?stream=true` with the same as newline-delimited JSON
+//! followed by every change as it happens, for gateways to follow. `provider_quarantined` is 1
+//! while a provider is recommended to be quarantined.
+
+use crate::alert::{Alert, AlertSink, AlertState, Status, StatusChange};
+use crate::score::ProviderScore;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use prometheus::{IntGauge, Registry};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::time;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_QUARANTINE_SCORE: f64 = 50.0;
+const DEFAULT_RESTORE_SCORE: f64 = 80.0;
+const DEFAULT_INTERVAL_SECS: u64 = 15;
+
+/// Changes waiting to be streamed to a slow client before it misses some.
+const STREAM_CAPACITY: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Quarantine,
+    Restore,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Quarantine => write!(f, "quarantine"),
+            Action::Restore => write!(f, "restore"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Recommendation {
+    pub chain: String,
+    pub rpc: String,
+    pub action: Action,
+    pub reasons: Vec<String>,
+    pub status: Status,
+    pub score: Option<f64>,
+    /// When the action was last changed.
+    pub since: DateTime<Utc>,
+}
+
+/// What the alerts told about a provider.
+#[derive(Debug)]
+struct Incident {
+    status: Status,
+    /// The firing rules of severity `down`, with their message.
+    down: HashMap<&'static str, String>,
+}
+
+impl Default for Incident {
+    fn default() -> Self {
+        Self {
+            status: Status::Up,
+            down: HashMap::new(),
+        }
+    }
+}
+
+struct FeedProvider {
+    chain: String,
+    rpc: String,
+    score: ProviderScore,
+    quarantined: IntGauge,
+    incident: Mutex<Incident>,
+    recommendation: Mutex<Option<Recommendation>>,
+}
+
+struct Feed {
+    quarantine_score: f64,
+    restore_score: f64,
+    providers: Vec<FeedProvider>,
+    changes: broadcast::Sender<Recommendation>,
+}
+
+impl Feed {
+    fn provider(&self, chain: &str, rpc: &str) -> Option<&FeedProvider> {
+        self.providers
+            .iter()
+            .find(|provider| provider.chain == chain && provider.rpc == rpc)
+    }
+
+    /// Update the recommendation of `provider`, streaming it if the action changed.
+    fn evaluate(&self, provider: &FeedProvider) {
+        let score = provider.score.get();
+        let incident = provider.incident.lock().unwrap();
+        let mut current = provider.recommendation.lock().unwrap();
+        let quarantined = current
+            .as_ref()
+            .is_some_and(|recommendation| recommendation.action == Action::Quarantine);
+
+        let mut reasons = Vec::new();
+        if incident.status == Status::Down {
+            if incident.down.is_empty() {
+                reasons.push("down".to_string());
+            }
+            reasons.extend(
+                incident
+                    .down
+                    .iter()
+                    .map(|(rule, message)| format!("{}: {}", rule, message)),
+            );
+        }
+        match score {
+            Some(score) if score < self.quarantine_score => reasons.push(format!(
+                "score {:.1} below {}",
+                score, self.quarantine_score
+            )),
+            Some(score) if quarantined && reasons.is_empty() && score < self.restore_score => {
+                reasons.push(format!(
+                    "score {:.1} not back to {}",
+                    score, self.restore_score
+                ))
+            }
+            _ => {}
+        }
+        let action = if reasons.is_empty() {
+            reasons.push(match score {
+                Some(score) => format!("{}, score {:.1}", incident.status, score),
+                None => incident.status.to_string(),
+            });
+            Action::Restore
+        } else {
+            Action::Quarantine
+        };
+
+        let changed = current
+            .as_ref()
+            .is_none_or(|recommendation| recommendation.action != action);
+        let since = match current.as_ref() {
+            Some(recommendation) if !changed => recommendation.since,
+            _ => Utc::now(),
+        };
+        let recommendation = Recommendation {
+            chain: provider.chain.clone(),
+            rpc: provider.rpc.clone(),
+            action,
+            reasons,
+            status: incident.status,
+            score,
+            since,
+        };
+        provider
+            .quarantined
+            .set((action == Action::Quarantine) as i64);
+        if changed {
+            if action == Action::Quarantine || current.is_some() {
+                log::info!(
+                    "[{}] Recommending to {} {}: {}",
+                    provider.chain,
+                    action,
+                    provider.rpc,
+                    recommendation.reasons.join(", ")
+                );
+            }
+            // without a streaming client there is no one to tell
+            let _ = self.changes.send(recommendation.clone());
+        }
+        *current = Some(recommendation);
+    }
+}
+
+/// The recommendations of every watched provider.
+pub struct Recommendations {
+    interval: Duration,
+    feed: Feed,
+}
+
+impl Recommendations {
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: f64| {
+            env::var(name)
+                .ok()
+                .map(|value| {
+                    value
+                        .parse::<f64>()
+                        .unwrap_or_else(|_| panic!("Invalid {}", name))
+                })
+                .unwrap_or(default)
+        };
+        let quarantine_score = var("RECOMMEND_QUARANTINE_SCORE", DEFAULT_QUARANTINE_SCORE);
+        let restore_score = var("RECOMMEND_RESTORE_SCORE", DEFAULT_RESTORE_SCORE);
+        if restore_score < quarantine_score {
+            panic!("Invalid RECOMMEND_RESTORE_SCORE: must be at least RECOMMEND_QUARANTINE_SCORE");
+        }
+        let interval = env::var("RECOMMEND_INTERVAL_SECS")
+            .ok()
+            .map(|secs| {
+                secs.parse::<u64>()
+                    .expect("Invalid RECOMMEND_INTERVAL_SECS")
+            })
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+        Self {
+            interval: Duration::from_secs(interval),
+            feed: Feed {
+                quarantine_score,
+                restore_score,
+                providers: Vec::new(),
+                changes: broadcast::channel(STREAM_CAPACITY).0,
+            },
+        }
+    }
+
+    /// Recommend what to do with the provider `rpc` of `chain`, scored by `score`.
+    pub fn watch(&mut self, chain: &str, rpc: &str, score: ProviderScore, registry: &Registry) {
+        let quarantined = IntGauge::new(
+            "provider_quarantined",
+            "Whether the provider is recommended to be quarantined",
+        )
+        .unwrap();
+        registry.register(Box::new(quarantined.clone())).unwrap();
+        self.feed.providers.push(FeedProvider {
+            chain: chain.to_string(),
+            rpc: rpc.to_string(),
+            score,
+            quarantined,
+            incident: Mutex::new(Incident::default()),
+            recommendation: Mutex::new(None),
+        });
+    }
+
+    /// Share the recommendations with the alerts sending status changes and the metrics server
+    /// serving them.
+    pub fn start(
+        self,
+    ) -> (
+        RecommendationFeed,
+        RecommendationSink,
+        RecommendationUpdates,
+    ) {
+        let feed = Arc::new(self.feed);
+        for provider in &feed.providers {
+            feed.evaluate(provider);
+        }
+        (
+            RecommendationFeed(feed.clone()),
+            RecommendationSink(feed.clone()),
+            RecommendationUpdates {
+                interval: self.interval,
+                feed,
+            },
+        )
+    }
+}
+
+/// Serves the recommendations.
+#[derive(Clone)]
+pub struct RecommendationFeed(Arc<Feed>);
+
+impl RecommendationFeed {
+    /// The current recommendation of every provider.
+    pub fn current(&self) -> Vec<Recommendation> {
+        self.0
+            .providers
+            .iter()
+            .filter_map(|provider| provider.recommendation.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Serve `/recommendations`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.current()).unwrap_or_default()
+    }
+
+    /// The changes of recommendations from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Recommendation> {
+        self.0.changes.subscribe()
+    }
+}
+
+/// Keeps the incident state of the providers from the alerts.
+pub struct RecommendationSink(Arc<Feed>);
+
+#[async_trait]
+impl AlertSink for RecommendationSink {
+    async fn alert(&self, alert: &Alert) {
+        if alert.severity != Status::Down {
+            return;
+        }
+        let Some(provider) = self.0.provider(&alert.chain, &alert.rpc) else {
+            return;
+        };
+        let mut incident = provider.incident.lock().unwrap();
+        match alert.state {
+            AlertState::Firing => incident.down.insert(alert.rule, alert.message.clone()),
+            AlertState::Resolved => incident.down.remove(alert.rule),
+        };
+    }
+
+    async fn status_changed(&self, change: &StatusChange) {
+        let Some(provider) = self.0.provider(&change.chain, &change.rpc) else {
+            return;
+        };
+        {
+            let mut incident = provider.incident.lock().unwrap();
+            incident.status = change.to;
+            // alerts raised outside of the rules never resolve
+            if change.to != Status::Down {
+                incident.down.clear();
+            }
+        }
+        self.0.evaluate(provider);
+    }
+}
+
+/// Re-evaluates the recommendations as the scores change.
+pub struct RecommendationUpdates {
+    interval: Duration,
+    feed: Arc<Feed>,
+}
+
+impl RecommendationUpdates {
+    pub async fn run(self) {
+        let mut interval = time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            for provider in &self.feed.providers {
+                self.feed.evaluate(provider);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed() -> Feed {
+        Feed {
+            quarantine_score: DEFAULT_QUARANTINE_SCORE,
+            restore_score: DEFAULT_RESTORE_SCORE,
+            providers: vec![FeedProvider {
+                chain: "ethereum".to_string(),
+                rpc: "eth.example".to_string(),
+                score: ProviderScore::default(),
+                quarantined: IntGauge::new("provider_quarantined", "help").unwrap(),
+                incident: Mutex::new(Incident::default()),
+                recommendation: Mutex::new(None),
+            }],
+            changes: broadcast::channel(STREAM_CAPACITY).0,
+        }
+    }
+
+    /// The action recommended for the provider of `feed` once scored `score`.
+    fn evaluate(feed: &Feed, score: f64) -> Action {
+        let provider = &feed.providers[0];
+        provider.score.set(score);
+        feed.evaluate(provider);
+        let action = provider
+            .recommendation
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .action;
+        assert_eq!(
+            provider.quarantined.get(),
+            (action == Action::Quarantine) as i64
+        );
+        action
+    }
+
+    #[test]
+    fn unscored_provider_is_restored() {
+        let feed = feed();
+        feed.evaluate(&feed.providers[0]);
+        let recommendation = feed.providers[0].recommendation.lock().unwrap();
+        assert_eq!(recommendation.as_ref().unwrap().action, Action::Restore);
+    }
+
+    #[test]
+    fn quarantine_and_restore_hysteresis() {
+        let feed = feed();
+        assert_eq!(evaluate(&feed, 90.0), Action::Restore);
+        // between the thresholds a restored provider stays in rotation
+        assert_eq!(evaluate(&feed, 60.0), Action::Restore);
+        assert_eq!(evaluate(&feed, 49.9), Action::Quarantine);
+        // and a quarantined one stays out until its score is back to the restore score
+        assert_eq!(evaluate(&feed, 60.0), Action::Quarantine);
+        assert_eq!(evaluate(&feed, 79.9), Action::Quarantine);
+        assert_eq!(evaluate(&feed, 80.0), Action::Restore);
+        assert_eq!(evaluate(&feed, 60.0), Action::Restore);
+    }
+
+    #[test]
+    fn down_provider_is_quarantined() {
+        let feed = feed();
+        let provider = &feed.providers[0];
+        provider.incident.lock().unwrap().status = Status::Down;
+        provider
+            .incident
+            .lock()
+            .unwrap()
+            .down
+            .insert("stalled", "no new block".to_string());
+        assert_eq!(evaluate(&feed, 100.0), Action::Quarantine);
+        assert_eq!(
+            provider
+                .recommendation
+                .lock()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .reasons,
+            ["stalled: no new block"]
+        );
+
+        // back up, a score between the thresholds doesn't restore it
+        {
+            let mut incident = provider.incident.lock().unwrap();
+            incident.status = Status::Up;
+            incident.down.clear();
+        }
+        assert_eq!(evaluate(&feed, 70.0), Action::Quarantine);
+        assert_eq!(evaluate(&feed, 95.0), Action::Restore);
+    }
+
+    #[test]
+    fn changes_are_streamed() {
+        let feed = feed();
+        let mut changes = feed.changes.subscribe();
+        evaluate(&feed, 90.0);
+        evaluate(&feed, 85.0);
+        evaluate(&feed, 10.0);
+        assert_eq!(changes.try_recv().unwrap().action, Action::Restore);
+        let quarantine = changes.try_recv().unwrap();
+        assert_eq!(quarantine.action, Action::Quarantine);
+        assert_eq!(quarantine.reasons, ["score 10.0 below 50"]);
+        assert!(changes.try_recv().is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! Canonical head estimation from independent reference endpoints.
+//!
+//! A provider's lag against the other benchmarked providers only tells how it compares to them.
+//! When `<CHAIN>_REFERENCE_URLS` lists a few independent public endpoints, their heads are polled
+//! and the median is used as an estimate of the canonical network head, robust to a single
+//! reference lagging or racing ahead. Every provider then exports its lag against that estimate.
+//!
+//! `<CHAIN>_REFERENCE_RPC_URL` names a single endpoint to measure against instead, e.g. the node
+//! an RPC reseller resells. Its head is polled along with the references and every provider
+//! exports `head_lag_blocks`, how many blocks it trails that head, and `head_lag_seconds`, how long
+//! ago the reference reached the block after the provider's head.
+
+use crate::block_metrics::ChainHead;
+use crate::chain::{ChainConfig, ChainKind};
+
+use ethers::providers::{Http, JsonRpcClient};
+use ethers::types::U64;
+use prometheus::{Gauge, GaugeVec, Opts, Registry};
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::time;
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+struct Reference {
+    host: String,
+    client: Http,
+}
+
+impl Reference {
+    fn new(url: &Url) -> Self {
+        Self {
+            host: url.host_str().unwrap_or_default().to_string(),
+            client: Http::from_str(url.as_str()).expect("could not initialize http"),
+        }
+    }
+}
+
+pub struct ReferenceHeads {
+    chain: Arc<ChainConfig>,
+    chain_head: ChainHead,
+    references: Vec<Reference>,
+    rpc: Option<Reference>,
+    canonical_head: Gauge,
+    canonical_head_sources: Gauge,
+    reference_head: GaugeVec,
+}
+
+impl ReferenceHeads {
+    /// Poll the canonical head references at `urls` and the reference RPC endpoint at `rpc_url`.
+    pub fn new(
+        chain: Arc<ChainConfig>,
+        urls: &[Url],
+        rpc_url: Option<&Url>,
+        chain_head: ChainHead,
+        registry: &Registry,
+    ) -> Self {
+        let references = urls.iter().map(Reference::new).collect();
+        let rpc = rpc_url.map(Reference::new);
+
+        let canonical_head = Gauge::new(
+            "canonical_head",
+            "Canonical head estimated as the median head of the reference endpoints",
+        )
+        .unwrap();
+        let canonical_head_sources = Gauge::new(
+            "canonical_head_sources",
+            "Number of reference endpoints the canonical head was estimated from",
+        )
+        .unwrap();
+        let reference_head = GaugeVec::new(
+            Opts::new(
+                "reference_head",
+                "Head height reported by a reference endpoint",
+            ),
+            &["reference"],
+        )
+        .unwrap();
+        registry.register(Box::new(canonical_head.clone())).unwrap();
+        registry
+            .register(Box::new(canonical_head_sources.clone()))
+            .unwrap();
+        registry.register(Box::new(reference_head.clone())).unwrap();
+
+        Self {
+            chain,
+            chain_head,
+            references,
+            rpc,
+            canonical_head,
+            canonical_head_sources,
+            reference_head,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = time::interval(self.chain.poll_interval);
+
+        loop {
+            interval.tick().await;
+
+            // a slow reference must not hold back the estimate
+            let timeout = self.chain.poll_interval * 4;
+            let (heads, rpc_head) = futures::future::join(
+                futures::future::join_all(
+                    self.references
+                        .iter()
+                        .map(|reference| time::timeout(timeout, self.get_head(reference))),
+                ),
+                async {
+                    match &self.rpc {
+                        Some(rpc) => time::timeout(timeout, self.get_head(rpc)).await.ok()?,
+                        None => None,
+                    }
+                },
+            )
+            .await;
+            if let Some(height) = rpc_head {
+                self.chain_head.observe_reference(height);
+            }
+
+            let mut heights = Vec::with_capacity(heads.len());
+            for (reference, head) in self.references.iter().zip(heads) {
+                match head {
+                    Ok(Some(height)) => {
+                        self.reference_head
+                            .with_label_values(&[&reference.host])
+                            .set(height as f64);
+                        heights.push(height);
+                    }
+                    Ok(None) => {}
+                    Err(_) => log::warn!(
+                        "[{}] Reference {} timed out",
+                        self.chain.name,
+                        reference.host
+                    ),
+                }
+            }
+
+            if self.references.is_empty() {
+                continue;
+            }
+            self.canonical_head_sources.set(heights.len() as f64);
+            if heights.is_empty() {
+                continue;
+            }
+
+            heights.sort_unstable();
+            let canonical = heights[heights.len() / 2];
+            self.canonical_head.set(canonical as f64);
+            self.chain_head.set_canonical(canonical);
+        }
+    }
+
+    async fn get_head(&self, reference: &Reference) -> Option<u64> {
+        let res: Result<Value, _> = match self.chain.kind {
+            ChainKind::Evm => reference.client.request("eth_blockNumber", json!([])).await,
+            ChainKind::Solana => {
+                reference
+                    .client
+                    .request("getSlot", json!([{ "commitment": "confirmed" }]))
+                    .await
+            }
+            ChainKind::Tendermint => reference.client.request("status", json!({})).await,
+            ChainKind::Starknet => {
+                reference
+                    .client
+                    .request("starknet_blockNumber", json!([]))
+                    .await
+            }
+            // refused with the chain's configuration
+            ChainKind::Bitcoin | ChainKind::Http => unreachable!(),
+        };
+
+        let head = match res {
+            Ok(head) => head,
+            Err(e) => {
+                log::warn!(
+                    "[{}] Failed to get head from reference {}: {:?}",
+                    self.chain.name,
+                    reference.host,
+                    e
+                );
+                return None;
+            }
+        };
+
+        match self.chain.kind {
+            ChainKind::Evm => serde_json::from_value::<U64>(head).ok().map(|h| h.as_u64()),
+            ChainKind::Tendermint => head["sync_info"]["latest_block_height"]
+                .as_str()
+                .and_then(|height| height.parse().ok()),
+            _ => head.as_u64(),
+        }
+    }
+}
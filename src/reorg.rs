@@ -0,0 +1,126 @@
+//! Reorg detection.
+//!
+//! The hashes of the last blocks observed from an EVM provider are kept along with their
+//! numbers. A new block whose parent hash isn't the hash observed at the height below it, or a
+//! height returning a different hash than before, means the provider switched to another chain
+//! segment. The replaced blocks are found by walking the new segment's parent hashes down until
+//! they meet an observed hash again, at most [`MAX_DEPTH`] blocks deep, and the reorg is logged
+//! with the replaced hashes and counted in `reorgs_total` by `depth`, the number of blocks
+//! replaced.
+
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+
+use ethers::prelude::*;
+use prometheus::{IntCounterVec, Opts, Registry};
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Blocks of history kept, the deepest reorg detected.
+pub const MAX_DEPTH: u64 = 64;
+
+pub struct Reorgs {
+    chain: String,
+    endpoint: String,
+    /// Hashes of the blocks observed, by number.
+    hashes: Mutex<BTreeMap<u64, H256>>,
+    reorgs: IntCounterVec,
+}
+
+impl Reorgs {
+    pub fn new(chain: &str, endpoint: &str, registry: &Registry) -> Self {
+        let reorgs = IntCounterVec::new(
+            Opts::new(
+                "reorgs_total",
+                "Number of chain reorganizations observed from the provider, by replaced blocks",
+            ),
+            &["depth"],
+        )
+        .unwrap();
+        registry.register(Box::new(reorgs.clone())).unwrap();
+
+        Self {
+            chain: chain.to_string(),
+            endpoint: endpoint.to_string(),
+            hashes: Mutex::new(BTreeMap::new()),
+            reorgs,
+        }
+    }
+
+    fn observed(&self, number: u64) -> Option<H256> {
+        self.hashes.lock().unwrap().get(&number).copied()
+    }
+
+    /// Check the linkage of block `number` with hash `hash` and parent `parent_hash`, fetching the
+    /// blocks below it from `provider` when they were replaced.
+    pub async fn observe(
+        &self,
+        provider: &Provider<MeasuredJsonRpc>,
+        number: u64,
+        hash: H256,
+        parent_hash: H256,
+    ) {
+        // (number, replaced hash, new hash) from the top down
+        let mut replaced = Vec::new();
+        if let Some(observed) = self.observed(number).filter(|observed| *observed != hash) {
+            replaced.push((number, observed, hash));
+        }
+
+        let mut height = number.saturating_sub(1);
+        let mut expected = parent_hash;
+        while number - height <= MAX_DEPTH {
+            let Some(observed) = self.observed(height) else {
+                break;
+            };
+            if observed == expected || height == 0 {
+                break;
+            }
+            replaced.push((height, observed, expected));
+            let parent = match provider.get_block(height).await {
+                Ok(Some(block)) => block.parent_hash,
+                res => {
+                    log::warn!(
+                        "[{}] Failed to walk back reorged block {} of {}: {:?}",
+                        self.chain,
+                        height,
+                        self.endpoint,
+                        res.err()
+                    );
+                    break;
+                }
+            };
+            height -= 1;
+            expected = parent;
+        }
+
+        {
+            let mut hashes = self.hashes.lock().unwrap();
+            for (height, _, hash) in &replaced {
+                hashes.insert(*height, *hash);
+            }
+            hashes.insert(number, hash);
+            *hashes = hashes.split_off(&number.saturating_sub(MAX_DEPTH));
+        }
+
+        let Some((lowest, ..)) = replaced.last() else {
+            return;
+        };
+        let segment = replaced
+            .iter()
+            .rev()
+            .map(|(height, old, new)| format!("{} {:?} -> {:?}", height, old, new))
+            .collect::<Vec<_>>()
+            .join(", ");
+        log::warn!(
+            "[{}] {} reorged {} blocks from block {}: {}",
+            self.chain,
+            self.endpoint,
+            replaced.len(),
+            lowest,
+            segment
+        );
+        self.reorgs
+            .with_label_values(&[&replaced.len().to_string()])
+            .inc();
+    }
+}
@@ -0,0 +1,177 @@
+//! Interactive JSON-RPC requests.
+//!
+//! During a provider incident it helps to poke at an endpoint by hand while seeing what BenchETH
+//! would measure. `bencheth repl` reads a method and optional JSON parameters per line, e.g.
+//! `eth_getBlockByNumber ["latest", false]`, and sends it through the measured transport, retries
+//! and all, to the current endpoint. For every request it prints the latency, the retries, the
+//! bytes received over all attempts and the pretty-printed result or error.
+//!
+//! The first RPC URL of the first chain is used to start with. `.list` prints the endpoints of
+//! every JSON-RPC chain, `.use <n>` switches to one by number or `endpoint` name, and `.quit`
+//! or the end of input exits.
+
+use crate::chain::{ChainConfig, ChainKind};
+use crate::measured_json_rpc_client::{MeasuredJsonRpc, MeasuredJsonRpcError};
+
+use ethers::providers::{Authorization, JsonRpcClient};
+use prometheus::Registry;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use std::error::Error;
+use std::io::Write;
+use std::time::Instant;
+
+struct Endpoint {
+    chain: String,
+    name: String,
+    client: MeasuredJsonRpc,
+    registry: Registry,
+}
+
+impl Endpoint {
+    /// Total of counter `name` over the label sets of `method`.
+    fn counter(&self, name: &str, method: &str) -> f64 {
+        self.registry
+            .gather()
+            .iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "method" && label.get_value() == method)
+            })
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    }
+}
+
+fn endpoints(chains: &[ChainConfig]) -> Vec<Endpoint> {
+    let mut endpoints = Vec::new();
+    for chain in chains {
+        if chain.kind == ChainKind::Http {
+            continue;
+        }
+        for (url, name) in chain.rpc_urls.iter().zip(&chain.endpoints) {
+            let registry = Registry::new();
+            let client = match &chain.basic_auth {
+                _ if url.scheme() == "file" => MeasuredJsonRpc::new_ipc(
+                    url.to_file_path().expect("Invalid RPC_URL"),
+                    &registry,
+                ),
+                Some((username, password)) => MeasuredJsonRpc::new_with_auth(
+                    url.as_str(),
+                    Authorization::basic(username, password),
+                    &registry,
+                ),
+                None => MeasuredJsonRpc::new(url.as_str(), &registry),
+            };
+            endpoints.push(Endpoint {
+                chain: chain.name.clone(),
+                name: name.clone(),
+                client,
+                registry,
+            });
+        }
+    }
+    endpoints
+}
+
+/// Parse a line into a method and its parameters, `[]` when there are none.
+fn parse(line: &str) -> Result<(&str, Value), String> {
+    let (method, params) = match line.split_once(char::is_whitespace) {
+        Some((method, params)) => (method, params.trim()),
+        None => (line, ""),
+    };
+    if params.is_empty() {
+        return Ok((method, Value::Array(Vec::new())));
+    }
+    match serde_json::from_str(params) {
+        Ok(params @ (Value::Array(_) | Value::Object(_))) => Ok((method, params)),
+        Ok(_) => Err("parameters must be a JSON array or object".to_string()),
+        Err(e) => Err(format!("invalid parameters: {}", e)),
+    }
+}
+
+async fn send(endpoint: &Endpoint, method: &str, params: Value) {
+    let errors = endpoint.counter("request_errors", method);
+    let received = endpoint.counter("response_bytes_total", method);
+
+    let start = Instant::now();
+    let res: Result<Value, MeasuredJsonRpcError> = endpoint.client.request(method, params).await;
+    let latency = start.elapsed();
+
+    // every failed attempt is counted, the last one too when the request failed
+    let failed = endpoint.counter("request_errors", method) - errors;
+    let retries = if res.is_ok() { failed } else { failed - 1.0 }.max(0.0);
+    let received = endpoint.counter("response_bytes_total", method) - received;
+    println!(
+        "{} via {}: {:.1}ms, {} retries, {} bytes",
+        method,
+        endpoint.name,
+        latency.as_secs_f64() * 1000.0,
+        retries,
+        received
+    );
+    match res {
+        Ok(result) => println!(
+            "{}",
+            serde_json::to_string_pretty(&result).unwrap_or_default()
+        ),
+        Err(e) => println!("error: {}", e),
+    }
+}
+
+/// Read requests from stdin and send them until the end of input.
+pub async fn run(chains: &[ChainConfig]) -> Result<(), Box<dyn Error>> {
+    let endpoints = endpoints(chains);
+    if endpoints.is_empty() {
+        return Err("No JSON-RPC endpoints configured".into());
+    }
+    let mut current = 0;
+    println!(
+        "Sending to {} ({}), .list for the endpoints, .use <n> to switch",
+        endpoints[current].name, endpoints[current].chain
+    );
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("{}> ", endpoints[current].name);
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            return Ok(());
+        };
+        let line = line.trim();
+
+        match line.split_once(char::is_whitespace).unwrap_or((line, "")) {
+            ("", _) => {}
+            (".quit" | ".exit", _) => return Ok(()),
+            (".list", _) => {
+                for (n, endpoint) in endpoints.iter().enumerate() {
+                    println!("{} {} ({})", n, endpoint.name, endpoint.chain);
+                }
+            }
+            (".use", target) => {
+                let target = target.trim();
+                match target
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n < endpoints.len())
+                {
+                    Some(n) => current = n,
+                    None => match endpoints.iter().position(|e| e.name == target) {
+                        Some(n) => current = n,
+                        None => println!("unknown endpoint {}", target),
+                    },
+                }
+            }
+            _ => match parse(line) {
+                Ok((method, params)) => send(&endpoints[current], method, params).await,
+                Err(e) => println!("{}", e),
+            },
+        }
+    }
+}
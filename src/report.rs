@@ -0,0 +1,489 @@
+//! Run report.
+//!
+//! Dashboards show the present, the run report summarizes a whole run. When `REPORT_PATH` is
+//! set, a JSON report is written there every `REPORT_INTERVAL_SECS` (default 60) and once more on
+//! shutdown.
+//!
+//! Per provider, the report contains percentiles of the block propagation delay (when the block
+//! was first seen minus its timestamp), over the whole run and over rolling windows. Averages
+//! hide providers with bimodal freshness, percentiles don't. The same goes for the interval
+//! between successive detections of a new head, which exposes stalls.
+//!
+//! Request latencies are summarized from [sketches](crate::sketch), which are included so
+//! reports of several agents can be merged, next to the number of requests and errors.
+//!
+//! With `REPORT_SAMPLES=true` the samples kept for the whole run are included as well, so that
+//! `bencheth compare` can test the significance of differences between providers or runs.
+//!
+//! Every report embeds the [manifest](crate::manifest) of the run and, with
+//! `MANIFEST_SIGNING_KEY` set, is signed.
+//!
+//! `LATENCY_TARGETS` declares the request latency expected from each geo, e.g.
+//! `us-east-1=40,eu-west-1=110,ap-southeast-1=220` in milliseconds, at the
+//! `LATENCY_TARGET_QUANTILE` (`p50`, `p90` (default) or `p99`). Every provider's latency is
+//! checked against the target of the agent's geo. A provider missing it is flagged with the
+//! farther geo whose target its latency fits, as a provider that answers from us-east-1 like
+//! from Europe most likely routes anycast traffic to the wrong PoP or has no PoP nearby.
+
+use crate::calibration::Calibration;
+use crate::limits;
+use crate::manifest::{Manifest, Signer};
+use crate::outliers::{self, Outlier};
+use crate::sketch::{DDSketch, LatencySketches};
+
+use chrono::{DateTime, Utc};
+use ethers::core::rand::{thread_rng, Rng};
+use prometheus::proto::MetricFamily;
+use prometheus::Registry;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Samples kept to estimate percentiles over the whole run, older samples are replaced at random
+/// (reservoir sampling) so every sample of the run has the same chance of being kept.
+const RUN_RESERVOIR_SIZE: usize = 10_000;
+
+/// Rolling windows reported next to the whole run.
+const WINDOWS: [(&str, Duration); 2] = [
+    ("5m", Duration::from_secs(5 * 60)),
+    ("1h", Duration::from_secs(60 * 60)),
+];
+
+/// Summary statistics of a set of samples.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Summary {
+    pub count: usize,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Summary {
+    pub fn from_samples(samples: impl IntoIterator<Item = f64>) -> Option<Self> {
+        let mut samples = samples.into_iter().collect::<Vec<_>>();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(|a, b| a.total_cmp(b));
+
+        let percentile = |p: f64| {
+            let rank = (p * (samples.len() - 1) as f64).round() as usize;
+            samples[rank]
+        };
+
+        Some(Self {
+            count: samples.len(),
+            mean: samples.iter().sum::<f64>() / samples.len() as f64,
+            p50: percentile(0.50),
+            p90: percentile(0.90),
+            p99: percentile(0.99),
+            max: samples[samples.len() - 1],
+        })
+    }
+
+    pub fn from_sketch(sketch: &DDSketch) -> Option<Self> {
+        Some(Self {
+            count: sketch.count as usize,
+            mean: sketch.sum / sketch.count as f64,
+            p50: sketch.quantile(0.50)?,
+            p90: sketch.quantile(0.90)?,
+            p99: sketch.quantile(0.99)?,
+            max: sketch.max,
+        })
+    }
+}
+
+/// A metric summarized over the whole run and over rolling windows.
+#[derive(Debug, Default)]
+struct SampleSet {
+    seen: u64,
+    reservoir: Vec<f64>,
+    recent: VecDeque<(DateTime<Utc>, f64)>,
+}
+
+impl SampleSet {
+    fn record(&mut self, value: f64) {
+        self.seen += 1;
+        if self.reservoir.len() < RUN_RESERVOIR_SIZE {
+            self.reservoir.push(value);
+        } else {
+            let slot = thread_rng().gen_range(0..self.seen) as usize;
+            if slot < RUN_RESERVOIR_SIZE {
+                self.reservoir[slot] = value;
+            }
+        }
+
+        let now = Utc::now();
+        if limits::memory_exceeded() {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((now, value));
+        let longest = WINDOWS.iter().map(|(_, window)| *window).max().unwrap();
+        let cutoff = now - chrono::Duration::from_std(longest).unwrap();
+        while matches!(self.recent.front(), Some((at, _)) if *at < cutoff) {
+            self.recent.pop_front();
+        }
+    }
+
+    fn report(&self, include_samples: bool) -> SampleReport {
+        let now = Utc::now();
+        let windows = WINDOWS
+            .iter()
+            .filter_map(|(name, window)| {
+                let cutoff = now - chrono::Duration::from_std(*window).unwrap();
+                let samples = self
+                    .recent
+                    .iter()
+                    .filter(|(at, _)| *at >= cutoff)
+                    .map(|(_, value)| *value);
+                Summary::from_samples(samples).map(|summary| (name.to_string(), summary))
+            })
+            .collect();
+
+        SampleReport {
+            run: Summary::from_samples(self.reservoir.iter().copied()).map(|summary| Summary {
+                count: self.seen as usize,
+                ..summary
+            }),
+            windows,
+            samples: include_samples.then(|| self.reservoir.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SampleReport {
+    pub run: Option<Summary>,
+    pub windows: BTreeMap<String, Summary>,
+    /// Samples of the whole run, with `REPORT_SAMPLES=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Default)]
+struct ProviderSamples {
+    propagation_delay: SampleSet,
+    detection_interval: SampleSet,
+    latency: Option<LatencySketches>,
+    registry: Option<Registry>,
+}
+
+/// Handle used by a provider's monitor to record samples for the report.
+#[derive(Clone, Debug, Default)]
+pub struct ProviderStats(Arc<Mutex<ProviderSamples>>);
+
+impl ProviderStats {
+    /// Record how long after its timestamp a block was first seen, in seconds.
+    pub fn record_propagation_delay(&self, delay_secs: f64) {
+        self.0.lock().unwrap().propagation_delay.record(delay_secs);
+    }
+
+    /// Record the time between two successive detections of a new head, in seconds.
+    pub fn record_detection_interval(&self, interval_secs: f64) {
+        self.0
+            .lock()
+            .unwrap()
+            .detection_interval
+            .record(interval_secs);
+    }
+
+    /// Include the request latency sketches of the provider's transport.
+    pub fn track_latency(&self, sketches: LatencySketches) {
+        self.0.lock().unwrap().latency = Some(sketches);
+    }
+
+    /// Include the request and error counters the provider's transport registers in `registry`.
+    pub fn track_requests(&self, registry: &Registry) {
+        self.0.lock().unwrap().registry = Some(registry.clone());
+    }
+}
+
+/// The requests made to a provider and the errors among them, from its counters.
+fn request_counts(registry: &Registry) -> (u64, u64) {
+    let sum = |family: &MetricFamily| {
+        family
+            .get_metric()
+            .iter()
+            .map(|metric| metric.get_counter().get_value())
+            .sum::<f64>() as u64
+    };
+    let mut counts = (0, 0);
+    for family in registry.gather() {
+        match family.get_name() {
+            "request_total" => counts.0 = sum(&family),
+            "request_errors" => counts.1 = sum(&family),
+            _ => {}
+        }
+    }
+    counts
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderReport {
+    pub chain: String,
+    pub rpc: String,
+    pub propagation_delay_seconds: SampleReport,
+    pub detection_interval_seconds: SampleReport,
+    /// Request latency over all methods, estimated from the sketches.
+    pub request_latency_seconds: Option<Summary>,
+    /// Mergeable request latency sketches by method, in seconds.
+    pub request_latency_sketches: BTreeMap<String, DDSketch>,
+    /// Requests made to the provider, retries included.
+    pub requests: u64,
+    /// Requests that failed, by timeout, transport or JSON-RPC error.
+    pub errors: u64,
+    /// Request latency checked against the target of the agent's geo.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_target: Option<TargetCheck>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TargetCheck {
+    pub quantile: &'static str,
+    pub target_ms: f64,
+    pub measured_ms: f64,
+    pub met: bool,
+    /// When the target is missed, the nearest farther geo whose target the latency meets, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub performs_like: Option<String>,
+}
+
+/// Request latencies expected from each geo.
+#[derive(Clone, Debug)]
+struct LatencyTargets {
+    quantile: &'static str,
+    /// Targets in milliseconds by geo, nearest first.
+    targets_ms: Vec<(String, f64)>,
+}
+
+impl LatencyTargets {
+    fn from_env() -> Option<Self> {
+        let quantile = match env::var("LATENCY_TARGET_QUANTILE").as_deref() {
+            Ok("p50") => "p50",
+            Ok("p90") | Err(_) => "p90",
+            Ok("p99") => "p99",
+            Ok(_) => panic!("Invalid LATENCY_TARGET_QUANTILE: must be p50, p90 or p99"),
+        };
+        let mut targets_ms = env::var("LATENCY_TARGETS")
+            .ok()?
+            .split(',')
+            .map(str::trim)
+            .filter(|target| !target.is_empty())
+            .map(|target| {
+                let (geo, ms) = target.split_once('=').expect("Invalid LATENCY_TARGETS");
+                let ms = ms.trim().parse::<f64>().expect("Invalid LATENCY_TARGETS");
+                (geo.trim().to_string(), ms)
+            })
+            .collect::<Vec<_>>();
+        targets_ms.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        Some(Self {
+            quantile,
+            targets_ms,
+        })
+    }
+
+    /// Check `latency`, in seconds, measured from `geo`. `None` without a target for `geo`.
+    fn check(&self, geo: &str, latency: &Summary) -> Option<TargetCheck> {
+        let (_, target_ms) = self.targets_ms.iter().find(|(name, _)| name == geo)?;
+        let measured_ms = match self.quantile {
+            "p50" => latency.p50,
+            "p99" => latency.p99,
+            _ => latency.p90,
+        } * 1000.0;
+        let met = measured_ms <= *target_ms;
+        let performs_like = (!met)
+            .then(|| {
+                self.targets_ms
+                    .iter()
+                    .find(|(_, ms)| *ms > *target_ms && measured_ms <= *ms)
+                    .map(|(name, _)| name.clone())
+            })
+            .flatten();
+
+        Some(TargetCheck {
+            quantile: self.quantile,
+            target_ms: *target_ms,
+            measured_ms,
+            met,
+            performs_like,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    pub version: String,
+    pub geo: String,
+    pub started_at: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    /// Harness overhead measured on startup, to be subtracted from provider latencies.
+    pub calibration: Option<Calibration>,
+    pub manifest: Manifest,
+    pub providers: Vec<ProviderReport>,
+    /// Slow and failed requests recorded with `OUTLIER_LATENCY_MS`, to reproduce with `curl`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outliers: Vec<Outlier>,
+}
+
+/// Collects the stats of every provider of the run.
+#[derive(Clone, Debug)]
+pub struct Report {
+    geo: String,
+    manifest: Arc<Mutex<Manifest>>,
+    signer: Option<Arc<Signer>>,
+    include_samples: bool,
+    include_outliers: bool,
+    latency_targets: Option<LatencyTargets>,
+    calibration: Arc<Mutex<Option<Calibration>>>,
+    providers: Arc<Mutex<Vec<(String, String, ProviderStats)>>>,
+}
+
+impl Report {
+    pub fn new(geo: impl Into<String>, manifest: Manifest) -> Self {
+        Self {
+            geo: geo.into(),
+            manifest: Arc::new(Mutex::new(manifest)),
+            signer: Signer::from_env().map(Arc::new),
+            include_samples: env::var("REPORT_SAMPLES")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            include_outliers: false,
+            latency_targets: LatencyTargets::from_env(),
+            calibration: Default::default(),
+            providers: Default::default(),
+        }
+    }
+
+    /// Include the outliers recorded by the agent. They aren't told apart by provider, so only
+    /// the report covering every provider should.
+    pub fn with_outliers(mut self) -> Self {
+        self.include_outliers = true;
+        self
+    }
+
+    /// Mark the run as ended, for the final report.
+    pub fn finish(&self) {
+        self.manifest.lock().unwrap().ended_at = Some(Utc::now());
+    }
+
+    pub fn set_calibration(&self, calibration: Calibration) {
+        *self.calibration.lock().unwrap() = Some(calibration);
+    }
+
+    /// Register a provider, returning the handle its monitor records samples with.
+    pub fn provider(&self, chain: &str, rpc: &str) -> ProviderStats {
+        let stats = ProviderStats::default();
+        self.providers
+            .lock()
+            .unwrap()
+            .push((chain.to_string(), rpc.to_string(), stats.clone()));
+        stats
+    }
+
+    /// Include the provider already registered with another report, sharing its `stats`.
+    pub fn include(&self, chain: &str, rpc: &str, stats: ProviderStats) {
+        self.providers
+            .lock()
+            .unwrap()
+            .push((chain.to_string(), rpc.to_string(), stats));
+    }
+
+    pub fn build(&self) -> RunReport {
+        let providers = self
+            .providers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(chain, rpc, stats)| {
+                let samples = stats.0.lock().unwrap();
+                let sketches = samples
+                    .latency
+                    .as_ref()
+                    .map(LatencySketches::snapshot)
+                    .unwrap_or_default();
+                let mut all = DDSketch::default();
+                sketches.values().for_each(|sketch| all.merge(sketch));
+                let request_latency = Summary::from_sketch(&all);
+                let (requests, errors) = samples
+                    .registry
+                    .as_ref()
+                    .map(request_counts)
+                    .unwrap_or_default();
+                let latency_target = self
+                    .latency_targets
+                    .as_ref()
+                    .zip(request_latency.as_ref())
+                    .and_then(|(targets, latency)| targets.check(&self.geo, latency));
+                ProviderReport {
+                    chain: chain.clone(),
+                    rpc: rpc.clone(),
+                    propagation_delay_seconds: samples
+                        .propagation_delay
+                        .report(self.include_samples),
+                    detection_interval_seconds: samples
+                        .detection_interval
+                        .report(self.include_samples),
+                    request_latency_seconds: request_latency,
+                    request_latency_sketches: sketches,
+                    requests,
+                    errors,
+                    latency_target,
+                }
+            })
+            .collect();
+
+        let manifest = self.manifest.lock().unwrap().clone();
+        RunReport {
+            version: manifest.version.clone(),
+            geo: self.geo.clone(),
+            started_at: manifest.started_at,
+            generated_at: Utc::now(),
+            calibration: self.calibration.lock().unwrap().clone(),
+            manifest,
+            providers,
+            outliers: if self.include_outliers {
+                outliers::recent()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Write the report to `path` as pretty-printed JSON, and its signature to `<path>.sig`
+    /// when signing is enabled.
+    pub fn write(&self, path: &str) {
+        let report = self.build();
+        let json = serde_json::to_string_pretty(&report).expect("Failed to serialize report");
+        // the signature goes first, so a report is never newer than its signature
+        if let Some(signer) = &self.signer {
+            let signature = serde_json::to_string_pretty(&signer.sign(json.as_bytes()))
+                .expect("Failed to serialize signature");
+            write_atomically(&format!("{}.sig", path), signature);
+        }
+        write_atomically(path, json);
+    }
+
+    /// Write the report to `path` every `interval`.
+    pub async fn run(self, path: String, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            self.write(&path);
+        }
+    }
+}
+
+/// Write to a temporary file first so readers never see a partial file.
+fn write_atomically(path: &str, contents: String) {
+    let tmp = format!("{}.tmp", path);
+    match std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, path)) {
+        Ok(()) => log::debug!("Report written to {}", path),
+        Err(e) => log::warn!("Failed to write report to {}: {:?}", path, e),
+    }
+}
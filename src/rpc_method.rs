@@ -0,0 +1,141 @@
+//! EVM JSON-RPC methods.
+//!
+//! Scenarios name their methods with [`RpcMethod`] rather than strings, so that a typo fails
+//! loudly instead of silently creating a new `method` label value. Configured methods, e.g.
+//! `EXPERIMENT_METHOD` or `<CHAIN>_CALL_<NAME>_METHODS`, must be known methods as well, unless
+//! prefixed with `raw:` to send any other method as is, e.g. `raw:eth_getUserOperationByHash`.
+//!
+//! Every method belongs to a [`MethodClass`] derived from its name: `trace_*` and
+//! `debug_trace*` methods trace, `eth_send*` methods write, and everything else reads. Scenarios
+//! repeating a configured request refuse to repeat writes.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Prefix of configured methods that aren't known.
+const RAW_PREFIX: &str = "raw:";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MethodClass {
+    Read,
+    Trace,
+    Write,
+}
+
+macro_rules! rpc_methods {
+    ($($variant:ident => $name:literal,)*) => {
+        #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum RpcMethod {
+            $($variant,)*
+            /// Any other method, configured with the `raw:` prefix.
+            Raw(String),
+        }
+
+        impl RpcMethod {
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(RpcMethod::$variant => $name,)*
+                    RpcMethod::Raw(method) => method,
+                }
+            }
+
+            fn known(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(RpcMethod::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+rpc_methods! {
+    Web3ClientVersion => "web3_clientVersion",
+    NetVersion => "net_version",
+    EthChainId => "eth_chainId",
+    EthBlockNumber => "eth_blockNumber",
+    EthSyncing => "eth_syncing",
+    EthGasPrice => "eth_gasPrice",
+    EthMaxPriorityFeePerGas => "eth_maxPriorityFeePerGas",
+    EthBlobBaseFee => "eth_blobBaseFee",
+    EthFeeHistory => "eth_feeHistory",
+    EthGetBlockByNumber => "eth_getBlockByNumber",
+    EthGetBlockByHash => "eth_getBlockByHash",
+    EthGetBlockReceipts => "eth_getBlockReceipts",
+    EthGetBalance => "eth_getBalance",
+    EthGetCode => "eth_getCode",
+    EthGetStorageAt => "eth_getStorageAt",
+    EthGetTransactionCount => "eth_getTransactionCount",
+    EthGetTransactionByHash => "eth_getTransactionByHash",
+    EthGetTransactionReceipt => "eth_getTransactionReceipt",
+    EthGetProof => "eth_getProof",
+    EthGetLogs => "eth_getLogs",
+    EthCall => "eth_call",
+    EthEstimateGas => "eth_estimateGas",
+    EthCreateAccessList => "eth_createAccessList",
+    EthSimulateV1 => "eth_simulateV1",
+    EthSendRawTransaction => "eth_sendRawTransaction",
+    TxpoolStatus => "txpool_status",
+    TraceBlock => "trace_block",
+    TraceTransaction => "trace_transaction",
+    DebugTraceBlockByNumber => "debug_traceBlockByNumber",
+    DebugTraceTransaction => "debug_traceTransaction",
+    DebugGetRawBlock => "debug_getRawBlock",
+    DebugGetRawReceipts => "debug_getRawReceipts",
+    DebugGetRawTransaction => "debug_getRawTransaction",
+    OtsGetApiLevel => "ots_getApiLevel",
+    OtsGetBlockDetails => "ots_getBlockDetails",
+    OtsGetBlockTransactions => "ots_getBlockTransactions",
+    OtsHasCode => "ots_hasCode",
+    ErigonBlockNumber => "erigon_blockNumber",
+    ErigonGetHeaderByNumber => "erigon_getHeaderByNumber",
+    ErigonGetLogsByHash => "erigon_getLogsByHash",
+    ErigonForks => "erigon_forks",
+    RpcModules => "rpc_modules",
+    RpcDiscover => "rpc.discover",
+}
+
+impl RpcMethod {
+    pub fn class(&self) -> MethodClass {
+        let name = self.as_str();
+        if name.starts_with("trace_") || name.starts_with("debug_trace") {
+            MethodClass::Trace
+        } else if name.starts_with("eth_send") {
+            MethodClass::Write
+        } else {
+            MethodClass::Read
+        }
+    }
+
+    /// Parse a configured method that a scenario sends repeatedly, refusing writes.
+    pub fn repeatable(name: &str) -> Result<Self, String> {
+        let method = name.parse::<RpcMethod>()?;
+        if method.class() == MethodClass::Write {
+            return Err(format!("{} writes to the chain", method));
+        }
+        Ok(method)
+    }
+}
+
+impl FromStr for RpcMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(method) = s.strip_prefix(RAW_PREFIX) {
+            return Ok(RpcMethod::known(method).unwrap_or_else(|| RpcMethod::Raw(method.into())));
+        }
+        RpcMethod::known(s).ok_or_else(|| {
+            format!(
+                "unknown method {}, prefix it with {} to send it anyway",
+                s, RAW_PREFIX
+            )
+        })
+    }
+}
+
+impl fmt::Display for RpcMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,112 @@
+//! Tokio runtime construction.
+//!
+//! The measurement hot path (monitors and probes) runs on its own runtime, while auxiliary tasks
+//! (metrics server, report writer) run on a separate one, so a slow scrape or a large report
+//! flush can't delay the timing of in-flight requests. `AUXILIARY_THREADS` (default 1) sets the
+//! number of worker threads of the auxiliary runtime.
+//!
+//! On shared hosts the scheduler moves measurement threads between cores and other processes
+//! preempt them, which shows up as jitter in tail latencies. The runtime can be pinned to
+//! dedicated cores and given a higher priority:
+//!
+//! - `MEASUREMENT_CPUS`: comma-separated core ids (e.g. `2,3`). One worker thread is started per
+//!   core and pinned to it.
+//! - `MEASUREMENT_NICE`: nice value of the worker threads (e.g. `-10`). Raising priority requires
+//!   `CAP_SYS_NICE` or root; failures are logged and ignored. Only supported on unix.
+
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const DEFAULT_AUXILIARY_THREADS: usize = 1;
+
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeConfig {
+    pub cpus: Vec<usize>,
+    pub nice: Option<i32>,
+}
+
+impl RuntimeConfig {
+    pub fn from_env() -> Self {
+        let cpus = env::var("MEASUREMENT_CPUS")
+            .map(|cpus| {
+                cpus.split(',')
+                    .map(str::trim)
+                    .filter(|cpu| !cpu.is_empty())
+                    .map(|cpu| cpu.parse::<usize>().expect("Invalid MEASUREMENT_CPUS"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let nice = env::var("MEASUREMENT_NICE")
+            .ok()
+            .map(|nice| nice.parse::<i32>().expect("Invalid MEASUREMENT_NICE"));
+
+        Self { cpus, nice }
+    }
+
+    /// Build the measurement runtime, applying the affinity and priority settings to each of
+    /// its worker threads.
+    pub fn build(&self) -> tokio::runtime::Runtime {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.enable_all().thread_name("bencheth-measure");
+
+        if !self.cpus.is_empty() {
+            builder.worker_threads(self.cpus.len());
+        }
+
+        let cpus = self.cpus.clone();
+        let nice = self.nice;
+        let next_cpu = Arc::new(AtomicUsize::new(0));
+        builder.on_thread_start(move || {
+            if !cpus.is_empty() {
+                // blocking threads are started the same way, they share the cores round-robin
+                let cpu = cpus[next_cpu.fetch_add(1, Ordering::Relaxed) % cpus.len()];
+                if !core_affinity::set_for_current(core_affinity::CoreId { id: cpu }) {
+                    log::warn!("Failed to pin thread to CPU {}", cpu);
+                }
+            }
+            if let Some(nice) = nice {
+                set_current_thread_nice(nice);
+            }
+        });
+
+        builder.build().expect("Failed to build tokio runtime")
+    }
+}
+
+/// Build the runtime for auxiliary tasks. It is never pinned or prioritized so it doesn't
+/// compete with the measurement runtime.
+pub fn build_auxiliary() -> tokio::runtime::Runtime {
+    let threads = env::var("AUXILIARY_THREADS")
+        .ok()
+        .map(|n| n.parse::<usize>().expect("Invalid AUXILIARY_THREADS"))
+        .unwrap_or(DEFAULT_AUXILIARY_THREADS);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(threads)
+        .thread_name("bencheth-aux")
+        .build()
+        .expect("Failed to build auxiliary tokio runtime")
+}
+
+#[cfg(unix)]
+fn set_current_thread_nice(nice: i32) {
+    // on Linux the nice value is per thread and `who = 0` targets the calling thread
+    let res = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) };
+    if res != 0 {
+        log::warn!(
+            "Failed to set thread nice value to {}: {}",
+            nice,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn set_current_thread_nice(nice: i32) {
+    log::warn!(
+        "Setting the thread nice value to {} is not supported on this platform",
+        nice
+    );
+}
@@ -0,0 +1,278 @@
+//! Time-of-day schedules for heavy scenarios.
+//!
+//! Benchmarks sharing API keys with production traffic shouldn't compete with it at peak hours.
+//! Head polling always runs, but the heavier scenarios of a chain can be restricted to a
+//! schedule:
+//!
+//! - `<CHAIN>_TRANSACTIONS_SCHEDULE`: fetching the transactions of every new block.
+//! - `<CHAIN>_CALLS_SCHEDULE`: the calls made at every new block, e.g. `starknet_call` and call
+//!   templates.
+//! - `<CHAIN>_RAW_SCHEDULE`: fetching every new block as RLP and JSON.
+//! - `<CHAIN>_TRANSACTIONS_ROOT_SCHEDULE`: verifying the transactions root of every new block.
+//! - `<CHAIN>_RECEIPTS_ROOT_SCHEDULE`: verifying the receipts root of every new block.
+//! - `<CHAIN>_EXTENDED_SCHEDULE`: the `ots` and `erigon` methods called at every new block.
+//! - `<CHAIN>_LOGS_SCHEDULE`: the log queries made at every new block.
+//! - `<CHAIN>_PROBE_<NAME>_SCHEDULE`: the HTTP probe `<NAME>`.
+//! - `<CHAIN>_HEALTH_SCHEDULE`: health endpoint checks.
+//! - `<CHAIN>_ARCHIVAL_DEPTH_SCHEDULE`: archival depth searches.
+//! - `<CHAIN>_BACKEND_FINGERPRINT_SCHEDULE`: backend node fingerprinting.
+//! - `<CHAIN>_BACKFILL_SCHEDULE`: backfilling missed blocks.
+//!
+//! A schedule is a `;` separated list of ranges `[<days>] <HH:MM>-<HH:MM> [<timezone>]`, where
+//! the days are a range (`Mon-Fri`) or a `,` separated list (`Sat,Sun`) and default to every day,
+//! and the timezone is an IANA name that defaults to UTC. A range ending before it starts runs
+//! past midnight and `24:00` ends at midnight:
+//!
+//! ```text
+//! ETHEREUM_TRANSACTIONS_SCHEDULE="Mon-Fri 22:00-06:00 Europe/Berlin; Sat,Sun 00:00-24:00 Europe/Berlin"
+//! ```
+
+use crate::chain::ChainConfig;
+
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+
+use std::str::FromStr;
+
+const DAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Range {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    /// Midnight ends the range at the end of the day.
+    end: NaiveTime,
+    timezone: Tz,
+}
+
+impl FromStr for Range {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let tokens = s.split_whitespace().collect::<Vec<_>>();
+        let position = tokens
+            .iter()
+            .position(|token| token.contains(':'))
+            .ok_or_else(|| format!("invalid schedule {}: missing time range", s))?;
+
+        let days = match &tokens[..position] {
+            [] | ["*"] => DAYS.to_vec(),
+            [days] => parse_days(days).map_err(|e| format!("invalid schedule {}: {}", s, e))?,
+            _ => return Err(format!("invalid schedule {}", s)),
+        };
+
+        let (start, end) = tokens[position]
+            .split_once('-')
+            .ok_or_else(|| format!("invalid schedule {}", s))?;
+        let parse = |time: &str| {
+            if time == "24:00" {
+                return Ok(NaiveTime::MIN);
+            }
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|e| format!("invalid schedule {}: {}", s, e))
+        };
+
+        let timezone = match &tokens[position + 1..] {
+            [] => Tz::UTC,
+            [timezone] => timezone
+                .parse::<Tz>()
+                .map_err(|e| format!("invalid schedule {}: {}", s, e))?,
+            _ => return Err(format!("invalid schedule {}", s)),
+        };
+
+        Ok(Range {
+            days,
+            start: parse(start)?,
+            end: parse(end)?,
+            timezone,
+        })
+    }
+}
+
+fn parse_days(days: &str) -> Result<Vec<Weekday>, String> {
+    let parse = |day: &str| {
+        day.parse::<Weekday>()
+            .map_err(|_| format!("unknown weekday {}", day))
+    };
+
+    if let Some((first, last)) = days.split_once('-') {
+        let (first, last) = (parse(first)?, parse(last)?);
+        let mut days = vec![first];
+        let mut day = first;
+        while day != last {
+            day = day.succ();
+            days.push(day);
+        }
+        return Ok(days);
+    }
+
+    days.split(',').map(parse).collect()
+}
+
+impl Range {
+    fn contains(&self, at: DateTime<Utc>) -> bool {
+        let local = at.with_timezone(&self.timezone);
+        let (today, time) = (local.weekday(), local.time());
+
+        let until_midnight = self.end == NaiveTime::MIN;
+        if self.start < self.end || until_midnight {
+            return self.days.contains(&today)
+                && self.start <= time
+                && (until_midnight || time < self.end);
+        }
+
+        // past midnight, the early hours belong to the previous day's range
+        (self.days.contains(&today) && self.start <= time)
+            || (self.days.contains(&today.pred()) && time < self.end)
+    }
+}
+
+/// When a scenario may run, always unless configured.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schedule(Vec<Range>);
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|range| !range.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Schedule)
+    }
+}
+
+impl Schedule {
+    /// The schedule of `scenario`, read from `<CHAIN>_<scenario>_SCHEDULE`.
+    pub fn from_chain(chain: &ChainConfig, scenario: &str) -> Self {
+        chain
+            .var(&format!("{}_SCHEDULE", scenario))
+            .map(|schedule| {
+                schedule
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Invalid {}_SCHEDULE: {}", scenario, e))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether the scenario may run now.
+    pub fn is_active(&self) -> bool {
+        let now = Utc::now();
+        self.0.is_empty() || self.0.iter().any(|range| range.contains(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parse_day_ranges() {
+        let range = "Mon-Fri 09:00-17:00".parse::<Range>().unwrap();
+        assert_eq!(range.days, &DAYS[..5]);
+        assert_eq!((range.start, range.end), (time(9, 0), time(17, 0)));
+        assert_eq!(range.timezone, Tz::UTC);
+
+        // ranges wrap around the week
+        let range = "Fri-Mon 09:00-17:00".parse::<Range>().unwrap();
+        assert_eq!(
+            range.days,
+            [Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon]
+        );
+        let range = "Sat,Sun 00:00-24:00 Europe/Berlin"
+            .parse::<Range>()
+            .unwrap();
+        assert_eq!(range.days, [Weekday::Sat, Weekday::Sun]);
+        assert_eq!(range.end, NaiveTime::MIN);
+        assert_eq!(range.timezone, Tz::Europe__Berlin);
+
+        for every_day in ["09:00-17:00", "* 09:00-17:00"] {
+            assert_eq!(every_day.parse::<Range>().unwrap().days, DAYS);
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for schedule in [
+            "Mon-Fri",
+            "Someday 09:00-17:00",
+            "Mon Tue 09:00-17:00",
+            "09:00",
+            "09:00-25:00",
+            "09:00-17:00 Mars/Olympus",
+            "09:00-17:00 UTC extra",
+        ] {
+            assert!(schedule.parse::<Range>().is_err(), "{} parsed", schedule);
+        }
+    }
+
+    #[test]
+    fn parse_schedule() {
+        assert_eq!("".parse::<Schedule>().unwrap(), Schedule::default());
+        let schedule = "Mon-Fri 22:00-06:00; Sat,Sun 00:00-24:00;"
+            .parse::<Schedule>()
+            .unwrap();
+        assert_eq!(schedule.0.len(), 2);
+        assert!("Mon-Fri 22:00-06:00; Someday 00:00-24:00"
+            .parse::<Schedule>()
+            .is_err());
+    }
+
+    #[test]
+    fn contains() {
+        let range = "Mon-Fri 09:00-17:00".parse::<Range>().unwrap();
+        // 2024-05-06 is a Monday
+        assert!(range.contains(at("2024-05-06T09:00:00Z")));
+        assert!(range.contains(at("2024-05-10T16:59:59Z")));
+        assert!(!range.contains(at("2024-05-06T17:00:00Z")));
+        assert!(!range.contains(at("2024-05-11T12:00:00Z")));
+    }
+
+    #[test]
+    fn contains_until_midnight() {
+        let range = "Sun 18:00-24:00".parse::<Range>().unwrap();
+        assert!(range.contains(at("2024-05-05T23:59:59Z")));
+        assert!(!range.contains(at("2024-05-06T00:00:00Z")));
+        assert!(!range.contains(at("2024-05-05T17:59:59Z")));
+    }
+
+    #[test]
+    fn contains_past_midnight() {
+        let range = "Fri 22:00-06:00".parse::<Range>().unwrap();
+        assert!(range.contains(at("2024-05-10T22:00:00Z")));
+        // the early hours of Saturday belong to Friday's range
+        assert!(range.contains(at("2024-05-11T05:59:59Z")));
+        assert!(!range.contains(at("2024-05-11T06:00:00Z")));
+        // and those of Friday to Thursday's
+        assert!(!range.contains(at("2024-05-10T05:00:00Z")));
+    }
+
+    #[test]
+    fn contains_in_timezone() {
+        let range = "Mon 09:00-10:00 Europe/Berlin".parse::<Range>().unwrap();
+        // summer time in Berlin is UTC+2
+        assert!(range.contains(at("2024-05-06T07:30:00Z")));
+        assert!(!range.contains(at("2024-05-06T09:30:00Z")));
+        // winter time is UTC+1
+        assert!(range.contains(at("2024-01-08T08:30:00Z")));
+        assert!(!range.contains(at("2024-01-08T07:30:00Z")));
+    }
+}
@@ -0,0 +1,240 @@
+//! Exponentially decayed provider scores.
+//!
+//! Rankings built from whole-run averages never forget: a single bad hour a week ago keeps
+//! depressing a provider's score forever. Scores instead decay old observations exponentially
+//! with a half-life of `SCORE_HALF_LIFE_SECS` (default one day), so an observation's weight
+//! halves every half-life.
+//!
+//! Every `SCORE_INTERVAL_SECS` (default 60) the provider's request success ratio, mean request
+//! latency and freshness score over the interval are folded into decayed means. The
+//! `provider_score` is 100 times the decayed availability, divided by the decayed freshness
+//! score once blocks arrive more than one block time late.
+
+use crate::block_metrics::BlockMetrics;
+
+use prometheus::{Gauge, Registry};
+use tokio::time;
+
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A mean in which every sample's weight halves every `half_life`.
+#[derive(Clone, Debug)]
+pub struct DecayedMean {
+    half_life: Duration,
+    mean: f64,
+    weight: f64,
+    updated: Option<Instant>,
+}
+
+impl DecayedMean {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            mean: 0.0,
+            weight: 0.0,
+            updated: None,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let now = Instant::now();
+        if let Some(updated) = self.updated {
+            let age = now.duration_since(updated).as_secs_f64();
+            self.weight *= 0.5f64.powf(age / self.half_life.as_secs_f64());
+        }
+        self.weight += 1.0;
+        self.mean += (value - self.mean) / self.weight;
+        self.updated = Some(now);
+    }
+
+    /// The decayed mean, `None` before the first sample.
+    pub fn get(&self) -> Option<f64> {
+        self.updated.map(|_| self.mean)
+    }
+}
+
+/// The latest `provider_score` of a provider, shared with what acts on it.
+#[derive(Clone, Debug, Default)]
+pub struct ProviderScore(Arc<Mutex<Option<f64>>>);
+
+impl ProviderScore {
+    /// The score, `None` until the provider has been scored.
+    pub fn get(&self) -> Option<f64> {
+        *self.0.lock().unwrap()
+    }
+
+    pub(crate) fn set(&self, score: f64) {
+        *self.0.lock().unwrap() = Some(score);
+    }
+}
+
+/// Totals of a provider's request counters.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RequestTotals {
+    pub(crate) requests: f64,
+    pub(crate) errors: f64,
+    pub(crate) latency_sum: f64,
+}
+
+impl RequestTotals {
+    /// Read the request counters registered by the provider's transport.
+    pub(crate) fn gather(registry: &Registry) -> Self {
+        let mut totals = Self::default();
+        for family in registry.gather() {
+            let metrics = family.get_metric();
+            match family.get_name() {
+                "request_total" => {
+                    totals.requests = metrics.iter().map(|m| m.get_counter().get_value()).sum()
+                }
+                "request_errors" => {
+                    totals.errors = metrics.iter().map(|m| m.get_counter().get_value()).sum()
+                }
+                "request_latency" => {
+                    totals.latency_sum = metrics
+                        .iter()
+                        .map(|m| m.get_histogram().get_sample_sum())
+                        .sum()
+                }
+                _ => {}
+            }
+        }
+        totals
+    }
+}
+
+struct ScoredProvider {
+    registry: Registry,
+    block_metrics: BlockMetrics,
+    last: RequestTotals,
+    availability: DecayedMean,
+    latency: DecayedMean,
+    freshness: DecayedMean,
+    availability_gauge: Gauge,
+    latency_gauge: Gauge,
+    freshness_gauge: Gauge,
+    score_gauge: Gauge,
+    score: ProviderScore,
+}
+
+impl ScoredProvider {
+    fn update(&mut self) {
+        let totals = RequestTotals::gather(&self.registry);
+        let requests = totals.requests - self.last.requests;
+        let errors = totals.errors - self.last.errors;
+        let latency_sum = totals.latency_sum - self.last.latency_sum;
+        self.last = totals;
+
+        if requests > 0.0 {
+            // retried requests can count several errors for a single request
+            self.availability
+                .observe((1.0 - errors / requests).clamp(0.0, 1.0));
+            self.latency.observe(latency_sum / requests);
+        } else if errors > 0.0 {
+            self.availability.observe(0.0);
+        }
+        self.freshness.observe(self.block_metrics.freshness_score());
+
+        if let Some(latency) = self.latency.get() {
+            self.latency_gauge.set(latency);
+        }
+        let freshness = self.freshness.get().unwrap_or_default();
+        self.freshness_gauge.set(freshness);
+        if let Some(availability) = self.availability.get() {
+            self.availability_gauge.set(availability);
+            let score = 100.0 * availability / freshness.max(1.0);
+            self.score_gauge.set(score);
+            self.score.set(score);
+        }
+    }
+}
+
+/// Keeps the decayed scores of every provider up to date.
+pub struct Scores {
+    half_life: Duration,
+    interval: Duration,
+    providers: Vec<ScoredProvider>,
+}
+
+impl Scores {
+    pub fn from_env() -> Self {
+        let half_life = env::var("SCORE_HALF_LIFE_SECS")
+            .ok()
+            .map(|secs| secs.parse::<u64>().expect("Invalid SCORE_HALF_LIFE_SECS"))
+            .unwrap_or(24 * 60 * 60);
+        if half_life == 0 {
+            panic!("Invalid SCORE_HALF_LIFE_SECS: must be greater than zero");
+        }
+        let interval = env::var("SCORE_INTERVAL_SECS")
+            .ok()
+            .map(|secs| secs.parse::<u64>().expect("Invalid SCORE_INTERVAL_SECS"))
+            .unwrap_or(60);
+
+        Self {
+            half_life: Duration::from_secs(half_life),
+            interval: Duration::from_secs(interval),
+            providers: Vec::new(),
+        }
+    }
+
+    /// Score the provider whose metrics are registered with `registry`.
+    pub fn watch(&mut self, registry: &Registry, block_metrics: BlockMetrics) -> ProviderScore {
+        let availability_gauge = Gauge::new(
+            "availability_decayed",
+            "Exponentially decayed ratio of successful requests",
+        )
+        .unwrap();
+        let latency_gauge = Gauge::new(
+            "request_latency_decayed_seconds",
+            "Exponentially decayed mean request latency",
+        )
+        .unwrap();
+        let freshness_gauge = Gauge::new(
+            "freshness_score_decayed",
+            "Exponentially decayed freshness score",
+        )
+        .unwrap();
+        let score_gauge = Gauge::new(
+            "provider_score",
+            "Provider score from 0 to 100 based on decayed availability and freshness",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(availability_gauge.clone()))
+            .unwrap();
+        registry.register(Box::new(latency_gauge.clone())).unwrap();
+        registry
+            .register(Box::new(freshness_gauge.clone()))
+            .unwrap();
+        registry.register(Box::new(score_gauge.clone())).unwrap();
+
+        let score = ProviderScore::default();
+        self.providers.push(ScoredProvider {
+            registry: registry.clone(),
+            block_metrics,
+            last: RequestTotals::default(),
+            availability: DecayedMean::new(self.half_life),
+            latency: DecayedMean::new(self.half_life),
+            freshness: DecayedMean::new(self.half_life),
+            availability_gauge,
+            latency_gauge,
+            freshness_gauge,
+            score_gauge,
+            score: score.clone(),
+        });
+        score
+    }
+
+    pub async fn run(mut self) {
+        let mut interval = time::interval(self.interval);
+        // the first tick completes immediately, before there is anything to score
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            for provider in &mut self.providers {
+                provider.update();
+            }
+        }
+    }
+}
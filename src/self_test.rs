@@ -0,0 +1,203 @@
+//! Startup self-test.
+//!
+//! A misconfigured endpoint, e.g. a wrong URL or key, a provider serving another chain or a host
+//! with a skewed clock, otherwise shows up as warnings repeated forever and metrics that quietly
+//! make no sense. With `SELF_TEST=true`, every RPC URL of the EVM chains is checked before
+//! monitoring starts:
+//!
+//! - `chain_id`: `eth_chainId` answers, with `<CHAIN>_CHAIN_ID` when set.
+//! - `recent_block`: the latest block can be fetched.
+//! - `get_logs`: `eth_getLogs` answers for the latest block.
+//! - `clock`: the latest block's timestamp is neither ahead of the local clock, which would make
+//!   block delays read too low, nor older than [`STALE_BLOCK_TIMES`] expected block times.
+//!
+//! Every check is logged with its outcome and, when it failed, what to look at. A single failure
+//! fails the run before anything is measured. Checks are sent through the measured transport, so
+//! they wait for the [request limits](crate::limits) like every other request.
+
+use crate::chain::{self, ChainConfig, ChainKind};
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::rpc_method::RpcMethod;
+
+use chrono::Utc;
+use ethers::providers::{Authorization, JsonRpcClient};
+use ethers::types::{Block, H256, U64};
+use prometheus::Registry;
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::time;
+
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+
+/// How long every check may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How far the latest block may be ahead of the local clock, for chains rounding timestamps.
+const CLOCK_TOLERANCE_SECS: i64 = 5;
+
+/// Expected block times after which the latest block is considered stale.
+const STALE_BLOCK_TIMES: u32 = 10;
+
+/// Lower bound of the staleness threshold, for chains with very short block times.
+const MIN_STALE_SECS: u64 = 60;
+
+/// Whether `SELF_TEST=true`.
+pub fn enabled() -> bool {
+    env::var("SELF_TEST").map(|v| v == "true").unwrap_or(false)
+}
+
+struct Check {
+    name: &'static str,
+    /// What was found, or what went wrong and what to look at.
+    outcome: Result<String, String>,
+}
+
+async fn request<R: serde::de::DeserializeOwned + Send>(
+    client: &MeasuredJsonRpc,
+    method: RpcMethod,
+    params: Value,
+) -> Result<R, String> {
+    match time::timeout(TIMEOUT, client.request(method.as_str(), params)).await {
+        Ok(Ok(res)) => Ok(res),
+        Ok(Err(e)) => Err(format!("{} failed: {}", method, e)),
+        Err(_) => Err(format!("{} timed out after {:?}", method, TIMEOUT)),
+    }
+}
+
+async fn check_chain_id(chain: &ChainConfig, client: &MeasuredJsonRpc) -> Result<String, String> {
+    let chain_id = request::<U64>(client, RpcMethod::EthChainId, json!([]))
+        .await
+        .map_err(|e| format!("{}, check the RPC URL and its credentials", e))?
+        .as_u64();
+    match chain.chain_id {
+        Some(expected) if expected != chain_id => Err(format!(
+            "chain id {} instead of {}, check that the RPC URL serves {} or fix its CHAIN_ID",
+            chain_id, expected, chain.name
+        )),
+        _ => Ok(format!("chain id {}", chain_id)),
+    }
+}
+
+async fn check_recent_block(client: &MeasuredJsonRpc) -> Result<Block<H256>, String> {
+    request::<Option<Block<H256>>>(
+        client,
+        RpcMethod::EthGetBlockByNumber,
+        json!(["latest", false]),
+    )
+    .await?
+    .filter(|block| block.number.is_some())
+    .ok_or_else(|| "no latest block, the node may still be syncing".to_string())
+}
+
+async fn check_get_logs(client: &MeasuredJsonRpc, number: U64) -> Result<String, String> {
+    let logs = request::<Vec<Value>>(
+        client,
+        RpcMethod::EthGetLogs,
+        json!([{ "fromBlock": number, "toBlock": number }]),
+    )
+    .await
+    .map_err(|e| format!("{}, the provider may not serve logs on this plan", e))?;
+    Ok(format!("{} logs in block {}", logs.len(), number))
+}
+
+fn check_clock(chain: &ChainConfig, block: &Block<H256>) -> Result<String, String> {
+    let age = Utc::now().timestamp() - block.timestamp.as_u64() as i64;
+    let stale = (chain.expected_block_time * STALE_BLOCK_TIMES)
+        .as_secs()
+        .max(MIN_STALE_SECS) as i64;
+    if age < -CLOCK_TOLERANCE_SECS {
+        Err(format!(
+            "the latest block is {}s ahead of the local clock, which is behind: sync it, e.g. with NTP",
+            -age
+        ))
+    } else if age > stale {
+        Err(format!(
+            "the latest block is {}s old: the node is out of sync, or the local clock is ahead",
+            age
+        ))
+    } else {
+        Ok(format!("latest block {}s old", age.max(0)))
+    }
+}
+
+async fn test(chain: &ChainConfig, url: &Url) -> Vec<Check> {
+    let registry = Registry::new();
+    let client = match &chain.basic_auth {
+        _ if url.scheme() == "file" => {
+            MeasuredJsonRpc::new_ipc(url.to_file_path().expect("Invalid RPC_URL"), &registry)
+        }
+        Some((username, password)) => MeasuredJsonRpc::new_with_auth(
+            url.as_str(),
+            Authorization::basic(username, password),
+            &registry,
+        ),
+        None => MeasuredJsonRpc::new(url.as_str(), &registry),
+    };
+
+    let mut checks = vec![Check {
+        name: "chain_id",
+        outcome: check_chain_id(chain, &client).await,
+    }];
+    match check_recent_block(&client).await {
+        Ok(block) => {
+            let number = block.number.unwrap_or_default();
+            checks.push(Check {
+                name: "recent_block",
+                outcome: Ok(format!("block {}", number)),
+            });
+            checks.push(Check {
+                name: "get_logs",
+                outcome: check_get_logs(&client, number).await,
+            });
+            checks.push(Check {
+                name: "clock",
+                outcome: check_clock(chain, &block),
+            });
+        }
+        Err(e) => checks.push(Check {
+            name: "recent_block",
+            outcome: Err(e),
+        }),
+    }
+    checks
+}
+
+/// Test every EVM RPC URL, failing if any check failed.
+pub async fn run(chains: &[ChainConfig]) -> Result<(), Box<dyn Error>> {
+    let mut failed = 0;
+    let mut total = 0;
+
+    for chain in chains {
+        if chain.kind != ChainKind::Evm {
+            log::warn!(
+                "[{}] The self-test only supports EVM chains, skipping",
+                chain.name
+            );
+            continue;
+        }
+
+        for url in &chain.rpc_urls {
+            let rpc = chain::rpc_label(url);
+            for check in test(chain, url).await {
+                total += 1;
+                match check.outcome {
+                    Ok(found) => {
+                        log::info!("[🩺][{}] {} {}: ok, {}", chain.name, rpc, check.name, found)
+                    }
+                    Err(e) => {
+                        failed += 1;
+                        log::error!("[🩺][{}] {} {}: {}", chain.name, rpc, check.name, e);
+                    }
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("Self-test failed {} of {} checks", failed, total).into());
+    }
+    log::info!("[🩺] Self-test passed {} checks, ready", total);
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! Rollup sequencer health probes.
+//!
+//! An L2's RPC can look perfectly healthy while its sequencer is stalled: the node keeps
+//! answering with the same head. These probes watch the sequencer directly.
+//!
+//! - `<CHAIN>_OP_NODE_URL`: rollup node (op-node) RPC of an OP Stack chain (Optimism, Base, ...),
+//!   polled with `optimism_syncStatus`.
+//! - `<CHAIN>_ARBITRUM_FEED_URL`: Arbitrum sequencer feed (e.g. `wss://arb1.arbitrum.io/feed`),
+//!   checked for reachability with a WebSocket handshake.
+
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+
+use chrono::Utc;
+use ethers::providers::{JsonRpcClient, Ws};
+use prometheus::{Gauge, Registry};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::time;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Debug, Deserialize)]
+struct L1BlockRef {
+    number: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct L2BlockRef {
+    number: u64,
+    timestamp: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SyncStatus {
+    current_l1: L1BlockRef,
+    head_l1: L1BlockRef,
+    unsafe_l2: L2BlockRef,
+    safe_l2: L2BlockRef,
+    finalized_l2: L2BlockRef,
+}
+
+/// Polls `optimism_syncStatus` on an OP Stack rollup node.
+pub struct OpSyncStatusProbe {
+    chain: Arc<ChainConfig>,
+    client: MeasuredJsonRpc,
+    sequencer_up: Gauge,
+    unsafe_lag_seconds: Gauge,
+    safe_lag_blocks: Gauge,
+    finalized_lag_blocks: Gauge,
+    l1_lag_blocks: Gauge,
+}
+
+impl OpSyncStatusProbe {
+    pub fn new(chain: Arc<ChainConfig>, client: MeasuredJsonRpc, registry: &Registry) -> Self {
+        let sequencer_up = Gauge::new(
+            "sequencer_up",
+            "Whether the sequencer is reachable and producing blocks",
+        )
+        .unwrap();
+        let unsafe_lag_seconds = Gauge::new(
+            "sequencer_unsafe_lag_seconds",
+            "Time since the timestamp of the latest unsafe L2 block",
+        )
+        .unwrap();
+        let safe_lag_blocks = Gauge::new(
+            "sequencer_safe_lag_blocks",
+            "Unsafe L2 blocks not yet derived from L1 (unsafe - safe)",
+        )
+        .unwrap();
+        let finalized_lag_blocks = Gauge::new(
+            "sequencer_finalized_lag_blocks",
+            "Unsafe L2 blocks not yet finalized (unsafe - finalized)",
+        )
+        .unwrap();
+        let l1_lag_blocks = Gauge::new(
+            "sequencer_l1_lag_blocks",
+            "L1 blocks the rollup node has yet to process (head_l1 - current_l1)",
+        )
+        .unwrap();
+        registry.register(Box::new(sequencer_up.clone())).unwrap();
+        registry
+            .register(Box::new(unsafe_lag_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(safe_lag_blocks.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(finalized_lag_blocks.clone()))
+            .unwrap();
+        registry.register(Box::new(l1_lag_blocks.clone())).unwrap();
+
+        Self {
+            chain,
+            client,
+            sequencer_up,
+            unsafe_lag_seconds,
+            safe_lag_blocks,
+            finalized_lag_blocks,
+            l1_lag_blocks,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let chain = &self.chain.name;
+        let mut interval = time::interval(self.chain.poll_interval);
+        // the sequencer is considered stalled after this many missed blocks
+        let stall_after = self.chain.expected_block_time.as_secs_f64() * 5.0;
+
+        loop {
+            interval.tick().await;
+
+            let status: SyncStatus =
+                match self.client.request("optimism_syncStatus", json!([])).await {
+                    Ok(status) => status,
+                    Err(e) => {
+                        log::warn!("[{}] Failed to get sequencer sync status: {:?}", chain, e);
+                        self.sequencer_up.set(0.0);
+                        continue;
+                    }
+                };
+
+            let unsafe_lag =
+                (Utc::now().timestamp_millis() as f64 / 1000.0) - status.unsafe_l2.timestamp as f64;
+            self.unsafe_lag_seconds.set(unsafe_lag);
+            self.safe_lag_blocks.set(
+                status
+                    .unsafe_l2
+                    .number
+                    .saturating_sub(status.safe_l2.number) as f64,
+            );
+            self.finalized_lag_blocks.set(
+                status
+                    .unsafe_l2
+                    .number
+                    .saturating_sub(status.finalized_l2.number) as f64,
+            );
+            self.l1_lag_blocks.set(
+                status
+                    .head_l1
+                    .number
+                    .saturating_sub(status.current_l1.number) as f64,
+            );
+
+            if unsafe_lag > stall_after {
+                log::warn!(
+                    "[{}] Sequencer looks stalled: unsafe L2 block {} is {:.1}s old",
+                    chain,
+                    status.unsafe_l2.number,
+                    unsafe_lag
+                );
+                self.sequencer_up.set(0.0);
+            } else {
+                self.sequencer_up.set(1.0);
+            }
+        }
+    }
+}
+
+/// Checks that an Arbitrum sequencer feed accepts WebSocket connections.
+pub struct ArbitrumFeedProbe {
+    chain: Arc<ChainConfig>,
+    url: Url,
+    feed_up: Gauge,
+    connect_seconds: Gauge,
+}
+
+impl ArbitrumFeedProbe {
+    pub fn new(chain: Arc<ChainConfig>, url: Url, registry: &Registry) -> Self {
+        let feed_up = Gauge::new(
+            "sequencer_feed_up",
+            "Whether the sequencer feed accepted a WebSocket handshake",
+        )
+        .unwrap();
+        let connect_seconds = Gauge::new(
+            "sequencer_feed_connect_seconds",
+            "Time taken to complete the sequencer feed WebSocket handshake",
+        )
+        .unwrap();
+        registry.register(Box::new(feed_up.clone())).unwrap();
+        registry
+            .register(Box::new(connect_seconds.clone()))
+            .unwrap();
+
+        Self {
+            chain,
+            url,
+            feed_up,
+            connect_seconds,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let chain = &self.chain.name;
+        // the feed is a long lived connection, checking it on every block would be excessive
+        let period = self.chain.poll_interval.max(self.chain.expected_block_time);
+        let mut interval = time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let start = Instant::now();
+            // the feed isn't JSON-RPC, connecting is all we need from the client
+            let res = time::timeout(period, Ws::connect(self.url.as_str())).await;
+            let elapsed = start.elapsed();
+
+            match res {
+                Ok(Ok(_)) => {
+                    self.feed_up.set(1.0);
+                    self.connect_seconds.set(elapsed.as_secs_f64());
+                }
+                Ok(Err(e)) => {
+                    log::warn!("[{}] Sequencer feed is unreachable: {:?}", chain, e);
+                    self.feed_up.set(0.0);
+                }
+                Err(_) => {
+                    log::warn!(
+                        "[{}] Sequencer feed WebSocket handshake timed out after {:?}",
+                        chain,
+                        period
+                    );
+                    self.feed_up.set(0.0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Provider reported processing times.
+//!
+//! Measured latency lumps the network and the provider's processing together. Some providers and
+//! CDNs report how long they spent on a request, which tells the two apart:
+//!
+//! - `Server-Timing` (e.g. `cache;dur=2.1, app;dur=47.2`): the `total` metric when present,
+//!   otherwise the sum of all durations, in milliseconds.
+//! - `X-Response-Time` (e.g. `12.3ms`, `0.012s`, a bare number being milliseconds).
+//!
+//! When a response carries either, its processing time is exported as
+//! `server_processing_seconds` and the rest of the measured latency as `network_seconds`.
+
+use reqwest::header::HeaderMap;
+
+use std::time::Duration;
+
+/// The processing time reported by the response headers.
+pub fn server_time(headers: &HeaderMap) -> Option<Duration> {
+    let millis = server_timing(headers).or_else(|| response_time(headers))?;
+    // negative, infinite or absurdly large values can't be a processing time
+    Duration::try_from_secs_f64(millis / 1000.0).ok()
+}
+
+fn server_timing(headers: &HeaderMap) -> Option<f64> {
+    let mut total = None;
+    let mut sum = None;
+    for value in headers.get_all("server-timing") {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for metric in value.split(',') {
+            let mut params = metric.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let duration = params
+                .filter_map(|param| param.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("dur"))
+                .and_then(|(_, dur)| dur.trim().trim_matches('"').parse::<f64>().ok());
+            let Some(duration) = duration else {
+                continue;
+            };
+            if name.eq_ignore_ascii_case("total") {
+                total = Some(duration);
+            }
+            *sum.get_or_insert(0.0) += duration;
+        }
+    }
+    total.or(sum)
+}
+
+fn response_time(headers: &HeaderMap) -> Option<f64> {
+    let value = headers.get("x-response-time")?.to_str().ok()?.trim();
+    if let Some(millis) = value.strip_suffix("ms") {
+        millis.trim().parse().ok()
+    } else if let Some(secs) = value.strip_suffix('s') {
+        secs.trim().parse::<f64>().ok().map(|secs| secs * 1000.0)
+    } else {
+        value.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::header::HeaderValue;
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn server_timing_total() {
+        let headers = headers("server-timing", "cache;dur=2.1, total;dur=47.5");
+        assert_eq!(server_time(&headers), Some(Duration::from_micros(47_500)));
+    }
+
+    #[test]
+    fn server_timing_sum() {
+        let headers = headers("server-timing", "cache;dur=2.5, app;desc=\"app\";dur=47.5");
+        assert_eq!(server_time(&headers), Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn x_response_time() {
+        assert_eq!(
+            server_time(&headers("x-response-time", "12ms")),
+            Some(Duration::from_millis(12))
+        );
+        assert_eq!(
+            server_time(&headers("x-response-time", "0.5s")),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert_eq!(server_time(&headers("server-timing", "app;dur=-1")), None);
+        assert_eq!(
+            server_time(&headers("server-timing", "app;dur=1e300")),
+            None
+        );
+        assert_eq!(server_time(&headers("x-response-time", "inf")), None);
+        assert_eq!(server_time(&HeaderMap::new()), None);
+    }
+}
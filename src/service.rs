@@ -0,0 +1,130 @@
+//! Windows service support.
+//!
+//! Some agents run on Windows VMs, where long running processes are services managed by the
+//! Service Control Manager rather than daemons:
+//!
+//! - `bencheth install-service` registers the current executable as the `bencheth` service,
+//!   started automatically on boot. Requires an elevated prompt.
+//! - `bencheth uninstall-service` removes it again.
+//! - `bencheth service` is what the Service Control Manager runs. The working directory is set
+//!   to the executable's directory, so the `.env` file next to it configures the service, and
+//!   a stop or system shutdown request shuts the agent down gracefully, writing the final
+//!   report.
+
+use tokio::sync::Notify;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const SERVICE_NAME: &str = "bencheth";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+/// Notified when the Service Control Manager asks the service to stop.
+static STOP: Notify = Notify::const_new();
+
+type Agent = fn() -> Result<(), Box<dyn Error>>;
+
+/// The agent run by the service.
+static AGENT: OnceLock<Agent> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Register the current executable as a service.
+pub fn install() -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("BenchETH"),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: env::current_exe()?,
+        launch_arguments: vec![OsString::from("service")],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Benchmarks blockchain RPC providers")?;
+    println!("Installed the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+/// Remove the service.
+pub fn uninstall() -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::DELETE)?;
+    service.delete()?;
+    println!("Uninstalled the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+/// Run `agent` as a service, returning once it stopped.
+pub fn run(agent: Agent) -> Result<(), Box<dyn Error>> {
+    // services start in the system directory, configure them from next to the executable
+    if let Some(dir) = env::current_exe()?.parent() {
+        env::set_current_dir(dir)?;
+    }
+    AGENT.set(agent).ok();
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+    Ok(())
+}
+
+/// Resolves when the Service Control Manager asks the service to stop.
+pub async fn stopped() {
+    STOP.notified().await;
+}
+
+fn status(current_state: ServiceState, exit_code: u32) -> ServiceStatus {
+    ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state,
+        controls_accepted: match current_state {
+            ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        },
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(30),
+        process_id: None,
+    }
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let handler = |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            STOP.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status_handle = match service_control_handler::register(SERVICE_NAME, handler) {
+        Ok(status_handle) => status_handle,
+        Err(e) => {
+            eprintln!("Failed to register the service control handler: {}", e);
+            return;
+        }
+    };
+
+    let _ = status_handle.set_service_status(status(ServiceState::Running, 0));
+    let res = AGENT.get().map(|agent| agent()).unwrap_or(Ok(()));
+    if let Err(e) = &res {
+        log::error!("The agent failed: {}", e);
+    }
+    let exit_code = if res.is_ok() { 0 } else { 1 };
+    let _ = status_handle.set_service_status(status(ServiceState::Stopped, exit_code));
+}
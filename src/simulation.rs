@@ -0,0 +1,357 @@
+//! Simulation scenarios.
+//!
+//! `eth_estimateGas`, `eth_createAccessList` and the simulation APIs execute a transaction against
+//! the state, which makes them the slowest and most variable methods across providers, and
+//! wallets call them before every transaction. For EVM chains, configured call templates are
+//! simulated at every new block:
+//!
+//! - `<CHAIN>_CALL_TEMPLATES`: comma separated template names.
+//! - `<CHAIN>_CALL_<NAME>_TX`: the transaction as a JSON call object, e.g.
+//!   `{"from": "0x...", "to": "0x...", "data": "0xa9059cbb..."}`.
+//! - `<CHAIN>_CALL_<NAME>_METHODS`: comma separated methods to simulate it with, by default
+//!   `eth_estimateGas,eth_createAccessList`. `eth_call` and `eth_simulateV1` are supported too.
+//! - `<CHAIN>_CALL_<NAME>_STATE_OVERRIDE`: state overrides as a JSON object of addresses to
+//!   `balance`, `nonce`, `code`, `state` or `stateDiff`, applied by `eth_call`, `eth_estimateGas`
+//!   and `eth_simulateV1`.
+//! - `<CHAIN>_CALL_<NAME>_BLOCK_OVERRIDE`: block overrides as a JSON object, e.g.
+//!   `{"time": "0x..."}`, applied by `eth_call` and `eth_simulateV1`.
+//!
+//! Each template is simulated at the block just observed, so providers are compared on the same
+//! state. Latencies are exported as `simulation_latency_seconds`, failures as
+//! `simulation_errors_total` and estimates as `simulation_gas`, all labelled with the `method`
+//! and `template`. `simulation_supported` is 0 once a provider refused a method, which isn't
+//! called again.
+//!
+//! With `<CHAIN>_SIMULATION_REFERENCE_URL`, the first of `<CHAIN>_REFERENCE_URLS` by default,
+//! every successful simulation is repeated against the reference and the results compared:
+//! return data for `eth_call`, the calls' return data, status and logs for `eth_simulateV1`, and
+//! the access list for `eth_createAccessList`. Gas estimates legitimately differ between clients
+//! and aren't compared. Differences are counted in `simulation_mismatches_total`.
+//!
+//! Simulations follow `<CHAIN>_CALLS_SCHEDULE`.
+
+use crate::capabilities::{self, Support};
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::rpc_method::RpcMethod;
+use crate::schedule::Schedule;
+
+use ethers::prelude::*;
+use prometheus::{GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+use serde_json::{json, Value};
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+const DEFAULT_METHODS: [RpcMethod; 2] = [RpcMethod::EthEstimateGas, RpcMethod::EthCreateAccessList];
+
+#[derive(Clone, Debug)]
+struct CallTemplate {
+    name: String,
+    tx: Value,
+    methods: Vec<RpcMethod>,
+    state_override: Option<Value>,
+    block_override: Option<Value>,
+}
+
+impl CallTemplate {
+    /// Read a template from `<CHAIN>_CALL_<NAME>_TX`, `_METHODS`, `_STATE_OVERRIDE` and
+    /// `_BLOCK_OVERRIDE`.
+    fn from_chain(chain: &ChainConfig, name: &str) -> Self {
+        let prefix = format!("CALL_{}_", name.to_uppercase().replace('-', "_"));
+        let json = |name: &str| {
+            chain.var(&format!("{}{}", prefix, name)).map(|value| {
+                serde_json::from_str::<Value>(&value)
+                    .unwrap_or_else(|_| panic!("Invalid {}{}", prefix, name))
+            })
+        };
+        let tx = json("TX").unwrap_or_else(|| panic!("{}TX is required", prefix));
+        let methods = chain
+            .var(&format!("{}METHODS", prefix))
+            .map(|methods| {
+                methods
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|method| !method.is_empty())
+                    .map(|method| {
+                        method
+                            .parse()
+                            .unwrap_or_else(|e| panic!("Invalid {}METHODS: {}", prefix, e))
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(|| DEFAULT_METHODS.to_vec());
+
+        Self {
+            name: name.to_string(),
+            tx,
+            methods,
+            state_override: json("STATE_OVERRIDE"),
+            block_override: json("BLOCK_OVERRIDE"),
+        }
+    }
+
+    /// The parameters of simulating the template with `method` at `block`.
+    fn params(&self, method: &RpcMethod, block: U64) -> Value {
+        let state_override = self.state_override.clone().unwrap_or_else(|| json!({}));
+        match method {
+            RpcMethod::EthCall => match &self.block_override {
+                Some(block_override) => json!([self.tx, block, state_override, block_override]),
+                None if self.state_override.is_some() => json!([self.tx, block, state_override]),
+                None => json!([self.tx, block]),
+            },
+            RpcMethod::EthEstimateGas if self.state_override.is_some() => {
+                json!([self.tx, block, state_override])
+            }
+            RpcMethod::EthSimulateV1 => {
+                let mut calls = json!({ "calls": [self.tx] });
+                if let Some(state_override) = &self.state_override {
+                    calls["stateOverrides"] = state_override.clone();
+                }
+                if let Some(block_override) = &self.block_override {
+                    calls["blockOverrides"] = block_override.clone();
+                }
+                json!([{ "blockStateCalls": [calls] }, block])
+            }
+            _ => json!([self.tx, block]),
+        }
+    }
+}
+
+/// The part of a simulation's result that must agree across providers, `None` when it may
+/// legitimately differ.
+fn comparable(method: &RpcMethod, result: &Value) -> Option<Value> {
+    match method {
+        RpcMethod::EthCall => Some(result.clone()),
+        RpcMethod::EthCreateAccessList => Some(result["accessList"].clone()),
+        RpcMethod::EthSimulateV1 => Some(
+            result
+                .as_array()?
+                .iter()
+                .flat_map(|block| block["calls"].as_array().cloned().unwrap_or_default())
+                .map(|call| json!([call["returnData"], call["status"], call["logs"]]))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+pub struct Simulations {
+    chain: String,
+    templates: Vec<CallTemplate>,
+    schedule: Schedule,
+    reference: Option<Http>,
+    /// Methods the provider refused.
+    unsupported: Mutex<HashSet<RpcMethod>>,
+    latency: HistogramVec,
+    errors: IntCounterVec,
+    gas: GaugeVec,
+    supported: GaugeVec,
+    mismatches: IntCounterVec,
+}
+
+impl Simulations {
+    /// The chain's call templates, reporting to the provider's `registry`. `None` unless
+    /// `<CHAIN>_CALL_TEMPLATES` is set.
+    pub fn from_chain(chain: &ChainConfig, registry: &Registry) -> Option<Self> {
+        let templates = chain
+            .var("CALL_TEMPLATES")?
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| CallTemplate::from_chain(chain, name))
+            .collect::<Vec<_>>();
+
+        let labels = &["method", "template"];
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "simulation_latency_seconds",
+                "Time taken for RPC URL to simulate a call template",
+            ),
+            labels,
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "simulation_errors_total",
+                "Number of failed simulations of a call template",
+            ),
+            labels,
+        )
+        .unwrap();
+        let gas = GaugeVec::new(
+            Opts::new(
+                "simulation_gas",
+                "Gas used by a call template as estimated by RPC URL",
+            ),
+            labels,
+        )
+        .unwrap();
+        let supported = GaugeVec::new(
+            Opts::new(
+                "simulation_supported",
+                "Whether RPC URL supports simulating a call template with the method",
+            ),
+            labels,
+        )
+        .unwrap();
+        let mismatches = IntCounterVec::new(
+            Opts::new(
+                "simulation_mismatches_total",
+                "Number of simulations of a call template differing from the reference",
+            ),
+            labels,
+        )
+        .unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(gas.clone())).unwrap();
+        registry.register(Box::new(supported.clone())).unwrap();
+        registry.register(Box::new(mismatches.clone())).unwrap();
+
+        let reference = chain
+            .var("SIMULATION_REFERENCE_URL")
+            .or_else(|| chain.reference_urls.first().map(|url| url.to_string()))
+            .map(|url| Http::from_str(&url).expect("Invalid SIMULATION_REFERENCE_URL"));
+
+        Some(Self {
+            chain: chain.name.clone(),
+            templates,
+            schedule: Schedule::from_chain(chain, "CALLS"),
+            reference,
+            unsupported: Mutex::new(HashSet::new()),
+            latency,
+            errors,
+            gas,
+            supported,
+            mismatches,
+        })
+    }
+
+    /// Simulate every template at `block`.
+    pub async fn run(&self, provider: &Provider<MeasuredJsonRpc>, block: U64) {
+        if !self.schedule.is_active() {
+            return;
+        }
+
+        let simulations = self.templates.iter().flat_map(|template| {
+            template
+                .methods
+                .iter()
+                .map(move |method| self.simulate(provider, template, method, block))
+        });
+        futures::future::join_all(simulations).await;
+    }
+
+    async fn simulate(
+        &self,
+        provider: &Provider<MeasuredJsonRpc>,
+        template: &CallTemplate,
+        method: &RpcMethod,
+        block: U64,
+    ) {
+        if self.unsupported.lock().unwrap().contains(method) {
+            return;
+        }
+        let labels = &[method.as_str(), &template.name];
+        let params = template.params(method, block);
+
+        let start = Instant::now();
+        let res = provider
+            .request::<_, Value>(method.as_str(), params.clone())
+            .await;
+        let elapsed = start.elapsed();
+
+        let result = match res {
+            Ok(result) => result,
+            Err(e) if capabilities::classify(&e) == Support::Unsupported => {
+                log::warn!(
+                    "[{}] {} is not supported, not simulating with it: {:?}",
+                    self.chain,
+                    method,
+                    e
+                );
+                self.supported.with_label_values(labels).set(0.0);
+                self.unsupported.lock().unwrap().insert(method.clone());
+                return;
+            }
+            Err(e) => {
+                log::warn!(
+                    "[{}] Failed to simulate {} with {} at block {}: {:?}",
+                    self.chain,
+                    template.name,
+                    method,
+                    block,
+                    e
+                );
+                self.errors.with_label_values(labels).inc();
+                return;
+            }
+        };
+        self.supported.with_label_values(labels).set(1.0);
+        self.latency
+            .with_label_values(labels)
+            .observe(elapsed.as_secs_f64());
+
+        // eth_estimateGas returns the quantity, eth_createAccessList an object with `gasUsed`
+        let gas = match &result {
+            Value::String(gas) => Some(gas.as_str()),
+            result => result["gasUsed"].as_str(),
+        };
+        if let Some(gas) =
+            gas.and_then(|gas| u64::from_str_radix(gas.trim_start_matches("0x"), 16).ok())
+        {
+            self.gas.with_label_values(labels).set(gas as f64);
+        }
+
+        self.compare(template, method, block, params, &result).await;
+    }
+
+    /// Repeat a simulation against the reference and count a mismatch when the results differ.
+    async fn compare(
+        &self,
+        template: &CallTemplate,
+        method: &RpcMethod,
+        block: U64,
+        params: Value,
+        result: &Value,
+    ) {
+        let Some(reference) = &self.reference else {
+            return;
+        };
+        let Some(actual) = comparable(method, result) else {
+            return;
+        };
+        let expected =
+            match JsonRpcClient::request::<_, Value>(reference, method.as_str(), params).await {
+                Ok(expected) => comparable(method, &expected),
+                Err(e) => {
+                    log::debug!(
+                        "[{}] Reference failed to simulate {} with {}: {:?}",
+                        self.chain,
+                        template.name,
+                        method,
+                        e
+                    );
+                    return;
+                }
+            };
+
+        if expected.as_ref() != Some(&actual) {
+            log::warn!(
+                "[{}] Simulating {} with {} at block {} differs from the reference: {} != {}",
+                self.chain,
+                template.name,
+                method,
+                block,
+                actual,
+                expected.unwrap_or_default()
+            );
+            self.mismatches
+                .with_label_values(&[method.as_str(), &template.name])
+                .inc();
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Mergeable latency sketches.
+//!
+//! Percentiles can't be averaged across agents, and estimating them from the `request_latency`
+//! histogram buckets is only as accurate as the buckets are narrow. A DDSketch keeps counts in
+//! logarithmically sized buckets, so every quantile it returns is within a fixed relative error
+//! (1%) of the true one, and two sketches merge by adding their counts. The run report includes
+//! the sketch of every method and provider, from which percentiles across agents, regions and
+//! runs can be computed exactly as accurately as from a single one.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Relative accuracy of the quantiles estimated by the sketches.
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Values below this are counted as zero.
+const MIN_INDEXABLE_VALUE: f64 = 1e-9;
+
+/// A DDSketch with unbounded buckets. Latencies span a few orders of magnitude at most, so the
+/// number of buckets stays in the hundreds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DDSketch {
+    pub relative_accuracy: f64,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub zero_count: u64,
+    /// Counts by bucket index, the bucket `i` covers `(gamma^(i-1), gamma^i]`.
+    pub bins: BTreeMap<i32, u64>,
+}
+
+impl Default for DDSketch {
+    fn default() -> Self {
+        Self {
+            relative_accuracy: RELATIVE_ACCURACY,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            zero_count: 0,
+            bins: BTreeMap::new(),
+        }
+    }
+}
+
+impl DDSketch {
+    fn gamma(&self) -> f64 {
+        (1.0 + self.relative_accuracy) / (1.0 - self.relative_accuracy)
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() || value < 0.0 {
+            return;
+        }
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if value < MIN_INDEXABLE_VALUE {
+            self.zero_count += 1;
+        } else {
+            let index = (value.ln() / self.gamma().ln()).ceil() as i32;
+            *self.bins.entry(index).or_default() += 1;
+        }
+    }
+
+    /// Add the counts of `other`, which must have the same relative accuracy.
+    pub fn merge(&mut self, other: &DDSketch) {
+        debug_assert_eq!(self.relative_accuracy, other.relative_accuracy);
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.zero_count += other.zero_count;
+        for (index, count) in &other.bins {
+            *self.bins.entry(*index).or_default() += count;
+        }
+    }
+
+    /// The `q` quantile, within the relative accuracy of the sketch.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * (self.count - 1) as f64).round() as u64;
+        if rank < self.zero_count {
+            return Some(0.0);
+        }
+
+        let gamma = self.gamma();
+        let mut seen = self.zero_count;
+        for (index, count) in &self.bins {
+            seen += count;
+            if seen > rank {
+                let value = 2.0 * gamma.powi(*index) / (gamma + 1.0);
+                return Some(value.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+}
+
+/// The latency sketches of a provider by method, shared by its transport and the run report.
+#[derive(Clone, Debug, Default)]
+pub struct LatencySketches(Arc<Mutex<BTreeMap<String, DDSketch>>>);
+
+impl LatencySketches {
+    /// Record a request to `method` that took `latency_secs`.
+    pub fn record(&self, method: &str, latency_secs: f64) {
+        let mut sketches = self.0.lock().unwrap();
+        match sketches.get_mut(method) {
+            Some(sketch) => sketch.add(latency_secs),
+            None => {
+                let mut sketch = DDSketch::default();
+                sketch.add(latency_secs);
+                sketches.insert(method.to_string(), sketch);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, DDSketch> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(values: impl IntoIterator<Item = f64>) -> DDSketch {
+        let mut sketch = DDSketch::default();
+        values.into_iter().for_each(|value| sketch.add(value));
+        sketch
+    }
+
+    fn assert_accurate(estimate: f64, exact: f64) {
+        assert!(
+            (estimate - exact).abs() <= exact * RELATIVE_ACCURACY,
+            "{} isn't within 1% of {}",
+            estimate,
+            exact
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(DDSketch::default().quantile(0.5), None);
+    }
+
+    #[test]
+    fn quantiles_within_relative_accuracy() {
+        // latencies from 1ms to 10s
+        let values = (1..=10_000)
+            .map(|ms| ms as f64 / 1000.0)
+            .collect::<Vec<_>>();
+        let sketch = sketch(values.iter().copied());
+        assert_eq!(sketch.count, 10_000);
+        for q in [0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            let exact = values[(q * (values.len() - 1) as f64).round() as usize];
+            assert_accurate(sketch.quantile(q).unwrap(), exact);
+        }
+        assert_eq!(sketch.quantile(0.0), Some(0.001));
+        assert_eq!(sketch.quantile(1.0), Some(10.0));
+    }
+
+    #[test]
+    fn zeros_and_invalid_values() {
+        let sketch = sketch([0.0, 0.0, 0.0, 1.0, -1.0, f64::NAN, f64::INFINITY]);
+        assert_eq!(sketch.count, 4);
+        assert_eq!(sketch.zero_count, 3);
+        assert_eq!(sketch.quantile(0.5), Some(0.0));
+        assert_accurate(sketch.quantile(1.0).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn merge_equals_single_sketch() {
+        let values = (1..=1000).map(|i| i as f64 * 0.003).collect::<Vec<_>>();
+        let (a, b) = values.split_at(300);
+        let mut merged = sketch(a.iter().copied());
+        merged.merge(&sketch(b.iter().copied()));
+        let single = sketch(values.iter().copied());
+        assert_eq!(merged.count, single.count);
+        assert_eq!(merged.bins, single.bins);
+        for q in [0.5, 0.9, 0.99] {
+            assert_eq!(merged.quantile(q), single.quantile(q));
+        }
+    }
+}
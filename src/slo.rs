@@ -0,0 +1,208 @@
+//! Service level objectives for load balancer health checks.
+//!
+//! Simple health checkers (HAProxy, Kubernetes probes) can't query Prometheus, but they can poll
+//! an HTTP endpoint. With any objective set, the metrics server answers `/slo` with `200 OK` when
+//! the providers asked about meet them, and `503 Service Unavailable` otherwise, so a provider can
+//! be pulled out of rotation and back in with bencheth as the judge:
+//!
+//! - `SLO_LATENCY_MS`: the highest mean request latency.
+//! - `SLO_AVAILABILITY`: the lowest ratio of successful requests, e.g. `0.99`.
+//! - `SLO_MAX_LAG_BLOCKS`: the most blocks (or slots) a provider's head may be behind the highest
+//!   head seen across the chain's providers.
+//!
+//! Latency and availability are measured over the last `SLO_WINDOW_SECS` (default 60), a provider
+//! meets them until its first window is complete. `/slo?chain=<chain>&provider=<endpoint>` judges
+//! a single provider, without parameters every provider (of the chain on a chain's port) must meet
+//! the objectives. `slo_met` is 1 while a provider meets them.
+
+use crate::block_metrics::BlockMetrics;
+use crate::score::RequestTotals;
+
+use prometheus::{Gauge, Registry};
+use tokio::time;
+
+use std::env;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_WINDOW_SECS: u64 = 60;
+
+#[derive(Clone, Copy, Debug)]
+struct Objectives {
+    latency: Option<Duration>,
+    availability: Option<f64>,
+    max_lag: Option<f64>,
+}
+
+/// The request totals at the start of the current window, and what was measured over the last.
+#[derive(Debug, Default)]
+struct Window {
+    start: RequestTotals,
+    latency: Option<f64>,
+    availability: Option<f64>,
+}
+
+struct SloProvider {
+    chain: String,
+    endpoint: String,
+    registry: Registry,
+    block_metrics: BlockMetrics,
+    window: Mutex<Window>,
+    met: Gauge,
+}
+
+impl SloProvider {
+    /// Close the current window and start the next.
+    fn roll(&self) {
+        let totals = RequestTotals::gather(&self.registry);
+        let mut window = self.window.lock().unwrap();
+        let requests = totals.requests - window.start.requests;
+        let errors = totals.errors - window.start.errors;
+        if requests > 0.0 {
+            window.latency = Some((totals.latency_sum - window.start.latency_sum) / requests);
+            // retried requests can count several errors for a single request
+            window.availability = Some((1.0 - errors / requests).clamp(0.0, 1.0));
+        } else if errors > 0.0 {
+            window.availability = Some(0.0);
+        }
+        window.start = totals;
+    }
+
+    /// The objectives the provider misses, empty when it meets them all.
+    fn violations(&self, objectives: &Objectives) -> Vec<String> {
+        let window = self.window.lock().unwrap();
+        let mut violations = Vec::new();
+        if let (Some(objective), Some(latency)) = (objectives.latency, window.latency) {
+            if latency > objective.as_secs_f64() {
+                violations.push(format!(
+                    "latency {:.0}ms > {}ms",
+                    latency * 1000.0,
+                    objective.as_millis()
+                ));
+            }
+        }
+        if let (Some(objective), Some(availability)) =
+            (objectives.availability, window.availability)
+        {
+            if availability < objective {
+                violations.push(format!("availability {:.4} < {}", availability, objective));
+            }
+        }
+        if let Some(max_lag) = objectives.max_lag {
+            let lag = self.block_metrics.height_lag();
+            if lag > max_lag {
+                violations.push(format!("lag {} blocks > {}", lag, max_lag));
+            }
+        }
+        self.met.set(if violations.is_empty() { 1.0 } else { 0.0 });
+        violations
+    }
+}
+
+/// The objectives and the providers judged against them.
+#[derive(Clone)]
+pub struct Slos {
+    objectives: Objectives,
+    window: Duration,
+    providers: Vec<Arc<SloProvider>>,
+}
+
+impl Slos {
+    /// `None` unless an objective is set.
+    pub fn from_env() -> Option<Self> {
+        let latency = env::var("SLO_LATENCY_MS")
+            .ok()
+            .map(|ms| Duration::from_millis(ms.parse::<u64>().expect("Invalid SLO_LATENCY_MS")));
+        let availability = env::var("SLO_AVAILABILITY").ok().map(|ratio| {
+            let ratio = ratio.parse::<f64>().expect("Invalid SLO_AVAILABILITY");
+            if !(0.0..=1.0).contains(&ratio) {
+                panic!("Invalid SLO_AVAILABILITY: must be between 0 and 1");
+            }
+            ratio
+        });
+        let max_lag = env::var("SLO_MAX_LAG_BLOCKS")
+            .ok()
+            .map(|blocks| blocks.parse::<f64>().expect("Invalid SLO_MAX_LAG_BLOCKS"));
+        if latency.is_none() && availability.is_none() && max_lag.is_none() {
+            return None;
+        }
+        let window = env::var("SLO_WINDOW_SECS")
+            .ok()
+            .map(|secs| secs.parse::<u64>().expect("Invalid SLO_WINDOW_SECS"))
+            .unwrap_or(DEFAULT_WINDOW_SECS);
+        if window == 0 {
+            panic!("Invalid SLO_WINDOW_SECS: must be greater than zero");
+        }
+
+        Some(Self {
+            objectives: Objectives {
+                latency,
+                availability,
+                max_lag,
+            },
+            window: Duration::from_secs(window),
+            providers: Vec::new(),
+        })
+    }
+
+    /// Judge the provider `endpoint` of `chain`, whose metrics are registered with `registry`.
+    pub fn watch(
+        &mut self,
+        chain: &str,
+        endpoint: &str,
+        registry: &Registry,
+        block_metrics: BlockMetrics,
+    ) {
+        let met = Gauge::new("slo_met", "Whether the provider meets the configured SLOs").unwrap();
+        met.set(1.0);
+        registry.register(Box::new(met.clone())).unwrap();
+        self.providers.push(Arc::new(SloProvider {
+            chain: chain.to_string(),
+            endpoint: endpoint.to_string(),
+            registry: registry.clone(),
+            block_metrics,
+            window: Mutex::new(Window {
+                start: RequestTotals::gather(registry),
+                ..Default::default()
+            }),
+            met,
+        }));
+    }
+
+    /// Judge the providers matching `chain` and `provider`, all when `None`: whether they all
+    /// meet the objectives, with a line per provider. `None` when no provider matches.
+    pub fn status(&self, chain: Option<&str>, provider: Option<&str>) -> Option<(bool, String)> {
+        let mut met = true;
+        let mut status = String::new();
+        let providers = self.providers.iter().filter(|slo| {
+            chain.is_none_or(|chain| slo.chain == chain)
+                && provider.is_none_or(|provider| slo.endpoint == provider)
+        });
+        for slo in providers {
+            let violations = slo.violations(&self.objectives);
+            met &= violations.is_empty();
+            let verdict = if violations.is_empty() {
+                "ok".to_string()
+            } else {
+                violations.join(", ")
+            };
+            let _ = writeln!(status, "{}/{}: {}", slo.chain, slo.endpoint, verdict);
+        }
+        (!status.is_empty()).then_some((met, status))
+    }
+
+    /// Measure the providers over consecutive windows.
+    pub async fn run(self) {
+        let mut interval = time::interval(self.window);
+        // the first tick completes immediately, the window starts when watched
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            for provider in &self.providers {
+                provider.roll();
+                provider.violations(&self.objectives);
+            }
+        }
+    }
+}
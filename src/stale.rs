@@ -0,0 +1,93 @@
+//! Stale data detection.
+//!
+//! Behind a load balancer, consecutive requests can land on backends at different heights: the
+//! head reported by `eth_blockNumber` goes backwards, or a block the provider just announced can't
+//! be fetched from the backend answering next. Both are counted by `magnitude`, the number of
+//! blocks the answering backend is behind (`1`, `2-5`, `6-20` or `21+`):
+//!
+//! - `block_number_regression_total`: `eth_blockNumber` returned a lower height than the one
+//!   already processed.
+//! - `stale_response_total`: a block at or below the announced head was missing, at least as
+//!   many blocks behind as there are from it up to the head.
+
+use prometheus::{IntCounterVec, Opts, Registry};
+
+/// The `magnitude` label of a gap of `blocks`.
+fn magnitude(blocks: u64) -> &'static str {
+    match blocks {
+        0 | 1 => "1",
+        2..=5 => "2-5",
+        6..=20 => "6-20",
+        _ => "21+",
+    }
+}
+
+pub struct StaleData {
+    chain: String,
+    endpoint: String,
+    regressions: IntCounterVec,
+    stale_responses: IntCounterVec,
+}
+
+impl StaleData {
+    pub fn new(chain: &str, endpoint: &str, registry: &Registry) -> Self {
+        let regressions = IntCounterVec::new(
+            Opts::new(
+                "block_number_regression_total",
+                "Number of times the provider's head went backwards, by blocks",
+            ),
+            &["magnitude"],
+        )
+        .unwrap();
+        let stale_responses = IntCounterVec::new(
+            Opts::new(
+                "stale_response_total",
+                "Number of announced blocks the provider then didn't return, by blocks behind",
+            ),
+            &["magnitude"],
+        )
+        .unwrap();
+        registry.register(Box::new(regressions.clone())).unwrap();
+        registry
+            .register(Box::new(stale_responses.clone()))
+            .unwrap();
+
+        Self {
+            chain: chain.to_string(),
+            endpoint: endpoint.to_string(),
+            regressions,
+            stale_responses,
+        }
+    }
+
+    /// `eth_blockNumber` returned `latest` after block `current` was processed.
+    pub fn regression(&self, current: u64, latest: u64) {
+        let blocks = current - latest;
+        log::warn!(
+            "[{}] Latest block height {} of {} is {} blocks lower than current block height {}",
+            self.chain,
+            latest,
+            self.endpoint,
+            blocks,
+            current
+        );
+        self.regressions
+            .with_label_values(&[magnitude(blocks)])
+            .inc();
+    }
+
+    /// Block `number` wasn't returned although the head was announced at `head`.
+    pub fn unavailable(&self, number: u64, head: u64) {
+        let blocks = head - number + 1;
+        log::warn!(
+            "[{}] Block {} of {} is unavailable, although its head is {}",
+            self.chain,
+            number,
+            self.endpoint,
+            head
+        );
+        self.stale_responses
+            .with_label_values(&[magnitude(blocks)])
+            .inc();
+    }
+}
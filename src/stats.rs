@@ -0,0 +1,216 @@
+//! Statistical tests for comparing latency samples.
+//!
+//! Latency distributions are skewed and heavy tailed, so the tests make no assumption about
+//! their shape: the Mann-Whitney U test tells whether one set of samples tends to be larger than
+//! the other, and bootstrap resampling gives confidence intervals for differences of quantiles.
+
+use ethers::core::rand::{thread_rng, Rng};
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+
+/// Resamples drawn for bootstrap confidence intervals.
+const BOOTSTRAP_RESAMPLES: usize = 2000;
+
+/// Quantiles whose differences are tested.
+pub const QUANTILES: [(&str, f64); 3] = [("p50", 0.50), ("p90", 0.90), ("p99", 0.99)];
+
+/// Quantile `p` of sorted samples, with the same rank rounding as [`crate::report::Summary`].
+pub fn quantile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank]
+}
+
+/// Standard normal cumulative distribution function.
+fn normal_cdf(x: f64) -> f64 {
+    // Abramowitz and Stegun 7.1.26, accurate to 1.5e-7
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs() / std::f64::consts::SQRT_2);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-(x * x) / 2.0).exp();
+    if x >= 0.0 {
+        (1.0 + erf) / 2.0
+    } else {
+        (1.0 - erf) / 2.0
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MannWhitney {
+    /// U statistic of the first sample.
+    pub u: f64,
+    /// Two-sided p-value, from the normal approximation with tie and continuity correction.
+    pub p_value: f64,
+}
+
+/// Mann-Whitney U test of whether `a` and `b` come from the same distribution.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> Option<MannWhitney> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let (n1, n2) = (a.len() as f64, b.len() as f64);
+    let n = n1 + n2;
+
+    let mut samples = a
+        .iter()
+        .map(|value| (*value, true))
+        .chain(b.iter().map(|value| (*value, false)))
+        .collect::<Vec<_>>();
+    samples.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    // ties get the average of the ranks they span
+    let mut rank_sum = 0.0;
+    let mut tie_correction = 0.0;
+    let mut i = 0;
+    while i < samples.len() {
+        let mut j = i;
+        while j + 1 < samples.len() && samples[j + 1].0 == samples[i].0 {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        rank_sum += rank * samples[i..=j].iter().filter(|(_, in_a)| *in_a).count() as f64;
+        let ties = (j - i + 1) as f64;
+        tie_correction += ties * ties * ties - ties;
+        i = j + 1;
+    }
+
+    let u = rank_sum - n1 * (n1 + 1.0) / 2.0;
+    let mean = n1 * n2 / 2.0;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)));
+    let p_value = if variance > 0.0 {
+        let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+        (2.0 * (1.0 - normal_cdf(z))).min(1.0)
+    } else {
+        1.0
+    };
+
+    Some(MannWhitney { u, p_value })
+}
+
+/// A difference with its confidence interval.
+#[derive(Clone, Debug, Serialize)]
+pub struct Delta {
+    pub estimate: f64,
+    pub ci_low: f64,
+    pub ci_high: f64,
+    /// Whether the confidence interval excludes zero.
+    pub significant: bool,
+}
+
+/// Bootstrap confidence interval at `confidence` (e.g. 0.95) of quantile `p` of `b` minus the
+/// same quantile of `a`.
+pub fn quantile_delta(a: &[f64], b: &[f64], p: f64, confidence: f64) -> Option<Delta> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let sorted = |samples: &[f64]| {
+        let mut samples = samples.to_vec();
+        samples.sort_by(|x, y| x.total_cmp(y));
+        samples
+    };
+    let estimate = quantile(&sorted(b), p) - quantile(&sorted(a), p);
+
+    let mut rng = thread_rng();
+    let mut resample = |samples: &[f64], buffer: &mut Vec<f64>| {
+        buffer.clear();
+        buffer.extend((0..samples.len()).map(|_| samples[rng.gen_range(0..samples.len())]));
+        buffer.sort_by(|x, y| x.total_cmp(y));
+        quantile(buffer, p)
+    };
+    let (mut buffer_a, mut buffer_b) = (Vec::new(), Vec::new());
+    let mut deltas = (0..BOOTSTRAP_RESAMPLES)
+        .map(|_| resample(b, &mut buffer_b) - resample(a, &mut buffer_a))
+        .collect::<Vec<_>>();
+    deltas.sort_by(|x, y| x.total_cmp(y));
+
+    let tail = (1.0 - confidence) / 2.0;
+    let (ci_low, ci_high) = (quantile(&deltas, tail), quantile(&deltas, 1.0 - tail));
+    Some(Delta {
+        estimate,
+        ci_low,
+        ci_high,
+        significant: ci_low > 0.0 || ci_high < 0.0,
+    })
+}
+
+/// [`quantile_delta`] of each of the [`QUANTILES`], by name.
+pub fn quantile_deltas(a: &[f64], b: &[f64], confidence: f64) -> BTreeMap<String, Delta> {
+    QUANTILES
+        .iter()
+        .filter_map(|(name, p)| {
+            quantile_delta(a, b, *p, confidence).map(|delta| (name.to_string(), delta))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantiles_round_the_rank() {
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(quantile(&sorted, 0.0), 1.0);
+        assert_eq!(quantile(&sorted, 0.5), 3.0);
+        assert_eq!(quantile(&sorted, 0.6), 3.0);
+        assert_eq!(quantile(&sorted, 0.65), 4.0);
+        assert_eq!(quantile(&sorted, 1.0), 5.0);
+    }
+
+    #[test]
+    fn normal_cdf_values() {
+        assert!((normal_cdf(0.0) - 0.5).abs() < 1e-7);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-4);
+        assert!((normal_cdf(-1.96) - 0.025).abs() < 1e-4);
+    }
+
+    #[test]
+    fn mann_whitney_separated_samples() {
+        let a = (0..20).map(f64::from).collect::<Vec<_>>();
+        let b = (100..120).map(f64::from).collect::<Vec<_>>();
+        let test = mann_whitney(&a, &b).unwrap();
+        // every sample of a is smaller than every sample of b
+        assert_eq!(test.u, 0.0);
+        assert!(test.p_value < 0.001);
+    }
+
+    #[test]
+    fn mann_whitney_identical_samples() {
+        let a = [1.0, 2.0, 2.0, 3.0, 4.0];
+        let test = mann_whitney(&a, &a).unwrap();
+        assert_eq!(test.u, 12.5);
+        assert!(test.p_value > 0.99);
+
+        // all ties leave no variance
+        assert_eq!(mann_whitney(&[1.0; 3], &[1.0; 4]).unwrap().p_value, 1.0);
+        assert!(mann_whitney(&[], &a).is_none());
+    }
+
+    #[test]
+    fn quantile_delta_significance() {
+        let a = (0..200)
+            .map(|i| 100.0 + (i % 10) as f64)
+            .collect::<Vec<_>>();
+        let slower = a.iter().map(|value| value + 50.0).collect::<Vec<_>>();
+        let delta = quantile_delta(&a, &slower, 0.5, 0.95).unwrap();
+        assert_eq!(delta.estimate, 50.0);
+        assert!(delta.significant);
+        assert!(delta.ci_low <= 50.0 && 50.0 <= delta.ci_high);
+
+        let same = quantile_delta(&a, &a, 0.5, 0.95).unwrap();
+        assert_eq!(same.estimate, 0.0);
+        assert!(!same.significant);
+    }
+
+    #[test]
+    fn quantile_deltas_by_name() {
+        let a = [1.0, 2.0, 3.0];
+        let deltas = quantile_deltas(&a, &a, 0.95);
+        assert_eq!(
+            deltas.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["p50", "p90", "p99"]
+        );
+        assert!(quantile_deltas(&[], &a, 0.95).is_empty());
+    }
+}
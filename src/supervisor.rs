@@ -0,0 +1,183 @@
+//! Supervision of a provider's tasks.
+//!
+//! Every provider runs a set of tasks: its monitor, with the backfill worker, and probes like the
+//! health check or the archival depth probe. Spawned on their own, a panic in one of them ends it
+//! silently while the rest of the agent keeps running and the provider's metrics go stale.
+//!
+//! A [`Supervisor`] owns the tasks of a provider, or of a chain for the reference heads and
+//! cross-validation. A task that panics is logged and restarted after a backoff doubling from
+//! 1 second up to 1 minute, which resets once the task ran for a minute without panicking.
+//! A task that returns, e.g. a monitor refusing a provider serving the wrong chain, isn't
+//! restarted.
+//!
+//! Panics are caught around the task rather than by its join handle, so the location recorded by
+//! the [panic hook](install_panic_hook) on the panicking thread goes with the message. Each one is
+//! counted in `task_panics_total` and restarts in `task_restarts_total`, both by `task`, and
+//! raised as a `panicked` [alert](crate::alert). The agent's own tasks, e.g. alerting or the
+//! metrics server, are [watched](Supervisor::watch) the same way, without restarts, by a
+//! supervisor whose metrics carry no provider labels.
+
+use crate::alert::{AlertEvents, Status};
+use crate::redact;
+
+use futures::FutureExt;
+use prometheus::{IntCounterVec, Opts, Registry};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Once};
+use std::time::Duration;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+thread_local! {
+    /// Where the last panic on this thread happened.
+    static LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Record where panics happen, on top of the panic hook already installed.
+pub fn install_panic_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            LOCATION.with(|location| {
+                *location.borrow_mut() = info.location().map(ToString::to_string);
+            });
+            previous(info);
+        }));
+    });
+}
+
+/// Run `future`, failing with the message and location of its panic.
+async fn catch(future: impl Future<Output = ()>) -> Result<(), String> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|panic| {
+            // caught on the thread that panicked, before anything else could run on it
+            match LOCATION.with(|location| location.borrow_mut().take()) {
+                Some(location) => format!("{} at {}", message(&panic), location),
+                None => message(&panic).to_string(),
+            }
+        })
+}
+
+#[derive(Clone)]
+pub struct Supervisor {
+    chain: String,
+    endpoint: String,
+    events: AlertEvents,
+    panics: IntCounterVec,
+    restarts: IntCounterVec,
+}
+
+impl Supervisor {
+    /// The supervisor of the tasks of `endpoint`, raising alerts through `events` and reporting
+    /// to its `registry`.
+    pub fn new(chain: &str, endpoint: &str, events: AlertEvents, registry: &Registry) -> Self {
+        let panics = IntCounterVec::new(
+            Opts::new("task_panics_total", "Number of times a task panicked"),
+            &["task"],
+        )
+        .unwrap();
+        let restarts = IntCounterVec::new(
+            Opts::new(
+                "task_restarts_total",
+                "Number of times a task of the provider was restarted after panicking",
+            ),
+            &["task"],
+        )
+        .unwrap();
+        registry.register(Box::new(panics.clone())).unwrap();
+        registry.register(Box::new(restarts.clone())).unwrap();
+
+        Self {
+            chain: chain.to_string(),
+            endpoint: endpoint.to_string(),
+            events,
+            panics,
+            restarts,
+        }
+    }
+
+    fn panicked(&self, name: &'static str, panic: &str) {
+        self.panics.with_label_values(&[name]).inc();
+        self.events.fire(
+            &self.chain,
+            &self.endpoint,
+            "panicked",
+            Status::Degraded,
+            // alerts leave the agent, unlike the logs the panic hook redacts
+            &format!("task {} panicked: {}", name, redact::text(panic)),
+        );
+    }
+
+    /// Spawn task `name` running `run` on `task`, restarting it whenever it panics.
+    pub fn spawn<T, F, Fut>(&self, name: &'static str, task: T, run: F) -> JoinHandle<()>
+    where
+        T: Send + Sync + 'static,
+        F: Fn(Arc<T>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task = Arc::new(task);
+        let supervisor = self.clone();
+
+        tokio::spawn(async move {
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                let started = Instant::now();
+                let Err(panic) = catch(run(task.clone())).await else {
+                    return;
+                };
+                if started.elapsed() >= MAX_BACKOFF {
+                    backoff = MIN_BACKOFF;
+                }
+                log::error!(
+                    "[{}] Task {} of {} panicked, restarting it in {:?}: {}",
+                    supervisor.chain,
+                    name,
+                    supervisor.endpoint,
+                    backoff,
+                    panic
+                );
+                supervisor.panicked(name, &panic);
+                supervisor.restarts.with_label_values(&[name]).inc();
+                time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        })
+    }
+
+    /// Run task `name`, which can't be restarted, reporting if it panics.
+    pub fn watch(
+        &self,
+        name: &'static str,
+        task: impl Future<Output = ()>,
+    ) -> impl Future<Output = ()> {
+        let supervisor = self.clone();
+        async move {
+            if let Err(panic) = catch(task).await {
+                log::error!("Task {} panicked and stopped: {}", name, panic);
+                supervisor.panicked(name, &panic);
+            }
+        }
+    }
+}
+
+/// The message of a panic payload.
+fn message(panic: &Box<dyn Any + Send>) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
@@ -0,0 +1,219 @@
+//! Tenants.
+//!
+//! A single shared agent can serve several internal teams. `TENANTS` lists them, and
+//! `<TENANT>_CHAINS` the chains of each, so every chain's providers and scenarios belong to at
+//! most one tenant:
+//!
+//! ```text
+//! TENANTS="payments,indexing"
+//! PAYMENTS_CHAINS="ethereum"
+//! PAYMENTS_MAX_QPS=20
+//! PAYMENTS_REPORT_PATH="/reports/payments.json"
+//! INDEXING_CHAINS="polygon,arbitrum"
+//! INDEXING_LABELS="team=data,cost_center=42"
+//! ```
+//!
+//! The metrics of a tenant's chains are labelled with `tenant` and the `key=value` pairs of
+//! `<TENANT>_LABELS`. `<TENANT>_MAX_IN_FLIGHT` and `<TENANT>_MAX_QPS` cap the requests of all
+//! its providers together, on top of the global limits, so one team's scenarios can't starve
+//! another's. With `<TENANT>_REPORT_PATH`, a run report of only the tenant's providers is
+//! written there, next to the full report at `REPORT_PATH`.
+//!
+//! `LABELS` adds `key=value` pairs to the metrics of every chain, tenant or not. A tenant's labels
+//! take precedence.
+
+use crate::chain::ChainConfig;
+use crate::limits::RequestLimits;
+
+use std::collections::HashMap;
+use std::env;
+
+/// Labels every registry already has, which `LABELS` and tenants can't override, and the labels
+/// of metric families, which a registry refuses to take as constant labels too.
+const RESERVED_LABELS: [&str; 33] = [
+    "chain",
+    "rpc",
+    "endpoint",
+    "vendor",
+    "geo",
+    "cloud",
+    "zone",
+    "tenant",
+    "run_id",
+    "config_hash",
+    "version",
+    "git_commit",
+    "code",
+    "depth",
+    "diverged_endpoint",
+    "encoding",
+    "endpoint_a",
+    "endpoint_b",
+    "field",
+    "kind",
+    "level",
+    "magnitude",
+    "method",
+    "outcome",
+    "payload",
+    "probe",
+    "range",
+    "reason",
+    "reference",
+    "stage",
+    "task",
+    "template",
+    "window",
+];
+
+#[derive(Clone, Debug)]
+pub struct Tenant {
+    pub name: String,
+    chains: Vec<String>,
+    labels: Vec<(String, String)>,
+    /// Limits shared by the requests of all the tenant's providers.
+    pub limits: RequestLimits,
+    /// Where to write the tenant's run report.
+    pub report_path: Option<String>,
+}
+
+impl Tenant {
+    fn from_env(name: &str) -> Self {
+        let prefix = format!("{}_", name.to_uppercase().replace('-', "_"));
+        let var = |var: &str| env::var(format!("{}{}", prefix, var)).ok();
+
+        let chains = var("CHAINS")
+            .unwrap_or_else(|| panic!("Invalid {}CHAINS", prefix))
+            .split(',')
+            .map(str::trim)
+            .filter(|chain| !chain.is_empty())
+            .map(str::to_string)
+            .collect();
+        let labels = labels_from_env(&format!("{}LABELS", prefix));
+
+        Self {
+            name: name.to_string(),
+            chains,
+            labels,
+            limits: RequestLimits::from_env_prefixed(&prefix),
+            report_path: var("REPORT_PATH"),
+        }
+    }
+
+    /// Labels to attach to every metric of the tenant's chains.
+    pub fn const_labels(&self) -> HashMap<String, String> {
+        let mut labels: HashMap<_, _> = self.labels.iter().cloned().collect();
+        labels.insert("tenant".to_string(), self.name.clone());
+        labels
+    }
+}
+
+/// Parse the `key=value` pairs of the labels variable `var`, comma-separated.
+fn labels_from_env(var: &str) -> Vec<(String, String)> {
+    let Ok(labels) = env::var(var) else {
+        return Vec::new();
+    };
+    labels
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(|label| {
+            let (key, value) = label
+                .split_once('=')
+                .unwrap_or_else(|| panic!("Invalid {}", var));
+            let key = key.trim();
+            if RESERVED_LABELS.contains(&key) {
+                panic!("Invalid {}: {} is reserved", var, key);
+            }
+            (key.to_string(), value.trim().to_string())
+        })
+        .collect()
+}
+
+/// Labels to attach to every metric, from `LABELS`.
+pub fn global_labels() -> HashMap<String, String> {
+    labels_from_env("LABELS").into_iter().collect()
+}
+
+/// The tenants of the agent, none unless `TENANTS` is set.
+#[derive(Clone, Debug, Default)]
+pub struct Tenants(Vec<Tenant>);
+
+impl Tenants {
+    /// Load the tenants from the environment, checking that their chains are configured and
+    /// belong to a single tenant.
+    pub fn from_env(chains: &[ChainConfig]) -> Self {
+        let Ok(names) = env::var("TENANTS") else {
+            return Self::default();
+        };
+        let tenants: Vec<Tenant> = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(Tenant::from_env)
+            .collect();
+
+        let mut owners = HashMap::new();
+        for tenant in &tenants {
+            for chain in &tenant.chains {
+                if !chains.iter().any(|config| &config.name == chain) {
+                    panic!(
+                        "Invalid TENANTS: {} has unknown chain {}",
+                        tenant.name, chain
+                    );
+                }
+                if let Some(owner) = owners.insert(chain, &tenant.name) {
+                    panic!(
+                        "Invalid TENANTS: {} belongs to both {} and {}",
+                        chain, owner, tenant.name
+                    );
+                }
+            }
+        }
+
+        Self(tenants)
+    }
+
+    /// The tenant `chain` belongs to, if any.
+    pub fn of(&self, chain: &str) -> Option<&Tenant> {
+        self.0
+            .iter()
+            .find(|tenant| tenant.chains.iter().any(|name| name == chain))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Tenant> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        env::set_var("TENANT_TEST_LABELS", "team=data, cost_center=42,");
+        assert_eq!(
+            labels_from_env("TENANT_TEST_LABELS"),
+            vec![
+                ("team".to_string(), "data".to_string()),
+                ("cost_center".to_string(), "42".to_string()),
+            ]
+        );
+        assert!(labels_from_env("TENANT_TEST_UNSET_LABELS").is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "vendor is reserved")]
+    fn reserved_const_label() {
+        env::set_var("TENANT_TEST_VENDOR_LABELS", "vendor=acme");
+        labels_from_env("TENANT_TEST_VENDOR_LABELS");
+    }
+
+    #[test]
+    #[should_panic(expected = "method is reserved")]
+    fn reserved_variable_label() {
+        env::set_var("TENANT_TEST_METHOD_LABELS", "method=eth_call");
+        labels_from_env("TENANT_TEST_METHOD_LABELS");
+    }
+}
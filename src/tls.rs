@@ -0,0 +1,170 @@
+//! TLS backend selection for the measured HTTP clients.
+//!
+//! `TLS_BACKEND` selects the TLS implementation used to talk to the providers: `native-tls`
+//! (the platform library, default when built with the `native-tls` feature) or `rustls`.
+//!
+//! A full TLS handshake costs one or two extra round trips compared to a resumed session, which
+//! shows up as periodic latency spikes whenever a pooled connection is re-established. With
+//! rustls, every provider gets its own session cache and counts how often a new connection could
+//! offer a cached session (`tls_session_cache_hits_total`) versus how often it had to start a
+//! full handshake (`tls_session_cache_misses_total`). A server may still decline an offered
+//! session, so the hit rate is an upper bound on the actual resumption rate. native-tls doesn't
+//! expose its session cache, so these counters are only exported with rustls.
+
+use prometheus::{IntCounter, Registry};
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, Resumption, ServerName, Tls12ClientSessionValue,
+    Tls13ClientSessionValue,
+};
+use rustls::{ClientConfig, NamedGroup, OwnedTrustAnchor, RootCertStore};
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Number of servers a provider keeps TLS sessions for.
+const SESSION_CACHE_SIZE: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsBackend {
+    Rustls,
+    NativeTls,
+}
+
+impl Default for TlsBackend {
+    fn default() -> Self {
+        if cfg!(feature = "native-tls") {
+            TlsBackend::NativeTls
+        } else {
+            TlsBackend::Rustls
+        }
+    }
+}
+
+impl FromStr for TlsBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rustls" => Ok(TlsBackend::Rustls),
+            "native" | "native-tls" => Ok(TlsBackend::NativeTls),
+            _ => Err(format!("unknown TLS backend {}", s)),
+        }
+    }
+}
+
+impl TlsBackend {
+    pub fn from_env() -> Self {
+        let backend = env::var("TLS_BACKEND")
+            .ok()
+            .map(|backend| backend.parse::<TlsBackend>().expect("Invalid TLS_BACKEND"))
+            .unwrap_or_default();
+
+        if backend == TlsBackend::NativeTls && !cfg!(feature = "native-tls") {
+            panic!("Invalid TLS_BACKEND: built without the native-tls feature");
+        }
+
+        backend
+    }
+}
+
+/// A [`ClientSessionMemoryCache`] counting cache hits and misses when a connection looks up a
+/// session to resume.
+struct MeasuredSessionStore {
+    cache: ClientSessionMemoryCache,
+    hits: IntCounter,
+    misses: IntCounter,
+}
+
+impl ClientSessionStore for MeasuredSessionStore {
+    fn set_kx_hint(&self, server_name: &ServerName, group: NamedGroup) {
+        self.cache.set_kx_hint(server_name, group)
+    }
+
+    fn kx_hint(&self, server_name: &ServerName) -> Option<NamedGroup> {
+        self.cache.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: &ServerName, value: Tls12ClientSessionValue) {
+        self.cache.set_tls12_session(server_name, value)
+    }
+
+    // rustls only falls back to a TLS 1.2 session when there is no TLS 1.3 ticket, so this is
+    // the last lookup of a handshake and decides whether it was a miss.
+    fn tls12_session(&self, server_name: &ServerName) -> Option<Tls12ClientSessionValue> {
+        let session = self.cache.tls12_session(server_name);
+        match session {
+            Some(_) => self.hits.inc(),
+            None => self.misses.inc(),
+        }
+        session
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName) {
+        self.cache.remove_tls12_session(server_name)
+    }
+
+    fn insert_tls13_ticket(&self, server_name: &ServerName, value: Tls13ClientSessionValue) {
+        self.cache.insert_tls13_ticket(server_name, value)
+    }
+
+    fn take_tls13_ticket(&self, server_name: &ServerName) -> Option<Tls13ClientSessionValue> {
+        let ticket = self.cache.take_tls13_ticket(server_name);
+        if ticket.is_some() {
+            self.hits.inc();
+        }
+        ticket
+    }
+}
+
+fn register_counter(registry: &Registry, name: &str, help: &str) -> IntCounter {
+    let counter = IntCounter::new(name, help).expect("could not create counter");
+    registry
+        .register(Box::new(counter.clone()))
+        .expect("could not register counter");
+    counter
+}
+
+/// The rustls configuration reqwest would build, with a measured session cache.
+fn rustls_config(registry: &Registry) -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config.resumption = Resumption::store(Arc::new(MeasuredSessionStore {
+        cache: ClientSessionMemoryCache::new(SESSION_CACHE_SIZE),
+        hits: register_counter(
+            registry,
+            "tls_session_cache_hits_total",
+            "Number of new TLS connections that could offer a cached session for resumption",
+        ),
+        misses: register_counter(
+            registry,
+            "tls_session_cache_misses_total",
+            "Number of new TLS connections that required a full handshake",
+        ),
+    }));
+    config
+}
+
+/// A [`reqwest::ClientBuilder`] using the TLS backend selected by `TLS_BACKEND`. TLS metrics
+/// are registered with `registry`.
+pub fn client_builder(registry: &Registry) -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder();
+    match TlsBackend::from_env() {
+        TlsBackend::Rustls => builder.use_preconfigured_tls(rustls_config(registry)),
+        #[cfg(feature = "native-tls")]
+        TlsBackend::NativeTls => builder.use_native_tls(),
+        #[cfg(not(feature = "native-tls"))]
+        TlsBackend::NativeTls => unreachable!(),
+    }
+}
@@ -0,0 +1,274 @@
+//! Transactions root verification.
+//!
+//! A provider dropping, adding or reordering transactions in its block responses goes unnoticed
+//! by anything fetching transactions by hash. With `<CHAIN>_TRANSACTIONS_ROOT=true`, every new
+//! block of an EVM chain is fetched again with full transactions, the trie of their encodings is
+//! rebuilt and its root compared with the block's `transactionsRoot`. Mismatches are logged and
+//! counted in `transactions_root_mismatch_total`.
+//!
+//! Legacy, access list, dynamic fee, blob and set code transactions are encoded, as well as OP
+//! Stack deposits. A block with a transaction of another type, or whose encoding doesn't hash to
+//! the transaction's hash, e.g. with fields a chain added, can't be verified and is skipped.
+//! Verification follows `<CHAIN>_TRANSACTIONS_ROOT_SCHEDULE`.
+
+use crate::chain::ChainConfig;
+use crate::measured_json_rpc_client::MeasuredJsonRpc;
+use crate::schedule::Schedule;
+use crate::trie;
+
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use ethers::utils::rlp::RlpStream;
+use prometheus::{IntCounter, Registry};
+use serde::Deserialize;
+
+/// An authorization of a set code transaction.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Authorization {
+    chain_id: U256,
+    address: Address,
+    nonce: U64,
+    y_parity: U64,
+    r: U256,
+    s: U256,
+}
+
+/// The fields common to blob and set code transactions, up to the access list.
+fn append_dynamic_fee(stream: &mut RlpStream, tx: &Transaction) -> Option<()> {
+    stream.append(&tx.chain_id?);
+    stream.append(&tx.nonce);
+    stream.append(&tx.max_priority_fee_per_gas?);
+    stream.append(&tx.max_fee_per_gas?);
+    stream.append(&tx.gas);
+    stream.append(&tx.to?);
+    stream.append(&tx.value);
+    stream.append(&tx.input.as_ref());
+    stream.append(&tx.access_list.clone().unwrap_or_default());
+    Some(())
+}
+
+/// Field `name` of `tx`, which ethers doesn't parse, `None` if missing.
+fn field<T: serde::de::DeserializeOwned>(tx: &Transaction, name: &str) -> Option<T> {
+    tx.other.get_deserialized(name)?.ok()
+}
+
+/// The encoding of `tx` in the transactions trie, `None` for unsupported types.
+fn encode(tx: &Transaction) -> Option<Vec<u8>> {
+    let kind = tx.transaction_type.map(|kind| kind.as_u64()).unwrap_or(0);
+    let payload = match kind {
+        // ethers already prefixes access list and dynamic fee transactions with their type
+        0..=2 => return Some(tx.rlp().to_vec()),
+        3 => {
+            let mut stream = RlpStream::new();
+            stream.begin_unbounded_list();
+            append_dynamic_fee(&mut stream, tx)?;
+            stream.append(&field::<U256>(tx, "maxFeePerBlobGas")?);
+            stream.append_list(&field::<Vec<H256>>(tx, "blobVersionedHashes")?);
+            stream.append(&tx.v);
+            stream.append(&tx.r);
+            stream.append(&tx.s);
+            stream.finalize_unbounded_list();
+            stream.out().to_vec()
+        }
+        4 => {
+            let mut stream = RlpStream::new();
+            stream.begin_unbounded_list();
+            append_dynamic_fee(&mut stream, tx)?;
+            let authorizations = field::<Vec<Authorization>>(tx, "authorizationList")?;
+            stream.begin_list(authorizations.len());
+            for authorization in &authorizations {
+                stream.begin_list(6);
+                stream.append(&authorization.chain_id);
+                stream.append(&authorization.address);
+                stream.append(&authorization.nonce);
+                stream.append(&authorization.y_parity);
+                stream.append(&authorization.r);
+                stream.append(&authorization.s);
+            }
+            stream.append(&tx.v);
+            stream.append(&tx.r);
+            stream.append(&tx.s);
+            stream.finalize_unbounded_list();
+            stream.out().to_vec()
+        }
+        // OP Stack deposit
+        0x7e => {
+            let mut stream = RlpStream::new();
+            stream.begin_list(8);
+            stream.append(&field::<H256>(tx, "sourceHash")?);
+            stream.append(&tx.from);
+            match &tx.to {
+                Some(to) => stream.append(to),
+                None => stream.append_empty_data(),
+            };
+            stream.append(&field::<U256>(tx, "mint").unwrap_or_default());
+            stream.append(&tx.value);
+            stream.append(&tx.gas);
+            stream.append(&field::<bool>(tx, "isSystemTx").unwrap_or_default());
+            stream.append(&tx.input.as_ref());
+            stream.out().to_vec()
+        }
+        _ => return None,
+    };
+    Some([&[kind as u8], payload.as_slice()].concat())
+}
+
+pub struct TransactionsRoot {
+    chain: String,
+    endpoint: String,
+    schedule: Schedule,
+    mismatches: IntCounter,
+}
+
+impl TransactionsRoot {
+    /// The verification of the blocks of `endpoint`, reporting to its `registry`. `None` unless
+    /// `<CHAIN>_TRANSACTIONS_ROOT` is `true`.
+    pub fn from_chain(chain: &ChainConfig, endpoint: &str, registry: &Registry) -> Option<Self> {
+        let enabled = chain
+            .var("TRANSACTIONS_ROOT")
+            .map(|enabled| enabled.parse::<bool>().expect("Invalid TRANSACTIONS_ROOT"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let mismatches = IntCounter::new(
+            "transactions_root_mismatch_total",
+            "Number of blocks whose transactions don't hash to their transactions root",
+        )
+        .unwrap();
+        registry.register(Box::new(mismatches.clone())).unwrap();
+
+        Some(Self {
+            chain: chain.name.clone(),
+            endpoint: endpoint.to_string(),
+            schedule: Schedule::from_chain(chain, "TRANSACTIONS_ROOT"),
+            mismatches,
+        })
+    }
+
+    /// Fetch block `number` with its transactions and verify its transactions root.
+    pub async fn run(&self, provider: &Provider<MeasuredJsonRpc>, number: U64) {
+        if !self.schedule.is_active() {
+            return;
+        }
+        let block = match provider.get_block_with_txs(number).await {
+            Ok(Some(block)) => block,
+            res => {
+                log::warn!(
+                    "[{}] Failed to get block {} with transactions from {}: {:?}",
+                    self.chain,
+                    number,
+                    self.endpoint,
+                    res.err()
+                );
+                return;
+            }
+        };
+
+        let mut encoded = Vec::with_capacity(block.transactions.len());
+        for tx in &block.transactions {
+            match encode(tx) {
+                Some(tx_encoded) if H256(keccak256(&tx_encoded)) == tx.hash => {
+                    encoded.push(tx_encoded)
+                }
+                _ => {
+                    log::debug!(
+                        "[{}] Can't verify the transactions root of block {}, transaction {:?} of type {:?} can't be encoded",
+                        self.chain,
+                        number,
+                        tx.hash,
+                        tx.transaction_type
+                    );
+                    return;
+                }
+            }
+        }
+
+        let root = trie::ordered_root(&encoded);
+        if root != block.transactions_root {
+            log::warn!(
+                "[{}] Block {} from {} has transactions root {:?}, but its {} transactions hash to {:?}",
+                self.chain,
+                number,
+                self.endpoint,
+                block.transactions_root,
+                encoded.len(),
+                root
+            );
+            self.mismatches.inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn transaction(json: serde_json::Value) -> Transaction {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn legacy() {
+        let tx = transaction(json!({
+            "hash": "0xc3c5f700243de37ae986082fd2af88d2a7c2752a0c0f7b9d6ac47c729d45e067",
+            "nonce": "0x2",
+            "from": "0xfdcedc3bfca10ecb0890337fbdd1977aba84807a",
+            "to": "0xdca8ce283150ab773bcbeb8d38289bdb5661de1e",
+            "value": "0x0",
+            "gas": "0x15f90",
+            "gasPrice": "0x4a817c800",
+            "input": "0x",
+            "v": "0x25",
+            "r": "0x19f2694eb9113656dbea0b925e2e7ceb43df83e601c4116aee9c0dd99130be88",
+            "s": "0x73e5764b324a4f7679d890a198ba658ba1c8cd36983ff9797e10b1b89dbb448e",
+        }));
+        let encoded = encode(&tx).unwrap();
+        assert_eq!(H256(keccak256(&encoded)), tx.hash);
+        assert_eq!(
+            trie::ordered_root(&[encoded]),
+            "0x7270c1c4440180f2bd5215809ee3d545df042b67329499e1ab97eb759d31610d"
+                .parse::<H256>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn dynamic_fee() {
+        // a WETH deposit on mainnet
+        let tx = transaction(json!({
+            "type": "0x2",
+            "hash": "0x781d57642f4e3277fe01d370bd45ba1361b475bea6a35f26814e02a0a2b26549",
+            "chainId": "0x1",
+            "nonce": "0x1df",
+            "from": "0x057f8d0f6fb2703197363f75c002f766f1c4287a",
+            "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            "value": "0x2b40d6d551c8970c",
+            "gas": "0x6d22",
+            "gasPrice": "0x1344ead983",
+            "maxFeePerGas": "0x1344ead983",
+            "maxPriorityFeePerGas": "0x1344ead983",
+            "input": "0xd0e30db0",
+            "accessList": [],
+            "v": "0x1",
+            "r": "0x5616cdaec839ca14d209b59eafb706e623169dc9d0fa58fbf13931cef5b5e3b0",
+            "s": "0x3e708f8044bd158d29c2e250b6a98ea637c3bc460beeea63a8f00f7cebac432a",
+        }));
+        let encoded = encode(&tx).unwrap();
+        assert_eq!(encoded[0], 2);
+        assert_eq!(H256(keccak256(&encoded)), tx.hash);
+    }
+
+    #[test]
+    fn unsupported_type() {
+        let tx = Transaction {
+            transaction_type: Some(U64::from(0x42)),
+            ..Default::default()
+        };
+        assert_eq!(encode(&tx), None);
+    }
+}
@@ -0,0 +1,211 @@
+//! Roots of ordered Merkle Patricia tries.
+//!
+//! A block commits to its transactions and receipts with the root of a trie keyed by the RLP
+//! encoded index of each item. Only the root is needed to verify what a provider returned, so
+//! the trie is hashed bottom-up from the sorted keys instead of being built.
+
+use ethers::types::H256;
+use ethers::utils::keccak256;
+use ethers::utils::rlp::{self, RlpStream};
+
+/// The root of the trie of `values`, keyed by their index.
+pub fn ordered_root(values: &[Vec<u8>]) -> H256 {
+    if values.is_empty() {
+        return H256(keccak256(rlp::NULL_RLP));
+    }
+    let mut items = values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| (nibbles(&rlp::encode(&index)), value.as_slice()))
+        .collect::<Vec<_>>();
+    items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    H256(keccak256(node(&items, 0)))
+}
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// The compact encoding of a path of `nibbles`, flagged as a leaf's or an extension's.
+fn hex_prefix(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 0x20 } else { 0x00 };
+    let mut encoded = Vec::with_capacity(nibbles.len() / 2 + 1);
+    let rest = if nibbles.len() % 2 == 1 {
+        encoded.push(flag | 0x10 | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        encoded.push(flag);
+        nibbles
+    };
+    encoded.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    encoded
+}
+
+/// Append the reference to the node encoded as `node`: the node itself when shorter than a hash.
+fn append_reference(stream: &mut RlpStream, node: &[u8]) {
+    if node.len() < 32 {
+        stream.append_raw(node, 1);
+    } else {
+        stream.append(&H256(keccak256(node)));
+    }
+}
+
+/// The RLP encoded node of sorted `items`, whose keys share their first `depth` nibbles.
+fn node(items: &[(Vec<u8>, &[u8])], depth: usize) -> Vec<u8> {
+    let mut stream = RlpStream::new();
+
+    if let [(key, value)] = items {
+        stream.begin_list(2);
+        stream.append(&hex_prefix(&key[depth..], true));
+        stream.append(value);
+        return stream.out().to_vec();
+    }
+
+    // sorted, the first and last keys share the prefix of all of them
+    let (first, last) = (&items[0].0, &items[items.len() - 1].0);
+    let shared = first[depth..]
+        .iter()
+        .zip(&last[depth..])
+        .take_while(|(a, b)| a == b)
+        .count();
+    if shared > 0 {
+        stream.begin_list(2);
+        stream.append(&hex_prefix(&first[depth..depth + shared], false));
+        append_reference(&mut stream, &node(items, depth + shared));
+        return stream.out().to_vec();
+    }
+
+    stream.begin_list(17);
+    // a key ending here sorts first
+    let (value, mut rest) = match items.split_first() {
+        Some(((key, value), rest)) if key.len() == depth => (Some(*value), rest),
+        _ => (None, items),
+    };
+    for nibble in 0..16 {
+        let end = rest
+            .iter()
+            .position(|(key, _)| key[depth] != nibble)
+            .unwrap_or(rest.len());
+        let (children, next) = rest.split_at(end);
+        if children.is_empty() {
+            stream.append_empty_data();
+        } else {
+            append_reference(&mut stream, &node(children, depth + 1));
+        }
+        rest = next;
+    }
+    match value {
+        Some(value) => stream.append(&value),
+        None => stream.append_empty_data(),
+    };
+    stream.out().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The root of the trie of `pairs`, keyed by their raw keys.
+    fn root(pairs: &[(&[u8], &[u8])]) -> H256 {
+        let mut items = pairs
+            .iter()
+            .map(|(key, value)| (nibbles(key), *value))
+            .collect::<Vec<_>>();
+        items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        H256(keccak256(node(&items, 0)))
+    }
+
+    fn h256(hex: &str) -> H256 {
+        hex.parse().unwrap()
+    }
+
+    #[test]
+    fn empty_root() {
+        assert_eq!(
+            ordered_root(&[]),
+            h256("0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421")
+        );
+    }
+
+    #[test]
+    fn hex_prefix_flags() {
+        assert_eq!(hex_prefix(&[1, 2, 3, 4, 5], false), [0x11, 0x23, 0x45]);
+        assert_eq!(
+            hex_prefix(&[0, 1, 2, 3, 4, 5], false),
+            [0x00, 0x01, 0x23, 0x45]
+        );
+        assert_eq!(
+            hex_prefix(&[0, 15, 1, 12, 11, 8], true),
+            [0x20, 0x0f, 0x1c, 0xb8]
+        );
+        assert_eq!(hex_prefix(&[15, 1, 12, 11, 8], true), [0x3f, 0x1c, 0xb8]);
+    }
+
+    // vectors of the ethereum/tests trieanyorder suite
+    #[test]
+    fn dogs() {
+        assert_eq!(
+            root(&[
+                (b"doe", b"reindeer"),
+                (b"dog", b"puppy"),
+                (b"dogglesworth", b"cat"),
+            ]),
+            h256("0x8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3")
+        );
+    }
+
+    #[test]
+    fn puppy() {
+        assert_eq!(
+            root(&[
+                (b"do", b"verb"),
+                (b"horse", b"stallion"),
+                (b"doge", b"coin"),
+                (b"dog", b"puppy"),
+            ]),
+            h256("0x5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84")
+        );
+    }
+
+    #[test]
+    fn foo() {
+        assert_eq!(
+            root(&[(b"foo", b"bar"), (b"food", b"bass")]),
+            h256("0x17beaa1648bafa633cda809c90c04af50fc8aed3cb40d16efbddee6fdf63c4c3")
+        );
+    }
+
+    #[test]
+    fn small_values() {
+        assert_eq!(
+            root(&[(b"be", b"e"), (b"dog", b"puppy"), (b"bed", b"d")]),
+            h256("0x3f67c7a47520f79faa29255d2d3c084a7a6df0453116ed7232ff10277a8be68b")
+        );
+    }
+
+    #[test]
+    fn testy() {
+        assert_eq!(
+            root(&[(b"test", b"test"), (b"te", b"testy")]),
+            h256("0x8452568af70d8d140f58d941338542f645fcca50094b20f3c3d8c3df49337928")
+        );
+    }
+
+    #[test]
+    fn ordered_root_is_keyed_by_rlp_index() {
+        let values = (0..300u32)
+            .map(|i| rlp::encode(&i).to_vec())
+            .collect::<Vec<_>>();
+        let keys = (0..values.len())
+            .map(|index| rlp::encode(&index).to_vec())
+            .collect::<Vec<_>>();
+        let pairs = keys
+            .iter()
+            .zip(&values)
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+            .collect::<Vec<_>>();
+        assert_eq!(ordered_root(&values), root(&pairs));
+    }
+}
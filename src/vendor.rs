@@ -0,0 +1,93 @@
+//! Provider identification from RPC URLs.
+//!
+//! Well-known providers are recognized by the host of their URLs, e.g. `*.g.alchemy.com` or
+//! `*.quiknode.pro`, and every metric of the provider carries their normalized name as the
+//! `vendor` label, `unknown` otherwise. A recognized provider gets its defaults without manual
+//! tuning:
+//!
+//! - Its errors are [normalized](crate::error_kind) with its own codes, e.g. QuickNode's `-32007`
+//!   is a rate limit and retried.
+//! - `<CHAIN>_COST_PER_MILLION_REQUESTS` defaults to an estimate from its pay-as-you-go pricing,
+//!   for the [cost budgets](crate::budget).
+//! - [`Vendor::max_batch_size`] tells embedders how many requests its batches take.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vendor {
+    Alchemy,
+    Infura,
+    QuickNode,
+    Ankr,
+    Chainstack,
+    Blast,
+    Drpc,
+    PublicNode,
+    LlamaRpc,
+    GetBlock,
+}
+
+/// Host suffixes of the vendors' endpoints.
+const HOSTS: [(&str, Vendor); 11] = [
+    ("alchemy.com", Vendor::Alchemy),
+    ("infura.io", Vendor::Infura),
+    ("quiknode.pro", Vendor::QuickNode),
+    ("ankr.com", Vendor::Ankr),
+    ("chainstack.com", Vendor::Chainstack),
+    ("p2pify.com", Vendor::Chainstack),
+    ("blastapi.io", Vendor::Blast),
+    ("drpc.org", Vendor::Drpc),
+    ("publicnode.com", Vendor::PublicNode),
+    ("llamarpc.com", Vendor::LlamaRpc),
+    ("getblock.io", Vendor::GetBlock),
+];
+
+impl Vendor {
+    /// The vendor serving `host`, the host of an RPC URL.
+    pub fn detect(host: &str) -> Option<Self> {
+        let host = host.to_lowercase();
+        HOSTS
+            .iter()
+            .find(|(suffix, _)| host == *suffix || host.ends_with(&format!(".{}", suffix)))
+            .map(|(_, vendor)| *vendor)
+    }
+
+    /// The `vendor` label of a provider at `host`.
+    pub fn label(host: &str) -> &'static str {
+        Self::detect(host).map_or("unknown", |vendor| vendor.as_str())
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Vendor::Alchemy => "alchemy",
+            Vendor::Infura => "infura",
+            Vendor::QuickNode => "quicknode",
+            Vendor::Ankr => "ankr",
+            Vendor::Chainstack => "chainstack",
+            Vendor::Blast => "blast",
+            Vendor::Drpc => "drpc",
+            Vendor::PublicNode => "publicnode",
+            Vendor::LlamaRpc => "llamarpc",
+            Vendor::GetBlock => "getblock",
+        }
+    }
+
+    /// Estimated cost of a million requests of a head polling mix on the vendor's pay-as-you-go
+    /// plan, in USD, for vendors pricing by compute units at roughly 20 units per request.
+    pub fn cost_per_million_requests(&self) -> Option<f64> {
+        match self {
+            // $0.45 per million compute units
+            Vendor::Alchemy => Some(9.0),
+            // $0.50 per million API credits
+            Vendor::QuickNode => Some(10.0),
+            _ => None,
+        }
+    }
+
+    /// Requests a JSON-RPC batch of the vendor takes at most, `None` if unknown.
+    pub fn max_batch_size(&self) -> Option<usize> {
+        match self {
+            Vendor::Alchemy => Some(1000),
+            Vendor::QuickNode => Some(100),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! systemd readiness and watchdog notifications.
+//!
+//! Under systemd with `Type=notify`, the agent reports `READY=1` once every monitor has been
+//! started and `STOPPING=1` on shutdown. With `WatchdogSec=` set on the unit, it also sends
+//! watchdog keep-alives at half the watchdog interval, but only while it is healthy: at least
+//! one provider's head advanced within the last `WATCHDOG_STALL_BLOCKS` (default 10) expected
+//! block times, which requires both the provider to be reachable and the measurement runtime to
+//! make progress. A wedged agent stops sending keep-alives and systemd restarts it. Providers
+//! whose [request budget](crate::budget) is used up count as advancing, restarting wouldn't help.
+//!
+//! Outside of systemd (no `NOTIFY_SOCKET`), and on platforms other than unix, nothing is sent.
+//! The same check backs the healthcheck endpoint of the metrics server.
+
+use crate::block_metrics::BlockMetrics;
+use crate::budget::Budget;
+
+use tokio::time;
+
+use std::env;
+use std::time::{Duration, Instant};
+
+const DEFAULT_STALL_BLOCKS: f64 = 10.0;
+
+#[cfg(unix)]
+fn notify(state: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(state) {
+        log::warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// Tell systemd that startup is complete.
+#[cfg(unix)]
+pub fn ready() {
+    notify(&[sd_notify::NotifyState::Ready]);
+}
+
+/// Tell systemd that the agent is shutting down.
+#[cfg(unix)]
+pub fn stopping() {
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+#[cfg(unix)]
+fn keep_alive(status: &str) {
+    notify(&[
+        sd_notify::NotifyState::Watchdog,
+        sd_notify::NotifyState::Status(status),
+    ]);
+}
+
+#[cfg(unix)]
+fn watchdog_interval() -> Option<Duration> {
+    sd_notify::watchdog_enabled()
+}
+
+#[cfg(not(unix))]
+pub fn ready() {}
+
+#[cfg(not(unix))]
+pub fn stopping() {}
+
+#[cfg(not(unix))]
+fn keep_alive(_status: &str) {}
+
+#[cfg(not(unix))]
+fn watchdog_interval() -> Option<Duration> {
+    None
+}
+
+#[derive(Clone)]
+pub struct Watchdog {
+    stall_blocks: f64,
+    started: Instant,
+    /// Longest time each provider may go without a new head, with its block metrics and budget.
+    providers: Vec<(Duration, BlockMetrics, Budget)>,
+}
+
+impl Watchdog {
+    pub fn from_env() -> Self {
+        let stall_blocks = env::var("WATCHDOG_STALL_BLOCKS")
+            .ok()
+            .map(|n| n.parse::<f64>().expect("Invalid WATCHDOG_STALL_BLOCKS"))
+            .unwrap_or(DEFAULT_STALL_BLOCKS);
+
+        Self {
+            stall_blocks,
+            started: Instant::now(),
+            providers: Vec::new(),
+        }
+    }
+
+    pub fn watch(
+        &mut self,
+        expected_block_time: Duration,
+        block_metrics: BlockMetrics,
+        budget: Budget,
+    ) {
+        let max_silence = expected_block_time.mul_f64(self.stall_blocks);
+        self.providers.push((max_silence, block_metrics, budget));
+    }
+
+    /// Number of providers whose head advanced recently. Providers still within their first
+    /// stall period count as advancing.
+    fn advancing(&self) -> usize {
+        self.providers
+            .iter()
+            .filter(|(max_silence, block_metrics, budget)| {
+                if budget.exhausted().is_some() {
+                    return true;
+                }
+                let silence = block_metrics
+                    .since_last_head()
+                    .unwrap_or_else(|| self.started.elapsed());
+                silence <= *max_silence
+            })
+            .count()
+    }
+
+    /// Whether some provider's head advanced recently, or there are no providers to watch.
+    pub fn healthy(&self) -> bool {
+        self.providers.is_empty() || self.advancing() > 0
+    }
+
+    pub fn status(&self) -> String {
+        format!(
+            "{}/{} providers advancing",
+            self.advancing(),
+            self.providers.len()
+        )
+    }
+
+    /// Send keep-alives while healthy, when systemd's watchdog is enabled.
+    pub async fn run(self) {
+        let Some(watchdog) = watchdog_interval() else {
+            return;
+        };
+
+        let mut interval = time::interval(watchdog / 2);
+        loop {
+            interval.tick().await;
+
+            if self.healthy() {
+                keep_alive(&self.status());
+            } else {
+                log::warn!("No provider's head is advancing, withholding the watchdog keep-alive");
+            }
+        }
+    }
+}